@@ -0,0 +1,378 @@
+//! The load generator of the raw binary
+//!
+//! Starts receive clients and send clients against a server on loopback, sends messages at a fixed rate for a
+//! fixed duration and reports the throughput and the end-to-end latency percentiles of the messages delivered.
+//! Unless `--server` is given the server is started in the process, without rate limits, so that runs of different
+//! releases can be compared. Every message body starts with the time it was sent at, relative to the start of the run
+//!
+//! ```text
+//! raw bench: 4 receivers, 4 senders, 1000 msg/s, 256 bytes, 10s on 127.0.0.1:41234 (blocking)
+//! messages   sent 10000, delivered 10000, refused 0, lost 0
+//! throughput 1000.0 msg/s, 0.24 MiB/s
+//! latency    min 61us, p50 140us, p90 230us, p99 810us, p99.9 2.04ms, max 3.10ms
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use raw::client::{Client, ClientError, Incoming, Receiver};
+use raw::server::protocol::frame::FrameError;
+use raw::server::ratelimit::RateLimit;
+use raw::server::shutdown::ShutdownHandle;
+use raw::{Proto, ServerBuilder};
+
+use crate::cli::{BenchOptions, EXIT_FAILURE, EXIT_SUCCESS};
+
+/// Size of the `<sent at>;` header of the bench messages, the send time in microseconds padded to 20 digits
+const HEADER_SIZE:usize = 21;
+
+/// Time the receivers are given to be delivered the messages still in flight once the senders stopped
+const DRAIN_TIMEOUT:Duration = Duration::from_secs(5);
+
+/// Time the receivers wait for a message before checking whether the run is over
+const RECV_POLL:Duration = Duration::from_millis(100);
+
+/// Time given to the receive clients to be registered by the server
+const READY_TIMEOUT:Duration = Duration::from_secs(5);
+
+/// Secret the receive clients claim their alias with, servers require one by default
+const RECEIVER_SECRET:&str = "raw-bench";
+
+
+/// A struct representing the server a run is sent to
+///
+/// # Fields
+///
+/// - `addr`: The address of the server
+/// - `kind`: Describes the server in the report
+/// - `shutdown`: The [ShutdownHandle] of the server started by the run, none for an external server
+/// - `thread`: The thread serving the server started by the run
+struct Target{
+     addr:String,
+     kind:&'static str,
+     shutdown:Option<ShutdownHandle>,
+     thread:Option<JoinHandle<()>>
+}
+
+/// A struct representing what a send client did during a run
+///
+/// # Fields
+///
+/// - `sent`: The number of messages the server accepted
+/// - `refused`: The number of messages refused, by status
+/// - `error`: The error that stopped the client, if any
+#[derive(Default)]
+struct SenderReport{
+     sent:u64,
+     refused:BTreeMap<String, u64>,
+     error:Option<ClientError>
+}
+
+/// A struct representing what a receive client was delivered during a run
+///
+/// # Fields
+///
+/// - `latencies`: The end-to-end latency of every message delivered, in microseconds
+/// - `bytes`: The number of body bytes delivered
+#[derive(Default)]
+struct ReceiverReport{
+     latencies:Vec<u64>,
+     bytes:u64
+}
+
+/// Runs the load generator
+///
+/// # Returns
+/// - `u8`: The exit code of the command, [EXIT_FAILURE] if a client could not connect or failed during the run
+pub fn run(options:BenchOptions)->u8{
+     let mut target = match start(&options){
+          Ok(target)=>target,
+          Err(e)=>{
+               eprintln!("Could not start the server: {e}");
+               return EXIT_FAILURE;
+          }
+     };
+     let code = bench(&options, &target);
+     if let Some(shutdown) = target.shutdown.take(){
+          shutdown.shutdown();
+     }
+     if let Some(thread) = target.thread.take(){
+          let _ = thread.join();
+     }
+     code
+}
+
+fn bench(options:&BenchOptions, target:&Target)->u8{
+     println!(
+          "raw bench: {} receivers, {} senders, {}, {} bytes, {}s on {} ({})",
+          options.receivers, options.senders,
+          match options.rate{
+               0=>"unlimited rate".to_string(),
+               rate=>format!("{rate} msg/s")
+          },
+          options.size, options.duration.as_secs_f64(), target.addr, target.kind
+     );
+
+     let origin = Instant::now();
+     let stop = Arc::new(AtomicBool::new(false));
+     let delivered = Arc::new(AtomicU64::new(0));
+
+     //every receive client is connected and registered before the first message is sent
+     let mut receivers = Vec::new();
+     for i in 0..options.receivers{
+          match Receiver::connect(target.addr.as_str(), &receiver_alias(i), Some(RECEIVER_SECRET)){
+               Ok(receiver)=>receivers.push(receiver),
+               Err(e)=>{
+                    eprintln!("Could not connect receiver {i}: {e}");
+                    return EXIT_FAILURE;
+               }
+          }
+     }
+     if let Err(e) = wait_until_registered(&target.addr, options.receivers){
+          eprintln!("The receivers were not registered: {e}");
+          return EXIT_FAILURE;
+     }
+     let receivers:Vec<_> = receivers.into_iter()
+          .map(|receiver|spawn_receiver(receiver, origin, stop.clone(), delivered.clone()))
+          .collect();
+
+     let started = Instant::now();
+     let senders:Vec<_> = (0..options.senders)
+          .map(|j|spawn_sender(options, target.addr.clone(), j, origin, started))
+          .collect();
+     let senders:Vec<SenderReport> = senders.into_iter().map(|s|s.join().unwrap_or_default()).collect();
+     let elapsed = started.elapsed();
+
+     //waiting for the messages in flight
+     let sent:u64 = senders.iter().map(|s|s.sent).sum();
+     let drain = Instant::now();
+     while delivered.load(Ordering::Relaxed)<sent && drain.elapsed()<DRAIN_TIMEOUT{
+          sleep(RECV_POLL/10);
+     }
+     stop.store(true, Ordering::Relaxed);
+     let receivers:Vec<ReceiverReport> = receivers.into_iter().map(|r|r.join().unwrap_or_default()).collect();
+
+     report(&senders, receivers, elapsed);
+     let mut code = EXIT_SUCCESS;
+     for (j, sender) in senders.iter().enumerate(){
+          if let Some(e) = &sender.error{
+               eprintln!("Sender {j} stopped {e}");
+               code = EXIT_FAILURE;
+          }
+     }
+     code
+}
+
+/// Starts the server of the run on an ephemeral loopback port, or targets the server given
+fn start(options:&BenchOptions)->Result<Target, String>{
+     if let Some(server) = &options.server{
+          return Ok(Target{
+               addr:server.clone(),
+               kind:"external",
+               shutdown:None,
+               thread:None
+          });
+     }
+
+     //the run measures the relay, not its limits
+     let unlimited = RateLimit{
+          messages_per_second:0,
+          bytes_per_second:0,
+          ..RateLimit::default()
+     };
+     let loopback = SocketAddr::from(([127, 0, 0, 1], 0));
+     match options.asynchronous{
+          #[cfg(feature="async")]
+          true=>{
+               //the async server does not report the port it binds, a free one is picked beforehand
+               let addr = std::net::TcpListener::bind(loopback).and_then(|l|l.local_addr()).map_err(|e|e.to_string())?;
+               let mut server = raw::server::aio::AsyncServer::new(addr);
+               server.set_rate_limit(unlimited);
+               let shutdown = server.get_shutdown_handle();
+               let thread = spawn(move ||{
+                    if let Err(e) = server.serve(){
+                         eprintln!("Server exited with an error {e}");
+                    }
+               });
+               //the server binds once it runs
+               let deadline = Instant::now()+READY_TIMEOUT;
+               while std::net::TcpStream::connect(addr).is_err(){
+                    if Instant::now()>=deadline{
+                         return Err(format!("the server did not listen on {addr}"));
+                    }
+                    sleep(RECV_POLL/10);
+               }
+               Ok(Target{
+                    addr:addr.to_string(),
+                    kind:"async",
+                    shutdown:Some(shutdown),
+                    thread:Some(thread)
+               })
+          },
+          #[cfg(not(feature="async"))]
+          true=>Err("the async server requires raw to be built with the `async` feature".to_string()),
+          false=>{
+               let mut server = ServerBuilder::new().addr(loopback).rate_limit(unlimited).build();
+               let addr = server.bind().map_err(|e|e.to_string())?;
+               let shutdown = server.get_shutdown_handle();
+               let thread = spawn(move ||{
+                    if let Err(e) = server.serve(){
+                         eprintln!("Server exited with an error {e}");
+                    }
+               });
+               Ok(Target{
+                    addr:addr.to_string(),
+                    kind:"blocking",
+                    shutdown:Some(shutdown),
+                    thread:Some(thread)
+               })
+          }
+     }
+}
+
+/// Sends a probe to every receiver alias until the server accepts it, the handshakes are not answered.
+/// The probes are not bench messages, the receivers skip them
+fn wait_until_registered(addr:&str, receivers:usize)->Result<(), ClientError>{
+     let mut probe = Client::connect(addr, "bench_probe")?;
+     let deadline = Instant::now()+READY_TIMEOUT;
+     for i in 0..receivers{
+          loop {
+               match probe.send(&receiver_alias(i), "probe"){
+                    Ok(_)=>break,
+                    Err(ClientError::Refused(_)) if Instant::now()<deadline=>sleep(RECV_POLL/10),
+                    Err(e)=>return Err(e)
+               }
+          }
+     }
+     Ok(())
+}
+
+fn spawn_receiver(mut receiver:Receiver, origin:Instant, stop:Arc<AtomicBool>, delivered:Arc<AtomicU64>)->JoinHandle<ReceiverReport>{
+     spawn(move ||{
+          let mut report = ReceiverReport::default();
+          if receiver.set_read_timeout(Some(RECV_POLL)).is_err(){
+               return report;
+          }
+          while !stop.load(Ordering::Relaxed){
+               let message = match receiver.recv(){
+                    Ok(Some(Incoming::Message(message)))=>message,
+                    Ok(Some(Incoming::Notice(_))) | Ok(None)=>break,
+                    //the read timed out, or a frame was skipped
+                    Err(ClientError::StreamError(FrameError::Io(e))) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)=>continue,
+                    Err(ClientError::ProtocolError(_) | ClientError::StreamError(FrameError::TooLarge(_)))=>continue,
+                    Err(_)=>break
+               };
+               let now = origin.elapsed().as_micros() as u64;
+               let sent_at = match message.get_body().get(..HEADER_SIZE-1).and_then(|h|h.parse::<u64>().ok()){
+                    Some(sent_at)=>sent_at,
+                    None=>continue
+               };
+               report.latencies.push(now.saturating_sub(sent_at));
+               report.bytes += message.get_body().len() as u64;
+               delivered.fetch_add(1, Ordering::Relaxed);
+          }
+          report
+     })
+}
+
+fn spawn_sender(options:&BenchOptions, addr:String, j:usize, origin:Instant, started:Instant)->JoinHandle<SenderReport>{
+     let receivers = options.receivers;
+     let size = options.size.max(HEADER_SIZE);
+     let end = started+options.duration;
+     //each sender sends its share of the rate, evenly spaced
+     let interval = match options.rate{
+          0=>Duration::ZERO,
+          rate=>Duration::from_secs_f64(options.senders as f64/rate as f64)
+     };
+     spawn(move ||{
+          let mut report = SenderReport::default();
+          let mut client = match Client::connect(addr.as_str(), &format!("bench_s{j}")){
+               Ok(client)=>client,
+               Err(e)=>{
+                    report.error = Some(e);
+                    return report;
+               }
+          };
+          let padding = "x".repeat(size-HEADER_SIZE);
+
+          let mut next = started;
+          for k in 0usize.. {
+               let now = Instant::now();
+               if now>=end{
+                    break;
+               }
+               if next>now{
+                    sleep(next-now);
+               }
+               next += interval;
+
+               let body = format!("{:020};{padding}", origin.elapsed().as_micros());
+               match client.send(&receiver_alias((j+k)%receivers), &body){
+                    Ok(_)=>report.sent += 1,
+                    Err(ClientError::Refused(res))=>*report.refused.entry(res.get_status().to_string()).or_default() += 1,
+                    Err(e)=>{
+                         report.error = Some(e);
+                         break;
+                    }
+               }
+          }
+          report
+     })
+}
+
+fn receiver_alias(i:usize)->String{
+     format!("bench_r{i}")
+}
+
+/// Prints the throughput and latency percentiles of the run
+fn report(senders:&[SenderReport], receivers:Vec<ReceiverReport>, elapsed:Duration){
+     let sent:u64 = senders.iter().map(|s|s.sent).sum();
+     let mut refused = BTreeMap::new();
+     for (status, count) in senders.iter().flat_map(|s|s.refused.iter()){
+          *refused.entry(status.as_str()).or_insert(0u64) += count;
+     }
+     let bytes:u64 = receivers.iter().map(|r|r.bytes).sum();
+     let mut latencies:Vec<u64> = receivers.into_iter().flat_map(|r|r.latencies).collect();
+     latencies.sort_unstable();
+     let delivered = latencies.len() as u64;
+
+     let refused_total:u64 = refused.values().sum();
+     let refused_detail = match refused.is_empty(){
+          true=>String::new(),
+          false=>format!(" ({})", refused.iter().map(|(s, c)|format!("{s} {c}")).collect::<Vec<_>>().join(", "))
+     };
+     println!("messages   sent {sent}, delivered {delivered}, refused {refused_total}{refused_detail}, lost {}", sent.saturating_sub(delivered));
+
+     let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+     println!("throughput {:.1} msg/s, {:.2} MiB/s", delivered as f64/seconds, bytes as f64/seconds/(1024.0*1024.0));
+
+     if latencies.is_empty(){
+          println!("latency    no message delivered");
+          return;
+     }
+     println!(
+          "latency    min {}, p50 {}, p90 {}, p99 {}, p99.9 {}, max {}",
+          micros(latencies[0]), micros(percentile(&latencies, 0.5)), micros(percentile(&latencies, 0.9)),
+          micros(percentile(&latencies, 0.99)), micros(percentile(&latencies, 0.999)), micros(latencies[latencies.len()-1])
+     );
+}
+
+/// Returns the nearest-rank percentile `q` of sorted values
+fn percentile(sorted:&[u64], q:f64)->u64{
+     let rank = (q*sorted.len() as f64).ceil() as usize;
+     sorted[rank.clamp(1, sorted.len())-1]
+}
+
+/// Formats microseconds with the unit that keeps them readable
+fn micros(us:u64)->String{
+     match us{
+          0..1_000=>format!("{us}us"),
+          1_000..1_000_000=>format!("{:.2}ms", us as f64/1_000.0),
+          _=>format!("{:.2}s", us as f64/1_000_000.0)
+     }
+}
@@ -0,0 +1,362 @@
+//! The interactive chat of the raw binary
+//!
+//! A RECEIVE session prints the messages sent to the alias in a scrolling pane, while a SEND session sends the lines
+//! typed on the input line to the current recipient. Lines starting with `/` are commands:
+//!
+//! ```text
+//! /to <alias>       sets the recipient of the lines typed
+//! /who [alias..]    shows whether the aliases, or everyone talked to, are online
+//! /help             lists the commands
+//! /quit             leaves the chat
+//! ```
+//!
+//! The pane and input line are drawn with ANSI escape sequences when stdin and stdout are terminals,
+//! otherwise every message is printed on its own line
+
+use std::collections::BTreeSet;
+use std::env::var_os;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, IsTerminal, Stdout, Write};
+use std::process::{exit, Command};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use raw::client::{Client, ClientError, Incoming, PresenceClient, ReconnectEvent, ReconnectingReceiver};
+use raw::server::shutdown::ShutdownHandle;
+use raw::{Proto, Status};
+
+use crate::cli::{ChatOptions, EXIT_FAILURE, EXIT_SUCCESS};
+
+const HELP:&str = "/to <alias> sets the recipient, /who [alias..] shows who is online, /help lists the commands, /quit leaves";
+
+/// ANSI colours aliases are printed in, picked from the alias so that an alias keeps its colour
+const ALIAS_COLOURS:[u8;6] = [31, 32, 33, 34, 35, 36];
+
+/// Rows of the terminal when its size cannot be read
+const DEFAULT_ROWS:u16 = 24;
+
+/// Interval the signal watcher checks for ctrl-c at
+const SIGNAL_POLL:Duration = Duration::from_millis(100);
+
+
+/// A struct representing the terminal of the chat, shared by the input loop and the receiving thread
+///
+/// # Fields
+///
+/// - `out`: The stdout the chat is drawn on
+/// - `rows`: The rows of the terminal when the pane and input line are drawn, none when printing line by line
+/// - `colour`: True if aliases are coloured
+/// - `alias`: The alias of the user
+/// - `to`: The recipient of the lines typed, if chosen
+struct Screen{
+     out:Stdout,
+     rows:Option<u16>,
+     colour:bool,
+     alias:String,
+     to:Option<String>
+}
+
+/// An enum representing a line typed by the user
+///
+/// # Variants
+///
+/// - `Message`: A message to send to the recipient
+/// - `To`: Sets the recipient, or shows it when no alias is given
+/// - `Who`: Shows the presence of the aliases, or of everyone talked to when none is given
+/// - `Help`: Lists the commands
+/// - `Quit`: Leaves the chat
+/// - `Unknown`: A command that does not exist
+/// - `Empty`: A blank line
+#[derive(Debug, PartialEq, Eq)]
+enum Input{
+     Message(String),
+     To(Option<String>),
+     Who(Vec<String>),
+     Help,
+     Quit,
+     Unknown(String),
+     Empty
+}
+
+/// Runs the chat of `options.alias` until the user quits
+///
+/// # Returns
+/// - `u8`: The exit code of the chat
+pub fn run(options:ChatOptions)->u8{
+     let interactive = stdin().is_terminal() && stdout().is_terminal() && var_os("TERM").is_some_and(|t|t!="dumb");
+     let colour = interactive && options.colour && var_os("NO_COLOR").is_none();
+
+     //the sending session is opened first, so that an unreachable server is reported before the screen is drawn
+     let mut client = match Client::connect_with_secret(options.server.as_str(), &options.alias, options.secret.as_deref()){
+          Ok(client)=>client,
+          Err(e)=>{
+               eprintln!("Could not join {}: {e}", options.server);
+               return EXIT_FAILURE;
+          }
+     };
+
+     let screen = Arc::new(Mutex::new(Screen::new(options.alias.clone(), colour, interactive)));
+     let contacts = Arc::new(Mutex::new(BTreeSet::new()));
+     screen.lock().unwrap().open();
+     if let Some(to) = &options.to{
+          screen.lock().unwrap().set_to(to);
+          contacts.lock().unwrap().insert(to.clone());
+     }
+     screen.lock().unwrap().notice(HELP);
+     restore_on_signal(screen.clone());
+     spawn_receiver(&options, screen.clone(), contacts.clone());
+
+     screen.lock().unwrap().prompt();
+     for line in stdin().lock().lines(){
+          let line = match line{
+               Ok(line)=>line,
+               Err(_)=>break
+          };
+
+          match Input::parse(&line){
+               Input::Empty=>(),
+               Input::Quit=>break,
+               Input::Help=>screen.lock().unwrap().notice(HELP),
+               Input::Unknown(command)=>screen.lock().unwrap().notice(&format!("Unknown command {command}, /help lists the commands")),
+               Input::To(None)=>{
+                    let mut screen = screen.lock().unwrap();
+                    let text = match &screen.to{
+                         Some(to)=>format!("Sending to {to}"),
+                         None=>"No recipient, choose one with /to <alias>".to_string()
+                    };
+                    screen.notice(&text);
+               },
+               Input::To(Some(alias))=>{
+                    contacts.lock().unwrap().insert(alias.clone());
+                    screen.lock().unwrap().set_to(&alias);
+               },
+               Input::Who(aliases)=>{
+                    let aliases = match aliases.is_empty(){
+                         true=>contacts.lock().unwrap().iter().cloned().collect(),
+                         false=>aliases
+                    };
+                    who(&options, &screen, &aliases);
+               },
+               Input::Message(body)=>{
+                    let to = screen.lock().unwrap().to.clone();
+                    match to{
+                         Some(to)=>send(&options, &mut client, &screen, &to, &body),
+                         None=>screen.lock().unwrap().notice("No recipient, choose one with /to <alias>")
+                    }
+               }
+          }
+          screen.lock().unwrap().prompt();
+     }
+
+     screen.lock().unwrap().close();
+     EXIT_SUCCESS
+}
+
+/// Sends a message to `to`, reconnecting the sending session once if its connection was lost
+fn send(options:&ChatOptions, client:&mut Client, screen:&Mutex<Screen>, to:&str, body:&str){
+     let mut sent = client.send(to, body);
+     if let Err(ClientError::StreamError(_) | ClientError::Closed) = sent{
+          sent = Client::connect_with_secret(options.server.as_str(), &options.alias, options.secret.as_deref()).and_then(|reconnected|{
+               *client = reconnected;
+               client.send(to, body)
+          });
+     }
+
+     let mut screen = screen.lock().unwrap();
+     match sent{
+          Ok(_)=>{
+               let line = format!("{} -> {}: {}", screen.paint(&options.alias), screen.paint(to), body);
+               screen.line(&line);
+          },
+          Err(ClientError::Refused(res)) if res.get_status()==Status::QueueFull=>screen.notice(&format!("{to} could not take the message, it was not delivered: {}", res.get_message())),
+          Err(ClientError::Refused(res))=>screen.notice(&format!("{}: {}", res.get_status(), res.get_message())),
+          Err(e)=>screen.notice(&format!("Could not send the message {e}"))
+     }
+}
+
+/// Shows whether the aliases are online, with their custom status
+fn who(options:&ChatOptions, screen:&Mutex<Screen>, aliases:&[String]){
+     if aliases.is_empty(){
+          screen.lock().unwrap().notice("No one to look up, use /who <alias>..");
+          return;
+     }
+     let aliases:Vec<&str> = aliases.iter().map(|a|a.as_str()).collect();
+     let queried = PresenceClient::connect(options.server.as_str(), &options.alias, options.secret.as_deref())
+          .and_then(|mut presence|presence.query(&aliases));
+
+     let mut screen = screen.lock().unwrap();
+     match queried{
+          Ok(events)=>for event in events{
+               let state = event.get_state();
+               let line = match event.get_status(){
+                    Some(status)=>format!("  {} is {state} ({status})", screen.paint(event.get_alias())),
+                    None=>format!("  {} is {state}", screen.paint(event.get_alias()))
+               };
+               screen.line(&line);
+          },
+          Err(e)=>screen.notice(&format!("Could not look up who is online {e}"))
+     }
+}
+
+/// Spawns the thread receiving the messages of the alias, reconnecting whenever the connection is closed
+fn spawn_receiver(options:&ChatOptions, screen:Arc<Mutex<Screen>>, contacts:Arc<Mutex<BTreeSet<String>>>){
+     let mut receiver = ReconnectingReceiver::new(&options.server, &options.alias);
+     if let Some(secret) = &options.secret{
+          receiver.set_secret(secret.clone());
+     }
+     let events = screen.clone();
+     receiver.on_event(move |event|{
+          let text = match event{
+               ReconnectEvent::Connected{ attempt, .. } if *attempt>1=>"Reconnected".to_string(),
+               ReconnectEvent::Connected{ .. }=>return,
+               ReconnectEvent::Disconnected(_)=>"Disconnected from the server, reconnecting..".to_string(),
+               ReconnectEvent::Retrying{ .. }=>return,
+               ReconnectEvent::GaveUp(reason)=>format!("Stopped receiving messages {reason}")
+          };
+          events.lock().unwrap().notice(&text);
+     });
+
+     spawn(move ||{
+          for incoming in receiver.messages(){
+               let mut screen = screen.lock().unwrap();
+               match incoming{
+                    Ok(Incoming::Message(message))=>{
+                         contacts.lock().unwrap().insert(message.get_sender().clone());
+                         let line = format!("{}: {}", screen.paint(message.get_sender()), message.get_body());
+                         screen.line(&line);
+                    },
+                    Ok(Incoming::Notice(res))=>screen.notice(&format!("{}: {}", res.get_status(), res.get_message())),
+                    Err(e)=>screen.notice(&e.to_string())
+               }
+          }
+     });
+}
+
+/// Restores the terminal and exits once ctrl-c is pressed
+fn restore_on_signal(screen:Arc<Mutex<Screen>>){
+     let signals = ShutdownHandle::new();
+     signals.on_signal();
+     spawn(move ||{
+          while !signals.is_requested(){
+               sleep(SIGNAL_POLL);
+          }
+          screen.lock().unwrap().close();
+          exit(EXIT_SUCCESS as i32);
+     });
+}
+
+impl Input{
+     /// Parses a line typed by the user
+     fn parse(line:&str)->Self{
+          let line = line.trim_end_matches(['\n', '\r']);
+          if line.trim().is_empty(){
+               return Input::Empty;
+          }
+          let command = match line.strip_prefix('/'){
+               None=>return Input::Message(line.to_string()),
+               Some(command)=>command
+          };
+
+          let mut words = command.split_whitespace();
+          let name = words.next().unwrap_or("");
+          match name{
+               "to"=>Input::To(words.next().map(|alias|alias.to_string())),
+               "who"=>Input::Who(words.map(|alias|alias.to_string()).collect()),
+               "help"=>Input::Help,
+               "quit" | "exit"=>Input::Quit,
+               _=>Input::Unknown(format!("/{name}"))
+          }
+     }
+}
+
+impl Screen{
+     fn new(alias:String, colour:bool, interactive:bool)->Self{
+          Screen{
+               out:stdout(),
+               rows:if interactive { terminal_rows() } else { None },
+               colour,
+               alias,
+               to:None
+          }
+     }
+
+     /// Clears the terminal and confines scrolling to the pane, above the status and input lines
+     fn open(&mut self){
+          if let Some(rows) = self.rows{
+               let _ = write!(self.out, "\x1b[2J\x1b[1;{}r", rows-2);
+               self.status();
+          }
+     }
+
+     /// Gives the whole terminal back to the shell
+     fn close(&mut self){
+          if let Some(rows) = self.rows{
+               let _ = write!(self.out, "\x1b[r\x1b[{rows};1H\x1b[2K");
+          }
+          let _ = self.out.flush();
+     }
+
+     /// Prints a line at the bottom of the pane, scrolling the pane up, and puts the cursor back on the input line
+     fn line(&mut self, line:&str){
+          let _ = match self.rows{
+               Some(rows)=>write!(self.out, "\x1b7\x1b[{};1H\n{line}\x1b8", rows-2),
+               None=>writeln!(self.out, "{line}")
+          };
+          let _ = self.out.flush();
+     }
+
+     /// Prints a notice of the chat, dimmed when colouring
+     fn notice(&mut self, text:&str){
+          let line = match self.colour{
+               true=>format!("\x1b[2m* {text}\x1b[0m"),
+               false=>format!("* {text}")
+          };
+          self.line(&line);
+     }
+
+     fn set_to(&mut self, to:&str){
+          self.to = Some(to.to_string());
+          self.status();
+          if self.rows.is_none(){
+               self.notice(&format!("Sending to {to}"));
+          }
+     }
+
+     /// Draws the status line, between the pane and the input line
+     fn status(&mut self){
+          if let Some(rows) = self.rows{
+               let to = self.to.clone().unwrap_or("no recipient, /to <alias>".to_string());
+               let _ = write!(self.out, "\x1b7\x1b[{};1H\x1b[2K\x1b[7m {} -> {} \x1b[0m\x1b8", rows-1, self.alias, to);
+               let _ = self.out.flush();
+          }
+     }
+
+     /// Clears the input line and draws the prompt
+     fn prompt(&mut self){
+          if let Some(rows) = self.rows{
+               let _ = write!(self.out, "\x1b[{rows};1H\x1b[2K> ");
+               let _ = self.out.flush();
+          }
+     }
+
+     /// Returns the alias in its colour
+     fn paint(&self, alias:&str)->String{
+          if !self.colour{
+               return alias.to_string();
+          }
+          let hash = alias.bytes().fold(2166136261u32, |h, b|(h^b as u32).wrapping_mul(16777619));
+          format!("\x1b[{}m{}\x1b[0m", ALIAS_COLOURS[hash as usize%ALIAS_COLOURS.len()], alias)
+     }
+}
+
+/// Returns the rows of the terminal, none when it is too small to draw the pane
+fn terminal_rows()->Option<u16>{
+     let from_stty = File::open("/dev/tty").ok()
+          .and_then(|tty|Command::new("stty").arg("size").stdin(tty).output().ok())
+          .and_then(|out|String::from_utf8_lossy(&out.stdout).split_whitespace().next().and_then(|r|r.parse().ok()))
+          .filter(|rows|*rows>0);
+     let from_env = var_os("LINES").and_then(|l|l.to_string_lossy().parse().ok());
+     let rows = from_stty.or(from_env).unwrap_or(DEFAULT_ROWS);
+     (rows>=4).then_some(rows)
+}
@@ -0,0 +1,934 @@
+//! The command line interface of the raw binary
+//!
+//! ```text
+//! raw serve  [--config <file>] [--bind <host:port>] [--name <name>] [--peers <host:port,..>] [--cluster] [--log-level <level>] [--async]
+//! raw send   <to> [message] [--from <alias>] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+//! raw listen <alias> [--secret <secret>] [--format <text|raw|json>] [--reconnect] [--server <host:port>] [--log-level <level>]
+//! raw chat   <alias> [--to <alias>] [--secret <secret>] [--no-color] [--server <host:port>] [--log-level <level>]
+//! raw send-file    <to> <file> [--from <alias>] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+//! raw receive-file <alias> [--dir <dir>] [--yes] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+//! raw bench  [--receivers <n>] [--senders <n>] [--rate <msg/s>] [--size <bytes>] [--duration <secs>] [--server <host:port>] [--async]
+//! ```
+
+use std::fmt::Display;
+use std::env::var_os;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::str::FromStr;
+
+use log::{error, LevelFilter};
+
+use crate::{bench, chat};
+use raw::client::{Client, ClientError, FileReceiver, FileSender, Incoming, Receiver, ReconnectEvent, ReconnectingReceiver, TransferError};
+use raw::server::protocol::frame::FrameError;
+use raw::{BaseProto, BaseProtocol, DataTransferProtocol, Proto, Status};
+use raw::server::config::{split_list, ConfigError, ServerConfig};
+
+
+/// Exit code of a command that succeeded
+pub const EXIT_SUCCESS:u8 = 0;
+
+/// Exit code of a command that failed, eg.. the server could not be reached or could not bind
+pub const EXIT_FAILURE:u8 = 1;
+
+/// Exit code of a command line that could not be parsed
+pub const EXIT_USAGE:u8 = 2;
+
+/// Exit code of a command refused by the server, eg.. a message answered with a status other than Success
+pub const EXIT_REFUSED:u8 = 3;
+
+/// Exit code of a server whose configuration could not be loaded or is invalid
+pub const EXIT_CONFIG:u8 = 4;
+
+/// Address the server binds on, and clients connect to, by default
+pub const DEFAULT_ADDR:&str = "localhost:5000";
+
+/// Alias messages are sent from when `--from` is not given
+const DEFAULT_FROM:&str = "cli";
+
+const USAGE:&str = "\
+Usage:
+     raw serve  [--config <file>] [--bind <host:port>] [--name <name>] [--peers <host:port,..>] [--cluster] [--log-level <level>] [--async]
+     raw send   <to> [message] [--from <alias>] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+     raw listen <alias> [--secret <secret>] [--format <text|raw|json>] [--reconnect] [--server <host:port>] [--log-level <level>]
+     raw chat   <alias> [--to <alias>] [--secret <secret>] [--no-color] [--server <host:port>] [--log-level <level>]
+     raw send-file    <to> <file> [--from <alias>] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+     raw receive-file <alias> [--dir <dir>] [--yes] [--secret <secret>] [--server <host:port>] [--log-level <level>]
+     raw bench  [--receivers <n>] [--senders <n>] [--rate <msg/s>] [--size <bytes>] [--duration <secs>] [--server <host:port>] [--async]
+     raw help
+
+serve reads its settings from the config file, then from RAW_<SECTION>_<KEY> environment variables,
+then from its options. serve --name and --peers federate the server with the servers at --peers, which share the
+secret set as federation.secret or RAW_FEDERATION_SECRET; messages to aliases hosted by a peer are forwarded to it.
+serve --cluster makes the server a node of a cluster of its peers, each alias's pending messages are held by the node
+it hashes to, and are handed over whenever a node joins or leaves.
+listen, chat, send-file and receive-file claim their alias with --secret, servers require it unless
+server.require_secret is false.
+send reads the messages from stdin, one per line, when it is not given as an argument.
+listen --format json prints one JSON object per message: from, to, id, timestamp (unix milliseconds) and body.
+listen --reconnect reconnects whenever the connection is closed, resuming after the last message it printed.
+chat sends the lines typed to the recipient chosen with /to <alias>, /who shows who is online, /help lists the commands.
+send-file offers a file and streams it once accepted. receive-file waits for the next offer, asks whether to accept it
+unless --yes is given, writes the file to --dir (the current directory by default) and verifies its SHA-256 hash.
+bench sends messages from --senders clients to --receivers clients at --rate msg/s in total (0 for no limit) for
+--duration seconds, and reports the throughput and latency percentiles. It starts its own server on loopback
+unless --server is given. Defaults: 4 receivers, 4 senders, 1000 msg/s, 256 bytes, 10 seconds.
+Exit codes: 0 success, 1 failure, 2 usage error, 3 refused by the server, 4 invalid configuration";
+
+/// An enum representing the commands of the raw binary
+///
+/// # Variants
+///
+/// - `Serve`: Runs the relay server
+/// - `Send`: Sends a single message
+/// - `Listen`: Prints the messages sent to an alias
+/// - `Chat`: Runs the interactive chat of an alias
+/// - `SendFile`: Offers a file to an alias
+/// - `ReceiveFile`: Receives the next file offered to an alias
+/// - `Bench`: Runs the load generator
+/// - `Help`: Prints the usage
+#[derive(Debug)]
+pub enum Command{
+     Serve(ServeOptions),
+     Send(SendOptions),
+     Listen(ListenOptions),
+     Chat(ChatOptions),
+     SendFile(SendFileOptions),
+     ReceiveFile(ReceiveFileOptions),
+     Bench(BenchOptions),
+     Help
+}
+
+/// A struct representing the options of the `serve` command
+///
+/// # Fields
+///
+/// - `config`: The path of the [ServerConfig] file
+/// - `bind`: The `host:port` the server binds on, overrides the configuration
+/// - `name`: The name of the server in the federation, overrides the configuration
+/// - `peers`: The `host:port` addresses of the peers to dial, overrides the configuration
+/// - `cluster`: Makes the server a node of a cluster of its peers
+/// - `log_level`: The maximum level of the logs written to stderr, overrides the configuration
+/// - `asynchronous`: Runs the async server core, requires the `async` feature
+#[derive(Debug)]
+pub struct ServeOptions{
+     pub config:Option<String>,
+     pub bind:Option<String>,
+     pub name:Option<String>,
+     pub peers:Option<Vec<String>>,
+     pub cluster:bool,
+     pub log_level:Option<LevelFilter>,
+     pub asynchronous:bool
+}
+
+/// A struct representing the options of the `send` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server
+/// - `from`: The alias the message is sent from
+/// - `secret`: The secret claiming the alias the message is sent from, if any
+/// - `to`: The alias the message is sent to
+/// - `message`: The body of the message, the lines of stdin are sent as messages when not given
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct SendOptions{
+     pub server:String,
+     pub from:String,
+     pub secret:Option<String>,
+     pub to:String,
+     pub message:Option<String>,
+     pub log_level:LevelFilter
+}
+
+/// A struct representing the options of the `listen` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server
+/// - `alias`: The alias to receive the messages of
+/// - `secret`: The secret claiming the alias, if any
+/// - `format`: The [OutputFormat] messages are printed in
+/// - `reconnect`: Reconnects whenever the connection is closed, resuming after the last message printed
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct ListenOptions{
+     pub server:String,
+     pub alias:String,
+     pub secret:Option<String>,
+     pub format:OutputFormat,
+     pub reconnect:bool,
+     pub log_level:LevelFilter
+}
+
+/// A struct representing the options of the `chat` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server
+/// - `alias`: The alias of the user, messages are sent from and received on it
+/// - `secret`: The secret claiming the alias, if any
+/// - `to`: The recipient of the lines typed, until changed with `/to`
+/// - `colour`: Colours the aliases, unless `--no-color` is given
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct ChatOptions{
+     pub server:String,
+     pub alias:String,
+     pub secret:Option<String>,
+     pub to:Option<String>,
+     pub colour:bool,
+     pub log_level:LevelFilter
+}
+
+/// A struct representing the options of the `send-file` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server
+/// - `from`: The alias the file is offered from, the answers of the recipient are received on it
+/// - `secret`: The secret claiming the alias, if any
+/// - `to`: The alias the file is offered to
+/// - `file`: The path of the file
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct SendFileOptions{
+     pub server:String,
+     pub from:String,
+     pub secret:Option<String>,
+     pub to:String,
+     pub file:PathBuf,
+     pub log_level:LevelFilter
+}
+
+/// A struct representing the options of the `receive-file` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server
+/// - `alias`: The alias files are offered to
+/// - `secret`: The secret claiming the alias, if any
+/// - `dir`: The directory the file is written to
+/// - `yes`: Accepts the offer without asking
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct ReceiveFileOptions{
+     pub server:String,
+     pub alias:String,
+     pub secret:Option<String>,
+     pub dir:PathBuf,
+     pub yes:bool,
+     pub log_level:LevelFilter
+}
+
+/// A struct representing the options of the `bench` command
+///
+/// # Fields
+///
+/// - `server`: The `host:port` of the server, a server is started on loopback when not given
+/// - `receivers`: The number of receive clients, each with its own alias
+/// - `senders`: The number of send clients, sending to the receivers in turn
+/// - `rate`: The number of messages sent per second by all the senders, 0 sends as fast as the server answers
+/// - `size`: The size of the body of the messages
+/// - `duration`: The time messages are sent for
+/// - `asynchronous`: Starts the async server core, requires the `async` feature
+/// - `log_level`: The maximum level of the logs written to stderr
+#[derive(Debug)]
+pub struct BenchOptions{
+     pub server:Option<String>,
+     pub receivers:usize,
+     pub senders:usize,
+     pub rate:u32,
+     pub size:usize,
+     pub duration:Duration,
+     pub asynchronous:bool,
+     pub log_level:LevelFilter
+}
+
+/// An enum representing the formats the `listen` command prints messages in
+///
+/// # Variants
+///
+/// - `Text`: `<from>: <body>`, one message per line
+/// - `Raw`: The frames as sent by the server, one per line
+/// - `Json`: JSON Lines, one `{"from","to","id","timestamp","body"}` object per message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat{
+     Text,
+     Raw,
+     Json
+}
+
+/// An enum representing the errors of a command line that could not be parsed
+///
+/// # Variants
+///
+/// - `UnknownCommand`: The command is not one of serve, send, listen, chat, send-file, receive-file, bench or help
+/// - `UnknownArgument`: An option or argument the command does not take
+/// - `MissingArgument`: A required argument, or the value of an option, is missing
+/// - `InvalidValue`: The value of an option could not be parsed
+#[derive(Debug)]
+pub enum CliError{
+     UnknownCommand(String),
+     UnknownArgument(String),
+     MissingArgument(String),
+     InvalidValue(String, String)
+}
+
+/// Parses the arguments of the binary, without the program name
+pub fn parse<I:IntoIterator<Item = String>>(args:I)->Result<Command, CliError>{
+     let mut args = args.into_iter();
+     let command = match args.next(){
+          None=>return Ok(Command::Help),
+          Some(command)=>command
+     };
+     let mut options = Options::parse(args)?;
+
+     let command = match command.as_str(){
+          "serve"=>Command::Serve(ServeOptions{
+               config:options.value("--config")?,
+               bind:options.value("--bind")?,
+               name:options.value("--name")?,
+               peers:options.value("--peers")?.map(|peers|split_list(&peers)),
+               cluster:options.flag("--cluster"),
+               log_level:options.optional_log_level()?,
+               asynchronous:options.flag("--async")
+          }),
+          "send"=>Command::Send(SendOptions{
+               server:options.value("--server")?.unwrap_or(DEFAULT_ADDR.to_string()),
+               from:options.value("--from")?.unwrap_or(DEFAULT_FROM.to_string()),
+               secret:options.value("--secret")?,
+               log_level:options.log_level(LevelFilter::Warn)?,
+               to:options.positional("to")?,
+               message:options.optional_positional()
+          }),
+          "listen"=>Command::Listen(ListenOptions{
+               server:options.value("--server")?.unwrap_or(DEFAULT_ADDR.to_string()),
+               secret:options.value("--secret")?,
+               format:match options.value("--format")?{
+                    None=>OutputFormat::Text,
+                    Some(format)=>OutputFormat::from_str(&format)?
+               },
+               reconnect:options.flag("--reconnect"),
+               log_level:options.log_level(LevelFilter::Warn)?,
+               alias:options.positional("alias")?
+          }),
+          "chat"=>Command::Chat(ChatOptions{
+               server:options.value("--server")?.unwrap_or(DEFAULT_ADDR.to_string()),
+               secret:options.value("--secret")?,
+               to:options.value("--to")?,
+               colour:!options.flag("--no-color"),
+               //logs would be drawn over the chat
+               log_level:options.log_level(LevelFilter::Off)?,
+               alias:options.positional("alias")?
+          }),
+          "send-file"=>Command::SendFile(SendFileOptions{
+               server:options.value("--server")?.unwrap_or(DEFAULT_ADDR.to_string()),
+               from:options.value("--from")?.unwrap_or(DEFAULT_FROM.to_string()),
+               secret:options.value("--secret")?,
+               log_level:options.log_level(LevelFilter::Warn)?,
+               to:options.positional("to")?,
+               file:PathBuf::from(options.positional("file")?)
+          }),
+          "receive-file"=>Command::ReceiveFile(ReceiveFileOptions{
+               server:options.value("--server")?.unwrap_or(DEFAULT_ADDR.to_string()),
+               secret:options.value("--secret")?,
+               dir:PathBuf::from(options.value("--dir")?.unwrap_or(".".to_string())),
+               yes:options.flag("--yes"),
+               log_level:options.log_level(LevelFilter::Warn)?,
+               alias:options.positional("alias")?
+          }),
+          "bench"=>Command::Bench(BenchOptions{
+               server:options.value("--server")?,
+               receivers:options.number("--receivers", 4)?.max(1),
+               senders:options.number("--senders", 4)?.max(1),
+               rate:options.number("--rate", 1000)?,
+               size:options.number("--size", 256)?,
+               duration:Duration::from_secs(options.number("--duration", 10)?),
+               asynchronous:options.flag("--async"),
+               //the connections of the run would be logged by the server
+               log_level:options.log_level(LevelFilter::Error)?
+          }),
+          "help" | "--help" | "-h"=>Command::Help,
+          _=>return Err(CliError::UnknownCommand(command))
+     };
+
+     options.finish()?;
+     Ok(command)
+}
+
+/// Runs a parsed command
+///
+/// # Returns
+/// - `u8`: The exit code of the command
+pub fn run(command:Command)->u8{
+     match command{
+          Command::Help=>{
+               println!("{USAGE}");
+               EXIT_SUCCESS
+          },
+          Command::Serve(options)=>serve(options),
+          Command::Send(options)=>{
+               init_logger(options.log_level);
+               send(options)
+          },
+          Command::Listen(options)=>{
+               init_logger(options.log_level);
+               listen(options)
+          },
+          Command::Chat(options)=>{
+               init_logger(options.log_level);
+               chat::run(options)
+          },
+          Command::SendFile(options)=>{
+               init_logger(options.log_level);
+               send_file(options)
+          },
+          Command::ReceiveFile(options)=>{
+               init_logger(options.log_level);
+               receive_file(options)
+          },
+          Command::Bench(options)=>{
+               init_logger(options.log_level);
+               bench::run(options)
+          }
+     }
+}
+
+/// Prints a command line error and the usage
+///
+/// # Returns
+/// - `u8`: [EXIT_USAGE]
+pub fn usage_error(e:CliError)->u8{
+     eprintln!("{e}\n\n{USAGE}");
+     EXIT_USAGE
+}
+
+fn init_logger(level:LevelFilter){
+     env_logger::builder().filter_level(level).init();
+}
+
+fn serve(options:ServeOptions)->u8{
+     let config = match load_config(&options){
+          Ok(config)=>config,
+          Err(e)=>{
+               eprintln!("Invalid configuration {e}");
+               return EXIT_CONFIG;
+          }
+     };
+     init_logger(config.log_level);
+
+     let served = match config.asynchronous{
+          #[cfg(feature="async")]
+          true=>{
+               let mut server = match config.async_server(){
+                    Ok(server)=>server,
+                    Err(e)=>{
+                         eprintln!("Invalid configuration {e}");
+                         return EXIT_CONFIG;
+                    }
+               };
+               server.get_shutdown_handle().on_signal();
+               server.serve()
+          },
+          #[cfg(not(feature="async"))]
+          true=>{
+               eprintln!("Invalid configuration: the async server requires raw to be built with the `async` feature");
+               return EXIT_CONFIG;
+          },
+          false=>{
+               let mut server = match config.builder(){
+                    Ok(builder)=>builder.build(),
+                    Err(e)=>{
+                         eprintln!("Invalid configuration {e}");
+                         return EXIT_CONFIG;
+                    }
+               };
+               server.get_shutdown_handle().on_signal();
+               server.serve()
+          }
+     };
+
+     match served{
+          Ok(())=>EXIT_SUCCESS,
+          Err(e)=>{
+               error!("Server exited with an error {}", e);
+               EXIT_FAILURE
+          }
+     }
+}
+
+/// Loads the configuration of the server from its file, the environment and the command line, in increasing precedence
+fn load_config(options:&ServeOptions)->Result<ServerConfig, ConfigError>{
+     let mut config = match &options.config{
+          Some(path)=>ServerConfig::load(path)?,
+          None=>ServerConfig::default()
+     };
+     config.merge_env(|var|var_os(var).map(|v|v.to_string_lossy().to_string()))?;
+
+     if let Some(bind) = &options.bind{
+          config.bind = bind.clone();
+     }
+     if let Some(name) = &options.name{
+          config.federation_name = Some(name.clone());
+     }
+     if let Some(peers) = &options.peers{
+          config.peers = peers.clone();
+     }
+     if options.cluster{
+          config.cluster = true;
+     }
+     if let Some(level) = options.log_level{
+          config.log_level = level;
+     }
+     if options.asynchronous{
+          config.asynchronous = true;
+     }
+     config.validate()?;
+     Ok(config)
+}
+
+fn send(options:SendOptions)->u8{
+     let mut client = match Client::connect_with_secret(options.server.as_str(), &options.from, options.secret.as_deref()){
+          Ok(client)=>client,
+          Err(e)=>{
+               eprintln!("Could not send the message to {}: {e}", options.server);
+               return EXIT_FAILURE;
+          }
+     };
+
+     if let Some(body) = &options.message{
+          return match client.send(&options.to, body){
+               Ok(_)=>EXIT_SUCCESS,
+               Err(e @ ClientError::Refused(_))=>{
+                    eprintln!("{e}");
+                    EXIT_REFUSED
+               },
+               Err(e)=>{
+                    eprintln!("Could not send the message to {}: {e}", options.server);
+                    EXIT_FAILURE
+               }
+          };
+     }
+
+     //every line of stdin is a message, refused messages are reported and the following lines are still sent
+     let mut code = EXIT_SUCCESS;
+     for (number, line) in stdin().lock().lines().enumerate(){
+          let line = match line{
+               Ok(line)=>line,
+               Err(e)=>{
+                    eprintln!("Could not read the messages from stdin: {e}");
+                    return EXIT_FAILURE;
+               }
+          };
+          let body = line.trim_end_matches('\r');
+          if body.is_empty(){
+               continue;
+          }
+          match client.send(&options.to, body){
+               Ok(_)=>(),
+               Err(e @ ClientError::Refused(_))=>{
+                    eprintln!("Line {}: {e}", number+1);
+                    code = EXIT_REFUSED;
+               },
+               Err(e)=>{
+                    eprintln!("Line {}: could not send the message to {}: {e}", number+1, options.server);
+                    return EXIT_FAILURE;
+               }
+          }
+     }
+     code
+}
+
+fn listen(options:ListenOptions)->u8{
+     let mut receiver;
+     let mut reconnecting;
+     let messages:Box<dyn Iterator<Item = Result<Incoming, ClientError>>> = match options.reconnect{
+          true=>{
+               reconnecting = ReconnectingReceiver::new(&options.server, &options.alias);
+               if let Some(secret) = &options.secret{
+                    reconnecting.set_secret(secret.clone());
+               }
+               reconnecting.on_event(|event|match event{
+                    ReconnectEvent::Connected{ attempt, .. } if *attempt>1=>eprintln!("Reconnected after {attempt} attempts"),
+                    ReconnectEvent::Connected{ .. }=>(),
+                    ReconnectEvent::Disconnected(reason)=>eprintln!("Disconnected {reason}"),
+                    ReconnectEvent::Retrying{ attempt, delay, .. }=>eprintln!("Reconnecting in {}ms (attempt {attempt})", delay.as_millis()),
+                    ReconnectEvent::GaveUp(_)=>()
+               });
+               Box::new(reconnecting.messages())
+          },
+          false=>{
+               receiver = match Receiver::connect(options.server.as_str(), &options.alias, options.secret.as_deref()){
+                    Ok(receiver)=>receiver,
+                    Err(e)=>{
+                         eprintln!("Could not listen on {}: {e}", options.server);
+                         return EXIT_FAILURE;
+                    }
+               };
+               Box::new(receiver.messages())
+          }
+     };
+
+     let protocol = BaseProtocol::new();
+     let mut out = stdout();
+     let mut code = EXIT_SUCCESS;
+     for incoming in messages{
+          let printed = match (options.format, incoming){
+               (OutputFormat::Text, Ok(Incoming::Message(message)))=>writeln!(out, "{}: {}", message.get_sender(), message.get_body()),
+               (OutputFormat::Raw, Ok(Incoming::Message(message)))=>{
+                    let raw = protocol.to_raw(message).map(|raw|String::from_utf8_lossy(&raw).to_string()).unwrap_or_default();
+                    writeln!(out, "{}", raw.replace('\n', "\\n"))
+               },
+               (OutputFormat::Json, Ok(Incoming::Message(message)))=>writeln!(out, "{}", to_json_line(&message)),
+               (format, Ok(Incoming::Notice(notice)))=>{
+                    //a notice other than a shutdown means the server refused or dropped the client
+                    if notice.get_status()!=Status::ShuttingDown{
+                         code = EXIT_REFUSED;
+                    }
+                    match format{
+                         OutputFormat::Raw=>writeln!(out, "{notice}"),
+                         OutputFormat::Text | OutputFormat::Json=>{
+                              eprintln!("{notice}");
+                              Ok(())
+                         }
+                    }
+               },
+               (_, Err(e @ (ClientError::ProtocolError(_) | ClientError::StreamError(FrameError::TooLarge(_)))))=>{
+                    eprintln!("Skipped a frame of the server {e}");
+                    Ok(())
+               },
+               (_, Err(e))=>{
+                    eprintln!("Could not listen on {}: {e}", options.server);
+                    return match e.get_status(){
+                         Some(_)=>EXIT_REFUSED,
+                         None=>EXIT_FAILURE
+                    };
+               }
+          };
+          if printed.and_then(|_|out.flush()).is_err(){
+               break;
+          }
+     }
+     code
+}
+
+fn send_file(options:SendFileOptions)->u8{
+     let mut sender = match FileSender::connect(options.server.as_str(), &options.from, options.secret.as_deref()){
+          Ok(sender)=>sender,
+          Err(e)=>{
+               eprintln!("Could not connect to {}: {e}", options.server);
+               return EXIT_FAILURE;
+          }
+     };
+     eprintln!("Offering {} to {}", options.file.display(), options.to);
+     match sender.send_file(&options.to, &options.file){
+          Ok(offer)=>{
+               eprintln!("Sent {} ({} bytes) to {}, sha256 {} verified", offer.get_name(), offer.get_size(), options.to, offer.get_hash());
+               EXIT_SUCCESS
+          },
+          Err(e @ (TransferError::Declined(_) | TransferError::ClientError(ClientError::Refused(_))))=>{
+               eprintln!("{e}");
+               EXIT_REFUSED
+          },
+          Err(e)=>{
+               eprintln!("Could not send {}: {e}", options.file.display());
+               EXIT_FAILURE
+          }
+     }
+}
+
+fn receive_file(options:ReceiveFileOptions)->u8{
+     let mut receiver = match FileReceiver::connect(options.server.as_str(), &options.alias, options.secret.as_deref()){
+          Ok(receiver)=>receiver,
+          Err(e)=>{
+               eprintln!("Could not connect to {}: {e}", options.server);
+               return EXIT_FAILURE;
+          }
+     };
+     let offer = match receiver.next_offer(){
+          Ok(offer)=>offer,
+          Err(TransferError::ClientError(e @ ClientError::Refused(_)))=>{
+               eprintln!("{e}");
+               return EXIT_REFUSED;
+          },
+          Err(e)=>{
+               eprintln!("Could not receive an offer on {}: {e}", options.server);
+               return EXIT_FAILURE;
+          }
+     };
+
+     //the name is the sender's, only its last component is kept so that the file stays in the directory
+     let path = match offer.file_name(){
+          Some(name)=>options.dir.join(name),
+          None=>{
+               eprintln!("Declined the offer of {}, its file name '{}' is not usable", offer.get_from(), offer.get_name());
+               let _ = receiver.decline(&offer, "The file name is not usable");
+               return EXIT_FAILURE;
+          }
+     };
+     if path.exists(){
+          eprintln!("Declined the offer of {}, {} already exists", offer.get_from(), path.display());
+          let _ = receiver.decline(&offer, "The file already exists");
+          return EXIT_FAILURE;
+     }
+
+     eprint!("{} offers {} ({} bytes, sha256 {})", offer.get_from(), offer.get_name(), offer.get_size(), offer.get_hash());
+     if !options.yes{
+          eprint!(", accept? [y/N] ");
+          let mut answer = String::new();
+          if stdin().read_line(&mut answer).is_err() || !matches!(answer.trim(), "y" | "Y" | "yes"){
+               return match receiver.decline(&offer, "Declined by the recipient"){
+                    Ok(())=>EXIT_SUCCESS,
+                    Err(e)=>{
+                         eprintln!("Could not decline the offer: {e}");
+                         EXIT_FAILURE
+                    }
+               };
+          }
+     }else{
+          eprintln!();
+     }
+
+     match receiver.accept(&offer, &path){
+          Ok(())=>{
+               println!("{}", path.display());
+               EXIT_SUCCESS
+          },
+          Err(e)=>{
+               eprintln!("Could not receive {}: {e}", offer.get_name());
+               EXIT_FAILURE
+          }
+     }
+}
+
+/// Formats a message as a JSON object, timestamped with the time it was received at
+fn to_json_line(message:&BaseProto)->String{
+     let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d|d.as_millis()).unwrap_or(0);
+     let id = match message.get_id(){
+          Some(id)=>id.to_string(),
+          None=>"null".to_string()
+     };
+     format!(
+          "{{\"from\":{},\"to\":{},\"id\":{},\"timestamp\":{},\"body\":{}}}",
+          json_string(message.get_sender()), json_string(message.get_receiver()), id, timestamp, json_string(message.get_body())
+     )
+}
+
+/// Quotes and escapes a string as a JSON string
+fn json_string(s:&str)->String{
+     let mut quoted = String::with_capacity(s.len()+2);
+     quoted.push('"');
+     for c in s.chars(){
+          match c{
+               '"'=>quoted.push_str("\\\""),
+               '\\'=>quoted.push_str("\\\\"),
+               '\n'=>quoted.push_str("\\n"),
+               '\r'=>quoted.push_str("\\r"),
+               '\t'=>quoted.push_str("\\t"),
+               c if (c as u32)<0x20=>quoted.push_str(&format!("\\u{:04x}", c as u32)),
+               c=>quoted.push(c)
+          }
+     }
+     quoted.push('"');
+     quoted
+}
+
+/// The options and positional arguments of a command, taken by the command as it is parsed
+struct Options{
+     named:Vec<(String, Option<String>)>,
+     positional:Vec<String>
+}
+
+/// Options taking no value
+const FLAGS:[&str;5] = ["--async", "--reconnect", "--no-color", "--yes", "--cluster"];
+
+impl Options{
+     fn parse<I:Iterator<Item = String>>(mut args:I)->Result<Self, CliError>{
+          let mut named = Vec::new();
+          let mut positional = Vec::new();
+          while let Some(arg) = args.next(){
+               if !arg.starts_with("--") || arg=="--"{
+                    positional.push(arg);
+               }else if let Some((name, value)) = arg.split_once('='){
+                    named.push((name.to_string(), Some(value.to_string())));
+               }else if FLAGS.contains(&arg.as_str()){
+                    named.push((arg, None));
+               }else{
+                    match args.next(){
+                         Some(value)=>named.push((arg, Some(value))),
+                         None=>return Err(CliError::MissingArgument(arg))
+                    }
+               }
+          }
+          Ok(Options{
+               named,
+               positional
+          })
+     }
+
+     fn value(&mut self, name:&str)->Result<Option<String>, CliError>{
+          match self.named.iter().position(|(n, _)|n==name){
+               None=>Ok(None),
+               Some(i)=>match self.named.remove(i).1{
+                    Some(value)=>Ok(Some(value)),
+                    None=>Err(CliError::MissingArgument(name.to_string()))
+               }
+          }
+     }
+
+     fn flag(&mut self, name:&str)->bool{
+          match self.named.iter().position(|(n, _)|n==name){
+               None=>false,
+               Some(i)=>{
+                    self.named.remove(i);
+                    true
+               }
+          }
+     }
+
+     fn number<T:FromStr>(&mut self, name:&str, default:T)->Result<T, CliError>{
+          match self.value(name)?{
+               None=>Ok(default),
+               Some(value)=>value.parse().map_err(|_|CliError::InvalidValue(name.to_string(), value))
+          }
+     }
+
+     fn log_level(&mut self, default:LevelFilter)->Result<LevelFilter, CliError>{
+          Ok(self.optional_log_level()?.unwrap_or(default))
+     }
+
+     fn optional_log_level(&mut self)->Result<Option<LevelFilter>, CliError>{
+          match self.value("--log-level")?{
+               None=>Ok(None),
+               Some(level)=>LevelFilter::from_str(&level).map(Some).map_err(|_|CliError::InvalidValue("--log-level".to_string(), level))
+          }
+     }
+
+     fn positional(&mut self, name:&str)->Result<String, CliError>{
+          self.optional_positional().ok_or(CliError::MissingArgument(format!("<{name}>")))
+     }
+
+     fn optional_positional(&mut self)->Option<String>{
+          if self.positional.is_empty(){
+               None
+          }else{
+               Some(self.positional.remove(0))
+          }
+     }
+
+     /// Fails on the options and arguments the command did not take
+     fn finish(self)->Result<(), CliError>{
+          match self.named.into_iter().map(|(n, _)|n).chain(self.positional).next(){
+               Some(arg)=>Err(CliError::UnknownArgument(arg)),
+               None=>Ok(())
+          }
+     }
+}
+
+/// FromStr implementation for OutputFormat
+impl FromStr for OutputFormat{
+     type Err = CliError;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          match s{
+               "text"=>Ok(OutputFormat::Text),
+               "raw"=>Ok(OutputFormat::Raw),
+               "json"=>Ok(OutputFormat::Json),
+               _=>Err(CliError::InvalidValue("--format".to_string(), s.to_string()))
+          }
+     }
+}
+
+/// Display implementation for CliError
+impl Display for CliError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self{
+               Self::UnknownCommand(c)=>write!(f, "{{ error: UnknownCommand; info: {} }}", c),
+               Self::UnknownArgument(a)=>write!(f, "{{ error: UnknownArgument; info: {} }}", a),
+               Self::MissingArgument(a)=>write!(f, "{{ error: MissingArgument; info: {} }}", a),
+               Self::InvalidValue(o, v)=>write!(f, "{{ error: InvalidValue; info: {} {} }}", o, v)
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::net::{SocketAddr, TcpListener};
+     use std::thread::spawn;
+
+     use raw::ServerBuilder;
+
+     fn parse_line(line:&str)->Result<Command, CliError>{
+          parse(line.split_whitespace().map(str::to_string))
+     }
+
+     #[test]
+     fn parses_the_subcommands_and_their_defaults(){
+          assert!(matches!(parse_line(""), Ok(Command::Help)));
+          assert!(matches!(parse_line("--help"), Ok(Command::Help)));
+
+          let Ok(Command::Send(send)) = parse_line("send bob hello") else { panic!() };
+          assert_eq!((send.server.as_str(), send.from.as_str(), send.to.as_str()), (DEFAULT_ADDR, DEFAULT_FROM, "bob"));
+          assert_eq!(send.message.as_deref(), Some("hello"));
+          assert_eq!(send.log_level, LevelFilter::Warn);
+
+          let Ok(Command::Listen(listen)) = parse_line("listen bob --format=json --reconnect --server host:1") else { panic!() };
+          assert_eq!((listen.alias.as_str(), listen.server.as_str(), listen.format, listen.reconnect), ("bob", "host:1", OutputFormat::Json, true));
+
+          let Ok(Command::Serve(serve)) = parse_line("serve --peers a:1,b:2 --cluster --log-level debug") else { panic!() };
+          assert_eq!(serve.peers, Some(vec!["a:1".to_string(), "b:2".to_string()]));
+          assert!(serve.cluster && !serve.asynchronous);
+          assert_eq!(serve.log_level, Some(LevelFilter::Debug));
+
+          let Ok(Command::Bench(bench)) = parse_line("bench --receivers 0 --duration 3") else { panic!() };
+          assert_eq!((bench.receivers, bench.senders, bench.duration), (1, 4, Duration::from_secs(3)));
+
+          let Ok(Command::SendFile(send_file)) = parse_line("send-file bob notes.txt --from alice") else { panic!() };
+          assert_eq!((send_file.from.as_str(), send_file.file), ("alice", PathBuf::from("notes.txt")));
+     }
+
+     #[test]
+     fn refuses_unknown_and_incomplete_arguments(){
+          assert!(matches!(parse_line("shout bob"), Err(CliError::UnknownCommand(c)) if c=="shout"));
+          assert!(matches!(parse_line("send bob hello --loud"), Err(CliError::MissingArgument(a)) if a=="--loud"));
+          assert!(matches!(parse_line("send bob hello --loud 1"), Err(CliError::UnknownArgument(a)) if a=="--loud"));
+          assert!(matches!(parse_line("send bob hello again"), Err(CliError::UnknownArgument(a)) if a=="again"));
+          assert!(matches!(parse_line("listen --yes bob"), Err(CliError::UnknownArgument(a)) if a=="--yes"));
+          assert!(matches!(parse_line("send"), Err(CliError::MissingArgument(a)) if a=="<to>"));
+          assert!(matches!(parse_line("send-file bob"), Err(CliError::MissingArgument(a)) if a=="<file>"));
+          assert!(matches!(parse_line("send bob --from"), Err(CliError::MissingArgument(a)) if a=="--from"));
+          assert!(matches!(parse_line("listen bob --format xml"), Err(CliError::InvalidValue(o, v)) if o=="--format" && v=="xml"));
+          assert!(matches!(parse_line("bench --rate fast"), Err(CliError::InvalidValue(o, _)) if o=="--rate"));
+          assert!(matches!(parse_line("serve --log-level loud"), Err(CliError::InvalidValue(o, _)) if o=="--log-level"));
+     }
+
+     #[test]
+     fn maps_each_outcome_to_its_exit_code(){
+          assert_eq!(run(Command::Help), EXIT_SUCCESS);
+          assert_eq!(usage_error(CliError::UnknownCommand("shout".to_string())), EXIT_USAGE);
+
+          let Ok(Command::Serve(options)) = parse_line("serve --config /nonexistent/raw.conf") else { panic!() };
+          assert_eq!(serve(options), EXIT_CONFIG);
+
+          //a server that is not listening any more
+          let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+          let Ok(Command::Send(options)) = parse_line(&format!("send bob hello --server {closed}")) else { panic!() };
+          assert_eq!(send(options), EXIT_FAILURE);
+
+          //a message refused for its size
+          let mut server = ServerBuilder::new().addr(SocketAddr::from(([127, 0, 0, 1], 0))).max_body_size(4).drain_timeout(Duration::from_millis(200)).build();
+          let addr = server.bind().unwrap();
+          let shutdown = server.get_shutdown_handle();
+          let serving = spawn(move ||server.serve().unwrap());
+          let Ok(Command::Send(options)) = parse_line(&format!("send bob hi --server {addr}")) else { panic!() };
+          assert_eq!(send(options), EXIT_SUCCESS);
+          let Ok(Command::Send(options)) = parse_line(&format!("send bob too-long --server {addr}")) else { panic!() };
+          assert_eq!(send(options), EXIT_REFUSED);
+
+          shutdown.shutdown();
+          serving.join().unwrap();
+     }
+}
@@ -0,0 +1,88 @@
+//! Async send and receive clients for the `async` feature, speaking the same framed protocol as the blocking clients
+
+use std::io::{Error, ErrorKind};
+
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::server::aio::frame::{read_frame, write_frame};
+use crate::server::protocol::frame::FrameReader;
+use crate::server::protocol::res::Response;
+use crate::server::protocol::MAX_HEADER_SIZE;
+use crate::server::DEFAULT_MAX_BODY_SIZE;
+use super::into_io;
+
+
+/// An async client sending messages under an alias
+///
+/// # Fields
+///
+/// - `stream`: The stream connected to the server
+/// - `alias`: The alias the messages are sent from
+/// - `reader`: The [FrameReader] reading the responses of the server
+pub struct AsyncSender{
+     stream:TcpStream,
+     alias:String,
+     reader:FrameReader
+}
+
+impl AsyncSender{
+     /// Connects to the server and sends a SEND handshake for `alias`
+     pub async fn connect<A:ToSocketAddrs>(addr:A, alias:&str)->Result<Self, Error>{
+          let mut stream = TcpStream::connect(addr).await?;
+          write_frame(&mut stream, format!("SEND;{alias}").as_bytes()).await?;
+          Ok(AsyncSender{
+               stream,
+               alias:alias.to_string(),
+               reader:FrameReader::new(MAX_HEADER_SIZE)
+          })
+     }
+
+     /// Sends `body` to the receive clients of `to`
+     ///
+     /// # Returns
+     /// - `Response`: The response of the server, whatever its status
+     pub async fn send(&mut self, to:&str, body:&str)->Result<Response, Error>{
+          let frame = format!("{}-{}\n{}", self.alias, to, body);
+          write_frame(&mut self.stream, frame.as_bytes()).await?;
+          match read_frame(&mut self.stream, &mut self.reader).await.map_err(into_io)?{
+               Some(res)=>Response::parse(&String::from_utf8_lossy(&res)).map_err(|e|Error::new(ErrorKind::InvalidData, e.to_string())),
+               None=>Err(Error::new(ErrorKind::UnexpectedEof, "Server closed the connection"))
+          }
+     }
+}
+
+/// An async client receiving the messages sent to an alias
+///
+/// # Fields
+///
+/// - `stream`: The stream connected to the server
+/// - `reader`: The [FrameReader] reading the messages
+pub struct AsyncReceiver{
+     stream:TcpStream,
+     reader:FrameReader
+}
+
+impl AsyncReceiver{
+     /// Connects to the server and sends a RECEIVE handshake for `alias`, claimed with `secret` if any
+     pub async fn connect<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, Error>{
+          let mut stream = TcpStream::connect(addr).await?;
+          let handshake = match secret{
+               Some(secret)=>format!("RECEIVE;{alias};secret={secret}"),
+               None=>format!("RECEIVE;{alias}")
+          };
+          write_frame(&mut stream, handshake.as_bytes()).await?;
+          Ok(AsyncReceiver{
+               stream,
+               reader:FrameReader::new(MAX_HEADER_SIZE+DEFAULT_MAX_BODY_SIZE)
+          })
+     }
+
+     /// Awaits the next frame from the server, a message or a notice
+     ///
+     /// # Returns
+     /// - `Option<String>`: None once the server has closed the connection
+     pub async fn recv(&mut self)->Result<Option<String>, Error>{
+          let frame = read_frame(&mut self.stream, &mut self.reader).await.map_err(into_io)?;
+          Ok(frame.map(|f|String::from_utf8_lossy(&f).to_string()))
+     }
+}
@@ -0,0 +1,57 @@
+use std::fmt::Display;
+use std::io::Error;
+
+use crate::server::protocol::error::ProtocolError;
+use crate::server::protocol::frame::FrameError;
+use crate::server::protocol::res::{Response, Status};
+
+/// An enum representing the errors of a client connected to the server
+///
+/// # Variants
+///
+/// - `ConnectError`: Indicates that the server could not be reached, or the handshake could not be sent
+/// - `StreamError`: Indicates that a frame could not be written to or read from the stream
+/// - `ProtocolError`: Indicates that a frame sent by the server does not follow the protocol
+/// - `Refused`: Indicates that the server answered with a [Response] of a status other than [Status::Success],
+///   eg.. [Status::QueueFull] when the receive client of the alias a message was sent to, or the mailbox of the server, is full
+/// - `Closed`: Indicates that the server closed the connection before answering
+pub enum ClientError{
+     ConnectError(Error),
+     StreamError(FrameError),
+     ProtocolError(ProtocolError),
+     Refused(Response),
+     Closed
+}
+
+impl ClientError{
+     /// Returns the status the server refused the request with, if it did
+     pub fn get_status(&self)->Option<Status>{
+          match self{
+               Self::Refused(res)=>Some(res.get_status()),
+               _=>None
+          }
+     }
+}
+
+/// Display implementation for ClientError
+impl Display for ClientError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self{
+               Self::ConnectError(e)=>write!(f, "{{ error: ConnectError; info: {} }}", e),
+               Self::StreamError(e)=>write!(f, "{{ error: StreamError; info: {} }}", e),
+               Self::ProtocolError(e)=>write!(f, "{{ error: ProtocolError; info: {} }}", e),
+               Self::Refused(res)=>write!(f, "{{ error: Refused; info: {} }}", res),
+               Self::Closed=>write!(f, "{{ error: Closed; info: the server closed the connection }}")
+          }
+     }
+}
+
+/// Debug implementation for ClientError
+impl std::fmt::Debug for ClientError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "{}", self)
+     }
+}
+
+/// Error implementation for ClientError
+impl std::error::Error for ClientError{}
@@ -1,29 +1,265 @@
-use std::{io::{Read, Write}, net::TcpStream, thread, time::Duration};
-
-pub fn def_client(){
-     let mut  c = TcpStream::connect("localhost:5000").expect("Something went wrong while client tried to connect to server");
-     c.write("SEND;scale".as_bytes()).expect("sOMETHING WENT WRONG");
-     let str_buf = format!("scale-rand\n\rSome data is here");
-     let buf = str_buf.as_bytes();
-     thread::sleep(Duration::from_secs(6));
-     c.write(buf).expect("Something went wrong while sending data");
-     thread::sleep(Duration::from_secs(10));
-     let mut  read_buf = [0;1028];
-     c.read(&mut read_buf).expect("Something went wrong while reading from server...");
-     println!("{}", String::from_utf8_lossy(&read_buf));
-     c.shutdown(std::net::Shutdown::Both).expect("Something went wrong while trying to shutdown stream");
-}
-
-pub fn create_client(username:String){
-     let mut  c = TcpStream::connect("localhost:5000").expect("Something went wrong while client tried to connect to server");
-     let x = format!("RECEIVE;{username}");
-     c.write(x.as_bytes()).expect("sOMETHING WENT WRONG");
-     thread::sleep(Duration::from_secs(10));
-     let mut  read_buf = [0;1028];
-     c.read(&mut read_buf).expect("Something went wrong while reading from server...");
-     println!("{}", String::from_utf8_lossy(&read_buf));
-     read_buf = [0;1028];
-     println!("{}", String::from_utf8_lossy(&read_buf));
-     c.read(&mut read_buf).expect("Something went wrong while reading from server...");
-     c.shutdown(std::net::Shutdown::Both).expect("Something went wrong while trying to shutdown stream");
-}
\ No newline at end of file
+//! Blocking clients of the relay server
+//!
+//! A [Client] sends messages under an alias, a [Receiver] is delivered the messages sent to an alias:
+//!
+//! ```no_run
+//! use raw::client::{Client, Incoming, Receiver};
+//! use raw::Proto;
+//!
+//! let mut receiver = Receiver::connect("localhost:5000", "bob", Some("bob-secret")).unwrap();
+//! let mut client = Client::connect("localhost:5000", "alice").unwrap();
+//! client.send("bob", "hello").unwrap();
+//!
+//! for incoming in receiver.messages(){
+//!      if let Incoming::Message(message) = incoming.unwrap(){
+//!           println!("{}: {}", message.get_sender(), message.get_body());
+//!      }
+//! }
+//! ```
+
+#[cfg(feature="async")]
+pub mod aio;
+pub mod error;
+pub mod presence;
+pub mod reconnect;
+pub mod transfer;
+
+#[cfg(feature="async")]
+use std::io::Error;
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::server::protocol::frame::{FrameError, FrameReader, write_frame};
+use crate::server::protocol::pto::BaseProto;
+use crate::server::protocol::res::Response;
+use crate::server::protocol::{BaseProtocol, Data, DataTransferProtocol, DataTransferProtocolParsed, MAX_HEADER_SIZE};
+use crate::server::DEFAULT_MAX_BODY_SIZE;
+pub use error::ClientError;
+pub use presence::PresenceClient;
+pub use reconnect::{Backoff, ReconnectEvent, ReconnectingReceiver};
+pub use transfer::{FileOffer, FileReceiver, FileSender, TransferError};
+
+
+/// A client sending messages under an alias, connected with a SEND handshake
+///
+/// # Fields
+///
+/// - `stream`: The stream connected to the server
+/// - `alias`: The alias the messages are sent from
+/// - `protocol`: The [BaseProtocol] messages are encoded with
+/// - `reader`: The [FrameReader] reading the responses of the server
+pub struct Client{
+     stream:TcpStream,
+     alias:String,
+     protocol:BaseProtocol,
+     reader:FrameReader
+}
+
+impl Client{
+     /// Connects to the server and sends a SEND handshake for `alias`
+     ///
+     /// # Arguments
+     /// - `addr`: The address of the server, the first of its addresses that accepts the connection is used
+     /// - `alias`: The alias the messages are sent from
+     pub fn connect<A:ToSocketAddrs>(addr:A, alias:&str)->Result<Self, ClientError>{
+          Self::connect_with_secret(addr, alias, None)
+     }
+
+     /// Connects to the server and sends a SEND handshake for `alias`, claimed with `secret` if any.
+     /// The rate limits of the server are counted against a claimed alias, and against the address of the client otherwise
+     pub fn connect_with_secret<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, ClientError>{
+          let mut stream = TcpStream::connect(addr).map_err(ClientError::ConnectError)?;
+          let handshake = match secret{
+               Some(secret)=>format!("SEND;{alias};secret={secret}"),
+               None=>format!("SEND;{alias}")
+          };
+          write_frame(&mut stream, handshake.as_bytes()).map_err(ClientError::ConnectError)?;
+          Ok(Client{
+               stream,
+               alias:alias.to_string(),
+               protocol:BaseProtocol::new(),
+               reader:FrameReader::new(MAX_HEADER_SIZE)
+          })
+     }
+
+     /// Sends `body` to the receive clients of `to` and waits for the response of the server
+     ///
+     /// # Returns
+     /// - `Response`: The [Status::Success](crate::Status::Success) response, carrying the id the message was assigned
+     ///
+     /// # Errors
+     /// - [ClientError::Refused]: The server answered with another status, eg.. [Status::Throttled](crate::Status::Throttled)
+     ///   when the client exceeded its rate limit. A message to an alias without a receive client is kept for it and answered with
+     ///   [Status::Success](crate::Status::Success), or [Status::QueueFull](crate::Status::QueueFull) once the mailbox of the server is full
+     pub fn send(&mut self, to:&str, body:&str)->Result<Response, ClientError>{
+          let pto = BaseProto::create(self.alias.clone(), body.to_string(), to.to_string());
+          let raw = self.protocol.to_raw(pto).map_err(ClientError::ProtocolError)?;
+          write_frame(&mut self.stream, &raw).map_err(|e|ClientError::StreamError(FrameError::Io(e)))?;
+
+          let res = match self.reader.read_frame(&mut self.stream).map_err(ClientError::StreamError)?{
+               Some(res)=>String::from_utf8_lossy(&res).to_string(),
+               None=>return Err(ClientError::Closed)
+          };
+          into_result(&res)
+     }
+
+     //----Getters----
+     /// Returns the alias the messages are sent from
+     pub fn get_alias(&self)->&String{
+          &self.alias
+     }
+}
+
+/// An enum representing a frame delivered to a [Receiver]
+///
+/// # Variants
+///
+/// - `Message`: A message sent to the alias of the receiver
+/// - `Notice`: A [Response] of the server that is not about a message, eg.. [Status::ShuttingDown](crate::Status::ShuttingDown)
+///   before it closes the connection
+#[derive(Debug, Clone)]
+pub enum Incoming{
+     Message(BaseProto),
+     Notice(Response)
+}
+
+/// A client receiving the messages sent to an alias, connected with a RECEIVE handshake
+///
+/// # Fields
+///
+/// - `stream`: The stream connected to the server
+/// - `protocol`: The [BaseProtocol] messages are decoded with
+/// - `reader`: The [FrameReader] reading the messages
+/// - `last_id`: The id of the last message delivered, to resume after it
+/// - `closed`: True once the connection has been closed or has failed
+pub struct Receiver{
+     stream:TcpStream,
+     protocol:BaseProtocol,
+     reader:FrameReader,
+     last_id:Option<u64>,
+     closed:bool
+}
+
+impl Receiver{
+     /// Connects to the server and sends a RECEIVE handshake for `alias`, claimed with `secret` if any
+     pub fn connect<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, ClientError>{
+          Self::open(addr, alias, secret, None)
+     }
+
+     /// Connects to the server and sends a RECEIVE handshake for `alias` resuming after the message `last`,
+     /// the messages the server wrote to the alias after it are delivered again
+     pub fn resume<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>, last:u64)->Result<Self, ClientError>{
+          Self::open(addr, alias, secret, Some(last))
+     }
+
+     fn open<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>, resume:Option<u64>)->Result<Self, ClientError>{
+          let mut stream = TcpStream::connect(addr).map_err(ClientError::ConnectError)?;
+          let mut handshake = format!("RECEIVE;{alias}");
+          if let Some(secret) = secret{
+               handshake.push_str(&format!(";secret={secret}"));
+          }
+          if let Some(last) = resume{
+               handshake.push_str(&format!(";resume={last}"));
+          }
+          write_frame(&mut stream, handshake.as_bytes()).map_err(ClientError::ConnectError)?;
+          Ok(Receiver{
+               stream,
+               protocol:BaseProtocol::new(),
+               reader:FrameReader::new(MAX_HEADER_SIZE+DEFAULT_MAX_BODY_SIZE),
+               last_id:resume,
+               closed:false
+          })
+     }
+
+     /// Blocks until the next frame from the server
+     ///
+     /// # Returns
+     /// - `Ok(Some(incoming))`: The next message or notice
+     /// - `Ok(None)`: The server closed the connection
+     pub fn recv(&mut self)->Result<Option<Incoming>, ClientError>{
+          if self.closed{
+               return Ok(None);
+          }
+          let frame = match self.reader.read_frame(&mut self.stream){
+               Ok(Some(frame))=>frame,
+               Ok(None)=>{
+                    self.closed = true;
+                    return Ok(None);
+               },
+               //a frame that was too large is skipped, the stream is still usable
+               Err(e @ FrameError::TooLarge(_))=>return Err(ClientError::StreamError(e)),
+               //so is a read that timed out, the bytes of the frame read so far are kept
+               Err(FrameError::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)=>{
+                    return Err(ClientError::StreamError(FrameError::Io(e)));
+               },
+               Err(e)=>{
+                    self.closed = true;
+                    return Err(ClientError::StreamError(e));
+               }
+          };
+
+          //messages carry a `<from>-<to>` header line, notices do not
+          if !frame.contains(&b'\n'){
+               let res = Response::parse(&String::from_utf8_lossy(&frame)).map_err(ClientError::ProtocolError)?;
+               return Ok(Some(Incoming::Notice(res)));
+          }
+          let parsed = self.protocol.parse(Data::Utf8(frame)).map_err(ClientError::ProtocolError)?;
+          let body = parsed.get_body().map_err(ClientError::ProtocolError)?.to_string();
+          let mut message = BaseProto::create(parsed.get_client_id().to_string(), body, parsed.get_to().to_string());
+          if let Some(id) = parsed.get_id(){
+               self.last_id = Some(id);
+               message = message.with_id(id);
+          }
+          Ok(Some(Incoming::Message(message)))
+     }
+
+     /// Sets the time [Receiver::recv] waits for a frame before failing with a [ErrorKind::WouldBlock] or [ErrorKind::TimedOut]
+     /// [ClientError::StreamError], the receiver stays usable. None waits without limit
+     pub fn set_read_timeout(&mut self, timeout:Option<Duration>)->Result<(), ClientError>{
+          self.stream.set_read_timeout(timeout).map_err(|e|ClientError::StreamError(FrameError::Io(e)))
+     }
+
+     /// Returns a blocking iterator over the frames delivered to the receiver, ending once the server closes the connection
+     pub fn messages(&mut self)->Messages<'_>{
+          Messages{
+               receiver:self
+          }
+     }
+
+     //----Getters----
+     /// Returns the id of the last message delivered, or the id resumed after if none was delivered yet
+     pub fn get_last_id(&self)->Option<u64>{
+          self.last_id
+     }
+}
+
+/// A blocking iterator over the frames delivered to a [Receiver], see [Receiver::messages]
+pub struct Messages<'a>{
+     receiver:&'a mut Receiver
+}
+
+impl Iterator for Messages<'_>{
+     type Item = Result<Incoming, ClientError>;
+
+     fn next(&mut self)->Option<Self::Item>{
+          self.receiver.recv().transpose()
+     }
+}
+
+/// Parses a response of the server, a status other than [Status::Success](crate::Status::Success) is a [ClientError::Refused]
+fn into_result(res:&str)->Result<Response, ClientError>{
+     let res = Response::parse(res).map_err(ClientError::ProtocolError)?;
+     match res.get_status().is_success(){
+          true=>Ok(res),
+          false=>Err(ClientError::Refused(res))
+     }
+}
+
+#[cfg(feature="async")]
+fn into_io(e:FrameError)->Error{
+     match e{
+          FrameError::Io(e)=>e,
+          e=>Error::new(ErrorKind::InvalidData, e.to_string())
+     }
+}
@@ -0,0 +1,68 @@
+//! A client querying the online status of aliases, connected with a PRESENCE handshake
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+
+use crate::server::presence::PresenceEvent;
+use crate::server::protocol::frame::{FrameError, FrameReader, write_frame};
+use crate::server::protocol::MAX_HEADER_SIZE;
+use super::{into_result, ClientError};
+
+
+/// A client querying the online status of aliases
+///
+/// # Fields
+///
+/// - `stream`: The stream connected to the server
+/// - `reader`: The [FrameReader] reading the responses of the server
+pub struct PresenceClient{
+     stream:TcpStream,
+     reader:FrameReader
+}
+
+impl PresenceClient{
+     /// Connects to the server and sends a PRESENCE handshake for `alias`, claimed with `secret` if any
+     pub fn connect<A:ToSocketAddrs>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, ClientError>{
+          let mut stream = TcpStream::connect(addr).map_err(ClientError::ConnectError)?;
+          let handshake = match secret{
+               Some(secret)=>format!("PRESENCE;{alias};secret={secret}"),
+               None=>format!("PRESENCE;{alias}")
+          };
+          write_frame(&mut stream, handshake.as_bytes()).map_err(ClientError::ConnectError)?;
+          Ok(PresenceClient{
+               stream,
+               reader:FrameReader::new(MAX_HEADER_SIZE)
+          })
+     }
+
+     /// Queries the online status of the aliases
+     ///
+     /// # Returns
+     /// - `Vec<PresenceEvent>`: The presence of each alias, in the order they were given
+     pub fn query(&mut self, aliases:&[&str])->Result<Vec<PresenceEvent>, ClientError>{
+          let message = self.request(&format!("QUERY;{}", aliases.join(",")))?;
+          message.lines()
+               .map(|line|PresenceEvent::from_str(line).map_err(ClientError::ProtocolError))
+               .collect()
+     }
+
+     /// Sets the custom status text (away, busy...) of the alias of the client, refused unless it connected with the secret of its alias
+     pub fn set_status(&mut self, status:&str)->Result<(), ClientError>{
+          self.request(&format!("STATUS;{status}")).map(|_|())
+     }
+
+     /// Sends a command and reads its response, pushed presence changes are skipped
+     fn request(&mut self, command:&str)->Result<String, ClientError>{
+          write_frame(&mut self.stream, command.as_bytes()).map_err(|e|ClientError::StreamError(FrameError::Io(e)))?;
+          loop {
+               let res = match self.reader.read_frame(&mut self.stream).map_err(ClientError::StreamError)?{
+                    Some(res)=>String::from_utf8_lossy(&res).to_string(),
+                    None=>return Err(ClientError::Closed)
+               };
+               if res.starts_with("PRESENCE;"){
+                    continue;
+               }
+               return into_result(&res).map(|res|res.get_message().to_string());
+          }
+     }
+}
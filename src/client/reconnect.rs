@@ -0,0 +1,371 @@
+//! A receive client surviving server restarts and broken connections
+//!
+//! A [ReconnectingReceiver] reconnects with an exponential [Backoff] whenever its connection is closed, and resumes
+//! after the last message it was delivered so that the server delivers the messages written since again.
+//! The server journals the messages it wrote in memory only: across a restart of the server the messages written
+//! before it are not delivered again, nor are the messages that were pending for the alias
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::server::protocol::frame::FrameError;
+use crate::server::protocol::res::Status;
+use super::{ClientError, Incoming, Receiver};
+
+/// Delay before the first reconnection attempt by default
+pub const DEFAULT_INITIAL_DELAY:Duration = Duration::from_millis(500);
+
+/// Maximum delay between two reconnection attempts by default
+pub const DEFAULT_MAX_DELAY:Duration = Duration::from_secs(30);
+
+
+/// A struct representing the delays between the reconnection attempts of a [ReconnectingReceiver],
+/// doubling from `initial` up to `max` with every failed attempt
+///
+/// # Fields
+///
+/// - `initial`: The delay before the first attempt
+/// - `max`: The maximum delay between two attempts
+/// - `max_attempts`: The number of consecutive failed attempts after which the receiver gives up, unlimited if none
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff{
+     initial:Duration,
+     max:Duration,
+     max_attempts:Option<u32>
+}
+
+impl Backoff{
+     /// Default constructor for a [Backoff] retrying without limit
+     pub fn new(initial:Duration, max:Duration)->Self{
+          Backoff{
+               initial,
+               max:max.max(initial),
+               max_attempts:None
+          }
+     }
+
+     /// Sets the number of consecutive failed attempts after which the receiver gives up
+     pub fn set_max_attempts(&mut self, max_attempts:Option<u32>){
+          self.max_attempts = max_attempts;
+     }
+
+     /// Returns the delay before the attempt `attempt`, starting from 1
+     pub fn delay(&self, attempt:u32)->Duration{
+          let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+          self.initial.saturating_mul(factor).min(self.max)
+     }
+
+     //----Getters----
+     pub fn get_max_attempts(&self)->Option<u32>{
+          self.max_attempts
+     }
+
+     pub fn get_max(&self)->Duration{
+          self.max
+     }
+}
+
+/// Default implementation for Backoff
+impl Default for Backoff{
+     fn default() -> Self {
+          Self::new(DEFAULT_INITIAL_DELAY, DEFAULT_MAX_DELAY)
+     }
+}
+
+/// An enum representing the connection events of a [ReconnectingReceiver], handed to its callbacks
+///
+/// # Variants
+///
+/// - `Connected`: The receiver connected, resuming after the message with the given id if any. The messages written after it
+///   are only delivered again by the server that wrote them, and as long as it did not restart
+/// - `Disconnected`: The connection was closed or failed, with the reason
+/// - `Retrying`: The receiver waits before its next attempt, after the failure given as reason
+/// - `GaveUp`: The receiver stopped reconnecting, after too many attempts or a refusal that retrying cannot solve
+#[derive(Debug, Clone)]
+pub enum ReconnectEvent{
+     Connected{ attempt:u32, resumed_after:Option<u64> },
+     Disconnected(String),
+     Retrying{ attempt:u32, delay:Duration, reason:String },
+     GaveUp(String)
+}
+
+/// A callback handed the [ReconnectEvent]s of a [ReconnectingReceiver]
+pub type EventCallback = Box<dyn FnMut(&ReconnectEvent)+Send>;
+
+/// A receive client of an alias reconnecting to the server whenever its connection is closed.
+/// Each reconnection resumes after the last message delivered, so that the server delivers the messages it wrote since again.
+/// Notices of the server are handed to the application, the receiver reconnects once the server closes the connection
+///
+/// # Fields
+///
+/// - `addr`: The address of the server, resolved on every attempt
+/// - `alias`: The alias to receive the messages of
+/// - `secret`: The secret claiming the alias, if any
+/// - `backoff`: The [Backoff] between the reconnection attempts
+/// - `receiver`: The [Receiver] of the current connection, if connected
+/// - `last_id`: The id of the last message delivered
+/// - `connected_at`: The time the current connection was established
+/// - `failures`: The number of consecutive failed attempts
+/// - `callbacks`: The callbacks handed every [ReconnectEvent]
+/// - `stopped`: True once the receiver gave up
+pub struct ReconnectingReceiver{
+     addr:String,
+     alias:String,
+     secret:Option<String>,
+     backoff:Backoff,
+     receiver:Option<Receiver>,
+     last_id:Option<u64>,
+     connected_at:Instant,
+     failures:u32,
+     callbacks:Vec<EventCallback>,
+     stopped:bool
+}
+
+impl ReconnectingReceiver{
+     /// Default constructor for a [ReconnectingReceiver], connecting on the first call to [ReconnectingReceiver::recv]
+     pub fn new(addr:&str, alias:&str)->Self{
+          ReconnectingReceiver{
+               addr:addr.to_string(),
+               alias:alias.to_string(),
+               secret:None,
+               backoff:Backoff::default(),
+               receiver:None,
+               last_id:None,
+               connected_at:Instant::now(),
+               failures:0,
+               callbacks:Vec::new(),
+               stopped:false
+          }
+     }
+
+     /// Sets the secret claiming the alias
+     pub fn set_secret(&mut self, secret:String){
+          self.secret = Some(secret);
+     }
+
+     /// Sets the [Backoff] between the reconnection attempts
+     pub fn set_backoff(&mut self, backoff:Backoff){
+          self.backoff = backoff;
+     }
+
+     /// Sets the id of the last message delivered, eg.. persisted by a previous run of the application
+     pub fn set_last_id(&mut self, id:u64){
+          self.last_id = Some(id);
+     }
+
+     /// Registers a callback handed every [ReconnectEvent]
+     pub fn on_event<F:FnMut(&ReconnectEvent)+Send+'static>(&mut self, callback:F){
+          self.callbacks.push(Box::new(callback));
+     }
+
+     /// Blocks until the next frame from the server, reconnecting as long as the [Backoff] allows
+     ///
+     /// # Returns
+     /// - `Ok(Some(incoming))`: The next message or notice
+     /// - `Ok(None)`: The receiver gave up before
+     ///
+     /// # Errors
+     /// - The error the receiver gave up on, once, eg.. [ClientError::Refused] with [Status::Unauthorized]
+     /// - Frames of the server that could not be read or decoded, the connection is kept
+     pub fn recv(&mut self)->Result<Option<Incoming>, ClientError>{
+          let mut failure = None;
+          loop {
+               if self.stopped{
+                    return Ok(None);
+               }
+               let receiver = match &mut self.receiver{
+                    Some(receiver)=>receiver,
+                    None=>{
+                         self.connect(failure.take())?;
+                         continue;
+                    }
+               };
+
+               match receiver.recv(){
+                    Ok(Some(Incoming::Message(message)))=>{
+                         self.last_id = receiver.get_last_id();
+                         self.failures = 0;
+                         return Ok(Some(Incoming::Message(message)));
+                    },
+                    //retrying cannot claim an alias the receiver is not allowed to
+                    Ok(Some(Incoming::Notice(res))) if matches!(res.get_status(), Status::Unauthorized | Status::InvalidIdentifier)=>{
+                         let e = ClientError::Refused(res);
+                         self.give_up(&e);
+                         return Err(e);
+                    },
+                    Ok(Some(notice))=>return Ok(Some(notice)),
+                    Ok(None)=>failure = Some(self.disconnected(ClientError::Closed)),
+                    Err(e @ (ClientError::ProtocolError(_) | ClientError::StreamError(FrameError::TooLarge(_))))=>return Err(e),
+                    Err(e)=>failure = Some(self.disconnected(e))
+               }
+          }
+     }
+
+     /// Returns a blocking iterator over the frames delivered to the receiver, across reconnections
+     pub fn messages(&mut self)->ReconnectingMessages<'_>{
+          ReconnectingMessages{
+               receiver:self
+          }
+     }
+
+     /// Connects to the server, waiting for the [Backoff] before each attempt following a failure
+     fn connect(&mut self, mut failure:Option<ClientError>)->Result<(), ClientError>{
+          loop {
+               if let Some(e) = failure.take(){
+                    self.failures += 1;
+                    if self.backoff.get_max_attempts().is_some_and(|max|self.failures>max){
+                         self.give_up(&e);
+                         return Err(e);
+                    }
+                    let delay = self.backoff.delay(self.failures);
+                    self.emit(ReconnectEvent::Retrying{ attempt:self.failures, delay, reason:e.to_string() });
+                    sleep(delay);
+               }
+
+               let opened = match self.last_id{
+                    Some(last)=>Receiver::resume(self.addr.as_str(), &self.alias, self.secret.as_deref(), last),
+                    None=>Receiver::connect(self.addr.as_str(), &self.alias, self.secret.as_deref())
+               };
+               match opened{
+                    Ok(receiver)=>{
+                         self.receiver = Some(receiver);
+                         self.connected_at = Instant::now();
+                         self.emit(ReconnectEvent::Connected{ attempt:self.failures+1, resumed_after:self.last_id });
+                         return Ok(());
+                    },
+                    Err(e)=>failure = Some(e)
+               }
+          }
+     }
+
+     /// Drops the connection that failed with `e`, a connection that lasted longer than the maximum delay resets the backoff
+     fn disconnected(&mut self, e:ClientError)->ClientError{
+          self.receiver = None;
+          if self.connected_at.elapsed()>=self.backoff.get_max(){
+               self.failures = 0;
+          }
+          self.emit(ReconnectEvent::Disconnected(e.to_string()));
+          e
+     }
+
+     fn give_up(&mut self, e:&ClientError){
+          self.receiver = None;
+          self.stopped = true;
+          self.emit(ReconnectEvent::GaveUp(e.to_string()));
+     }
+
+     fn emit(&mut self, event:ReconnectEvent){
+          for callback in &mut self.callbacks{
+               callback(&event);
+          }
+     }
+
+     //----Getters----
+     /// Returns the id of the last message delivered, to resume after it
+     pub fn get_last_id(&self)->Option<u64>{
+          self.last_id
+     }
+}
+
+/// A blocking iterator over the frames delivered to a [ReconnectingReceiver], see [ReconnectingReceiver::messages]
+pub struct ReconnectingMessages<'a>{
+     receiver:&'a mut ReconnectingReceiver
+}
+
+impl Iterator for ReconnectingMessages<'_>{
+     type Item = Result<Incoming, ClientError>;
+
+     fn next(&mut self)->Option<Self::Item>{
+          self.receiver.recv().transpose()
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::net::{TcpListener, TcpStream};
+     use std::sync::{Arc, Mutex};
+     use std::thread::spawn;
+     use crate::server::protocol::frame::{write_frame, FrameReader};
+     use crate::server::protocol::pto::BaseProto;
+     use crate::server::protocol::res::Response;
+     use crate::server::protocol::{BaseProtocol, DataTransferProtocol};
+     use crate::Proto;
+
+     /// Accepts a receive client and returns its stream along with its handshake
+     fn accept(listener:&TcpListener)->(TcpStream, String){
+          let (mut stream, _) = listener.accept().unwrap();
+          let handshake = FrameReader::new(1024).read_frame(&mut stream).unwrap().unwrap();
+          (stream, String::from_utf8(handshake).unwrap())
+     }
+
+     #[test]
+     fn doubles_the_delay_up_to_the_maximum(){
+          let backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(1));
+          let delays:Vec<u128> = (1..=6).map(|attempt|backoff.delay(attempt).as_millis()).collect();
+          assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+          assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+          //the maximum is never below the first delay
+          assert_eq!(Backoff::new(Duration::from_secs(2), Duration::from_secs(1)).delay(3), Duration::from_secs(2));
+     }
+
+     #[test]
+     fn resumes_after_the_last_message_once_reconnected(){
+          let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+          let addr = listener.local_addr().unwrap().to_string();
+          let server = spawn(move ||{
+               let (mut stream, handshake) = accept(&listener);
+               assert_eq!(handshake, "RECEIVE;bob;secret=s");
+               let pto = BaseProto::create("alice".to_string(), "hello".to_string(), "bob".to_string()).with_id(7);
+               write_frame(&mut stream, &BaseProtocol::new().to_raw(pto).unwrap()).unwrap();
+               drop(stream);
+
+               let (mut stream, handshake) = accept(&listener);
+               assert_eq!(handshake, "RECEIVE;bob;secret=s;resume=7");
+               write_frame(&mut stream, Response::generate_res(Status::Unauthorized, "taken".to_string()).as_bytes()).unwrap();
+          });
+
+          let events = Arc::new(Mutex::new(Vec::new()));
+          let mut receiver = ReconnectingReceiver::new(&addr, "bob");
+          receiver.set_secret("s".to_string());
+          receiver.set_backoff(Backoff::new(Duration::from_millis(10), Duration::from_millis(10)));
+          let recorded = events.clone();
+          receiver.on_event(move |event|recorded.lock().unwrap().push(event.clone()));
+
+          match receiver.recv().unwrap(){
+               Some(Incoming::Message(message))=>assert_eq!(message.get_body(), "hello"),
+               other=>panic!("expected a message, got {other:?}")
+          }
+          assert_eq!(receiver.get_last_id(), Some(7));
+          //a refusal retrying cannot solve ends the receiver
+          assert!(matches!(receiver.recv(), Err(ClientError::Refused(res)) if res.get_status()==Status::Unauthorized));
+          assert!(receiver.recv().unwrap().is_none());
+          server.join().unwrap();
+
+          let events = events.lock().unwrap();
+          assert!(matches!(events[0], ReconnectEvent::Connected{ attempt:1, resumed_after:None }));
+          assert!(matches!(events[1], ReconnectEvent::Disconnected(_)));
+          assert!(matches!(events[2], ReconnectEvent::Retrying{ attempt:1, .. }));
+          assert!(matches!(events[3], ReconnectEvent::Connected{ attempt:2, resumed_after:Some(7) }));
+          assert!(matches!(events[4], ReconnectEvent::GaveUp(_)));
+     }
+
+     #[test]
+     fn gives_up_after_the_maximum_attempts(){
+          //a port nothing listens on once the listener is dropped
+          let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+          let mut backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(4));
+          backoff.set_max_attempts(Some(2));
+          let mut receiver = ReconnectingReceiver::new(&addr, "bob");
+          receiver.set_backoff(backoff);
+          let retries = Arc::new(Mutex::new(0));
+          let counted = retries.clone();
+          receiver.on_event(move |event|if matches!(event, ReconnectEvent::Retrying{ .. }){
+               *counted.lock().unwrap()+=1;
+          });
+
+          assert!(matches!(receiver.recv(), Err(ClientError::ConnectError(_))));
+          assert_eq!(*retries.lock().unwrap(), 2);
+          assert!(receiver.recv().unwrap().is_none());
+     }
+}
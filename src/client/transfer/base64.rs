@@ -0,0 +1,88 @@
+//! Standard base64 with padding, carrying the chunks of a file in the text body of messages
+
+const ALPHABET:&[u8;64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as base64
+pub fn encode(data:&[u8])->String{
+     let mut encoded = String::with_capacity(data.len().div_ceil(3)*4);
+     for chunk in data.chunks(3){
+          let n = match chunk.len(){
+               3=>(chunk[0] as u32)<<16 | (chunk[1] as u32)<<8 | chunk[2] as u32,
+               2=>(chunk[0] as u32)<<16 | (chunk[1] as u32)<<8,
+               _=>(chunk[0] as u32)<<16
+          };
+          for i in 0..4{
+               match i<=chunk.len(){
+                    true=>encoded.push(ALPHABET[(n>>(18-6*i) & 0x3f) as usize] as char),
+                    false=>encoded.push('=')
+               }
+          }
+     }
+     encoded
+}
+
+/// Decodes base64, returns None when the text is not valid base64
+pub fn decode(text:&str)->Option<Vec<u8>>{
+     let text = text.as_bytes();
+     if !text.len().is_multiple_of(4){
+          return None;
+     }
+     let mut decoded = Vec::with_capacity(text.len()/4*3);
+     for (i, quad) in text.chunks(4).enumerate(){
+          let last = i==text.len()/4-1;
+          let padding = quad.iter().rev().take_while(|c|**c==b'=').count();
+          if padding>2 || (padding>0 && !last){
+               return None;
+          }
+          let mut n = 0u32;
+          for c in &quad[..4-padding]{
+               n = n<<6 | ALPHABET.iter().position(|a|a==c)? as u32;
+          }
+          n <<= 6*padding;
+          let bytes = n.to_be_bytes();
+          decoded.extend_from_slice(&bytes[1..4-padding]);
+     }
+     Some(decoded)
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+
+     /// RFC 4648 section 10
+     const VECTORS:[(&str, &str);7] = [
+          ("", ""),
+          ("f", "Zg=="),
+          ("fo", "Zm8="),
+          ("foo", "Zm9v"),
+          ("foob", "Zm9vYg=="),
+          ("fooba", "Zm9vYmE="),
+          ("foobar", "Zm9vYmFy")
+     ];
+
+     #[test]
+     fn rfc_4648_vectors(){
+          for (data, encoded) in VECTORS{
+               assert_eq!(encode(data.as_bytes()), encoded);
+               assert_eq!(decode(encoded).as_deref(), Some(data.as_bytes()));
+          }
+     }
+
+     #[test]
+     fn round_trips_every_padding_length(){
+          //lengths 0, 1 and 2 modulo 3 are encoded with no, two and one padding characters
+          let data = (0..=255u8).collect::<Vec<_>>();
+          for len in 250..=256{
+               let encoded = encode(&data[..len]);
+               assert_eq!(encoded.len()-encoded.trim_end_matches('=').len(), (3-len%3)%3);
+               assert_eq!(decode(&encoded).as_deref(), Some(&data[..len]));
+          }
+     }
+
+     #[test]
+     fn rejects_invalid_text(){
+          for text in ["Zg=", "Zg==Zg==", "Z===", "Zm9*", "Zm9vY"]{
+               assert_eq!(decode(text), None, "{text}");
+          }
+     }
+}
@@ -0,0 +1,51 @@
+use std::fmt::Display;
+use std::io::Error;
+
+use crate::client::ClientError;
+
+/// An enum representing the errors of a file transfer
+///
+/// # Variants
+///
+/// - `ClientError`: Indicates that a message of the transfer could not be sent or received
+/// - `Io`: Indicates that the file could not be read or written
+/// - `Declined`: Indicates that the recipient declined the offer, along with its reason
+/// - `Cancelled`: Indicates that the other side cancelled the transfer, along with its reason
+/// - `HashMismatch`: Indicates that the file written by the recipient does not have the hash of the offer
+/// - `Timeout`: Indicates that the other side did not answer in time
+/// - `InvalidMessage`: Indicates that a message of the transfer does not follow the transfer protocol,
+///   or does not follow the messages before it
+pub enum TransferError{
+     ClientError(ClientError),
+     Io(Error),
+     Declined(String),
+     Cancelled(String),
+     HashMismatch{ expected:String, actual:String },
+     Timeout,
+     InvalidMessage(String)
+}
+
+/// Display implementation for TransferError
+impl Display for TransferError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self{
+               Self::ClientError(e)=>write!(f, "{{ error: ClientError; info: {} }}", e),
+               Self::Io(e)=>write!(f, "{{ error: Io; info: {} }}", e),
+               Self::Declined(reason)=>write!(f, "{{ error: Declined; info: {} }}", reason),
+               Self::Cancelled(reason)=>write!(f, "{{ error: Cancelled; info: {} }}", reason),
+               Self::HashMismatch{ expected, actual }=>write!(f, "{{ error: HashMismatch; info: expected {} got {} }}", expected, actual),
+               Self::Timeout=>write!(f, "{{ error: Timeout; info: the other side did not answer in time }}"),
+               Self::InvalidMessage(m)=>write!(f, "{{ error: InvalidMessage; info: {} }}", m)
+          }
+     }
+}
+
+/// Debug implementation for TransferError
+impl std::fmt::Debug for TransferError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "{}", self)
+     }
+}
+
+/// Error implementation for TransferError
+impl std::error::Error for TransferError{}
@@ -0,0 +1,549 @@
+//! File transfer between aliases, carried by the messages of the relay
+//!
+//! A [FileSender] offers a file to an alias, a [FileReceiver] of the alias accepts or declines the offer.
+//! Once accepted the file streams through the relay in base64 chunks, the recipient writes it to disk and checks
+//! its SHA-256 hash before answering the sender. Both sides connect a SEND and a RECEIVE client on the transfer channel
+//! of their alias, the alias followed by [CHANNEL_SUFFIX], so that a listener of the alias keeps its RECEIVE session.
+//! Messages of the channel that are not part of the transfer are kept and handed back by `take_skipped`
+//!
+//! ```text
+//! FILE;OFFER;<id>;<size>;<sha256>;<name>     sender -> recipient
+//! FILE;ACCEPT;<id>                          recipient -> sender
+//! FILE;DECLINE;<id>;<reason>                recipient -> sender
+//! FILE;CHUNK;<id>;<offset>;<base64>         sender -> recipient
+//! FILE;END;<id>                             sender -> recipient
+//! FILE;VERIFIED;<id>                        recipient -> sender
+//! FILE;CORRUPTED;<id>;<sha256>              recipient -> sender
+//! FILE;CANCEL;<id>;<reason>                 either side
+//! ```
+
+mod base64;
+pub mod error;
+mod sha256;
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::{remove_file, rename, File};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::server::protocol::frame::FrameError;
+use crate::server::protocol::pto::{next_message_id, BaseProto, Proto};
+use crate::server::protocol::res::Status;
+use super::{Client, ClientError, Incoming, Receiver};
+pub use error::TransferError;
+use sha256::Sha256;
+
+/// Number of bytes of the file carried by a chunk by default, its base64 fits the default maximum body size
+pub const DEFAULT_CHUNK_SIZE:usize = 32*1024;
+
+/// Time waited for the next message of the other side by default, eg.. the recipient accepting the offer
+pub const DEFAULT_ANSWER_TIMEOUT:Duration = Duration::from_secs(120);
+
+/// Suffix of the alias the transfers of an alias run on, eg.. `bob/files` for `bob`
+pub const CHANNEL_SUFFIX:&str = "/files";
+
+/// Number of messages not part of a transfer kept until taken, the oldest are dropped past it
+const MAX_SKIPPED:usize = 256;
+
+/// Prefix of the body of every message of a transfer
+const PREFIX:&str = "FILE;";
+
+/// Number of attempts at sending a message of a transfer refused for a transient reason
+const SEND_ATTEMPTS:u32 = 5;
+
+/// Delay before the second attempt at sending a message, doubling with every attempt
+const RETRY_DELAY:Duration = Duration::from_millis(200);
+
+
+/// A struct representing a file offered to an alias
+///
+/// # Fields
+///
+/// - `id`: The id of the transfer, identifying its messages
+/// - `from`: The alias offering the file
+/// - `name`: The name of the file, as given by the sender
+/// - `size`: The size of the file in bytes
+/// - `hash`: The SHA-256 hash of the file, as lowercase hexadecimal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOffer{
+     id:u64,
+     from:String,
+     name:String,
+     size:u64,
+     hash:String
+}
+
+impl FileOffer{
+     /// Returns the name of the file without any directory, None if the sender gave no usable name (eg.. `..`)
+     pub fn file_name(&self)->Option<&str>{
+          Path::new(&self.name).file_name().and_then(|name|name.to_str())
+     }
+
+     //----Getters----
+     pub fn get_id(&self)->u64{
+          self.id
+     }
+
+     pub fn get_from(&self)->&String{
+          &self.from
+     }
+
+     pub fn get_name(&self)->&String{
+          &self.name
+     }
+
+     pub fn get_size(&self)->u64{
+          self.size
+     }
+
+     pub fn get_hash(&self)->&String{
+          &self.hash
+     }
+}
+
+/// An enum representing the messages of a transfer, sent as the body of relay messages
+///
+/// # Variants
+///
+/// - `Offer`: The sender offers a file
+/// - `Accept`: The recipient accepts the offer
+/// - `Decline`: The recipient declines the offer, with a reason
+/// - `Chunk`: A part of the file, starting at `offset`
+/// - `End`: The sender sent every chunk
+/// - `Verified`: The recipient wrote the file and its hash matches the offer
+/// - `Corrupted`: The recipient wrote the file and its hash does not match the offer, with the hash it got
+/// - `Cancel`: Either side stopped the transfer, with a reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferMessage{
+     Offer(FileOffer),
+     Accept(u64),
+     Decline(u64, String),
+     Chunk{ id:u64, offset:u64, data:Vec<u8> },
+     End(u64),
+     Verified(u64),
+     Corrupted(u64, String),
+     Cancel(u64, String)
+}
+
+impl TransferMessage{
+     /// Returns the id of the transfer the message is part of
+     pub fn get_id(&self)->u64{
+          match self{
+               Self::Offer(offer)=>offer.id,
+               Self::Accept(id) | Self::Decline(id, _) | Self::Chunk{ id, .. } | Self::End(id) | Self::Verified(id)
+               | Self::Corrupted(id, _) | Self::Cancel(id, _)=>*id
+          }
+     }
+}
+
+/// A client offering files to aliases, connected with a SEND and a RECEIVE handshake on the transfer channel of its alias
+///
+/// # Fields
+///
+/// - `alias`: The alias offering the files
+/// - `client`: The [Client] sending the offers and chunks
+/// - `receiver`: The [Receiver] the answers of the recipients are delivered to
+/// - `skipped`: The messages delivered to the channel that were not part of a transfer
+/// - `chunk_size`: The number of bytes of the file carried by a chunk
+/// - `answer_timeout`: The time waited for an answer of the recipient
+pub struct FileSender{
+     alias:String,
+     client:Client,
+     receiver:Receiver,
+     skipped:VecDeque<BaseProto>,
+     chunk_size:usize,
+     answer_timeout:Duration
+}
+
+impl FileSender{
+     /// Connects the clients of the transfer channel of `alias`, claimed with `secret` if any
+     pub fn connect<A:ToSocketAddrs+Clone>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, TransferError>{
+          //the receive client is registered first, the recipient answers to it
+          let receiver = Receiver::connect(addr.clone(), &channel(alias), secret).map_err(TransferError::ClientError)?;
+          let client = Client::connect_with_secret(addr, &channel(alias), secret).map_err(TransferError::ClientError)?;
+          Ok(FileSender{
+               alias:alias.to_string(),
+               client,
+               receiver,
+               skipped:VecDeque::new(),
+               chunk_size:DEFAULT_CHUNK_SIZE,
+               answer_timeout:DEFAULT_ANSWER_TIMEOUT
+          })
+     }
+
+     /// Sets the number of bytes of the file carried by a chunk, its base64 must fit the maximum body size of the server
+     pub fn set_chunk_size(&mut self, chunk_size:usize){
+          self.chunk_size = chunk_size.max(1);
+     }
+
+     /// Sets the time waited for an answer of the recipient, to the offer and once the file is sent
+     pub fn set_answer_timeout(&mut self, timeout:Duration){
+          self.answer_timeout = timeout;
+     }
+
+     /// Returns the messages delivered to the transfer channel that were not part of a transfer, oldest first
+     pub fn take_skipped(&mut self)->Vec<BaseProto>{
+          self.skipped.drain(..).collect()
+     }
+
+     /// Offers the file at `path` to `to`, streams it once accepted and waits for the recipient to verify it
+     ///
+     /// # Returns
+     /// - `FileOffer`: The offer of the file the recipient verified
+     ///
+     /// # Errors
+     /// - [TransferError::Declined]: The recipient declined the offer
+     /// - [TransferError::HashMismatch]: The file the recipient wrote does not have the hash of the file sent
+     /// - [TransferError::Timeout]: The recipient did not answer in time
+     pub fn send_file(&mut self, to:&str, path:&Path)->Result<FileOffer, TransferError>{
+          let (size, hash) = hash_file(path)?;
+          let name = match path.file_name(){
+               Some(name)=>name.to_string_lossy().to_string(),
+               None=>return Err(TransferError::Io(ErrorKind::InvalidInput.into()))
+          };
+          let offer = FileOffer{
+               id:next_message_id(),
+               from:self.alias.clone(),
+               name,
+               size,
+               hash
+          };
+
+          let to = &channel(to);
+          deliver(&mut self.client, to, &TransferMessage::Offer(offer.clone()))?;
+          match wait_for(&mut self.receiver, &mut self.skipped, to, offer.id, self.answer_timeout)?{
+               TransferMessage::Accept(_)=>(),
+               TransferMessage::Decline(_, reason)=>return Err(TransferError::Declined(reason)),
+               TransferMessage::Cancel(_, reason)=>return Err(TransferError::Cancelled(reason)),
+               message=>return Err(unexpected(&message))
+          }
+
+          if let Err(e) = self.stream(to, &offer, path){
+               let _ = deliver(&mut self.client, to, &TransferMessage::Cancel(offer.id, e.to_string()));
+               return Err(e);
+          }
+          deliver(&mut self.client, to, &TransferMessage::End(offer.id))?;
+
+          match wait_for(&mut self.receiver, &mut self.skipped, to, offer.id, self.answer_timeout)?{
+               TransferMessage::Verified(_)=>Ok(offer),
+               TransferMessage::Corrupted(_, actual)=>Err(TransferError::HashMismatch{ expected:offer.hash, actual }),
+               TransferMessage::Cancel(_, reason)=>Err(TransferError::Cancelled(reason)),
+               message=>Err(unexpected(&message))
+          }
+     }
+
+     /// Sends the chunks of the file
+     fn stream(&mut self, to:&str, offer:&FileOffer, path:&Path)->Result<(), TransferError>{
+          let mut file = File::open(path).map_err(TransferError::Io)?;
+          let mut buf = vec![0;self.chunk_size];
+          let mut offset = 0;
+          loop {
+               let read = file.read(&mut buf).map_err(TransferError::Io)?;
+               if read==0{
+                    break;
+               }
+               let chunk = TransferMessage::Chunk{ id:offer.id, offset, data:buf[..read].to_vec() };
+               deliver(&mut self.client, to, &chunk)?;
+               offset += read as u64;
+          }
+          //the file changed since it was hashed
+          if offset!=offer.size{
+               return Err(TransferError::Io(ErrorKind::UnexpectedEof.into()));
+          }
+          Ok(())
+     }
+}
+
+/// A client receiving the files offered to an alias, connected with a RECEIVE and a SEND handshake on the transfer channel
+/// of its alias
+///
+/// # Fields
+///
+/// - `client`: The [Client] answering the senders
+/// - `receiver`: The [Receiver] the offers and chunks are delivered to
+/// - `skipped`: The messages delivered to the channel that were not part of the transfer running, offered again
+///   by [FileReceiver::next_offer] if they are offers
+/// - `answer_timeout`: The time waited for the next message of the sender during a transfer
+pub struct FileReceiver{
+     client:Client,
+     receiver:Receiver,
+     skipped:VecDeque<BaseProto>,
+     answer_timeout:Duration
+}
+
+impl FileReceiver{
+     /// Connects the clients of the transfer channel of `alias`, claimed with `secret` if any
+     pub fn connect<A:ToSocketAddrs+Clone>(addr:A, alias:&str, secret:Option<&str>)->Result<Self, TransferError>{
+          let receiver = Receiver::connect(addr.clone(), &channel(alias), secret).map_err(TransferError::ClientError)?;
+          let client = Client::connect_with_secret(addr, &channel(alias), secret).map_err(TransferError::ClientError)?;
+          Ok(FileReceiver{
+               client,
+               receiver,
+               skipped:VecDeque::new(),
+               answer_timeout:DEFAULT_ANSWER_TIMEOUT
+          })
+     }
+
+     /// Sets the time waited for the next message of the sender during a transfer
+     pub fn set_answer_timeout(&mut self, timeout:Duration){
+          self.answer_timeout = timeout;
+     }
+
+     /// Blocks until the next file is offered to the alias, starting with the offers skipped during a transfer.
+     /// The other messages delivered until then are kept, see [FileReceiver::take_skipped]
+     pub fn next_offer(&mut self)->Result<FileOffer, TransferError>{
+          if let Some(at) = self.skipped.iter().position(|message|as_offer(message).is_some()){
+               if let Some(offer) = self.skipped.remove(at).as_ref().and_then(as_offer){
+                    return Ok(offer);
+               }
+          }
+          self.receiver.set_read_timeout(None).map_err(TransferError::ClientError)?;
+          loop {
+               let message = match self.receiver.recv().map_err(TransferError::ClientError)?{
+                    Some(Incoming::Message(message))=>message,
+                    Some(Incoming::Notice(res))=>return Err(TransferError::ClientError(ClientError::Refused(res))),
+                    None=>return Err(TransferError::ClientError(ClientError::Closed))
+               };
+               match as_offer(&message){
+                    Some(offer)=>return Ok(offer),
+                    None=>skip(&mut self.skipped, message)
+               }
+          }
+     }
+
+     /// Returns the messages delivered to the transfer channel that were neither offers nor part of a transfer, oldest first
+     pub fn take_skipped(&mut self)->Vec<BaseProto>{
+          let (offers, others):(VecDeque<_>, VecDeque<_>) = self.skipped.drain(..).partition(|message|as_offer(message).is_some());
+          self.skipped = offers;
+          others.into()
+     }
+
+     /// Declines the offer, the sender is handed `reason`
+     pub fn decline(&mut self, offer:&FileOffer, reason:&str)->Result<(), TransferError>{
+          deliver(&mut self.client, &channel(&offer.from), &TransferMessage::Decline(offer.id, reason.to_string()))
+     }
+
+     /// Accepts the offer and writes the file to `path`, through `<path>.part` until its hash is verified
+     ///
+     /// # Errors
+     /// - [TransferError::HashMismatch]: The file written does not have the hash of the offer, it is removed
+     /// - [TransferError::Cancelled]: The sender cancelled the transfer
+     /// - [TransferError::Timeout]: The sender stopped sending chunks
+     pub fn accept(&mut self, offer:&FileOffer, path:&Path)->Result<(), TransferError>{
+          let mut part = path.as_os_str().to_owned();
+          part.push(".part");
+          let part = PathBuf::from(part);
+
+          let mut file = match File::create(&part){
+               Ok(file)=>file,
+               Err(e)=>{
+                    let _ = self.decline(offer, &format!("The file could not be created {e}"));
+                    return Err(TransferError::Io(e));
+               }
+          };
+          let from = &channel(&offer.from);
+          deliver(&mut self.client, from, &TransferMessage::Accept(offer.id))?;
+
+          if let Err(e) = self.receive(offer, &mut file).and_then(|_|file.sync_all().map_err(TransferError::Io)){
+               let _ = remove_file(&part);
+               if !matches!(e, TransferError::Cancelled(_)){
+                    let _ = deliver(&mut self.client, from, &TransferMessage::Cancel(offer.id, e.to_string()));
+               }
+               return Err(e);
+          }
+          drop(file);
+
+          //the hash is checked against the file as written to disk
+          let (_, actual) = hash_file(&part)?;
+          if actual!=offer.hash{
+               let _ = remove_file(&part);
+               deliver(&mut self.client, from, &TransferMessage::Corrupted(offer.id, actual.clone()))?;
+               return Err(TransferError::HashMismatch{ expected:offer.hash.clone(), actual });
+          }
+          rename(&part, path).map_err(TransferError::Io)?;
+          deliver(&mut self.client, from, &TransferMessage::Verified(offer.id))
+     }
+
+     /// Writes the chunks of the file up to the end of the transfer
+     fn receive(&mut self, offer:&FileOffer, file:&mut File)->Result<(), TransferError>{
+          let mut written = 0;
+          loop {
+               match wait_for(&mut self.receiver, &mut self.skipped, &channel(&offer.from), offer.id, self.answer_timeout)?{
+                    TransferMessage::Chunk{ offset, data, .. }=>{
+                         if offset!=written || written+data.len() as u64>offer.size{
+                              return Err(TransferError::InvalidMessage(format!("Chunk at {offset} does not follow the {written} bytes written")));
+                         }
+                         file.write_all(&data).map_err(TransferError::Io)?;
+                         written += data.len() as u64;
+                    },
+                    TransferMessage::End(_) if written==offer.size=>return Ok(()),
+                    TransferMessage::End(_)=>return Err(TransferError::InvalidMessage(format!("Transfer ended after {written} of {} bytes", offer.size))),
+                    TransferMessage::Cancel(_, reason)=>return Err(TransferError::Cancelled(reason)),
+                    message=>return Err(unexpected(&message))
+               }
+          }
+     }
+}
+
+/// Sends a message of a transfer, retrying the messages refused for a transient reason, eg.. a full delivery queue.
+/// A message sent before the receive client of the other side registered is kept for it by the server
+fn deliver(client:&mut Client, to:&str, message:&TransferMessage)->Result<(), TransferError>{
+     let body = message.to_string();
+     let mut delay = RETRY_DELAY;
+     for attempt in 1..=SEND_ATTEMPTS{
+          match client.send(to, &body){
+               Ok(_)=>return Ok(()),
+               Err(ClientError::Refused(res)) if attempt<SEND_ATTEMPTS
+                    && matches!(res.get_status(), Status::Throttled | Status::QueueFull)=>{
+                    sleep(delay);
+                    delay *= 2;
+               },
+               Err(e)=>return Err(TransferError::ClientError(e))
+          }
+     }
+     unreachable!("the last attempt returns")
+}
+
+/// Returns the transfer channel of an alias
+fn channel(alias:&str)->String{
+     format!("{alias}{CHANNEL_SUFFIX}")
+}
+
+/// Returns the offer a message carries, None if it is not an offer sent from a transfer channel
+fn as_offer(message:&BaseProto)->Option<FileOffer>{
+     let from = message.get_sender().strip_suffix(CHANNEL_SUFFIX)?;
+     match TransferMessage::from_str(message.get_body()){
+          Ok(TransferMessage::Offer(mut offer))=>{
+               offer.from = from.to_string();
+               Some(offer)
+          },
+          _=>None
+     }
+}
+
+/// Keeps a message that is not part of the transfer running, dropping the oldest past [MAX_SKIPPED]
+fn skip(skipped:&mut VecDeque<BaseProto>, message:BaseProto){
+     if skipped.len()>=MAX_SKIPPED{
+          skipped.pop_front();
+     }
+     skipped.push_back(message);
+}
+
+/// Waits for the next message of the transfer `id` sent by `from`, the other messages are kept in `skipped`
+fn wait_for(receiver:&mut Receiver, skipped:&mut VecDeque<BaseProto>, from:&str, id:u64, timeout:Duration)->Result<TransferMessage, TransferError>{
+     let deadline = Instant::now()+timeout;
+     loop {
+          let remaining = deadline.saturating_duration_since(Instant::now());
+          if remaining.is_zero(){
+               return Err(TransferError::Timeout);
+          }
+          receiver.set_read_timeout(Some(remaining)).map_err(TransferError::ClientError)?;
+
+          let message = match receiver.recv(){
+               Ok(Some(Incoming::Message(message)))=>message,
+               Ok(Some(Incoming::Notice(res)))=>return Err(TransferError::ClientError(ClientError::Refused(res))),
+               Ok(None)=>return Err(TransferError::ClientError(ClientError::Closed)),
+               Err(ClientError::StreamError(FrameError::Io(e))) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)=>continue,
+               Err(e)=>return Err(TransferError::ClientError(e))
+          };
+          if message.get_sender()!=from || !message.get_body().starts_with(PREFIX){
+               skip(skipped, message);
+               continue;
+          }
+          let parsed = TransferMessage::from_str(message.get_body())?;
+          if parsed.get_id()==id{
+               return Ok(parsed);
+          }
+          skip(skipped, message);
+     }
+}
+
+/// Returns the size and SHA-256 hash of a file
+fn hash_file(path:&Path)->Result<(u64, String), TransferError>{
+     let mut file = BufReader::new(File::open(path).map_err(TransferError::Io)?);
+     let mut sha = Sha256::new();
+     let mut buf = [0;64*1024];
+     let mut size = 0;
+     loop {
+          let read = file.read(&mut buf).map_err(TransferError::Io)?;
+          if read==0{
+               return Ok((size, sha.finish()));
+          }
+          sha.update(&buf[..read]);
+          size += read as u64;
+     }
+}
+
+fn unexpected(message:&TransferMessage)->TransferError{
+     TransferError::InvalidMessage(format!("Unexpected message {}", message.to_string().split(';').take(2).collect::<Vec<_>>().join(";")))
+}
+
+/// Display implementation for TransferMessage, the body it is sent as
+impl Display for TransferMessage{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self{
+               Self::Offer(o)=>write!(f, "{PREFIX}OFFER;{};{};{};{}", o.id, o.size, o.hash, o.name),
+               Self::Accept(id)=>write!(f, "{PREFIX}ACCEPT;{id}"),
+               Self::Decline(id, reason)=>write!(f, "{PREFIX}DECLINE;{id};{reason}"),
+               Self::Chunk{ id, offset, data }=>write!(f, "{PREFIX}CHUNK;{id};{offset};{}", base64::encode(data)),
+               Self::End(id)=>write!(f, "{PREFIX}END;{id}"),
+               Self::Verified(id)=>write!(f, "{PREFIX}VERIFIED;{id}"),
+               Self::Corrupted(id, hash)=>write!(f, "{PREFIX}CORRUPTED;{id};{hash}"),
+               Self::Cancel(id, reason)=>write!(f, "{PREFIX}CANCEL;{id};{reason}")
+          }
+     }
+}
+
+/// FromStr implementation for TransferMessage, parsing the body it is sent as.
+/// The sender of an offer is not part of the body, it is left empty
+impl FromStr for TransferMessage{
+     type Err = TransferError;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          let invalid = ||TransferError::InvalidMessage(format!("Could not parse the transfer message '{}'", s.chars().take(64).collect::<String>()));
+          let (kind, rest) = s.strip_prefix(PREFIX).and_then(|s|s.split_once(';')).ok_or_else(invalid)?;
+          let (id, rest) = match rest.split_once(';'){
+               Some((id, rest))=>(id, Some(rest)),
+               None=>(rest, None)
+          };
+          let id = id.parse::<u64>().map_err(|_|invalid())?;
+
+          let message = match (kind, rest){
+               ("OFFER", Some(rest))=>{
+                    let mut parts = rest.splitn(3, ';');
+                    let (size, hash, name) = match (parts.next(), parts.next(), parts.next()){
+                         (Some(size), Some(hash), Some(name))=>(size, hash, name),
+                         _=>return Err(invalid())
+                    };
+                    TransferMessage::Offer(FileOffer{
+                         id,
+                         from:String::new(),
+                         name:name.to_string(),
+                         size:size.parse().map_err(|_|invalid())?,
+                         hash:hash.to_string()
+                    })
+               },
+               ("ACCEPT", None)=>TransferMessage::Accept(id),
+               ("DECLINE", Some(reason))=>TransferMessage::Decline(id, reason.to_string()),
+               ("CHUNK", Some(rest))=>{
+                    let (offset, data) = rest.split_once(';').ok_or_else(invalid)?;
+                    TransferMessage::Chunk{
+                         id,
+                         offset:offset.parse().map_err(|_|invalid())?,
+                         data:base64::decode(data).ok_or_else(invalid)?
+                    }
+               },
+               ("END", None)=>TransferMessage::End(id),
+               ("VERIFIED", None)=>TransferMessage::Verified(id),
+               ("CORRUPTED", Some(hash))=>TransferMessage::Corrupted(id, hash.to_string()),
+               ("CANCEL", Some(reason))=>TransferMessage::Cancel(id, reason.to_string()),
+               _=>return Err(invalid())
+          };
+          Ok(message)
+     }
+}
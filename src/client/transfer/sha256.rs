@@ -0,0 +1,148 @@
+//! SHA-256 digest of the files transferred, see FIPS 180-4
+
+/// Round constants, the first 32 bits of the fractional parts of the cube roots of the first 64 primes
+const K:[u32;64] = [
+     0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+     0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+     0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+     0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+     0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+     0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+     0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+     0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+/// Initial hash value, the first 32 bits of the fractional parts of the square roots of the first 8 primes
+const H:[u32;8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+/// A struct computing the SHA-256 digest of data fed in any number of parts
+///
+/// # Fields
+///
+/// - `state`: The intermediate hash value
+/// - `block`: The bytes of the block being filled
+/// - `filled`: The number of bytes of `block` filled
+/// - `len`: The number of bytes fed so far
+pub struct Sha256{
+     state:[u32;8],
+     block:[u8;64],
+     filled:usize,
+     len:u64
+}
+
+impl Sha256{
+     /// Default constructor for a [Sha256] of no data
+     pub fn new()->Self{
+          Sha256{
+               state:H,
+               block:[0;64],
+               filled:0,
+               len:0
+          }
+     }
+
+     /// Feeds the next part of the data
+     pub fn update(&mut self, mut data:&[u8]){
+          self.len += data.len() as u64;
+          while !data.is_empty(){
+               let n = (64-self.filled).min(data.len());
+               self.block[self.filled..self.filled+n].copy_from_slice(&data[..n]);
+               self.filled += n;
+               data = &data[n..];
+               if self.filled==64{
+                    let block = self.block;
+                    self.compress(&block);
+                    self.filled = 0;
+               }
+          }
+     }
+
+     /// Pads the data and returns its digest as lowercase hexadecimal
+     pub fn finish(mut self)->String{
+          let bits = self.len.wrapping_mul(8);
+          self.update(&[0x80]);
+          while self.filled!=56{
+               self.update(&[0]);
+          }
+          self.update(&bits.to_be_bytes());
+          self.state.iter().map(|word|format!("{word:08x}")).collect()
+     }
+
+     fn compress(&mut self, block:&[u8;64]){
+          let mut w = [0u32;64];
+          for (i, word) in block.chunks_exact(4).enumerate(){
+               w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+          }
+          for i in 16..64{
+               let s0 = w[i-15].rotate_right(7) ^ w[i-15].rotate_right(18) ^ (w[i-15]>>3);
+               let s1 = w[i-2].rotate_right(17) ^ w[i-2].rotate_right(19) ^ (w[i-2]>>10);
+               w[i] = w[i-16].wrapping_add(s0).wrapping_add(w[i-7]).wrapping_add(s1);
+          }
+
+          let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+          for i in 0..64{
+               let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+               let ch = (e & f) ^ (!e & g);
+               let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+               let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+               let maj = (a & b) ^ (a & c) ^ (b & c);
+               let t2 = s0.wrapping_add(maj);
+               h = g;
+               g = f;
+               f = e;
+               e = d.wrapping_add(t1);
+               d = c;
+               c = b;
+               b = a;
+               a = t1.wrapping_add(t2);
+          }
+          for (state, word) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]){
+               *state = state.wrapping_add(word);
+          }
+     }
+}
+
+/// Default implementation for Sha256
+impl Default for Sha256{
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+
+     fn digest(data:&[u8])->String{
+          let mut sha = Sha256::new();
+          sha.update(data);
+          sha.finish()
+     }
+
+     #[test]
+     fn fips_180_4_vectors(){
+          assert_eq!(digest(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+          assert_eq!(digest(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+          assert_eq!(digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"), "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+          assert_eq!(digest(&[b'a';1_000_000]), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+     }
+
+     #[test]
+     fn padding_boundaries(){
+          //55 bytes leave room for the length in the last block, 56 do not, 64 fill a whole block
+          assert_eq!(digest(&[b'a';55]), "9f4390f8d30c2dd92ec9f095b65e2b9ae9b0a925a5258e241c9f1e910f734318");
+          assert_eq!(digest(&[b'a';56]), "b35439a4ac6f0948b6d6f9e3c6af0f5f590ce20f1bde7090ef7970686ec6738a");
+          assert_eq!(digest(&[b'a';64]), "ffe054fe7ae0cb6dc65c3af9b61d5209f439851db43d0ba5997337df154668eb");
+     }
+
+     #[test]
+     fn parts_hash_as_the_whole(){
+          let data = (0..300u32).map(|i|i as u8).collect::<Vec<_>>();
+          for split in [0, 1, 55, 56, 63, 64, 65, 128, 299]{
+               let mut sha = Sha256::new();
+               sha.update(&data[..split]);
+               sha.update(&data[split..]);
+               assert_eq!(sha.finish(), digest(&data), "split at {split}");
+          }
+     }
+}
@@ -0,0 +1,26 @@
+//! raw, a relay delivering messages between clients addressed by alias
+//!
+//! Send clients hand messages to the [Server], which delivers them to the receive clients registered under
+//! the alias they are addressed to. The [client] module connects to a server with a [Client] or a [Receiver], the [server] module embeds one:
+//!
+//! ```no_run
+//! use std::net::SocketAddr;
+//! use raw::ServerBuilder;
+//!
+//! let mut server = ServerBuilder::new().addr(SocketAddr::from(([127, 0, 0, 1], 0))).build();
+//! let addr = server.bind().unwrap();
+//! println!("serving on {addr}");
+//! server.serve().unwrap();
+//! ```
+
+pub mod server;
+pub mod client;
+
+pub use client::{Client, ClientError, Incoming, Receiver, ReconnectingReceiver};
+pub use server::{Server, ServerBuilder};
+pub use server::error::ServerError;
+pub use server::handler::{StreamHandler, TransmitService};
+pub use server::protocol::{BaseProtocol, DataTransferProtocol};
+pub use server::protocol::error::ProtocolError;
+pub use server::protocol::pto::{BaseProto, Proto};
+pub use server::protocol::res::{Response, Status};
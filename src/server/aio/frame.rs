@@ -0,0 +1,42 @@
+use std::io::Error;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::server::protocol::frame::{FrameError, FrameReader, FRAME_TERMINATOR};
+
+
+/// Size of the chunks read from the stream
+const READ_CHUNK:usize = 1024;
+
+/// Reads the next frame from an async stream, the async counterpart of [FrameReader::read_frame]
+pub async fn read_frame<R:AsyncRead+Unpin>(stream:&mut R, reader:&mut FrameReader)->Result<Option<Vec<u8>>, FrameError>{
+     read_until(stream, reader, FrameReader::next_buffered_frame).await
+}
+
+/// Reads the handshake frame from an async stream, the async counterpart of [FrameReader::read_handshake]
+pub async fn read_handshake<R:AsyncRead+Unpin>(stream:&mut R, reader:&mut FrameReader)->Result<Option<Vec<u8>>, FrameError>{
+     read_until(stream, reader, FrameReader::next_buffered_handshake).await
+}
+
+async fn read_until<R:AsyncRead+Unpin, F:Fn(&mut FrameReader)->Option<Result<Vec<u8>, FrameError>>>(stream:&mut R, reader:&mut FrameReader, take:F)->Result<Option<Vec<u8>>, FrameError>{
+     loop {
+          if let Some(frame) = take(reader){
+               return frame.map(Some);
+          }
+
+          let mut chunk = [0;READ_CHUNK];
+          match stream.read(&mut chunk).await{
+               Ok(0)=>return Ok(None),
+               Ok(n)=>reader.feed(&chunk[..n]),
+               Err(e)=>return Err(FrameError::Io(e))
+          }
+     }
+}
+
+/// Writes a frame followed by its terminator to an async stream
+pub async fn write_frame<W:AsyncWrite+Unpin>(stream:&mut W, frame:&[u8])->Result<(), Error>{
+     let mut framed = Vec::with_capacity(frame.len()+1);
+     framed.extend_from_slice(frame);
+     framed.push(FRAME_TERMINATOR);
+     stream.write_all(&framed).await
+}
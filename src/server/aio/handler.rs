@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::{error, info, warn};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+use crate::server::DEFAULT_MAX_BODY_SIZE;
+use crate::server::container::DisconnectReason;
+use crate::server::delivery::{slow_consumer_notice, Dispatch};
+use crate::server::handler::TransmitService;
+use crate::server::mailbox::Mailbox;
+use crate::server::protocol::frame::{FrameError, FrameReader};
+use crate::server::protocol::pto::{next_message_id, BaseProto, Proto};
+use crate::server::protocol::res::{Response, Status};
+use crate::server::protocol::{Data, DataTransferProtocol, DataTransferProtocolParsed, MAX_HEADER_SIZE};
+use crate::server::ratelimit::Verdict;
+use super::Context;
+use super::frame::{read_frame, write_frame};
+use super::session::AsyncSession;
+
+
+/// A struct representing an async stream handler, the async counterpart of [crate::server::handler::StreamHandler].
+/// Handles a stream exclusive to one transmit type, [TransmitService::Send] or [TransmitService::Receive],
+/// following a protocol which implements [DataTransferProtocol]
+///
+/// # Fields
+///
+/// - `stream`: The async stream of the client
+/// - `transmit`: The transmit service subscribed by the client
+/// - `protocol`: The protocol followed by this handler which implements [DataTransferProtocol]
+/// - `reader`: The [FrameReader] splitting the stream into frames
+/// - `max_body_size`: The maximum size of the body of a message sent by the client
+pub struct AsyncStreamHandler<P>
+where P:DataTransferProtocol<String,String,String>{
+     stream:TcpStream,
+     transmit:TransmitService,
+     protocol:P,
+     reader:FrameReader,
+     max_body_size:usize
+}
+
+/// What a receive handler awoke for
+enum Incoming{
+     Message(Option<BaseProto>),
+     Read(std::io::Result<usize>)
+}
+
+impl <P:DataTransferProtocol<String,String,String>>AsyncStreamHandler<P>{
+     /// Creates a new handler object to handle a client
+     ///
+     /// # Arguments
+     ///
+     /// * `stream` - The async stream of the client
+     /// * `protocol` - A protocol that implements [DataTransferProtocol]
+     /// * `service` - The transmit service subscribed by the client
+     pub fn new(stream:TcpStream, protocol:P, service:TransmitService)->Self{
+          AsyncStreamHandler{
+               stream,
+               transmit:service,
+               protocol,
+               reader:FrameReader::new(DEFAULT_MAX_BODY_SIZE+MAX_HEADER_SIZE),
+               max_body_size:DEFAULT_MAX_BODY_SIZE
+          }
+     }
+
+     /// Sets the maximum size of the body of a message sent by the client.
+     /// Larger messages are answered with [Status::MessageTooLarge]
+     pub fn set_max_body_size(&mut self, max_body_size:usize){
+          self.max_body_size = max_body_size;
+          self.reader.set_max_frame_size(max_body_size+MAX_HEADER_SIZE);
+     }
+
+     /// Replaces the [FrameReader] of the handler, keeping the bytes it read past the handshake
+     pub fn set_frame_reader(&mut self, mut reader:FrameReader){
+          reader.set_max_frame_size(self.reader.get_max_frame_size());
+          self.reader = reader;
+     }
+
+     /// Handles [TransmitService::Send] type client, with the same responses as [crate::server::handler::StreamHandler::handle_client_send]
+     ///
+     /// # Arguments
+     /// - `ctx`: The [Context] shared by the tasks of the server
+     /// - `stop`: Set once the server is shutting down, the client is answered with [Status::ShuttingDown] between two messages
+     /// - `sender`: The alias the client claimed with its secret in its handshake, if any, whose rate limits and quota apply.
+     ///   The quota of a client claiming no alias is counted against its address
+     ///
+     /// # Returns
+     /// - `DisconnectReason`: The reason the handler stopped handling the client
+     pub async fn handle_client_send(&mut self, ctx:&Context, stop:&mut watch::Receiver<bool>, sender:Option<String>)->DisconnectReason{
+          let ip = match self.stream.peer_addr(){
+               Ok(addr)=>addr.ip(),
+               Err(e)=>return DisconnectReason::StreamError(e.to_string())
+          };
+
+          loop {
+               //reads the next message frame, unless the server is shutting down
+               let next = tokio::select!{
+                    frame = self.next_frame()=>Some(frame),
+                    _ = stop.wait_for(|stopping|*stopping)=>None
+               };
+               let frame = match next{
+                    None=>{
+                         self.respond(Status::ShuttingDown, "Server is shutting down".to_string()).await;
+                         return DisconnectReason::ShuttingDown;
+                    },
+                    Some(Err(reason))=>return reason,
+                    Some(Ok(frame))=>frame
+               };
+               let read = frame.len();
+               //every frame is identified, so that its response can be told apart
+               let id = next_message_id();
+
+               //parses read data
+               let parsed = match self.protocol.parse(Data::Utf8(frame)){
+                    Err(e)=>{
+                         error!("An error occured while parsing protocol {}", e);
+                         self.respond_to(id, Status::InvalidFormat, e.to_string()).await;
+                         continue;
+                    },
+                    Ok(s)=>s
+               };
+
+               //enforcing the maximum body size
+               let body_size = parsed.get_body().map(|b|b.len()).unwrap_or(0);
+               if body_size>self.max_body_size{
+                    warn!("Refused a message body of {body_size} bytes from {{ alias: {} }}", parsed.get_client_id());
+                    self.respond_to(id, Status::MessageTooLarge, format!("The message body exceeds the maximum size of {} bytes", self.max_body_size)).await;
+                    continue;
+               }
+
+               //enforcing the rate limits of the claimed sender alias and ip
+               let verdict = ctx.rate_limiter.lock().unwrap().check(sender.as_deref(), ip, read);
+               match verdict{
+                    Verdict::Allowed=>(),
+                    Verdict::Throttled=>{
+                         warn!("Throttled message from {{ alias: {}; ip: {} }}", sender.as_deref().unwrap_or("-"), ip);
+                         self.respond_to(id, Status::Throttled, "The rate limit has been exceeded, the message has been dropped".to_string()).await;
+                         continue;
+                    },
+                    Verdict::Banned(remaining)=>{
+                         warn!("Disconnecting banned client {{ alias: {}; ip: {}; remaining: {}s }}", sender.as_deref().unwrap_or("-"), ip, remaining.as_secs());
+                         self.respond_to(id, Status::Banned, format!("The rate limit has been exceeded repeatedly, banned for {}s", remaining.as_secs())).await;
+                         return DisconnectReason::Banned;
+                    }
+               }
+
+               //a client that claimed its alias can only send messages from that alias
+               if let Some(claimed) = sender.as_deref().filter(|claimed|*claimed!=parsed.get_client_id()){
+                    warn!("Refused a message from {{ alias: {}; claimed: {}; ip: {} }}", parsed.get_client_id(), claimed, ip);
+                    self.respond_to(id, Status::Unauthorized, format!("The message is sent from '{}' but the client claimed '{claimed}'", parsed.get_client_id())).await;
+                    continue;
+               }
+
+               //the queues of the receivers are cloned out of the registry
+               let username = parsed.get_to().to_string();
+               let queues = ctx.receivers.senders_for(&username);
+
+               let body = match parsed.get_body(){
+                    Ok(body)=>body.to_string(),
+                    Err(e)=>{
+                         warn!("Could not parse body {}",e);
+                         self.respond_to(id, Status::InvalidFormat, e.to_string()).await;
+                         continue;
+                    }
+               };
+               let alias = parsed.get_client_id().to_string();
+               let size = body.len();
+
+               //reserving the message in the quota of the claimed sender alias, or of the address of the client
+               let account = sender.clone().unwrap_or_else(||ip.to_string());
+               let checked = ctx.quotas.lock().unwrap().consume(&account, size);
+               if let Err(e) = checked{
+                    warn!("Refused message from {{ account: {} }} {}", account, e);
+                    self.respond_to(id, Status::QuotaExceeded, e.to_string()).await;
+                    continue;
+               }
+
+               //queueing the message for every session registered for the alias
+               let pto = BaseProto::create(alias, body, username.clone()).with_id(id);
+               let mut outcome = Dispatch::new();
+               for queue in &queues{
+                    outcome.record(&username, queue.send(pto.clone()).await);
+               }
+
+               //the tasks of the sessions whose queue has been closed notify their client
+               if outcome.has_slow_consumer(){
+                    for session in ctx.receivers.remove_closed(&username){
+                         warn!("Disconnecting slow consumer {}", session);
+                    }
+               }
+               let res = outcome.respond(&ctx.receivers, &ctx.mailbox, pto, ctx.backpressure_policy);
+
+               //only the messages delivered are counted against the quota
+               if !Response::parse(&res).is_ok_and(|r|r.get_status().is_success()){
+                    ctx.quotas.lock().unwrap().refund(&account, size);
+               }
+               if let Err(e) = write_frame(&mut self.stream, res.as_bytes()).await{
+                    error!("Error occured while sending response status to client {{ {e} }}");
+               }
+               info!("Message has been dispactched to {{ username: {username} }} task listener...");
+          }
+     }
+
+     /// Handles [TransmitService::Receive] type client, with the same semantics as [crate::server::handler::StreamHandler::handle_client_receive].
+     /// The stream is read while the queue is awaited, so that a client closing its stream is noticed right away
+     ///
+     /// # Arguments
+     /// - `session`: The [AsyncSession] of the client, whose queue is awaited
+     /// - `mailbox`: The [Mailbox] messages still queued or in flight are deposited in when the client disconnects,
+     ///   and the messages written are journaled in
+     ///
+     /// # Returns
+     /// - `DisconnectReason`: The reason the handler stopped handling the client
+     pub async fn handle_client_receive(&mut self, session:&AsyncSession, mailbox:&Mutex<Mailbox<BaseProto>>)->DisconnectReason{
+          let queue = session.get_queue().clone();
+          let mut buf = [0;1024];
+
+          //the pending messages left in the mailbox while the queue was full, delivered before the queue once it is drained
+          let mut backlog = VecDeque::new();
+          let (reason, in_flight) = loop {
+               let incoming = match backlog.pop_front(){
+                    Some(pto)=>Incoming::Message(Some(pto)),
+                    None=>tokio::select!{
+                         pto = queue.recv()=>Incoming::Message(pto),
+                         read = self.stream.read(&mut buf)=>Incoming::Read(read)
+                    }
+               };
+
+               let pto = match incoming{
+                    Incoming::Message(None)=>break (DisconnectReason::QueueClosed, None),
+                    Incoming::Message(Some(pto))=>pto,
+                    //receive clients are not expected to send data, any data read is discarded
+                    Incoming::Read(Ok(0))=>break (DisconnectReason::Closed, None),
+                    Incoming::Read(Ok(_))=>continue,
+                    Incoming::Read(Err(e))=>break (DisconnectReason::StreamError(e.to_string()), None)
+               };
+
+               let username = pto.get_receiver().to_owned();
+               let raw = match self.protocol.to_raw(pto.clone()){
+                    Ok(byte_vec)=>byte_vec,
+                    Err(e)=>{
+                         error!("Error converting pto to raw bytes in handle_client_receive {}",e);
+                         continue;
+                    }
+               };
+
+               if let Err(e) = write_frame(&mut self.stream, &raw).await{
+                    error!("Error writing {{ {} }}", e);
+                    break (DisconnectReason::StreamError(e.to_string()), Some(pto));
+               }
+
+               //journaling the written message, for the client to resume after it
+               let mut mailbox = mailbox.lock().unwrap();
+               mailbox.record(session.get_alias(), &pto);
+               if backlog.is_empty() && queue.is_empty(){
+                    backlog.extend(mailbox.take(session.get_alias()));
+               }
+               drop(mailbox);
+               info!("Successfully written to {{ username: {}; type: RECEIVE }}", username);
+          };
+
+          //refusing further messages, and notifying a client disconnected by the server
+          queue.close();
+          if let DisconnectReason::QueueClosed = reason{
+               let notice = session.take_notice()
+                    .unwrap_or_else(slow_consumer_notice);
+               let _ = write_frame(&mut self.stream, notice.as_bytes()).await;
+          }
+          let _ = self.stream.shutdown().await;
+
+          //re-queueing the in-flight and queued messages for redelivery
+          let mut undelivered:Vec<BaseProto> = in_flight.into_iter().collect();
+          undelivered.extend(backlog);
+          undelivered.extend(queue.drain());
+          if !undelivered.is_empty(){
+               info!("Re-queued {} undelivered messages of {{ username: {} }}", undelivered.len(), session.get_alias());
+               mailbox.lock().unwrap().deposit(session.get_alias(), undelivered);
+          }
+
+          reason
+     }
+
+     /// Writes a response to the client
+     pub async fn respond(&mut self, status:Status, message:String){
+          let res = Response::generate_res(status, message);
+          if let Err(e) = write_frame(&mut self.stream, res.as_bytes()).await{
+               error!("Error occured while sending response status to client {{ {e} }}");
+          }
+     }
+
+     /// Writes the response of the message `id` to the client
+     async fn respond_to(&mut self, id:u64, status:Status, message:String){
+          let res = Response::generate_res_for(id, status, message);
+          if let Err(e) = write_frame(&mut self.stream, res.as_bytes()).await{
+               error!("Error occured while sending response status to client {{ {e} }}");
+          }
+     }
+
+     /// Reads the next message frame, answering frames that are too large
+     async fn next_frame(&mut self)->Result<Vec<u8>, DisconnectReason>{
+          loop {
+               match read_frame(&mut self.stream, &mut self.reader).await{
+                    Ok(Some(frame))=>return Ok(frame),
+                    Ok(None)=>{
+                         warn!("Stream has disconnected");
+                         return Err(DisconnectReason::Closed);
+                    },
+                    Err(FrameError::TooLarge(size))=>{
+                         warn!("Refused a frame of {size} bytes");
+                         self.respond(Status::MessageTooLarge, format!("The message body exceeds the maximum size of {} bytes", self.max_body_size)).await;
+                    },
+                    Err(e)=>{
+                         error!("An error occured while reading stream {{{}}}", e);
+                         return Err(DisconnectReason::StreamError(e.to_string()));
+                    }
+               }
+          }
+     }
+
+     //----Getters----
+     pub fn get_transmit(&self)->&TransmitService{
+          &self.transmit
+     }
+}
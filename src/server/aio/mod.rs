@@ -0,0 +1,434 @@
+//! An event-driven server core multiplexing connections on a small number of threads, enabled with the `async` feature.
+//! It keeps the [crate::server::protocol::DataTransferProtocol] abstraction and the SEND/RECEIVE semantics of the
+//! blocking [crate::server::Server], PRESENCE and ADMIN clients are only served by the blocking server
+
+pub mod queue;
+pub mod session;
+pub mod frame;
+pub mod handler;
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Builder;
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{interval, timeout, timeout_at, Instant};
+
+use super::auth::{AliasOwnership, DuplicatePolicy};
+use super::container::DisconnectReason;
+use super::delivery::{admit_receiver, claim_alias, hand_pending, redeliver_pending, replaced_notice};
+use super::error::ServerError;
+use super::handler::TransmitService;
+use super::mailbox::Mailbox;
+use super::protocol::frame::FrameReader;
+use super::protocol::pto::BaseProto;
+use super::protocol::res::{Response, Status};
+use super::protocol::{get_handshake_for_raw_utf8, BaseProtocol, MAX_HEADER_SIZE};
+use super::queue::BackpressurePolicy;
+use super::quota::{Quota, QuotaTracker};
+use super::ratelimit::{RateLimit, RateLimiter};
+use super::registry::{ReceiverRegistry, Registration};
+use super::shutdown::ShutdownHandle;
+use super::{DEFAULT_DRAIN_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_QUEUE_CAPACITY};
+use frame::{read_handshake, write_frame};
+use handler::AsyncStreamHandler;
+use queue::AsyncQueue;
+use session::AsyncSession;
+
+
+/// Default maximum number of connections handled at once by the async server
+pub const DEFAULT_ASYNC_MAX_CONNECTIONS:usize = 65536;
+
+/// Interval at which a shutdown request is checked
+const SHUTDOWN_POLL_INTERVAL:Duration = Duration::from_millis(50);
+
+/// A struct representing the state shared by the tasks handling the connections of an [AsyncServer]
+///
+/// # Fields
+///
+/// - `receivers`: The [ReceiverRegistry] of the [AsyncSession] of each alias
+/// - `ownership`: The [AliasOwnership] deciding which client may claim an alias
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+/// - `queue_capacity`: The maximum number of messages queued for each receive client
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the queue of a receive client is full
+/// - `rate_limiter`: The [RateLimiter] enforced on send clients
+/// - `quotas`: The [QuotaTracker] counting the messages and bytes sent by each alias
+/// - `max_body_size`: The maximum size of the body of a message
+/// - `mailbox`: The [Mailbox] holding the messages of disconnected receive clients
+/// - `handshake_timeout`: The time a client is given to send its handshake
+/// - `stream_counter`: Maintains the id of each incoming stream
+pub struct Context{
+     receivers:ReceiverRegistry<AsyncSession>,
+     ownership:Arc<Mutex<AliasOwnership>>,
+     duplicate_policy:DuplicatePolicy,
+     queue_capacity:usize,
+     backpressure_policy:BackpressurePolicy,
+     rate_limiter:Arc<Mutex<RateLimiter>>,
+     quotas:Arc<Mutex<QuotaTracker>>,
+     max_body_size:usize,
+     mailbox:Arc<Mutex<Mailbox<BaseProto>>>,
+     handshake_timeout:Duration,
+     stream_counter:AtomicU64
+}
+
+/// A struct representing an async [AsyncServer] instance, the event-driven counterpart of [crate::server::Server].
+/// Connections are handled by tasks multiplexed on `worker_threads` threads instead of a thread each
+///
+/// # Fields
+///
+/// - `addrs`: The addresses the server binds on, the first that can be bound is used
+/// - `ownership`: The [AliasOwnership] deciding which client may claim an alias in a RECEIVE handshake
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+/// - `queue_capacity`: The maximum number of messages queued for each receive client
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the queue of a receive client is full
+/// - `rate_limiter`: The [RateLimiter] enforcing message and byte rates per alias and per source IP on send clients
+/// - `quotas`: The [QuotaTracker] counting the messages and bytes sent by each alias against its quota
+/// - `max_body_size`: The maximum size of the body of a message sent by a send client
+/// - `mailbox`: The [Mailbox] holding the messages of disconnected receive clients until their alias registers again
+/// - `shutdown`: The [ShutdownHandle] stopping the server once triggered
+/// - `drain_timeout`: The time given to the receive clients to be delivered their queued messages on shutdown
+/// - `handshake_timeout`: The time a client is given to send its handshake before it is refused
+/// - `worker_threads`: The number of threads the connections are multiplexed on
+/// - `max_connections`: The maximum number of connections handled at once, past it clients are answered with [Status::Busy]
+#[derive(Debug)]
+pub struct AsyncServer{
+     addrs:Vec<SocketAddr>,
+     ownership:Arc<Mutex<AliasOwnership>>,
+     duplicate_policy:DuplicatePolicy,
+     queue_capacity:usize,
+     backpressure_policy:BackpressurePolicy,
+     rate_limiter:Arc<Mutex<RateLimiter>>,
+     quotas:Arc<Mutex<QuotaTracker>>,
+     max_body_size:usize,
+     mailbox:Arc<Mutex<Mailbox<BaseProto>>>,
+     shutdown:ShutdownHandle,
+     drain_timeout:Duration,
+     handshake_timeout:Duration,
+     worker_threads:usize,
+     max_connections:usize
+}
+
+impl AsyncServer{
+     /// Default constructor for the async server
+     ///
+     /// # Arguments
+     ///
+     /// * `addr` - The address the server binds on
+     pub fn new(addr:SocketAddr)->Self{
+          info!("Initialized async server.");
+          AsyncServer{
+               addrs:vec![addr],
+               ownership:Arc::new(Mutex::new(AliasOwnership::new())),
+               duplicate_policy:DuplicatePolicy::default(),
+               queue_capacity:DEFAULT_QUEUE_CAPACITY,
+               backpressure_policy:BackpressurePolicy::default(),
+               rate_limiter:Arc::new(Mutex::new(RateLimiter::new(RateLimit::default()))),
+               quotas:Arc::new(Mutex::new(QuotaTracker::new(Quota::default()))),
+               max_body_size:DEFAULT_MAX_BODY_SIZE,
+               mailbox:Arc::new(Mutex::new(Mailbox::new(DEFAULT_QUEUE_CAPACITY))),
+               shutdown:ShutdownHandle::new(),
+               drain_timeout:DEFAULT_DRAIN_TIMEOUT,
+               handshake_timeout:DEFAULT_HANDSHAKE_TIMEOUT,
+               worker_threads:std::thread::available_parallelism().map(|n|n.get()).unwrap_or(4),
+               max_connections:DEFAULT_ASYNC_MAX_CONNECTIONS
+          }
+     }
+
+     /// Starts serving at host port initailized while constructing the instance, on a runtime of `worker_threads` threads.
+     /// Returns once the [ShutdownHandle] of the server is triggered and the connected clients have been drained
+     pub fn serve(&mut self)->Result<(), ServerError>{
+          let runtime = match Builder::new_multi_thread().worker_threads(self.worker_threads).enable_all().build(){
+               Ok(runtime)=>runtime,
+               Err(e)=>return Err(ServerError::RuntimeError(e))
+          };
+          runtime.block_on(self.run())
+     }
+
+     async fn run(&self)->Result<(), ServerError>{
+          let listener = match TcpListener::bind(&self.addrs[..]).await{
+               Ok(listener)=>listener,
+               Err(e)=>return Err(ServerError::AddressBindError(e))
+          };
+          if let Ok(addr) = listener.local_addr(){
+               info!("Async server is initialized and is starting on \"{addr}\" with {} worker threads", self.worker_threads);
+          }
+
+          let ctx = Arc::new(Context{
+               receivers:ReceiverRegistry::new(),
+               ownership:self.ownership.clone(),
+               duplicate_policy:self.duplicate_policy,
+               queue_capacity:self.queue_capacity,
+               backpressure_policy:self.backpressure_policy,
+               rate_limiter:self.rate_limiter.clone(),
+               quotas:self.quotas.clone(),
+               max_body_size:self.max_body_size,
+               mailbox:self.mailbox.clone(),
+               handshake_timeout:self.handshake_timeout,
+               stream_counter:AtomicU64::new(0)
+          });
+          let permits = Arc::new(Semaphore::new(self.max_connections));
+          let (stop_tx, stop_rx) = watch::channel(false);
+          //every send task holds a clone, the channel closes once they have all exited
+          let (sending_tx, mut sending_rx) = mpsc::channel::<()>(1);
+          let mut tasks = JoinSet::new();
+          let mut poll = interval(SHUTDOWN_POLL_INTERVAL);
+
+          loop {
+               let (mut stream, addr) = tokio::select!{
+                    accepted = listener.accept()=>match accepted{
+                         Ok(tas)=>tas,
+                         Err(e)=>return Err(ServerError::StreamAcceptError(e))
+                    },
+                    _ = poll.tick()=>{
+                         if self.shutdown.is_requested(){
+                              break;
+                         }
+                         continue;
+                    },
+                    //reaping finished tasks
+                    Some(_) = tasks.join_next(), if !tasks.is_empty()=>continue
+               };
+
+               //refusing banned addresses before the handshake
+               let banned = ctx.rate_limiter.lock().unwrap().is_banned_ip(addr.ip());
+               if let Some(remaining) = banned{
+                    warn!("Refused incoming request from banned address {addr}");
+                    let res = Response::generate_res(Status::Banned, format!("Banned for {}s", remaining.as_secs()));
+                    let _ = write_frame(&mut stream, res.as_bytes()).await;
+                    continue;
+               }
+
+               //refusing the connection when the maximum number of connections is reached
+               let permit = match permits.clone().try_acquire_owned(){
+                    Ok(permit)=>permit,
+                    Err(_)=>{
+                         warn!("Refused incoming request from {addr} -- maximum of {} connections reached", self.max_connections);
+                         let res = Response::generate_res(Status::Busy, "The server is busy, try again later".to_string());
+                         let _ = write_frame(&mut stream, res.as_bytes()).await;
+                         continue;
+                    }
+               };
+
+               tasks.spawn(handle_connection(ctx.clone(), stream, addr, permit, stop_rx.clone(), sending_tx.clone()));
+          }
+
+          //stopping the send clients, then delivering the queued messages of the receive clients until the deadline
+          drop(listener);
+          let deadline = Instant::now()+self.drain_timeout;
+          info!("Shutting down -- {{ connections: {} }}", tasks.len());
+          let _ = stop_tx.send(true);
+          drop(sending_tx);
+          let _ = timeout_at(deadline, sending_rx.recv()).await;
+
+          for session in ctx.receivers.remove_all(){
+               session.drain(Response::generate_res(Status::ShuttingDown, "Server is shutting down".to_string()));
+          }
+          if timeout_at(deadline, async { while tasks.join_next().await.is_some() {} }).await.is_err(){
+               warn!("Drain timeout reached, aborting {} connections", tasks.len());
+               tasks.abort_all();
+               while tasks.join_next().await.is_some() {}
+          }
+
+          info!("Async server has shut down");
+          Ok(())
+     }
+
+     /// Adds an address to bind on, the first address that can be bound is used
+     pub fn add_addr(&mut self, addr:SocketAddr){
+          self.addrs.push(addr);
+     }
+
+     /// Sets the [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+     pub fn set_duplicate_policy(&mut self, policy:DuplicatePolicy){
+          self.duplicate_policy = policy;
+     }
+
+     /// Sets the maximum number of messages queued for each receive client, and kept for redelivery per alias
+     pub fn set_queue_capacity(&mut self, capacity:usize){
+          self.queue_capacity = capacity;
+          self.mailbox.lock().unwrap().set_capacity(capacity);
+     }
+
+     /// Sets the [BackpressurePolicy] applied when the queue of a receive client is full
+     pub fn set_backpressure_policy(&mut self, policy:BackpressurePolicy){
+          self.backpressure_policy = policy;
+     }
+
+     /// Replaces the [RateLimit] enforced on send clients, resetting the rate limiter
+     pub fn set_rate_limit(&mut self, limit:RateLimit){
+          self.rate_limiter = Arc::new(Mutex::new(RateLimiter::new(limit)));
+     }
+
+     /// Returns the [RateLimiter] of the server to inspect or lift bans
+     pub fn get_rate_limiter(&self)->Arc<Mutex<RateLimiter>>{
+          self.rate_limiter.clone()
+     }
+
+     /// Replaces the [Quota] enforced on every alias, resetting the counters
+     pub fn set_quota(&mut self, quota:Quota){
+          self.quotas = Arc::new(Mutex::new(QuotaTracker::new(quota)));
+     }
+
+     /// Returns the [QuotaTracker] of the server to inspect and reset the counters of aliases
+     pub fn get_quotas(&self)->Arc<Mutex<QuotaTracker>>{
+          self.quotas.clone()
+     }
+
+     /// Sets the maximum size of the body of a message sent by a send client
+     pub fn set_max_body_size(&mut self, max_body_size:usize){
+          self.max_body_size = max_body_size;
+     }
+
+     /// Returns a [ShutdownHandle] stopping the server once triggered, from another thread or a signal handler
+     pub fn get_shutdown_handle(&self)->ShutdownHandle{
+          self.shutdown.clone()
+     }
+
+     /// Sets the time given to the receive clients to be delivered their queued messages when the server shuts down
+     pub fn set_drain_timeout(&mut self, timeout:Duration){
+          self.drain_timeout = timeout;
+     }
+
+     /// Sets the time a client is given to send its handshake, clients sending none in time are refused
+     pub fn set_handshake_timeout(&mut self, timeout:Duration){
+          self.handshake_timeout = timeout;
+     }
+
+     /// Sets the number of threads the connections are multiplexed on
+     pub fn set_worker_threads(&mut self, worker_threads:usize){
+          self.worker_threads = worker_threads.max(1);
+     }
+
+     /// Sets the maximum number of connections handled at once, past it clients are answered with [Status::Busy]
+     pub fn set_max_connections(&mut self, max_connections:usize){
+          self.max_connections = max_connections;
+     }
+
+     /// Returns the [AliasOwnership] of the server to register the owners of aliases
+     pub fn get_ownership(&self)->Arc<Mutex<AliasOwnership>>{
+          self.ownership.clone()
+     }
+}
+
+impl Context{
+     fn generate_id(&self)->u64{
+          self.stream_counter.fetch_add(1, Ordering::SeqCst)+1
+     }
+}
+
+/// Handles a connection from its handshake until it disconnects
+///
+/// # Arguments
+/// - `permit`: Held until the connection is closed, counting it against the maximum number of connections
+/// - `stop`: Set once the server is shutting down
+/// - `sending`: Held while a send client is handled, so that the shutdown awaits the messages being dispatched
+async fn handle_connection(ctx:Arc<Context>, mut stream:TcpStream, addr:SocketAddr, permit:OwnedSemaphorePermit, mut stop:watch::Receiver<bool>, sending:mpsc::Sender<()>){
+     let _permit = permit;
+
+     //reading the handshake on the task of the connection, the accept loop is never blocked by a client
+     let mut reader = FrameReader::new(MAX_HEADER_SIZE);
+     let frame = match timeout(ctx.handshake_timeout, read_handshake(&mut stream, &mut reader)).await{
+          Ok(Ok(Some(frame)))=>frame,
+          Ok(Ok(None))=>return,
+          Ok(Err(e))=>{
+               error!("An error occured when type was being extracted from incoming stream {}", e);
+               let res = Response::generate_res(Status::InvalidIdentifier, e.to_string());
+               let _ = write_frame(&mut stream, res.as_bytes()).await;
+               return;
+          },
+          Err(_)=>{
+               warn!("Refused incoming request from {addr} -- handshake timed out");
+               let res = Response::generate_res(Status::InvalidIdentifier, "Handshake timed out".to_string());
+               let _ = write_frame(&mut stream, res.as_bytes()).await;
+               return;
+          }
+     };
+     let handshake = match get_handshake_for_raw_utf8(&frame){
+          Ok(handshake)=>handshake,
+          Err(e)=>{
+               error!("An error occured when type was being extracted from incoming stream {:?}", e);
+               let res = Response::generate_res(Status::InvalidIdentifier, format!("{e:?}"));
+               let _ = write_frame(&mut stream, res.as_bytes()).await;
+               return;
+          }
+     };
+     let client_service = handshake.get_service().clone();
+
+     //refusing the services of the blocking server before their alias is claimed
+     if let TransmitService::Presence(name) | TransmitService::Admin(name) | TransmitService::Peer(name) = &client_service{
+          warn!("Refused incoming request from {addr} -- {{ name: {} }} is not served by the async server", name);
+          let res = Response::generate_res(Status::InvalidIdentifier, "PRESENCE, ADMIN and PEER clients are only served by the blocking server".to_string());
+          let _ = write_frame(&mut stream, res.as_bytes()).await;
+          return;
+     }
+
+     //claiming the alias of receive clients, and of send clients giving a secret
+     let claimed = handshake.get_secret().is_some();
+     let claim = claim_alias(&ctx.ownership, &handshake);
+     if let Err(e) = claim{
+          warn!("Refused incoming request from {addr} -- {}", e);
+          let res = Response::generate_res(Status::Unauthorized, e.to_string());
+          let _ = write_frame(&mut stream, res.as_bytes()).await;
+          return;
+     }
+
+     let key = ctx.generate_id();
+     let mut handler = AsyncStreamHandler::new(stream, BaseProtocol::new(), client_service.clone());
+     handler.set_max_body_size(ctx.max_body_size);
+     handler.set_frame_reader(reader);
+
+     match client_service{
+          TransmitService::Send(from)=>{
+               info!("Accepted incoming request from {addr} -- {{ id: {}; from_alias: {} }}", key, from);
+               let sender = claimed.then(||from.clone());
+               let reason = handler.handle_client_send(&ctx, &mut stop, sender).await;
+               drop(sending);
+               info!("Disconnected -- {{ id: {}; from_alias: {}; reason: {} }}", key, from, reason);
+          },
+          TransmitService::Receive(alias)=>{
+               drop(sending);
+               if *stop.borrow(){
+                    handler.respond(Status::ShuttingDown, "Server is shutting down".to_string()).await;
+                    return;
+               }
+               match admit_receiver(&ctx.receivers, &alias, claimed, ctx.duplicate_policy){
+                    Err(reason)=>{
+                         handler.respond(Status::Conflict, reason).await;
+                         warn!("Refused incoming request from {addr} -- {{ alias: {}; policy: {} }}", alias, ctx.duplicate_policy);
+                         return;
+                    },
+                    //the tasks of the replaced sessions notify their client
+                    Ok(replaced)=>for session in replaced{
+                         info!("Disconnecting replaced session {}", session);
+                         session.disconnect(replaced_notice(&alias));
+                    }
+               }
+
+               //delivering the messages re-queued while the alias was disconnected,
+               //after the messages written since the last message a resuming client was delivered
+               let queue = Arc::new(AsyncQueue::new(ctx.queue_capacity, ctx.backpressure_policy));
+               hand_pending(&queue, &alias, handshake.get_resume(), &ctx.mailbox);
+
+               let session = AsyncSession::new(key, alias.clone(), queue);
+               ctx.receivers.register_with(&alias, ||session.clone());
+               info!("Accepted incoming request from {addr} -- {{ id: {}; receive_alias: {} }}", key, alias);
+
+               let reason = handler.handle_client_receive(&session, &ctx.mailbox).await;
+               ctx.receivers.deregister(&alias, session.get_id());
+               info!("Disconnected -- {{ id: {}; receive_alias: {}; reason: {} }}", key, alias, reason);
+               //a client closing its connection does not resume, the journal is dropped once the last session of the alias left
+               if matches!(reason, DisconnectReason::Closed) && !ctx.receivers.contains(&alias){
+                    ctx.mailbox.lock().unwrap().forget(&alias);
+               }
+               //handing the re-queued messages to a remaining session of the alias
+               redeliver_pending(&alias, &ctx.receivers, &ctx.mailbox);
+          },
+          //refused before their alias is claimed
+          TransmitService::Presence(_) | TransmitService::Admin(_) | TransmitService::Peer(_)=>()
+     }
+}
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::pin::pin;
+use std::sync::Mutex;
+
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+use crate::server::queue::{BackpressurePolicy, Delivery, Offer, QueueError, BLOCK_TIMEOUT};
+
+
+/// The messages of an [AsyncQueue] and whether it is still open
+#[derive(Debug)]
+struct State<T>{
+     items:VecDeque<T>,
+     closed:bool,
+     draining:bool
+}
+
+/// A bounded delivery queue awaited by an async receive client, the async counterpart of [crate::server::queue::bounded].
+/// The same [BackpressurePolicy] applies when it is full, a blocked sender awaits room instead of blocking its thread,
+/// for at most [BLOCK_TIMEOUT].
+/// It is shared behind an [std::sync::Arc] by the receive client and every sender
+///
+/// # Fields
+///
+/// - `state`: The queued messages, the lock is never held across an await
+/// - `not_empty`: Notified when a message is queued or the queue is closed
+/// - `not_full`: Notified when a message is taken or the queue is closed
+/// - `capacity`: The maximum number of queued messages
+/// - `policy`: The [BackpressurePolicy] applied when the queue is full
+#[derive(Debug)]
+pub struct AsyncQueue<T>{
+     state:Mutex<State<T>>,
+     not_empty:Notify,
+     not_full:Notify,
+     capacity:usize,
+     policy:BackpressurePolicy
+}
+
+impl <T>AsyncQueue<T>{
+     /// Default constructor for an [AsyncQueue] holding at most `capacity` messages
+     pub fn new(capacity:usize, policy:BackpressurePolicy)->Self{
+          AsyncQueue{
+               state:Mutex::new(State{
+                    items:VecDeque::new(),
+                    closed:false,
+                    draining:false
+               }),
+               not_empty:Notify::new(),
+               not_full:Notify::new(),
+               capacity:capacity.max(1),
+               policy
+          }
+     }
+
+     /// Pushes a message to the queue, applying the backpressure policy when the queue is full
+     pub async fn send(&self, item:T)->Result<Delivery, QueueError<T>>{
+          let deadline = Instant::now()+BLOCK_TIMEOUT;
+          loop {
+               //registering for a notification before checking, so that a slot freed in between is not missed
+               let mut notified = pin!(self.not_full.notified());
+               notified.as_mut().enable();
+
+               {
+                    let mut state = self.state.lock().unwrap();
+                    if state.closed || state.draining{
+                         return Err(QueueError::Closed(item));
+                    }
+
+                    if state.items.len()<self.capacity{
+                         state.items.push_back(item);
+                         self.not_empty.notify_one();
+                         return Ok(Delivery::Queued);
+                    }
+
+                    match self.policy{
+                         BackpressurePolicy::Block=>(),
+                         BackpressurePolicy::DropOldest=>{
+                              state.items.pop_front();
+                              state.items.push_back(item);
+                              self.not_empty.notify_one();
+                              return Ok(Delivery::DroppedOldest);
+                         },
+                         BackpressurePolicy::DropNewest=>return Err(QueueError::Full(item)),
+                         BackpressurePolicy::Disconnect=>{
+                              state.closed = true;
+                              self.not_empty.notify_waiters();
+                              self.not_full.notify_waiters();
+                              return Err(QueueError::Evicted(item));
+                         }
+                    }
+               }
+
+               //awaiting room in the queue, the message is dropped once the deadline is reached
+               if timeout_at(deadline, notified).await.is_err(){
+                    return Err(QueueError::Full(item));
+               }
+          }
+     }
+
+     /// Awaits the next message.
+     /// Returns `None` once the queue is closed, or once it is drained after [AsyncQueue::close_when_drained]
+     pub async fn recv(&self)->Option<T>{
+          loop {
+               let mut notified = pin!(self.not_empty.notified());
+               notified.as_mut().enable();
+
+               {
+                    let mut state = self.state.lock().unwrap();
+                    if state.closed{
+                         return None;
+                    }
+                    if let Some(item) = state.items.pop_front(){
+                         self.not_full.notify_one();
+                         return Some(item);
+                    }
+                    if state.draining{
+                         return None;
+                    }
+               }
+
+               notified.await;
+          }
+     }
+
+     /// Closes the queue, the receiver stops awaiting messages and senders are refused. Queued messages are kept for [AsyncQueue::drain]
+     pub fn close(&self){
+          self.state.lock().unwrap().closed = true;
+          self.not_empty.notify_waiters();
+          self.not_full.notify_waiters();
+     }
+
+     /// Refuses new messages, the receiver is handed the queued messages before being told the queue is closed
+     pub fn close_when_drained(&self){
+          self.state.lock().unwrap().draining = true;
+          self.not_empty.notify_waiters();
+          self.not_full.notify_waiters();
+     }
+
+     /// Takes every queued message, even once the queue is closed
+     pub fn drain(&self)->Vec<T>{
+          self.state.lock().unwrap().items.drain(..).collect()
+     }
+
+     /// Returns true if the queue has been closed, by the receiver or for being too slow
+     pub fn is_closed(&self)->bool{
+          self.state.lock().unwrap().closed
+     }
+
+     /// Returns true if no message is queued
+     pub fn is_empty(&self)->bool{
+          self.state.lock().unwrap().items.is_empty()
+     }
+
+     /// Returns the [BackpressurePolicy] of the queue
+     pub fn get_policy(&self)->BackpressurePolicy{
+          self.policy
+     }
+}
+
+/// Offer implementation for AsyncQueue, pending messages are handed over without awaiting room
+impl <T>Offer<T> for AsyncQueue<T>{
+     fn offer(&self, item:T)->Result<(), QueueError<T>>{
+          let mut state = self.state.lock().unwrap();
+          if state.closed || state.draining{
+               return Err(QueueError::Closed(item));
+          }
+          if state.items.len()>=self.capacity{
+               return Err(QueueError::Full(item));
+          }
+          state.items.push_back(item);
+          self.not_empty.notify_one();
+          Ok(())
+     }
+}
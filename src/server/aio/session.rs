@@ -0,0 +1,92 @@
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use crate::server::protocol::pto::BaseProto;
+use crate::server::registry::Registration;
+use super::queue::AsyncQueue;
+
+
+/// A struct representing a receive client of the async server registered in the [crate::server::registry::ReceiverRegistry],
+/// the async counterpart of [crate::server::container::ClientReceiverContainer].
+/// The task handling the client owns its stream, it is disconnected by closing its queue
+///
+/// # Fields
+///
+/// - `id`: A unique identifier of the session
+/// - `alias`: The alias registered by the client
+/// - `queue`: The [AsyncQueue] delivering messages to the task handling the client
+/// - `notice`: The frame the task writes to the client before closing its stream, once the queue is closed
+#[derive(Debug, Clone)]
+pub struct AsyncSession{
+     id:u64,
+     alias:String,
+     queue:Arc<AsyncQueue<BaseProto>>,
+     notice:Arc<Mutex<Option<String>>>
+}
+
+impl AsyncSession{
+     /// Default constructor for an [AsyncSession]
+     pub fn new(id:u64, alias:String, queue:Arc<AsyncQueue<BaseProto>>)->Self{
+          AsyncSession{
+               id,
+               alias,
+               queue,
+               notice:Arc::new(Mutex::new(None))
+          }
+     }
+
+     /// Disconnects the client of this session.
+     /// The notice is written to the client by its task before its stream is closed, queued messages are kept for redelivery
+     pub fn disconnect(&self, notice:String){
+          *self.notice.lock().unwrap() = Some(notice);
+          self.queue.close();
+     }
+
+     /// Disconnects the client of this session once its queued messages are delivered
+     pub fn drain(&self, notice:String){
+          *self.notice.lock().unwrap() = Some(notice);
+          self.queue.close_when_drained();
+     }
+
+     /// Takes the notice to write to the client, if it was disconnected by the server
+     pub fn take_notice(&self)->Option<String>{
+          self.notice.lock().unwrap().take()
+     }
+
+     //----Getters----
+     pub fn get_alias(&self)->&String{
+          &self.alias
+     }
+
+     pub fn get_queue(&self)->&Arc<AsyncQueue<BaseProto>>{
+          &self.queue
+     }
+}
+
+/// Registration implementation for AsyncSession
+impl Registration for AsyncSession{
+     type Sender = Arc<AsyncQueue<BaseProto>>;
+
+     fn get_id(&self)->u64{
+          self.id
+     }
+
+     fn get_sender(&self)->Option<Self::Sender>{
+          Some(self.queue.clone())
+     }
+
+     fn is_closed(&self)->bool{
+          self.queue.is_closed()
+     }
+
+     fn is_finished(&self)->bool{
+          self.queue.is_closed()
+     }
+}
+
+/// Display implementation for AsyncSession
+impl Display for AsyncSession{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "{{ id: {}; alias: {}; type: RECEIVE }}", self.id, self.alias)
+     }
+}
@@ -1,19 +1,250 @@
-//Developement feature.
-//To be implemented
-#[cfg(feature="development")]
-struct Authentication{
-
-}
-//Developement feature.
-//To be implemented [docs]
-#[cfg(feature="development")]
-trait BaseUserAuth<A,B>{
-     fn auth(username:A, password:B)->Option<User<A,B>>;
-}
-//Developement feature.
-//To be implemented
-#[cfg(feature="development")]
-struct User<A,B>{
-     username:A,
-     password:B
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The time after which an owner bound on first use is released if its alias has not been claimed since, 30 days
+pub const OWNER_MAX_IDLE:Duration = Duration::from_secs(30*24*60*60);
+
+/// The minimum time between two sweeps of the idle owners
+const SWEEP_INTERVAL:Duration = Duration::from_secs(60);
+
+
+/// An enum representing the policy applied when a client registers an alias that already has a registered receive client
+///
+/// # Variants
+///
+/// - `Reject`: The new registration is refused and the existing session is kept
+/// - `Replace`: The existing sessions are disconnected and replaced by the new registration
+/// - `Coexist`: Both sessions are kept, messages to the alias are dispatched to every session
+///
+/// A registration that did not claim the alias with a secret is always rejected while the alias is registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy{
+     #[default]
+     Reject,
+     Replace,
+     Coexist
+}
+
+/// An enum representing the errors that can occur while claiming an alias
+///
+/// # Variants
+///
+/// - `InvalidSecret`: The alias is owned and the secret provided does not match the owner's secret
+/// - `SecretRequired`: The server only accepts aliases claimed with a secret
+#[derive(Debug)]
+pub enum AuthError{
+     InvalidSecret(String),
+     SecretRequired(String)
+}
+
+/// A struct representing the owner of an alias
+///
+/// # Fields
+///
+/// - `secret`: The secret of the owner
+/// - `registered`: True if the owner was registered up front, registered owners are never released when idle
+/// - `last_claim`: The last time the alias was claimed by its owner
+#[derive(Debug)]
+struct Owner{
+     secret:String,
+     registered:bool,
+     last_claim:Instant
+}
+
+/// A struct representing the ownership of aliases.
+/// An alias is owned by the client that knows its secret. Owners are either registered up front
+/// or bound on first use, when an unowned alias is claimed with a secret.
+///
+/// Binding on first use trusts whichever client claims an unowned alias first: nothing proves it is the
+/// client the alias was meant for. An owner bound this way is released once its alias has not been claimed
+/// for `max_idle`, so that an abandoned alias can be claimed again. Owners are also released by [AliasOwnership::unregister],
+/// which the RELEASE command of admin clients calls.
+///
+/// # Fields
+///
+/// - `owners`: The owner of each owned alias
+/// - `require_secret`: When true, aliases cannot be claimed without a secret, true by default
+/// - `max_idle`: The time after which an owner bound on first use is released if its alias has not been claimed since
+/// - `last_sweep`: The last time the idle owners were released
+#[derive(Debug)]
+pub struct AliasOwnership{
+     owners:HashMap<String, Owner>,
+     require_secret:bool,
+     max_idle:Duration,
+     last_sweep:Instant
+}
+
+impl AliasOwnership{
+     /// Default constructor for [AliasOwnership]. Aliases can only be claimed with a secret,
+     /// an unowned alias becomes owned by the first secret it is claimed with
+     pub fn new()->Self{
+          AliasOwnership{
+               owners:HashMap::new(),
+               require_secret:true,
+               max_idle:OWNER_MAX_IDLE,
+               last_sweep:Instant::now()
+          }
+     }
+
+     /// Registers the owner's secret of an alias, replacing any previous owner. Registered owners are never released when idle
+     pub fn register(&mut self, alias:&str, secret:&str){
+          self.owners.insert(alias.to_string(), Owner{ secret:secret.to_string(), registered:true, last_claim:Instant::now() });
+     }
+
+     /// Removes the owner of an alias, the next secret it is claimed with owns it
+     ///
+     /// # Returns
+     ///
+     /// True if the alias had an owner
+     pub fn unregister(&mut self, alias:&str)->bool{
+          self.owners.remove(alias).is_some()
+     }
+
+     /// Sets wether aliases can only be claimed with a secret
+     pub fn set_require_secret(&mut self, require_secret:bool){
+          self.require_secret = require_secret;
+     }
+
+     /// Sets the time after which an owner bound on first use is released if its alias has not been claimed since
+     pub fn set_max_idle(&mut self, max_idle:Duration){
+          self.max_idle = max_idle;
+     }
+
+     /// Returns true if the alias has an owner
+     pub fn is_owned(&self, alias:&str)->bool{
+          self.owners.get(alias).is_some_and(|owner|!self.is_idle(owner, Instant::now()))
+     }
+
+     /// Claims an alias with an optional secret.
+     /// An unowned alias claimed with a secret becomes owned by that secret, whoever claims it first.
+     ///
+     /// # Arguments
+     ///
+     /// * `alias`: The alias being claimed
+     /// * `secret`: The secret provided in the handshake, if any
+     pub fn claim(&mut self, alias:&str, secret:Option<&str>)->Result<(), AuthError>{
+          let now = Instant::now();
+          self.expire(now);
+          if self.owners.get(alias).is_some_and(|owner|self.is_idle(owner, now)){
+               self.owners.remove(alias);
+          }
+          match (self.owners.get_mut(alias), secret){
+               (Some(owner), Some(s)) if constant_time_eq(owner.secret.as_bytes(), s.as_bytes())=>{
+                    owner.last_claim = now;
+                    Ok(())
+               },
+               (Some(_), _)=>Err(AuthError::InvalidSecret(alias.to_string())),
+               (None, None) if self.require_secret=>Err(AuthError::SecretRequired(alias.to_string())),
+               (None, None)=>Ok(()),
+               (None, Some(s))=>{
+                    self.owners.insert(alias.to_string(), Owner{ secret:s.to_string(), registered:false, last_claim:now });
+                    Ok(())
+               }
+          }
+     }
+
+     /// Returns true if the owner was bound on first use and its alias has not been claimed for `max_idle`
+     fn is_idle(&self, owner:&Owner, now:Instant)->bool{
+          !owner.registered && now.duration_since(owner.last_claim)>=self.max_idle
+     }
+
+     /// Releases the idle owners, at most once every [SWEEP_INTERVAL]
+     fn expire(&mut self, now:Instant){
+          if now.duration_since(self.last_sweep)<SWEEP_INTERVAL{
+               return;
+          }
+          self.last_sweep = now;
+          let max_idle = self.max_idle;
+          self.owners.retain(|_, owner|owner.registered || now.duration_since(owner.last_claim)<max_idle);
+     }
+}
+
+/// Default implementation for AliasOwnership
+impl Default for AliasOwnership{
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+/// Compares two byte slices without returning early on the first mismatch, used to check secrets
+pub(crate) fn constant_time_eq(a:&[u8], b:&[u8])->bool{
+     if a.len()!=b.len(){
+          return false;
+     }
+     a.iter().zip(b).fold(0, |acc, (x, y)|acc | (x ^ y))==0
+}
+
+/// Display implementation for DuplicatePolicy
+impl Display for DuplicatePolicy{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Reject=>write!(f, "reject"),
+               Self::Replace=>write!(f, "replace"),
+               Self::Coexist=>write!(f, "coexist")
+          }
+     }
+}
+
+/// FromStr implementation for DuplicatePolicy, parses the names it is displayed with
+impl FromStr for DuplicatePolicy{
+     type Err = String;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          match s {
+               "reject"=>Ok(Self::Reject),
+               "replace"=>Ok(Self::Replace),
+               "coexist"=>Ok(Self::Coexist),
+               _=>Err(format!("unknown duplicate policy '{s}', expected reject, replace or coexist"))
+          }
+     }
+}
+
+/// Display implementation for AuthError
+impl Display for AuthError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::InvalidSecret(alias)=>{
+                    write!(f, "{{ error: InvalidSecret; info: alias '{}' is owned by another client }}", alias)
+               },
+               Self::SecretRequired(alias)=>{
+                    write!(f, "{{ error: SecretRequired; info: alias '{}' can only be claimed with a secret }}", alias)
+               }
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+
+     #[test]
+     fn binds_an_unowned_alias_to_the_first_secret(){
+          let mut ownership = AliasOwnership::new();
+          assert!(matches!(ownership.claim("bob", None), Err(AuthError::SecretRequired(_))));
+          assert!(ownership.claim("bob", Some("first")).is_ok());
+          assert!(matches!(ownership.claim("bob", Some("second")), Err(AuthError::InvalidSecret(_))));
+          assert!(ownership.claim("bob", Some("first")).is_ok());
+
+          //a released alias is bound to the next secret it is claimed with
+          assert!(ownership.unregister("bob"));
+          assert!(!ownership.unregister("bob"));
+          assert!(ownership.claim("bob", Some("second")).is_ok());
+          assert!(matches!(ownership.claim("bob", Some("first")), Err(AuthError::InvalidSecret(_))));
+     }
+
+     #[test]
+     fn releases_the_idle_owners_bound_on_first_use(){
+          let mut ownership = AliasOwnership::new();
+          ownership.register("admin", "registered");
+          assert!(ownership.claim("bob", Some("first")).is_ok());
+
+          ownership.set_max_idle(Duration::ZERO);
+          assert!(!ownership.is_owned("bob"));
+          assert!(ownership.claim("bob", Some("second")).is_ok());
+          //registered owners are kept however long they are idle
+          assert!(ownership.is_owned("admin"));
+          assert!(matches!(ownership.claim("admin", Some("second")), Err(AuthError::InvalidSecret(_))));
+     }
 }
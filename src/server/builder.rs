@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::auth::{DuplicatePolicy, OWNER_MAX_IDLE};
+use super::queue::BackpressurePolicy;
+use super::quota::Quota;
+use super::ratelimit::RateLimit;
+use super::{Server, DEFAULT_DRAIN_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_PENDING_CONNECTIONS, DEFAULT_QUEUE_CAPACITY};
+
+
+/// A struct building a [Server] from its addresses and options, for embedding the server in an application
+///
+/// ```ignore
+/// let mut server = ServerBuilder::new()
+///      .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+///      .queue_capacity(64)
+///      .build();
+/// let addr = server.bind()?;      //the port picked by the system
+/// server.serve()?;
+/// ```
+///
+/// # Fields
+///
+/// - `addrs`: The addresses the server binds on, the first that can be bound is used
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered twice
+/// - `queue_capacity`: The maximum number of messages queued for each receive client
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the delivery queue of a receive client is full
+/// - `rate_limit`: The [RateLimit] enforced on send clients
+/// - `quota`: The [Quota] enforced on every alias
+/// - `max_body_size`: The maximum size of the body of a message
+/// - `admin_secret`: The secret admin clients authenticate with
+/// - `require_secret`: When true, aliases cannot be claimed without a secret, true by default
+/// - `owner_max_idle`: The time after which the owner an alias was bound to on first use is released if it has not claimed it since
+/// - `max_connections`: The maximum number of connections handled at once, receive and presence connections count twice
+/// - `max_pending_connections`: The maximum number of connections waiting for a worker
+/// - `handshake_timeout`: The time a client is given to send its handshake
+/// - `drain_timeout`: The time given to the receive clients to be delivered their queued messages on shutdown
+/// - `federation`: The name of the server and the secret shared with its peers, when federated
+/// - `peers`: The `host:port` addresses of the peers the server dials
+/// - `cluster`: The number of points the server is placed at on the ring of its cluster, when it is a node of one
+#[derive(Debug, Clone)]
+pub struct ServerBuilder{
+     addrs:Vec<SocketAddr>,
+     duplicate_policy:DuplicatePolicy,
+     queue_capacity:usize,
+     backpressure_policy:BackpressurePolicy,
+     rate_limit:RateLimit,
+     quota:Quota,
+     max_body_size:usize,
+     admin_secret:Option<String>,
+     require_secret:bool,
+     owner_max_idle:Duration,
+     max_connections:usize,
+     max_pending_connections:usize,
+     handshake_timeout:Duration,
+     drain_timeout:Duration,
+     federation:Option<(String, String)>,
+     peers:Vec<String>,
+     cluster:Option<usize>
+}
+
+impl ServerBuilder{
+     /// Default constructor for a [ServerBuilder] with no address and the default options
+     pub fn new()->Self{
+          ServerBuilder{
+               addrs:Vec::new(),
+               duplicate_policy:DuplicatePolicy::default(),
+               queue_capacity:DEFAULT_QUEUE_CAPACITY,
+               backpressure_policy:BackpressurePolicy::default(),
+               rate_limit:RateLimit::default(),
+               quota:Quota::default(),
+               max_body_size:DEFAULT_MAX_BODY_SIZE,
+               admin_secret:None,
+               require_secret:true,
+               owner_max_idle:OWNER_MAX_IDLE,
+               max_connections:DEFAULT_MAX_CONNECTIONS,
+               max_pending_connections:DEFAULT_MAX_PENDING_CONNECTIONS,
+               handshake_timeout:DEFAULT_HANDSHAKE_TIMEOUT,
+               drain_timeout:DEFAULT_DRAIN_TIMEOUT,
+               federation:None,
+               peers:Vec::new(),
+               cluster:None
+          }
+     }
+
+     /// Adds an address to bind on, port 0 binds an ephemeral port
+     pub fn addr(mut self, addr:SocketAddr)->Self{
+          self.addrs.push(addr);
+          self
+     }
+
+     /// Adds addresses to bind on, eg.. the addresses a host name resolves to
+     pub fn addrs<I:IntoIterator<Item = SocketAddr>>(mut self, addrs:I)->Self{
+          self.addrs.extend(addrs);
+          self
+     }
+
+     /// Sets the [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+     pub fn duplicate_policy(mut self, policy:DuplicatePolicy)->Self{
+          self.duplicate_policy = policy;
+          self
+     }
+
+     /// Sets the maximum number of messages queued for each receive client
+     pub fn queue_capacity(mut self, capacity:usize)->Self{
+          self.queue_capacity = capacity;
+          self
+     }
+
+     /// Sets the [BackpressurePolicy] applied when the delivery queue of a receive client is full
+     pub fn backpressure_policy(mut self, policy:BackpressurePolicy)->Self{
+          self.backpressure_policy = policy;
+          self
+     }
+
+     /// Sets the [RateLimit] enforced on send clients
+     pub fn rate_limit(mut self, limit:RateLimit)->Self{
+          self.rate_limit = limit;
+          self
+     }
+
+     /// Sets the [Quota] enforced on every alias
+     pub fn quota(mut self, quota:Quota)->Self{
+          self.quota = quota;
+          self
+     }
+
+     /// Sets the maximum size of the body of a message sent by a send client
+     pub fn max_body_size(mut self, max_body_size:usize)->Self{
+          self.max_body_size = max_body_size;
+          self
+     }
+
+     /// Sets the secret admin clients authenticate with, admin clients are refused without it
+     pub fn admin_secret(mut self, secret:String)->Self{
+          self.admin_secret = Some(secret);
+          self
+     }
+
+     /// Refuses aliases claimed without a secret when true, the default. When false an unowned alias can be
+     /// received from without a secret, such a client never replaces nor coexists with a registered session
+     pub fn require_secret(mut self, require_secret:bool)->Self{
+          self.require_secret = require_secret;
+          self
+     }
+
+     /// Sets the time after which the owner an alias was bound to on first use is released if it has not claimed it since,
+     /// 30 days by default
+     pub fn owner_max_idle(mut self, max_idle:Duration)->Self{
+          self.owner_max_idle = max_idle;
+          self
+     }
+
+     /// Sets the maximum number of connections handled at once, and waiting for a worker past it.
+     /// Each receive and presence connection takes two workers, a server handling `n` receive clients needs `2n` workers
+     pub fn connection_limits(mut self, max_connections:usize, max_pending:usize)->Self{
+          self.max_connections = max_connections;
+          self.max_pending_connections = max_pending;
+          self
+     }
+
+     /// Sets the time a client is given to send its handshake
+     pub fn handshake_timeout(mut self, timeout:Duration)->Self{
+          self.handshake_timeout = timeout;
+          self
+     }
+
+     /// Sets the time given to the receive clients to be delivered their queued messages on shutdown
+     pub fn drain_timeout(mut self, timeout:Duration)->Self{
+          self.drain_timeout = timeout;
+          self
+     }
+
+     /// Federates the server with its peers under `name`, peers authenticate with the shared `secret`
+     pub fn federation(mut self, name:String, secret:String)->Self{
+          self.federation = Some((name, secret));
+          self
+     }
+
+     /// Adds the `host:port` address of a peer the server dials
+     pub fn peer(mut self, addr:String)->Self{
+          self.peers.push(addr);
+          self
+     }
+
+     /// Makes the federated server a node of a cluster placed at `virtual_nodes` points on its ring,
+     /// eg.. [super::cluster::DEFAULT_VIRTUAL_NODES]
+     pub fn cluster(mut self, virtual_nodes:usize)->Self{
+          self.cluster = Some(virtual_nodes);
+          self
+     }
+
+     /// Builds the [Server], its listener is bound by [Server::bind] or [Server::serve]
+     pub fn build(self)->Server{
+          let mut server = Server::with_addrs(self.addrs);
+          server.set_duplicate_policy(self.duplicate_policy);
+          server.set_queue_capacity(self.queue_capacity);
+          server.set_backpressure_policy(self.backpressure_policy);
+          server.set_rate_limit(self.rate_limit);
+          server.set_quota(self.quota);
+          server.set_max_body_size(self.max_body_size);
+          if let Some(secret) = self.admin_secret{
+               server.set_admin_secret(secret);
+          }
+          server.get_ownership().lock().unwrap().set_require_secret(self.require_secret);
+          server.get_ownership().lock().unwrap().set_max_idle(self.owner_max_idle);
+          server.set_connection_limits(self.max_connections, self.max_pending_connections);
+          server.set_handshake_timeout(self.handshake_timeout);
+          server.set_drain_timeout(self.drain_timeout);
+          if let Some((name, secret)) = self.federation{
+               server.set_federation(name, secret);
+          }
+          for peer in self.peers{
+               server.add_peer(peer);
+          }
+          if let Some(virtual_nodes) = self.cluster{
+               server.set_cluster(virtual_nodes);
+          }
+          server
+     }
+}
+
+/// Default implementation for ServerBuilder
+impl Default for ServerBuilder{
+     fn default() -> Self {
+          Self::new()
+     }
+}
@@ -0,0 +1,153 @@
+//! Consistent hashing of aliases onto the nodes of a cluster
+//!
+//! Each node is placed on a ring of 64-bit hashes at several points, its virtual nodes. An alias is owned by the node of
+//! the first point at or after the hash of the alias, wrapping around the ring. A node joining or leaving only moves the
+//! aliases owned around its own points, about one in every number of nodes, the other aliases keep their owner.
+//!
+//! ```text
+//!              0 ──── a#3 ──── b#0 ──── c#1 ──── a#0 ──── ... ──── b#2 ──── u64::MAX
+//!                          ^          ^
+//!                     hash(bob)  hash(alice)         bob is owned by b, alice by c
+//! ```
+
+use std::collections::BTreeMap;
+
+
+/// Default number of points each node is placed at on the ring
+pub const DEFAULT_VIRTUAL_NODES:usize = 64;
+
+/// FNV-1a offset basis and prime, see <http://www.isthe.com/chongo/tech/comp/fnv/>
+const FNV_OFFSET:u64 = 0xcbf29ce484222325;
+const FNV_PRIME:u64 = 0x100000001b3;
+
+/// A struct representing the ring of a cluster, deciding which node owns each alias
+///
+/// # Fields
+///
+/// - `points`: The nodes by the hash of each of their points
+/// - `virtual_nodes`: The number of points each node is placed at
+#[derive(Debug, Clone)]
+pub struct HashRing{
+     points:BTreeMap<u64, String>,
+     virtual_nodes:usize
+}
+
+impl HashRing{
+     /// Default constructor for an empty [HashRing] placing each node at `virtual_nodes` points
+     pub fn new(virtual_nodes:usize)->Self{
+          HashRing{
+               points:BTreeMap::new(),
+               virtual_nodes:virtual_nodes.max(1)
+          }
+     }
+
+     /// Places a node on the ring, it owns the aliases hashed right before its points from now on
+     pub fn add(&mut self, node:&str){
+          for i in 0..self.virtual_nodes{
+               self.points.insert(hash(&format!("{node}#{i}")), node.to_string());
+          }
+     }
+
+     /// Removes a node from the ring, its aliases are owned by the nodes of the following points from now on
+     pub fn remove(&mut self, node:&str){
+          self.points.retain(|_, n|n!=node);
+     }
+
+     /// Returns the node owning `alias`, None when the ring is empty
+     pub fn owner(&self, alias:&str)->Option<&String>{
+          let h = hash(alias);
+          self.points.range(h..).next()
+               .or_else(||self.points.iter().next())
+               .map(|(_, node)|node)
+     }
+
+     /// Returns true if the node is placed on the ring
+     pub fn contains(&self, node:&str)->bool{
+          self.points.values().any(|n|n==node)
+     }
+
+     /// Returns the nodes placed on the ring, sorted by name
+     pub fn nodes(&self)->Vec<String>{
+          let mut nodes:Vec<String> = self.points.values().cloned().collect();
+          nodes.sort();
+          nodes.dedup();
+          nodes
+     }
+}
+
+/// Hashes a key to its place on the ring, the same on every node whatever the platform or the build
+fn hash(key:&str)->u64{
+     let mut h = FNV_OFFSET;
+     for b in key.as_bytes(){
+          h ^= *b as u64;
+          h = h.wrapping_mul(FNV_PRIME);
+     }
+     //finalizer of splitmix64, FNV alone leaves keys differing in their last bytes close to each other
+     h ^= h>>30;
+     h = h.wrapping_mul(0xbf58476d1ce4e5b9);
+     h ^= h>>27;
+     h = h.wrapping_mul(0x94d049bb133111eb);
+     h ^ (h>>31)
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::collections::HashMap;
+
+     const ALIASES:usize = 10000;
+
+     fn ring(nodes:&[&str])->HashRing{
+          let mut ring = HashRing::new(DEFAULT_VIRTUAL_NODES);
+          for node in nodes{
+               ring.add(node);
+          }
+          ring
+     }
+
+     fn owners(ring:&HashRing)->Vec<String>{
+          (0..ALIASES).map(|i|ring.owner(&format!("alias-{i}")).unwrap().clone()).collect()
+     }
+
+     #[test]
+     fn spreads_the_aliases_over_the_nodes(){
+          assert_eq!(HashRing::new(DEFAULT_VIRTUAL_NODES).owner("bob"), None);
+
+          let ring = ring(&["a", "b", "c", "d"]);
+          assert_eq!(ring.nodes(), ["a", "b", "c", "d"]);
+          let mut counts = HashMap::new();
+          for owner in owners(&ring){
+               *counts.entry(owner).or_insert(0) += 1;
+          }
+          //each node owns about a quarter of the aliases
+          for (node, count) in counts{
+               assert!((ALIASES/8..ALIASES*3/8).contains(&count), "{node} owns {count} aliases");
+          }
+     }
+
+     #[test]
+     fn moves_only_the_aliases_of_the_node_added_or_removed(){
+          let mut ring = ring(&["a", "b", "c", "d"]);
+          let before = owners(&ring);
+
+          //the aliases moving all move to the node added, about a fifth of them
+          ring.add("e");
+          let added = owners(&ring);
+          let moved = before.iter().zip(&added).filter(|(b, a)|b!=a).inspect(|(_, a)|assert_eq!(*a, "e")).count();
+          assert!((ALIASES/10..ALIASES*3/10).contains(&moved), "{moved} aliases moved");
+
+          //removing it again gives them back to their previous owner
+          ring.remove("e");
+          assert!(!ring.contains("e"));
+          assert_eq!(owners(&ring), before);
+
+          //only the aliases of the node removed move
+          ring.remove("b");
+          for (before, after) in before.iter().zip(owners(&ring)){
+               match before.as_str(){
+                    "b"=>assert_ne!(after, "b"),
+                    _=>assert_eq!(*before, after)
+               }
+          }
+     }
+}
@@ -0,0 +1,573 @@
+//! The configuration file of the server
+//!
+//! A TOML subset of `[section]` headers and `key = value` lines, values are integers, booleans or strings.
+//! Durations are written `500ms`, `10s`, `5m` or `1h`. Every key can be overridden by the environment variable
+//! `RAW_<SECTION>_<KEY>`, eg.. `RAW_LIMITS_QUEUE_CAPACITY=256`
+//!
+//! ```text
+//! [server]
+//! bind = "0.0.0.0:5000"
+//! async = false
+//! worker_threads = 8
+//! admin_secret = "changeme"
+//! require_secret = true
+//! owner_max_idle = "720h"
+//!
+//! [limits]
+//! queue_capacity = 1024
+//! max_body_size = 65536
+//! max_connections = 1024
+//! max_pending_connections = 64
+//!
+//! [timeouts]
+//! handshake = "10s"
+//! drain = "5s"
+//!
+//! [logging]
+//! level = "info"
+//!
+//! [policies]
+//! duplicate = "reject"
+//! backpressure = "block"
+//!
+//! [rate_limit]
+//! messages_per_second = 1000
+//! bytes_per_second = 1048576
+//! burst_seconds = 2
+//! max_violations = 100
+//! violation_window = "10s"
+//! ban_duration = "60s"
+//!
+//! [quota]
+//! max_messages = 0
+//! max_bytes = 0
+//! period = "daily"
+//!
+//! [federation]
+//! name = "eu-1"
+//! secret = "shared-by-the-peers"
+//! peers = "10.0.0.2:5000,10.0.0.3:5000"
+//!
+//! [cluster]
+//! enabled = false
+//! virtual_nodes = 64
+//! ```
+
+use std::fmt::Display;
+use std::fs::read_to_string;
+use std::io::Error;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::LevelFilter;
+
+use super::auth::{DuplicatePolicy, OWNER_MAX_IDLE};
+use super::cluster::DEFAULT_VIRTUAL_NODES;
+use super::queue::BackpressurePolicy;
+use super::quota::{Quota, QuotaPeriod};
+use super::ratelimit::RateLimit;
+use super::ServerBuilder;
+use super::{DEFAULT_DRAIN_TIMEOUT, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_PENDING_CONNECTIONS, DEFAULT_QUEUE_CAPACITY};
+
+
+/// Prefix of the environment variables overriding the configuration
+pub const ENV_PREFIX:&str = "RAW_";
+
+/// Largest body size a configuration may allow, in bytes
+const MAX_BODY_SIZE_LIMIT:usize = 64*1024*1024;
+
+/// Every key of the configuration, as `<section>.<key>`
+const KEYS:[&str;29] = [
+     "server.bind", "server.async", "server.worker_threads", "server.admin_secret", "server.require_secret", "server.owner_max_idle",
+     "limits.queue_capacity", "limits.max_body_size", "limits.max_connections", "limits.max_pending_connections",
+     "timeouts.handshake", "timeouts.drain",
+     "logging.level",
+     "policies.duplicate", "policies.backpressure",
+     "rate_limit.messages_per_second", "rate_limit.bytes_per_second", "rate_limit.burst_seconds",
+     "rate_limit.max_violations", "rate_limit.violation_window", "rate_limit.ban_duration",
+     "quota.max_messages", "quota.max_bytes", "quota.period",
+     "federation.name", "federation.secret", "federation.peers",
+     "cluster.enabled", "cluster.virtual_nodes"
+];
+
+/// A struct representing the configuration of a server, read from a file and the environment
+///
+/// # Fields
+///
+/// - `bind`: The `host:port` the server binds on
+/// - `asynchronous`: Runs the async server core, requires the `async` feature. It only serves SEND and RECEIVE clients
+/// - `worker_threads`: The number of threads of the async server, defaults to the number of cores. The blocking server
+///   starts up to `max_connections` workers instead
+/// - `admin_secret`: The secret admin clients authenticate with
+/// - `require_secret`: When true, aliases cannot be claimed without a secret, true by default
+/// - `owner_max_idle`: The time after which the owner an alias was bound to on first use is released if it has not claimed it since
+/// - `queue_capacity`: The maximum number of messages queued for each receive client
+/// - `max_body_size`: The maximum size of the body of a message
+/// - `max_connections`: The maximum number of connections handled at once, defaults to the limit of the server core.
+///   The blocking server takes two workers for each receive and presence connection, which counts twice
+/// - `max_pending_connections`: The maximum number of connections waiting for a worker
+/// - `handshake_timeout`: The time a client is given to send its handshake
+/// - `drain_timeout`: The time given to the receive clients to be delivered their queued messages on shutdown
+/// - `log_level`: The maximum level of the logs
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered twice
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the delivery queue of a receive client is full
+/// - `rate_limit`: The [RateLimit] enforced on send clients
+/// - `quota`: The [Quota] enforced on every alias
+/// - `federation_name`: The name the server is known by to its peers
+/// - `federation_secret`: The secret shared by the federated servers, the server is federated once it is set
+/// - `peers`: The `host:port` addresses of the peers the server dials
+/// - `cluster`: When true, the federated server is a node of a cluster owning the aliases hashed to it
+/// - `virtual_nodes`: The number of points each node is placed at on the ring of the cluster
+#[derive(Debug, Clone)]
+pub struct ServerConfig{
+     pub bind:String,
+     pub asynchronous:bool,
+     pub worker_threads:Option<usize>,
+     pub admin_secret:Option<String>,
+     pub require_secret:bool,
+     pub owner_max_idle:Duration,
+     pub queue_capacity:usize,
+     pub max_body_size:usize,
+     pub max_connections:Option<usize>,
+     pub max_pending_connections:usize,
+     pub handshake_timeout:Duration,
+     pub drain_timeout:Duration,
+     pub log_level:LevelFilter,
+     pub duplicate_policy:DuplicatePolicy,
+     pub backpressure_policy:BackpressurePolicy,
+     pub rate_limit:RateLimit,
+     pub quota:Quota,
+     pub federation_name:Option<String>,
+     pub federation_secret:Option<String>,
+     pub peers:Vec<String>,
+     pub cluster:bool,
+     pub virtual_nodes:usize
+}
+
+/// An enum representing the errors of a configuration that could not be loaded
+///
+/// # Variants
+///
+/// - `Io`: The configuration file could not be read
+/// - `Syntax`: A line of the configuration file could not be parsed
+/// - `UnknownKey`: A key that is not part of the configuration
+/// - `InvalidValue`: The value of a key could not be parsed or is out of range
+#[derive(Debug)]
+pub enum ConfigError{
+     Io(String, Error),
+     Syntax(usize, String),
+     UnknownKey(String),
+     InvalidValue(String, String)
+}
+
+impl ServerConfig{
+     /// Reads the configuration file at `path` over the defaults
+     pub fn load(path:&str)->Result<Self, ConfigError>{
+          let text = read_to_string(path).map_err(|e|ConfigError::Io(path.to_string(), e))?;
+          let mut config = ServerConfig::default();
+          config.merge_str(&text)?;
+          Ok(config)
+     }
+
+     /// Applies the `key = value` lines of a configuration file
+     pub fn merge_str(&mut self, text:&str)->Result<(), ConfigError>{
+          let mut section = String::new();
+          for (n, line) in text.lines().enumerate(){
+               let line = strip_comment(line).trim();
+               if line.is_empty(){
+                    continue;
+               }
+
+               if let Some(header) = line.strip_prefix('[').and_then(|l|l.strip_suffix(']')){
+                    section = header.trim().to_string();
+                    continue;
+               }
+
+               let (key, value) = match line.split_once('='){
+                    Some((key, value))=>(key.trim(), value.trim()),
+                    None=>return Err(ConfigError::Syntax(n+1, format!("expected `key = value` or `[section]`, found `{line}`")))
+               };
+               let value = unquote(value).ok_or(ConfigError::Syntax(n+1, format!("unterminated string `{value}`")))?;
+               let key = if section.is_empty(){ key.to_string() }else{ format!("{section}.{key}") };
+               self.set(&key, &value)?;
+          }
+          Ok(())
+     }
+
+     /// Applies the `RAW_<SECTION>_<KEY>` variables found by `lookup`, eg.. [std::env::var]
+     pub fn merge_env<F:Fn(&str)->Option<String>>(&mut self, lookup:F)->Result<(), ConfigError>{
+          for key in KEYS{
+               let var = format!("{ENV_PREFIX}{}", key.replace('.', "_").to_uppercase());
+               if let Some(value) = lookup(&var){
+                    self.set(key, &value)?;
+               }
+          }
+          Ok(())
+     }
+
+     /// Sets a key of the configuration, `<section>.<key>`
+     pub fn set(&mut self, key:&str, value:&str)->Result<(), ConfigError>{
+          match key{
+               "server.bind"=>self.bind = value.to_string(),
+               "server.async"=>self.asynchronous = parse(key, value)?,
+               "server.worker_threads"=>self.worker_threads = Some(parse(key, value)?),
+               "server.admin_secret"=>self.admin_secret = Some(value.to_string()),
+               "server.require_secret"=>self.require_secret = parse(key, value)?,
+               "server.owner_max_idle"=>self.owner_max_idle = parse_duration(key, value)?,
+               "limits.queue_capacity"=>self.queue_capacity = parse(key, value)?,
+               "limits.max_body_size"=>self.max_body_size = parse(key, value)?,
+               "limits.max_connections"=>self.max_connections = Some(parse(key, value)?),
+               "limits.max_pending_connections"=>self.max_pending_connections = parse(key, value)?,
+               "timeouts.handshake"=>self.handshake_timeout = parse_duration(key, value)?,
+               "timeouts.drain"=>self.drain_timeout = parse_duration(key, value)?,
+               "logging.level"=>self.log_level = parse(key, value)?,
+               "policies.duplicate"=>self.duplicate_policy = parse(key, value)?,
+               "policies.backpressure"=>self.backpressure_policy = parse(key, value)?,
+               "rate_limit.messages_per_second"=>self.rate_limit.messages_per_second = parse(key, value)?,
+               "rate_limit.bytes_per_second"=>self.rate_limit.bytes_per_second = parse(key, value)?,
+               "rate_limit.burst_seconds"=>self.rate_limit.burst_seconds = parse(key, value)?,
+               "rate_limit.max_violations"=>self.rate_limit.max_violations = parse(key, value)?,
+               "rate_limit.violation_window"=>self.rate_limit.violation_window = parse_duration(key, value)?,
+               "rate_limit.ban_duration"=>self.rate_limit.ban_duration = parse_duration(key, value)?,
+               "quota.max_messages"=>self.quota.max_messages = parse(key, value)?,
+               "quota.max_bytes"=>self.quota.max_bytes = parse(key, value)?,
+               "quota.period"=>self.quota.period = match value{
+                    "daily"=>QuotaPeriod::Daily,
+                    _=>QuotaPeriod::Rolling(parse_duration(key, value)?)
+               },
+               "federation.name"=>self.federation_name = Some(value.to_string()),
+               "federation.secret"=>self.federation_secret = Some(value.to_string()),
+               "federation.peers"=>self.peers = split_list(value),
+               "cluster.enabled"=>self.cluster = parse(key, value)?,
+               "cluster.virtual_nodes"=>self.virtual_nodes = parse(key, value)?,
+               _=>return Err(ConfigError::UnknownKey(key.to_string()))
+          }
+          Ok(())
+     }
+
+     /// Checks that the values of the configuration can be served with
+     pub fn validate(&self)->Result<(), ConfigError>{
+          let invalid = |key:&str, reason:&str|Err(ConfigError::InvalidValue(key.to_string(), reason.to_string()));
+
+          //the async server only serves send and receive clients, the settings of the other clients are refused
+          if self.asynchronous{
+               let unsupported = [
+                    ("server.admin_secret", self.admin_secret.is_some()),
+                    ("federation.name", self.federation_name.is_some()),
+                    ("federation.secret", self.federation_secret.is_some()),
+                    ("federation.peers", !self.peers.is_empty()),
+                    ("cluster.enabled", self.cluster)
+               ];
+               if let Some((key, _)) = unsupported.iter().find(|(_, set)|*set){
+                    return invalid(key, "is only served by the blocking server, the async server serves SEND and RECEIVE clients");
+               }
+          }
+
+          match self.bind.rsplit_once(':'){
+               Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok()=>(),
+               _=>return invalid("server.bind", &format!("'{}' is not a <host>:<port> address", self.bind))
+          }
+          if self.addrs()?.is_empty(){
+               return invalid("server.bind", &format!("'{}' resolved to no address", self.bind));
+          }
+          if self.worker_threads==Some(0){
+               return invalid("server.worker_threads", "must be at least 1");
+          }
+          if matches!(&self.admin_secret, Some(secret) if secret.is_empty()){
+               return invalid("server.admin_secret", "must not be empty");
+          }
+          if self.owner_max_idle.is_zero(){
+               return invalid("server.owner_max_idle", "must be greater than 0");
+          }
+          if self.queue_capacity==0{
+               return invalid("limits.queue_capacity", "must be at least 1");
+          }
+          if self.max_body_size==0 || self.max_body_size>MAX_BODY_SIZE_LIMIT{
+               return invalid("limits.max_body_size", &format!("must be between 1 and {MAX_BODY_SIZE_LIMIT}"));
+          }
+          if self.max_connections==Some(0){
+               return invalid("limits.max_connections", "must be at least 1");
+          }
+          if self.handshake_timeout.is_zero(){
+               return invalid("timeouts.handshake", "must be greater than 0");
+          }
+          if matches!(self.quota.period, QuotaPeriod::Rolling(window) if window.is_zero()){
+               return invalid("quota.period", "must be `daily` or a duration greater than 0");
+          }
+          match (&self.federation_name, &self.federation_secret){
+               (_, Some(secret)) if secret.is_empty()=>return invalid("federation.secret", "must not be empty"),
+               (None, Some(_))=>return invalid("federation.name", "must be set to federate the server"),
+               (Some(name), _) if name.is_empty() || name.contains([';', ',', '\n'])=>{
+                    return invalid("federation.name", "must not be empty, nor contain ';', ',' or a new line");
+               },
+               (_, None) if !self.peers.is_empty()=>return invalid("federation.secret", "must be set to dial peers"),
+               _=>()
+          }
+          if self.cluster && self.federation_secret.is_none(){
+               return invalid("cluster.enabled", "the nodes of a cluster are federated, federation.secret must be set");
+          }
+          if self.virtual_nodes==0{
+               return invalid("cluster.virtual_nodes", "must be at least 1");
+          }
+          Ok(())
+     }
+
+     /// Resolves the bind address, a host name may resolve to several addresses
+     pub fn addrs(&self)->Result<Vec<SocketAddr>, ConfigError>{
+          match self.bind.to_socket_addrs(){
+               Ok(addrs)=>Ok(addrs.collect()),
+               Err(e)=>Err(ConfigError::InvalidValue("server.bind".to_string(), format!("could not resolve '{}', {e}", self.bind)))
+          }
+     }
+
+     /// Returns a [ServerBuilder] with the addresses and options of the configuration
+     pub fn builder(&self)->Result<ServerBuilder, ConfigError>{
+          let mut builder = ServerBuilder::new()
+               .addrs(self.addrs()?)
+               .queue_capacity(self.queue_capacity)
+               .max_body_size(self.max_body_size)
+               .connection_limits(self.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS), self.max_pending_connections)
+               .handshake_timeout(self.handshake_timeout)
+               .drain_timeout(self.drain_timeout)
+               .duplicate_policy(self.duplicate_policy)
+               .backpressure_policy(self.backpressure_policy)
+               .rate_limit(self.rate_limit.clone())
+               .quota(self.quota.clone())
+               .require_secret(self.require_secret)
+               .owner_max_idle(self.owner_max_idle);
+          if let Some(secret) = &self.admin_secret{
+               builder = builder.admin_secret(secret.clone());
+          }
+          if let (Some(name), Some(secret)) = (&self.federation_name, &self.federation_secret){
+               builder = builder.federation(name.clone(), secret.clone());
+          }
+          for peer in &self.peers{
+               builder = builder.peer(peer.clone());
+          }
+          if self.cluster{
+               builder = builder.cluster(self.virtual_nodes);
+          }
+          Ok(builder)
+     }
+
+     /// Returns an [super::aio::AsyncServer] with the addresses and options of the configuration
+     #[cfg(feature="async")]
+     pub fn async_server(&self)->Result<super::aio::AsyncServer, ConfigError>{
+          let mut addrs = self.addrs()?.into_iter();
+          let mut server = match addrs.next(){
+               Some(addr)=>super::aio::AsyncServer::new(addr),
+               None=>return Err(ConfigError::InvalidValue("server.bind".to_string(), format!("'{}' resolved to no address", self.bind)))
+          };
+          for addr in addrs{
+               server.add_addr(addr);
+          }
+          server.set_queue_capacity(self.queue_capacity);
+          server.set_max_body_size(self.max_body_size);
+          if let Some(max_connections) = self.max_connections{
+               server.set_max_connections(max_connections);
+          }
+          if let Some(worker_threads) = self.worker_threads{
+               server.set_worker_threads(worker_threads);
+          }
+          server.set_handshake_timeout(self.handshake_timeout);
+          server.set_drain_timeout(self.drain_timeout);
+          server.set_duplicate_policy(self.duplicate_policy);
+          server.set_backpressure_policy(self.backpressure_policy);
+          server.set_rate_limit(self.rate_limit.clone());
+          server.set_quota(self.quota.clone());
+          server.get_ownership().lock().unwrap().set_require_secret(self.require_secret);
+          server.get_ownership().lock().unwrap().set_max_idle(self.owner_max_idle);
+          Ok(server)
+     }
+}
+
+/// Default implementation for ServerConfig, the defaults of the server
+impl Default for ServerConfig{
+     fn default() -> Self {
+          ServerConfig{
+               bind:"localhost:5000".to_string(),
+               asynchronous:false,
+               worker_threads:None,
+               admin_secret:None,
+               require_secret:true,
+               owner_max_idle:OWNER_MAX_IDLE,
+               queue_capacity:DEFAULT_QUEUE_CAPACITY,
+               max_body_size:DEFAULT_MAX_BODY_SIZE,
+               max_connections:None,
+               max_pending_connections:DEFAULT_MAX_PENDING_CONNECTIONS,
+               handshake_timeout:DEFAULT_HANDSHAKE_TIMEOUT,
+               drain_timeout:DEFAULT_DRAIN_TIMEOUT,
+               log_level:LevelFilter::Info,
+               duplicate_policy:DuplicatePolicy::default(),
+               backpressure_policy:BackpressurePolicy::default(),
+               rate_limit:RateLimit::default(),
+               quota:Quota::default(),
+               federation_name:None,
+               federation_secret:None,
+               peers:Vec::new(),
+               cluster:false,
+               virtual_nodes:DEFAULT_VIRTUAL_NODES
+          }
+     }
+}
+
+/// Removes a `#` comment that is not inside a string
+fn strip_comment(line:&str)->&str{
+     let mut quoted = false;
+     for (i, c) in line.char_indices(){
+          match c{
+               '"'=>quoted = !quoted,
+               '#' if !quoted=>return &line[..i],
+               _=>()
+          }
+     }
+     line
+}
+
+/// Removes the quotes around a string value, bare values are kept as is
+fn unquote(value:&str)->Option<String>{
+     match value.strip_prefix('"'){
+          None=>Some(value.to_string()),
+          Some(rest)=>rest.strip_suffix('"').map(|s|s.replace("\\\"", "\"").replace("\\\\", "\\"))
+     }
+}
+
+/// Splits a comma separated list, eg.. the addresses of the peers
+pub fn split_list(value:&str)->Vec<String>{
+     value.split(',').map(|v|v.trim()).filter(|v|!v.is_empty()).map(|v|v.to_string()).collect()
+}
+
+fn parse<T:FromStr>(key:&str, value:&str)->Result<T, ConfigError> where T::Err:Display{
+     value.parse::<T>().map_err(|e|ConfigError::InvalidValue(key.to_string(), format!("could not parse '{value}', {e}")))
+}
+
+/// Parses a duration written `500ms`, `10s`, `5m`, `1h`, or a number of seconds
+fn parse_duration(key:&str, value:&str)->Result<Duration, ConfigError>{
+     let (number, unit) = value.find(|c:char|!c.is_ascii_digit()).map(|i|value.split_at(i)).unwrap_or((value, "s"));
+     let number:u64 = parse(key, number)?;
+     let seconds = |per_unit:u64|number.checked_mul(per_unit).map(Duration::from_secs)
+          .ok_or(ConfigError::InvalidValue(key.to_string(), format!("'{value}' is too long a duration")));
+     match unit.trim(){
+          "ms"=>Ok(Duration::from_millis(number)),
+          "s"=>seconds(1),
+          "m"=>seconds(60),
+          "h"=>seconds(3600),
+          _=>Err(ConfigError::InvalidValue(key.to_string(), format!("'{value}' is not a duration, eg.. 500ms, 10s, 5m or 1h")))
+     }
+}
+
+/// Display implementation for ConfigError
+impl Display for ConfigError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self{
+               Self::Io(path, e)=>write!(f, "{{ error: ConfigIo; info: {}: {} }}", path, e),
+               Self::Syntax(line, info)=>write!(f, "{{ error: ConfigSyntax; info: line {}: {} }}", line, info),
+               Self::UnknownKey(key)=>write!(f, "{{ error: UnknownKey; info: {} }}", key),
+               Self::InvalidValue(key, info)=>write!(f, "{{ error: InvalidValue; info: {}: {} }}", key, info)
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::collections::HashMap;
+
+     fn invalid_key(result:Result<(), ConfigError>)->String{
+          match result{
+               Err(ConfigError::InvalidValue(key, _))=>key,
+               other=>panic!("expected an invalid value, got {other:?}")
+          }
+     }
+
+     #[test]
+     fn parses_sections_comments_and_quoted_strings(){
+          let mut config = ServerConfig::default();
+          config.merge_str(r#"
+               # the defaults are kept for the keys left out
+               [server]
+               bind = "127.0.0.1:6000"   # a trailing comment
+               admin_secret = "a#b\"c"
+
+               [limits]
+               queue_capacity = 16
+
+               [timeouts]
+               handshake = "500ms"
+               drain = 3
+
+               [quota]
+               period = "5m"
+          "#).unwrap();
+          assert_eq!(config.bind, "127.0.0.1:6000");
+          assert_eq!(config.admin_secret.as_deref(), Some("a#b\"c"));
+          assert_eq!(config.queue_capacity, 16);
+          assert_eq!(config.handshake_timeout, Duration::from_millis(500));
+          assert_eq!(config.drain_timeout, Duration::from_secs(3));
+          assert!(matches!(config.quota.period, QuotaPeriod::Rolling(window) if window==Duration::from_secs(300)));
+          assert_eq!(config.max_body_size, DEFAULT_MAX_BODY_SIZE);
+     }
+
+     #[test]
+     fn refuses_malformed_lines_and_unknown_keys(){
+          let mut config = ServerConfig::default();
+          assert!(matches!(config.merge_str("[server]\nbind"), Err(ConfigError::Syntax(2, _))));
+          assert!(matches!(config.merge_str("bind = \"open"), Err(ConfigError::Syntax(1, _))));
+          assert!(matches!(config.merge_str("[server]\nport = 1"), Err(ConfigError::UnknownKey(key)) if key=="server.port"));
+          assert_eq!(invalid_key(config.merge_str("[limits]\nqueue_capacity = many")), "limits.queue_capacity");
+     }
+
+     #[test]
+     fn parses_durations(){
+          assert_eq!(parse_duration("k", "250ms").unwrap(), Duration::from_millis(250));
+          assert_eq!(parse_duration("k", "10").unwrap(), Duration::from_secs(10));
+          assert_eq!(parse_duration("k", "2m").unwrap(), Duration::from_secs(120));
+          assert_eq!(parse_duration("k", "720h").unwrap(), Duration::from_secs(720*3600));
+          assert!(matches!(parse_duration("k", "1d"), Err(ConfigError::InvalidValue(..))));
+          assert!(matches!(parse_duration("k", "-1s"), Err(ConfigError::InvalidValue(..))));
+          //a duration overflowing its number of seconds is refused rather than wrapped
+          assert!(matches!(parse_duration("k", &format!("{}h", u64::MAX/3600+1)), Err(ConfigError::InvalidValue(..))));
+          assert!(matches!(parse_duration("k", &format!("{}m", u64::MAX)), Err(ConfigError::InvalidValue(..))));
+     }
+
+     #[test]
+     fn overrides_the_file_with_the_environment(){
+          let env = HashMap::from([
+               ("RAW_LIMITS_QUEUE_CAPACITY", "256"),
+               ("RAW_SERVER_REQUIRE_SECRET", "false"),
+               ("RAW_POLICIES_DUPLICATE", "coexist")
+          ]);
+          let mut config = ServerConfig::default();
+          config.merge_str("[limits]\nqueue_capacity = 16").unwrap();
+          config.merge_env(|var|env.get(var).map(|v|v.to_string())).unwrap();
+          assert_eq!(config.queue_capacity, 256);
+          assert!(!config.require_secret);
+          assert_eq!(config.duplicate_policy, DuplicatePolicy::Coexist);
+
+          let env = HashMap::from([("RAW_TIMEOUTS_DRAIN", "soon")]);
+          assert_eq!(invalid_key(config.merge_env(|var|env.get(var).map(|v|v.to_string()))), "timeouts.drain");
+     }
+
+     #[test]
+     fn validates_the_values(){
+          let valid = ServerConfig{ bind:"127.0.0.1:5000".to_string(), ..ServerConfig::default() };
+          assert!(valid.validate().is_ok());
+
+          let refused = |change:fn(&mut ServerConfig)|{
+               let mut config = valid.clone();
+               change(&mut config);
+               invalid_key(config.validate())
+          };
+          assert_eq!(refused(|c|c.bind = "127.0.0.1".to_string()), "server.bind");
+          assert_eq!(refused(|c|c.worker_threads = Some(0)), "server.worker_threads");
+          assert_eq!(refused(|c|c.queue_capacity = 0), "limits.queue_capacity");
+          assert_eq!(refused(|c|c.max_body_size = MAX_BODY_SIZE_LIMIT+1), "limits.max_body_size");
+          assert_eq!(refused(|c|c.handshake_timeout = Duration::ZERO), "timeouts.handshake");
+          //the async server only serves send and receive clients
+          assert_eq!(refused(|c|{
+               c.asynchronous = true;
+               c.admin_secret = Some("s".to_string());
+          }), "server.admin_secret");
+     }
+}
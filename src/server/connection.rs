@@ -0,0 +1,370 @@
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use super::auth::{constant_time_eq, AliasOwnership, DuplicatePolicy};
+use super::delivery::{admit_receiver, claim_alias, hand_pending, redeliver_pending, replaced_notice};
+use super::container::{ClientReceiverContainer, ClientSenderContainer, DisconnectReason};
+use super::federation::Federation;
+use super::handler::{default_new, StreamHandler, TransmitService};
+use super::mailbox::Mailbox;
+use super::pool::{TaskHandle, WorkerPool};
+use super::presence::PresenceRegistry;
+use super::protocol::frame::{FrameError, FrameReader, write_frame};
+use super::protocol::pto::BaseProto;
+use super::protocol::res::{Response, Status};
+use super::protocol::{get_handshake_for_raw_utf8, BaseProtocol, Handshake, MAX_HEADER_SIZE};
+use super::queue::{bounded, BackpressurePolicy};
+use super::quota::QuotaTracker;
+use super::ratelimit::RateLimiter;
+use super::registry::{ReceiverRegistry, SenderRegistry};
+use super::shutdown::ShutdownHandle;
+
+
+/// A struct representing the state of the [crate::server::Server] a connection is established against.
+/// It is handed to the worker of each accepted connection, which reads the handshake and registers the client
+/// so that a slow or silent client never holds up the accept loop
+///
+/// # Fields
+///
+/// - `send_container_pool`: The [SenderRegistry] the send, presence and admin clients are registered in
+/// - `receive_container_pool`: The [ReceiverRegistry] the receive clients are registered in
+/// - `presence`: The [PresenceRegistry] tracking the online status of the aliases
+/// - `ownership`: The [AliasOwnership] deciding which client may claim an alias
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+/// - `queue_capacity`: The maximum number of messages queued for each receive client
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the delivery queue of a receive client is full
+/// - `rate_limiter`: The [RateLimiter] enforced on send clients
+/// - `quotas`: The [QuotaTracker] counting the messages and bytes sent by each alias
+/// - `max_body_size`: The maximum size of the body of a message
+/// - `admin_secret`: The secret admin clients authenticate with
+/// - `mailbox`: The [Mailbox] holding the messages of disconnected receive clients
+/// - `shutdown`: The [ShutdownHandle] of the server, connections completing their handshake after it is triggered are refused
+/// - `pool`: The [WorkerPool] of the server, reported to admin clients
+/// - `handshake_timeout`: The time a client is given to send its handshake
+/// - `federation`: The [Federation] of the server, peer links are handed over to it
+#[derive(Clone)]
+pub struct ConnectionContext{
+     pub send_container_pool:Arc<SenderRegistry>,
+     pub receive_container_pool:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>>,
+     pub presence:Arc<Mutex<PresenceRegistry>>,
+     pub ownership:Arc<Mutex<AliasOwnership>>,
+     pub duplicate_policy:DuplicatePolicy,
+     pub queue_capacity:usize,
+     pub backpressure_policy:BackpressurePolicy,
+     pub rate_limiter:Arc<Mutex<RateLimiter>>,
+     pub quotas:Arc<Mutex<QuotaTracker>>,
+     pub max_body_size:usize,
+     pub admin_secret:Option<String>,
+     pub mailbox:Arc<Mutex<Mailbox<BaseProto>>>,
+     pub shutdown:ShutdownHandle,
+     pub pool:WorkerPool,
+     pub handshake_timeout:Duration,
+     pub federation:Federation
+}
+
+/// Counts a connection as handshaking until dropped, whether the connection was registered, refused or never run
+pub struct Handshaking(Arc<AtomicUsize>);
+
+/// Reads from a stream until a deadline, each read is given the time left before it
+struct DeadlineReader<'a>{
+     stream:&'a mut TcpStream,
+     deadline:Instant
+}
+
+impl ConnectionContext{
+     /// Establishes an accepted connection on its worker: reads the handshake within the handshake timeout,
+     /// authenticates and registers the client, then runs its handler until it disconnects.
+     /// Clients that fail or time out their handshake are sent an error response and closed
+     ///
+     /// # Arguments
+     /// - `key`: The id of the connection
+     /// - `task`: Receives the [TaskHandle] of the job running this connection, stored in its container
+     /// - `handshaking`: Counts the connection as handshaking until it is registered
+     pub fn establish(self, mut stream:TcpStream, addr:SocketAddr, key:u64, task:Receiver<TaskHandle>, handshaking:Handshaking){
+          //the reader is handed over to the handler with any bytes read past the handshake
+          let mut reader = FrameReader::new(MAX_HEADER_SIZE);
+          let handshake = match self.read_handshake(&mut stream, &mut reader, addr){
+               None=>return,
+               Some(t)=>t
+          };
+          let client_service = handshake.get_service().clone();
+
+          //refusing the connections completing their handshake once the server is shutting down
+          if self.shutdown.is_requested(){
+               let res = Response::generate_res(Status::ShuttingDown, "Server is shutting down".to_string());
+               let _ = write_frame(&mut stream, res.as_bytes());
+               return;
+          }
+
+          //claiming the alias of receive and presence clients, and of send clients giving a secret
+          let claimed = handshake.get_secret().is_some();
+          if let Err(e) = claim_alias(&self.ownership, &handshake){
+               warn!("Refused incoming request from {addr} -- {}", e);
+               let res = Response::generate_res(Status::Unauthorized, e.to_string());
+               let _ = write_frame(&mut stream, res.as_bytes());
+               return;
+          }
+
+          //authenticating admin clients
+          if let TransmitService::Admin(name) = &client_service{
+               let authorized = match (&self.admin_secret, handshake.get_secret()){
+                    (Some(secret), Some(s))=>constant_time_eq(secret.as_bytes(), s.as_bytes()),
+                    _=>false
+               };
+               if !authorized{
+                    warn!("Refused admin request from {addr} -- {{ name: {} }}", name);
+                    let res = Response::generate_res(Status::Unauthorized, "Invalid admin secret".to_string());
+                    let _ = write_frame(&mut stream, res.as_bytes());
+                    return;
+               }
+          }
+
+          //authenticating federated peers
+          if let TransmitService::Peer(name) = &client_service{
+               if let Err((status, reason)) = self.federation.authenticate(name, handshake.get_secret()){
+                    warn!("Refused peer request from {addr} -- {{ name: {} }} {}", name, reason);
+                    let res = Response::generate_res(status, reason);
+                    let _ = write_frame(&mut stream, res.as_bytes());
+                    return;
+               }
+          }
+
+          //receive and presence clients run a second job on a worker of its own, watching their stream or notifying them.
+          //It is reserved before the duplicate policy applies, a busy server does not replace a session it cannot handle
+          let helper = match &client_service{
+               TransmitService::Receive(_) | TransmitService::Presence(_)=>match self.pool.reserve(){
+                    Some(slot)=>Some(slot),
+                    None=>{
+                         warn!("Refused incoming request from {addr} -- worker pool is full {{ {} }}", self.pool.metrics());
+                         let res = Response::generate_res(Status::Busy, "The server is busy, try again later".to_string());
+                         let _ = write_frame(&mut stream, res.as_bytes());
+                         return;
+                    }
+               },
+               _=>None
+          };
+
+          //applying the duplicate policy to receive clients
+          if let TransmitService::Receive(alias) = &client_service{
+               if !self.apply_duplicate_policy(alias, claimed, &mut stream){
+                    warn!("Refused incoming request from {addr} -- {{ alias: {}; policy: {} }}", alias, self.duplicate_policy);
+                    return;
+               }
+          }
+
+          //handing the link of a federated peer over to the federation, answering with the name of this server
+          if let TransmitService::Peer(name) = client_service{
+               let res = Response::generate_res(Status::Success, self.federation.get_name().clone());
+               if let Err(e) = write_frame(&mut stream, res.as_bytes()){
+                    error!("Could not answer the handshake of {{ peer: {} }} {}", name, e);
+                    return;
+               }
+               drop(handshaking);
+               info!("Accepted incoming request from {addr} -- {{ id: {}; peer: {} }}", key, name);
+               self.federation.run_link(name, stream, reader);
+               return;
+          }
+
+          //handle kept in the container to disconnect the client
+          let stream_handle = stream.try_clone().ok();
+
+          //handler creation to handle the incoming stream
+          let mut handler:StreamHandler<BaseProtocol> = match default_new(stream, client_service.clone()){
+               Ok(e)=>e,
+               Err(e)=>{
+                    error!("Could not initialize stream handler due to... {}", e);
+                    return;
+               }
+          };
+          handler.set_max_body_size(self.max_body_size);
+          handler.set_frame_reader(reader);
+
+          //the handle is sent right after the job is executed
+          let thread_handle = match task.recv(){
+               Ok(handle)=>handle,
+               Err(_)=>return
+          };
+
+          match client_service{
+               TransmitService::Receive(alias)=>{
+                    let Some(watcher) = helper else { return };
+                    //bounded delivery queue creation to communicate between streams in different thread
+                    let (sender, receiver) = bounded(self.queue_capacity, self.backpressure_policy);
+
+                    //marking the alias online, and offline once its handler exits
+                    self.presence.lock().unwrap().set_online(&alias);
+                    //delivering the messages re-queued while the alias was disconnected,
+                    //after the messages written since the last message a resuming client was delivered
+                    hand_pending(&sender, &alias, handshake.get_resume(), &self.mailbox);
+
+                    self.receive_container_pool.register_with(&alias, ||{
+                         ClientReceiverContainer::new(thread_handle, sender, key, alias.clone(), stream_handle)
+                    });
+                    drop(handshaking);
+                    info!("Accepted incoming request from {addr} -- {{ id: {}; receive_alias: {} }}", key, alias);
+                    self.federation.announce(&alias);
+
+                    let reason = handler.handle_client_receive(receiver, self.mailbox.clone(), watcher);
+                    //deregistering the container of this connection
+                    self.receive_container_pool.deregister(&alias, key);
+                    self.presence.lock().unwrap().set_offline(&alias);
+                    self.federation.announce(&alias);
+                    info!("Disconnected -- {{ id: {}; receive_alias: {}; reason: {} }}", key, alias, reason);
+                    //a client closing its connection does not resume, the journal is dropped once the last session of the alias left
+                    if matches!(reason, DisconnectReason::Closed) && !self.receive_container_pool.contains(&alias){
+                         self.mailbox.lock().unwrap().forget(&alias);
+                    }
+                    //handing the re-queued messages to a remaining session of the alias, or to the node owning the alias
+                    redeliver_pending(&alias, &self.receive_container_pool, &self.mailbox);
+                    if self.mailbox.lock().unwrap().len(&alias)>0{
+                         self.federation.rebalance();
+                    }
+               },
+               TransmitService::Send(from)=>{
+                    self.send_container_pool.register_with(||{
+                         ClientSenderContainer::new(thread_handle, key, from.clone(), stream_handle)
+                    });
+                    drop(handshaking);
+                    info!("Accepted incoming request from {addr} -- {{ id: {}; from_alias: {} }}", key, from);
+
+                    let sender = claimed.then(||from.clone());
+                    let reason = handler.handle_client_send(self.receive_container_pool.clone(), self.rate_limiter.clone(), self.quotas.clone(), self.mailbox.clone(), self.federation.clone(), sender);
+                    //deregistering the container of this connection
+                    self.send_container_pool.deregister(key);
+                    info!("Disconnected -- {{ id: {}; from_alias: {}; reason: {} }}", key, from, reason);
+               },
+               TransmitService::Presence(s)=>{
+                    let Some(notifier) = helper else { return };
+                    //registered with the send clients, to be sent ShuttingDown and joined on shutdown
+                    self.send_container_pool.register_with(||{
+                         let mut container = ClientSenderContainer::new(thread_handle, key, s.clone(), stream_handle);
+                         container.set_service("PRESENCE");
+                         container
+                    });
+                    drop(handshaking);
+                    info!("Accepted incoming request from {addr} -- {{ id: {}; presence_alias: {} }}", key, s);
+                    if let Err(e) = handler.handle_client_presence(self.presence.clone(), key, claimed, notifier){
+                         error!("Presence handler exited with an error {}", e);
+                    }
+                    self.send_container_pool.deregister(key);
+               },
+               TransmitService::Admin(s)=>{
+                    self.send_container_pool.register_with(||{
+                         let mut container = ClientSenderContainer::new(thread_handle, key, s.clone(), stream_handle);
+                         container.set_service("ADMIN");
+                         container
+                    });
+                    drop(handshaking);
+                    info!("Accepted incoming request from {addr} -- {{ id: {}; admin: {} }}", key, s);
+                    if let Err(e) = handler.handle_client_admin(self.quotas.clone(), self.rate_limiter.clone(), self.pool.clone(), self.federation.clone(), self.ownership.clone()){
+                         error!("Admin handler exited with an error {}", e);
+                    }
+                    self.send_container_pool.deregister(key);
+               },
+               //handed over to the federation once authenticated
+               TransmitService::Peer(_)=>()
+          }
+     }
+
+     /// Reads the handshake of a client within the handshake timeout, which bounds the whole handshake and not each read.
+     /// Clients sending an invalid handshake, or none in time, are answered with [Status::InvalidIdentifier]
+     fn read_handshake(&self, stream:&mut TcpStream, reader:&mut FrameReader, addr:SocketAddr)->Option<Handshake>{
+          //reading initial handshake frame, a client trickling bytes is still cut off at the deadline
+          let mut timed = DeadlineReader{
+               stream,
+               deadline:Instant::now()+self.handshake_timeout
+          };
+          let read = reader.read_handshake(&mut timed);
+          let stream = timed.stream;
+          let buf = match read{
+               Ok(Some(frame))=>frame,
+               Ok(None)=>return None,
+               Err(FrameError::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)=>{
+                    warn!("Refused incoming request from {addr} -- handshake timed out");
+                    let res = Response::generate_res(Status::InvalidIdentifier, "Handshake timed out".to_string());
+                    let _ = write_frame(stream, res.as_bytes());
+                    return None;
+               },
+               Err(e)=>{
+                    error!("An error occured when type was being extracted from incoming stream {}", e);
+                    let res = Response::generate_res(Status::InvalidIdentifier, e.to_string());
+                    let _ = write_frame(stream, res.as_bytes());
+                    return None;
+               }
+          };
+
+          //the handlers block on their reads once the client is identified
+          if let Err(e) = stream.set_read_timeout(None){
+               error!("Could not clear the handshake timeout of incoming stream from {addr} {}", e);
+               return None;
+          }
+
+          //readining initial handshake request
+          match get_handshake_for_raw_utf8(&buf){
+               Ok(t)=>Some(t),
+               Err(e)=>{
+                    error!("An error occured when type was being extracted from incoming stream {:?}", e);
+                    let res = Response::generate_res(Status::InvalidIdentifier, format!("{e:?}"));
+                    let _ = write_frame(stream, res.as_bytes());
+                    None
+               }
+          }
+     }
+
+     /// Applies the duplicate policy decided by [admit_receiver] for an alias being registered by a receive client,
+     /// answering a refused client and disconnecting the sessions it replaces
+     ///
+     /// # Returns
+     /// - `bool`: true if the registration may proceed
+     fn apply_duplicate_policy(&self, alias:&str, claimed:bool, tcp_stream:&mut TcpStream)->bool{
+          match admit_receiver(&self.receive_container_pool, alias, claimed, self.duplicate_policy){
+               Err(reason)=>{
+                    let res = Response::generate_res(Status::Conflict, reason);
+                    let _ = write_frame(tcp_stream, res.as_bytes());
+                    false
+               },
+               Ok(replaced)=>{
+                    //the replaced sessions are removed before being notified, no lock is held while writing to them
+                    let notice = replaced_notice(alias);
+                    for mut container in replaced{
+                         info!("Disconnecting replaced session {}", container);
+                         container.disconnect(notice.as_bytes());
+                    }
+                    true
+               }
+          }
+     }
+}
+
+impl Handshaking{
+     /// Counts a new connection in `counter`
+     pub fn new(counter:Arc<AtomicUsize>)->Self{
+          counter.fetch_add(1, Ordering::SeqCst);
+          Handshaking(counter)
+     }
+}
+
+/// Read implementation for DeadlineReader, failing with [ErrorKind::TimedOut] once the deadline is reached
+impl Read for DeadlineReader<'_>{
+     fn read(&mut self, buf:&mut [u8])->std::io::Result<usize>{
+          let remaining = self.deadline.saturating_duration_since(Instant::now());
+          if remaining.is_zero(){
+               return Err(std::io::Error::from(ErrorKind::TimedOut));
+          }
+          self.stream.set_read_timeout(Some(remaining))?;
+          self.stream.read(buf)
+     }
+}
+
+/// Drop implementation for Handshaking
+impl Drop for Handshaking{
+     fn drop(&mut self){
+          self.0.fetch_sub(1, Ordering::SeqCst);
+     }
+}
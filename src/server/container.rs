@@ -1,15 +1,34 @@
 use std::fmt::Display;
-use std::sync::mpsc::{Sender, Receiver};
-use std::thread:: JoinHandle;
+use std::net::{Shutdown, TcpStream};
+use super::protocol::frame::write_frame;
+use super::queue::QueueSender;
+use super::pool::TaskHandle;
 
 
+/// An enum representing the reason a handler thread stopped handling its client.
+/// Handlers return it when they exit so that their container can be deregistered and the reason logged
+///
+/// # Variants
+///
+/// - `Closed`: The client closed the stream
+/// - `StreamError`: The stream could not be read from or written to
+/// - `QueueClosed`: The delivery queue was closed, the client was replaced, too slow or disconnected by the server
+/// - `Banned`: The client was banned for exceeding its rate limit
+/// - `ShuttingDown`: The server is shutting down
+#[derive(Debug)]
+pub enum DisconnectReason{
+     Closed,
+     StreamError(String),
+     QueueClosed,
+     Banned,
+     ShuttingDown
+}
+
 /// A struct representing a thread-stream container
 /// contains instance of thread for the handling of incoming stream (listens to data being sent to the server),
 /// and thread handle associated with the handlers of the incoming data
-/// Contains the chanel for the thread which sends data to client receiver thread
 /// 
-/// Defualt channel_rx is None since the client sender attaches send data through the sender in client cnotainer
-/// Multiple sender instances can connect to the sender channel instance.
+/// The client sender sends data through the senders of the client receiver containers it looks up in the registry.
 /// # Diagram
 /// 
 /// ```text
@@ -36,14 +55,17 @@ use std::thread:: JoinHandle;
 ///
 /// - `id`: A unique identifier of a specific container
 /// - `thread_handle`: The thread to handle incoming data from stream
-/// - `channel_rx`: The receiver object of the channel(sender object) initialized in thread created
-/// - `to_alias`: The uniuqe identifier of the client to which the stream this container handles
+/// - `alias`: The alias of the client, the alias a send client sends from
+/// - `stream`: A handle to the client stream, used to stop reading from the client and disconnect it outside of the handling thread
+/// - `service`: The type of the client, SEND by default. PRESENCE and ADMIN clients are only read from as well,
+///   they are registered in the same containers to be disconnected on shutdown
 #[derive(Debug)]
-pub struct  ClientSenderContainer<T>{
+pub struct  ClientSenderContainer{
      id:u64,
-     thread_handle:JoinHandle<()>,   //thread handle for the incoming request listener 
-     channel_rx:Option<Receiver<T>>,
-     to_alias:String
+     thread_handle:TaskHandle,   //thread handle for the incoming request listener 
+     alias:String,
+     stream:Option<TcpStream>,
+     service:&'static str
 }
 
 /// A struct representing a thread-stream container
@@ -69,104 +91,145 @@ pub struct  ClientSenderContainer<T>{
 ///
 /// - `id`: A unique identifier of a specific container
 /// - `thread_handle`: The thread to handle incoming data from stream
-/// - `channel_tx`: The Sender object of the channel(Receiver) initialized and sent to the thread
+/// - `channel_tx`: The QueueSender object of the delivery queue(QueueReceiver) initialized and sent to the thread
 /// - `alias`: The unique identifier of the client stream registered in this container
+/// - `stream`: A handle to the client stream, used to disconnect the client from outside of the handling thread
 #[derive(Debug)]
 pub struct ClientReceiverContainer<T>{
      id:u64,
-     thread_handle:JoinHandle<()>,
-     channel_tx:Option<Sender<T>>,
-     alias:String
+     thread_handle:TaskHandle,
+     channel_tx:Option<QueueSender<T>>,
+     alias:String,
+     stream:Option<TcpStream>
 }
 
-impl <'s,T>ClientReceiverContainer<T> {
+impl <T>ClientReceiverContainer<T> {
      /// Defacult constructor for the ClientReceiverContainer instance
      /// 
      /// # Arguments
      /// 
-     /// * `handle`: JoinHandle<()> of the thread running a handler
-     /// * `channel_sender`: Sender<T> of the channel associated with the Receiver<T> in the executing in the thread
+     /// * `handle`: TaskHandle of the worker job running a handler
+     /// * `channel_sender`: QueueSender<T> of the delivery queue associated with the QueueReceiver<T> in the executing in the thread
      /// * `key`: Unique key for this container instance
      /// * `alias`: The unique identifier of the client
-     pub fn new(handle:JoinHandle<()>, channel_sender:Sender<T>, key:u64, alias:String)->Self{
+     /// * `stream`: A clone of the client stream handled in the thread, if any
+     pub fn new(handle:TaskHandle, channel_sender:QueueSender<T>, key:u64, alias:String, stream:Option<TcpStream>)->Self{
           ClientReceiverContainer{
                id:key,
                thread_handle:handle,
                channel_tx:Some(channel_sender),
-               alias
+               alias,
+               stream
           }
      }
 
+     /// Disconnects the client registered in this container.
+     /// The notice is written to the client before its stream is shut down, and the channel sender is dropped
+     /// so that the handling thread stops awaiting data
+     pub fn disconnect(&mut self, notice:&[u8]){
+          self.channel_tx = None;
+          if let Some(mut stream) = self.stream.take(){
+               let _ = write_frame(&mut stream, notice);
+               let _ = stream.shutdown(Shutdown::Both);
+          }
+     }
+
+     /// Drops the channel sender of this container, the handling thread stops awaiting data once the queued messages are delivered
+     pub fn close_sender(&mut self){
+          self.channel_tx = None;
+     }
+
      /// Destroys the container objec
      /// Do this before thread handle goes out of scope
      pub fn drop(self){
           drop(self);
      }
 
+     /// Returns the thread handle of this container to join it
+     pub fn into_thread_handle(self)->TaskHandle{
+          self.thread_handle
+     }
+
      //----Getters----
      pub fn get_id(&self)->u64{
           self.id
      }
 
-     pub fn get_sender(&self)->Option<Sender<T>>{
-          return match &self.channel_tx{
-               None=>None,
-               Some(channel)=>Some(channel.clone())
-          }
+     pub fn get_sender(&self)->Option<QueueSender<T>>{
+          self.channel_tx.clone()
      }
 
-     pub fn get_thread_handle(&self)->&JoinHandle<()>{
+     pub fn get_thread_handle(&self)->&TaskHandle{
           &self.thread_handle
      }
 
      pub fn get_alias(&self)->&String{
           &self.alias
      }
+
+     /// Returns true if the delivery queue of this container has been closed or dropped
+     pub fn is_closed(&self)->bool{
+          match &self.channel_tx{
+               None=>true,
+               Some(channel)=>channel.is_closed()
+          }
+     }
 }
 
 
 
-impl <T>ClientSenderContainer<T> {
-     /// Defacult constructor for the ClientReceiverContainer instance
+impl ClientSenderContainer {
+     /// Defacult constructor for the ClientSenderContainer instance
      /// 
      /// # Arguments
      /// 
-     /// * `handle`: JoinHandle<()> of the thread running a handler
-     /// * `channel_sender`: Sender<T> of the channel associated with the Receiver<T> in the executing in the thread
+     /// * `handle`: TaskHandle of the worker job running a handler
      /// * `key`: Unique key for this container instance
-     /// * `to_alias`: The unique identifier of the client to which the stream registered in this container sends to
-     pub fn new(handle:JoinHandle<()>, channel_receiver:Receiver<T>, key:u64, to_alias:String)->Self{
+     /// * `alias`: The alias of the client, the alias a send client sends from
+     /// * `stream`: A clone of the client stream handled in the thread, if any
+     pub fn new(handle:TaskHandle, key:u64, alias:String, stream:Option<TcpStream>)->Self{
           ClientSenderContainer{
                id:key,
                thread_handle:handle,
-               channel_rx:Some(channel_receiver),
-               to_alias
+               alias,
+               stream,
+               service:"SEND"
           }
      }
 
-     /// Destroys the container objec
-     /// Do this before thread handle goes out of scope
-     pub fn drop(self){
-          drop(self);
+     /// Sets the type of the client registered in this container, eg.. PRESENCE
+     pub fn set_service(&mut self, service:&'static str){
+          self.service = service;
      }
 
-     //----Getters----
-     pub fn get_id(&self)->u64{
-          self.id
+     /// Stops reading from the client registered in this container, the handling thread stops once its current message is handled
+     pub fn stop_reading(&self){
+          if let Some(stream) = &self.stream{
+               let _ = stream.shutdown(Shutdown::Read);
+          }
      }
 
-     pub fn get_receiver(&mut self)->Option<Receiver<T>>{
-
+     /// Disconnects the client registered in this container.
+     /// The notice is written to the client before its stream is shut down
+     pub fn disconnect(&mut self, notice:&[u8]){
+          if let Some(mut stream) = self.stream.take(){
+               let _ = write_frame(&mut stream, notice);
+               let _ = stream.shutdown(Shutdown::Both);
+          }
+     }
 
-          return self.channel_rx.take();
+     /// Returns the thread handle of this container to join it
+     pub fn into_thread_handle(self)->TaskHandle{
+          self.thread_handle
      }
 
-     pub fn get_thread_handle(&self)->&JoinHandle<()>{
-          &self.thread_handle
+     //----Getters----
+     pub fn get_id(&self)->u64{
+          self.id
      }
 
-     pub fn get_alias(&self)->&String{
-          &self.to_alias
+     pub fn get_thread_handle(&self)->&TaskHandle{
+          &self.thread_handle
      }
 }
 
@@ -178,8 +241,21 @@ impl <T>Display for ClientReceiverContainer<T> {
 }
 
 /// Display implementation for ClientSenderContainer
-impl <T>Display for ClientSenderContainer<T>{
+impl Display for ClientSenderContainer{
      fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-          write!(f, "{{ id: {}; to_alias: {}; type: SEND }}", self.id, self.to_alias)
+          write!(f, "{{ id: {}; alias: {}; type: {} }}", self.id, self.alias, self.service)
+     }
+}
+
+/// Display implementation for DisconnectReason
+impl Display for DisconnectReason{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Closed=>write!(f, "closed by client"),
+               Self::StreamError(e)=>write!(f, "stream error {}", e),
+               Self::QueueClosed=>write!(f, "delivery queue closed"),
+               Self::Banned=>write!(f, "banned"),
+               Self::ShuttingDown=>write!(f, "server shutting down")
+          }
      }
 }
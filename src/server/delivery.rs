@@ -0,0 +1,203 @@
+//! The protocol decisions shared by the blocking [crate::server::Server] and the async server core: claiming an alias,
+//! the duplicate policy, the redelivery of pending messages and the outcome of a dispatch.
+//! Each core only does the I/O these decisions call for
+
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+use super::auth::{AliasOwnership, AuthError, DuplicatePolicy};
+use super::handler::TransmitService;
+use super::mailbox::Mailbox;
+use super::protocol::pto::{BaseProto, Proto};
+use super::protocol::res::{Response, Status};
+use super::protocol::Handshake;
+use super::queue::{BackpressurePolicy, Delivery, Offer, QueueError};
+use super::registry::{ReceiverRegistry, Registration};
+
+
+/// The outcome of queueing a message for every session of its recipient
+///
+/// # Fields
+///
+/// - `queued`: The number of sessions the message has been queued for
+/// - `dropped_oldest`: True if a queue dropped its oldest message to take it
+/// - `full`: The number of sessions whose queue was full, the message was dropped for them
+/// - `slow_consumer`: The number of sessions disconnected for not keeping up
+#[derive(Debug, Default)]
+pub(crate) struct Dispatch{
+     queued:usize,
+     dropped_oldest:bool,
+     full:usize,
+     slow_consumer:usize
+}
+
+impl Dispatch{
+     /// Default constructor for a [Dispatch] to no session yet
+     pub fn new()->Self{
+          Dispatch::default()
+     }
+
+     /// Records the result of queueing the message to `username` for one of its sessions
+     pub fn record(&mut self, username:&str, result:Result<Delivery, QueueError<BaseProto>>){
+          match result{
+               Ok(Delivery::Queued)=>self.queued+=1,
+               Ok(Delivery::DroppedOldest)=>{
+                    warn!("Delivery queue of {{ username: {username} }} is full, dropped its oldest message");
+                    self.queued+=1;
+                    self.dropped_oldest = true;
+               },
+               Err(e @ QueueError::Full(_))=>{
+                    warn!("Delivery queue of {{ username: {username} }} is full, dropped the message {}", e);
+                    self.full+=1;
+               },
+               Err(e @ QueueError::Evicted(_))=>{
+                    warn!("Delivery queue of {{ username: {username} }} is full, disconnecting its receiver {}", e);
+                    self.slow_consumer+=1;
+               },
+               //the session is gone but not deregistered yet
+               Err(QueueError::Closed(_))=>()
+          }
+     }
+
+     /// Returns true if a session has been evicted, its queue is closed and it is to be disconnected as a slow consumer
+     pub fn has_slow_consumer(&self)->bool{
+          self.slow_consumer>0
+     }
+
+     /// Answers the message once it has been offered to every session of its recipient.
+     /// A message no session took, without any session missing it, is deposited in the mailbox
+     ///
+     /// # Returns
+     /// - `String`: The response frame of the message, [Status::Success] when it has been queued for at least one session
+     ///   of the alias or deposited. The sessions that could not take it are named in the message of the response
+     pub fn respond<C:Registration>(&self, registry:&ReceiverRegistry<C>, mailbox:&Mutex<Mailbox<BaseProto>>, pto:BaseProto, policy:BackpressurePolicy)->String
+     where C::Sender:Offer<BaseProto>{
+          let id = pto.get_id().unwrap_or(0);
+          let (queued, full, slow_consumer) = (self.queued, self.full, self.slow_consumer);
+
+          //a message queued for any session of the alias has been delivered, the sessions that missed it are reported
+          if queued>0 && full+slow_consumer>0{
+               Response::generate_res_for(id, Status::Success, format!("The message has been dispatched to {queued} of the sessions of the receiver, {full} had a full queue and {slow_consumer} could not keep up {{ policy: {policy} }}"))
+          }else if slow_consumer>0{
+               Response::generate_res_for(id, Status::SlowConsumer, format!("The receiver could not keep up and has been disconnected {{ policy: {policy} }}"))
+          }else if full>0{
+               Response::generate_res_for(id, Status::QueueFull, format!("The delivery queue of the receiver is full, the message has been dropped {{ policy: {policy} }}"))
+          }else if queued==0{
+               deposit(registry, mailbox, pto)
+          }else if self.dropped_oldest{
+               Response::generate_res_for(id, Status::Success, format!("The message has been dispatched from sender handler, the oldest queued message has been dropped {{ policy: {policy} }}"))
+          }else{
+               Response::generate_res_for(id, Status::Success, format!("The message has been dispatched from sender handler {{ policy: {policy} }}"))
+          }
+     }
+}
+
+/// Claims the alias of a client in its handshake, only a client giving the secret of its alias has claimed it.
+/// Receive and presence clients claim the alias they register, a send client claims the alias it sends from
+/// when it gives a secret, its rate limits and quota are then counted against it
+pub(crate) fn claim_alias(ownership:&Mutex<AliasOwnership>, handshake:&Handshake)->Result<(), AuthError>{
+     let secret = handshake.get_secret().map(|s|s.as_str());
+     match handshake.get_service(){
+          TransmitService::Receive(alias) | TransmitService::Presence(alias)=>ownership.lock().unwrap().claim(alias, secret),
+          TransmitService::Send(alias) if secret.is_some()=>ownership.lock().unwrap().claim(alias, secret),
+          _=>Ok(())
+     }
+}
+
+/// Applies the duplicate policy for an alias being registered by a receive client.
+/// A client that did not claim the alias with its secret is refused whatever the policy
+///
+/// # Returns
+/// - `Result<Vec<C>, String>`: The sessions replaced by the client, removed from the registry for the caller to disconnect
+///   with [replaced_notice], or the reason the client is refused with [Status::Conflict]
+pub(crate) fn admit_receiver<C:Registration>(registry:&ReceiverRegistry<C>, alias:&str, claimed:bool, policy:DuplicatePolicy)->Result<Vec<C>, String>{
+     if !registry.contains(alias){
+          return Ok(Vec::new());
+     }
+
+     match policy{
+          _ if !claimed=>Err(format!("Alias '{alias}' is already registered, only a client claiming it with its secret may take it over")),
+          DuplicatePolicy::Reject=>Err(format!("Alias '{alias}' is already registered")),
+          DuplicatePolicy::Replace=>Ok(registry.remove_alias(alias)),
+          DuplicatePolicy::Coexist=>Ok(Vec::new())
+     }
+}
+
+/// Returns the frame written to a session replaced by a new registration of its alias
+pub(crate) fn replaced_notice(alias:&str)->String{
+     Response::generate_res(Status::Conflict, format!("Session replaced by a new registration of alias '{alias}'"))
+}
+
+/// Returns the frame written to a receive client disconnected for not keeping up with incoming messages
+pub(crate) fn slow_consumer_notice()->String{
+     Response::generate_res(Status::SlowConsumer, "Disconnected for not keeping up with incoming messages".to_string())
+}
+
+/// Hands the messages re-queued while an alias was disconnected to the queue of its new session,
+/// after `resume`, the last message a resuming client was delivered.
+/// Messages the queue has no room for are kept in the mailbox, in order
+pub(crate) fn hand_pending<S:Offer<BaseProto>>(queue:&S, alias:&str, resume:Option<u64>, mailbox:&Mutex<Mailbox<BaseProto>>){
+     let pending = match resume{
+          Some(last)=>mailbox.lock().unwrap().take_after(alias, last),
+          None=>mailbox.lock().unwrap().take(alias)
+     };
+     if !pending.is_empty(){
+          info!("Redelivering {} pending messages to {{ receive_alias: {} }}", pending.len(), alias);
+     }
+     let undelivered = offer_all(queue, pending);
+     if !undelivered.is_empty(){
+          mailbox.lock().unwrap().restore(alias, undelivered);
+     }
+}
+
+/// Hands the pending messages of an alias to one of its registered sessions, if any.
+/// Messages that cannot be queued are kept in the mailbox
+pub(crate) fn redeliver_pending<C:Registration>(alias:&str, registry:&ReceiverRegistry<C>, mailbox:&Mutex<Mailbox<BaseProto>>)
+where C::Sender:Offer<BaseProto>{
+     let queue = match registry.senders_for(alias).into_iter().next(){
+          None=>return,
+          Some(queue)=>queue
+     };
+
+     let pending = mailbox.lock().unwrap().take(alias);
+     let undelivered = offer_all(&queue, pending);
+     if !undelivered.is_empty(){
+          mailbox.lock().unwrap().restore(alias, undelivered);
+     }
+}
+
+/// Deposits a message to an alias without a live receive client in the mailbox, refused with [Status::QueueFull]
+/// once the mailbox holds as many messages as it admits. A receive client registering in the meantime is handed the message right away
+///
+/// # Returns
+/// - `String`: The response frame of the message
+pub(crate) fn deposit<C:Registration>(registry:&ReceiverRegistry<C>, mailbox:&Mutex<Mailbox<BaseProto>>, pto:BaseProto)->String
+where C::Sender:Offer<BaseProto>{
+     let id = pto.get_id().unwrap_or(0);
+     let username = pto.get_receiver().clone();
+     info!("Depositing message {id} in the mailbox of {{ username: {username} }}");
+     if mailbox.lock().unwrap().admit(&username, pto).is_err(){
+          warn!("Mailbox is full, refused message {id} to {{ username: {username} }}");
+          return Response::generate_res_for(id, Status::QueueFull, format!("No receive client is connected for '{username}' and the mailbox of the server is full, the message has been dropped"));
+     }
+     redeliver_pending(&username, registry, mailbox);
+     Response::generate_res_for(id, Status::Success, format!("No receive client is connected for '{username}', the message has been queued for redelivery"))
+}
+
+/// Queues pending messages in order while the queue has room, without dropping the messages already queued
+///
+/// # Returns
+/// - `Vec<BaseProto>`: The messages that could not be queued, in order
+fn offer_all<S:Offer<BaseProto>>(queue:&S, pending:Vec<BaseProto>)->Vec<BaseProto>{
+     let mut pending = pending.into_iter();
+     let mut undelivered = Vec::new();
+     for pto in pending.by_ref(){
+          if let Err(QueueError::Full(pto) | QueueError::Closed(pto) | QueueError::Evicted(pto)) = queue.offer(pto){
+               undelivered.push(pto);
+               break;
+          }
+     }
+     undelivered.extend(pending);
+     undelivered
+}
@@ -9,13 +9,17 @@ use crate::server::protocol::error::ProtocolError;
 /// - `StreamReadError`: Indicates that data could not be read from data stream 
 /// - `ProtocolError`: Error associated with protocol create, read and update operations
 /// - `ThreadError`: Error associated with multithreaded operations
-
+/// - `RuntimeError`: Indicates that the async runtime could not be started
+/// - `PeerLinkError`: Indicates that a link to a federated peer could not be established
+#[allow(clippy::enum_variant_names)]
 pub enum ServerError {
      AddressBindError(Error),
      StreamAcceptError(Error),
      StreamReadError(Error),
      ProtocolError(ProtocolError),
-     ThreadError(ThreadError)
+     ThreadError(ThreadError),
+     RuntimeError(Error),
+     PeerLinkError(String)
 }
 
 ///
@@ -49,6 +53,12 @@ impl Display for ServerError{
             },
             Self::StreamReadError(e)=>{
                write!(f, "{{ error: StreamReadError; info: {} }}", e)
+            },
+            Self::RuntimeError(e)=>{
+               write!(f, "{{ error: RuntimeError; info: {} }}", e)
+            },
+            Self::PeerLinkError(e)=>{
+               write!(f, "{{ error: PeerLinkError; info: {} }}", e)
             }
         }
     }
@@ -0,0 +1,809 @@
+//! Federation of servers over authenticated server-to-server links
+//!
+//! A server dials the peers of its configuration and accepts the links of the peers dialing it. Once linked, each side
+//! advertises the aliases its receive clients are registered for, and a send client's message to an alias that is not
+//! registered locally is forwarded to the peer hosting it. The send client is answered with the response of that peer.
+//!
+//! Only the aliases registered locally are advertised, and a forwarded message is only delivered locally, so a message
+//! crosses at most one link. The servers a message went through are still listed in `via`, and a server finding its own
+//! name in it refuses the message. A link not heard from within [LINK_TIMEOUT] is closed: the aliases of its peer are no
+//! longer routed, and the messages waiting on its answer are answered with [Status::ServerError]. The dialing side
+//! reconnects with a backoff.
+//!
+//! Clustered servers, or nodes, also place themselves and their linked peers on a [HashRing]. The node an alias is
+//! hashed to owns its pending messages, the messages sent while it is registered on no node and the messages re-queued
+//! when its receive clients disconnected: they are deposited in the mailbox of the owner, and pushed from it to the
+//! node the alias registers on next. Whenever a node joins or leaves, the rebalance worker of every node hands the
+//! pending messages of the aliases it no longer owns to their new owner, and a node shutting down announces it is
+//! leaving then hands all of its pending messages over before closing its links.
+//! Nodes are expected to be linked to every other node.
+//!
+//! ```text
+//! PEER;<name>;secret=<secret>                  handshake of the dialing server
+//! <Code>;<Status>;-;<name>                     answer of the accepting server, with its name
+//!
+//! ONLINE;<alias>,<alias>..                     aliases now hosted by the sending server
+//! OFFLINE;<alias>                              alias no longer hosted by the sending server
+//! PING                                         heartbeat, sent every HEARTBEAT_INTERVAL
+//! FORWARD;<seq>;<name>,<name>..(\n)<message>   message to an alias hosted by the receiving server, via the named servers
+//! DEPOSIT;<seq>;<name>,<name>..(\n)<message>   pending message of an alias owned by the receiving node
+//! RESULT;<seq>;<Code>;<Status>;<Id>;<Message>  response to the forwarded or deposited message `seq`
+//! LEAVING                                      the sending server is shutting down, it is no longer routed to
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+use super::auth::constant_time_eq;
+use super::cluster::{HashRing, DEFAULT_VIRTUAL_NODES};
+use super::delivery::redeliver_pending;
+use super::container::ClientReceiverContainer;
+use super::error::ServerError;
+use super::handler::dispatch;
+use super::mailbox::Mailbox;
+use super::protocol::frame::{FrameError, FrameReader, write_frame};
+use super::protocol::pto::{BaseProto, Proto};
+use super::protocol::res::{Response, Status};
+use super::protocol::{BaseProtocol, Data, DataTransferProtocol, DataTransferProtocolParsed, MAX_HEADER_SIZE};
+use super::registry::ReceiverRegistry;
+use super::shutdown::ShutdownHandle;
+use super::DEFAULT_MAX_BODY_SIZE;
+
+
+/// Interval at which a heartbeat is written on every link
+pub const HEARTBEAT_INTERVAL:Duration = Duration::from_secs(2);
+
+/// Time after which a link nothing has been read from is considered down
+pub const LINK_TIMEOUT:Duration = Duration::from_secs(6);
+
+/// Time a send client waits for the answer of the peer its message was forwarded to
+pub const FORWARD_TIMEOUT:Duration = Duration::from_secs(10);
+
+/// Time a peer is given to answer the handshake of a link
+const HANDSHAKE_TIMEOUT:Duration = Duration::from_secs(10);
+
+/// Delay before dialing a peer again, doubled after each failed attempt
+const RECONNECT_DELAY:Duration = Duration::from_millis(500);
+
+/// Longest delay between two attempts at dialing a peer
+const MAX_RECONNECT_DELAY:Duration = Duration::from_secs(30);
+
+/// Interval at which the dialing threads and the rebalance worker check for a shutdown request while waiting
+const SHUTDOWN_POLL_INTERVAL:Duration = Duration::from_millis(100);
+
+/// Maximum size of the aliases advertised in one `ONLINE` frame
+const ADVERTISE_BATCH_SIZE:usize = 4096;
+
+/// A struct representing the federation of a [crate::server::Server] with its peers.
+/// It is cloned into every connection, the clones share the links and the routes
+///
+/// # Fields
+///
+/// - `name`: The name the server is known by to its peers, listed in the `via` of the messages it forwards
+/// - `secret`: The secret shared by the federated servers. Peer links are refused when it is not set
+/// - `peers`: The `host:port` addresses of the peers dialed by the server
+/// - `max_frame_size`: The maximum size of a frame read from a link
+/// - `rcp`: The [ReceiverRegistry] of the server, the aliases advertised and the messages forwarded to the server are delivered through it
+/// - `mailbox`: The [Mailbox] of the server, holding the pending messages of the aliases owned by the node once clustered
+/// - `cluster`: True once the server is a node of a cluster
+/// - `virtual_nodes`: The number of points each node is placed at on the [HashRing]
+/// - `state`: The links, routes and forwarded messages awaiting an answer
+/// - `rebalancing`: Held while pending messages are handed to their owner, so that two rebalances never take the same messages
+/// - `rebalance_requested`: Set by [Federation::rebalance] and cleared by the rebalance worker, which waits on its condvar
+#[derive(Debug, Clone)]
+pub struct Federation{
+     name:String,
+     secret:Option<String>,
+     peers:Vec<String>,
+     max_frame_size:usize,
+     rcp:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>>,
+     mailbox:Arc<Mutex<Mailbox<BaseProto>>>,
+     cluster:bool,
+     virtual_nodes:usize,
+     state:Arc<Mutex<FederationState>>,
+     rebalancing:Arc<Mutex<()>>,
+     rebalance_requested:Arc<(Mutex<bool>, Condvar)>
+}
+
+/// A struct representing the state shared by the clones of a [Federation]
+///
+/// # Fields
+///
+/// - `links`: The open links, two servers dialing each other are linked twice
+/// - `hosted`: The aliases advertised by each peer, keyed by peer name
+/// - `pending`: The forwarded and deposited messages awaiting an answer, keyed by the name of the peer and the sequence number
+///   of the transfer. Message ids are only unique to the server that assigned them, two transfers may carry the same id
+/// - `transfer_counter`: The sequence number of the last transfer written
+/// - `link_counter`: The id of the last link opened
+/// - `ring`: The [HashRing] of the nodes of the cluster, this node and its linked peers that are not leaving
+/// - `leaving`: The peers that announced they are shutting down
+#[derive(Debug)]
+struct FederationState{
+     links:Vec<PeerLink>,
+     hosted:HashMap<String, HashSet<String>>,
+     pending:HashMap<(String, u64), Sender<String>>,
+     transfer_counter:u64,
+     link_counter:u64,
+     ring:HashRing,
+     leaving:HashSet<String>
+}
+
+/// A struct representing an open link to a peer
+///
+/// # Fields
+///
+/// - `id`: The unique id of the link
+/// - `peer`: The name of the peer
+/// - `writer`: The stream of the link, shared by the threads writing to it
+#[derive(Debug)]
+struct PeerLink{
+     id:u64,
+     peer:String,
+     writer:Arc<Mutex<TcpStream>>
+}
+
+impl Federation{
+     /// Constructor for a disabled [Federation] of a server delivering through `rcp` and holding pending messages in `mailbox`
+     pub(crate) fn new(rcp:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>>, mailbox:Arc<Mutex<Mailbox<BaseProto>>>)->Self{
+          Federation{
+               name:String::new(),
+               secret:None,
+               peers:Vec::new(),
+               max_frame_size:DEFAULT_MAX_BODY_SIZE+2*MAX_HEADER_SIZE,
+               rcp,
+               mailbox,
+               cluster:false,
+               virtual_nodes:DEFAULT_VIRTUAL_NODES,
+               state:Arc::new(Mutex::new(FederationState{
+                    links:Vec::new(),
+                    hosted:HashMap::new(),
+                    pending:HashMap::new(),
+                    transfer_counter:0,
+                    link_counter:0,
+                    ring:HashRing::new(DEFAULT_VIRTUAL_NODES),
+                    leaving:HashSet::new()
+               })),
+               rebalancing:Arc::new(Mutex::new(())),
+               rebalance_requested:Arc::new((Mutex::new(false), Condvar::new()))
+          }
+     }
+
+     /// Enables the federation under the name of the server and the secret shared by the federated servers
+     pub fn set_identity(&mut self, name:String, secret:String){
+          self.name = name;
+          self.secret = Some(secret);
+     }
+
+     /// Adds the `host:port` address of a peer to dial
+     pub fn add_peer(&mut self, addr:String){
+          self.peers.push(addr);
+     }
+
+     /// Makes the server a node of a cluster, placed at `virtual_nodes` points on the [HashRing]
+     pub fn set_cluster(&mut self, virtual_nodes:usize){
+          self.cluster = true;
+          self.virtual_nodes = virtual_nodes;
+     }
+
+     /// Sets the maximum size of the body of a forwarded message
+     pub fn set_max_body_size(&mut self, max_body_size:usize){
+          self.max_frame_size = max_body_size+2*MAX_HEADER_SIZE;
+     }
+
+     //----Getters----
+     pub fn get_name(&self)->&String{
+          &self.name
+     }
+
+     pub fn get_peers(&self)->&Vec<String>{
+          &self.peers
+     }
+
+     /// Returns true once the identity of the server has been set
+     pub fn is_enabled(&self)->bool{
+          self.secret.is_some()
+     }
+
+     /// Returns true once the server is a node of a cluster
+     pub fn is_clustered(&self)->bool{
+          self.cluster && self.is_enabled()
+     }
+
+     /// Returns the node owning the pending messages of `alias`, None when the server is not clustered
+     pub fn owner(&self, alias:&str)->Option<String>{
+          if !self.is_clustered(){
+               return None;
+          }
+          self.state.lock().unwrap().ring.owner(alias).cloned()
+     }
+
+     /// Returns the nodes of the cluster as seen by this node, sorted by name
+     pub fn members(&self)->Vec<String>{
+          self.state.lock().unwrap().ring.nodes()
+     }
+
+     /// Returns the number of pending messages held by the server
+     pub fn pending_messages(&self)->usize{
+          let mailbox = self.mailbox.lock().unwrap();
+          mailbox.pending_aliases().iter().map(|alias|mailbox.len(alias)).sum()
+     }
+
+     /// Returns the names of the peers currently linked
+     pub fn linked_peers(&self)->Vec<String>{
+          let state = self.state.lock().unwrap();
+          let mut peers:Vec<String> = state.links.iter().map(|l|l.peer.clone()).collect();
+          peers.sort();
+          peers.dedup();
+          peers
+     }
+
+     /// Checks the handshake of a peer dialing the server
+     ///
+     /// # Returns
+     /// - `Result<(), (Status, String)>`: The status and reason of the refusal of the peer, if refused
+     pub fn authenticate(&self, peer:&str, secret:Option<&String>)->Result<(), (Status, String)>{
+          match (&self.secret, secret){
+               (None, _)=>return Err((Status::Unauthorized, "Federation is not enabled on this server".to_string())),
+               (Some(secret), Some(s)) if constant_time_eq(secret.as_bytes(), s.as_bytes())=>(),
+               _=>return Err((Status::Unauthorized, "Invalid peer secret".to_string()))
+          }
+          if peer.is_empty() || peer==self.name{
+               return Err((Status::Conflict, format!("Peer name '{peer}' is the name of this server")));
+          }
+          Ok(())
+     }
+
+     /// Starts dialing the peers of the server, each from its own thread, and the rebalance worker of a node.
+     /// A peer is dialed again with a backoff whenever its link goes down, until `shutdown` is triggered
+     pub(crate) fn start(&self, shutdown:ShutdownHandle){
+          if !self.is_enabled(){
+               return;
+          }
+          if self.cluster{
+               {
+                    let mut state = self.state.lock().unwrap();
+                    state.ring = HashRing::new(self.virtual_nodes);
+                    state.ring.add(&self.name);
+               }
+               let federation = self.clone();
+               let shutdown = shutdown.clone();
+               spawn(move ||federation.run_rebalancer(shutdown));
+          }
+          for addr in self.peers.iter().cloned(){
+               let federation = self.clone();
+               let shutdown = shutdown.clone();
+               spawn(move ||federation.dial(addr, shutdown));
+          }
+     }
+
+     /// Announces to every peer that the server is shutting down, they no longer route messages to it.
+     /// A clustered server also removes itself from its ring, so that it owns no alias anymore
+     pub(crate) fn leave(&self){
+          if !self.is_enabled(){
+               return;
+          }
+          let writers:Vec<Arc<Mutex<TcpStream>>> = {
+               let mut state = self.state.lock().unwrap();
+               state.ring.remove(&self.name);
+               state.links.iter().map(|l|l.writer.clone()).collect()
+          };
+          for writer in writers{
+               let _ = write_frame(&mut *writer.lock().unwrap(), b"LEAVING");
+          }
+     }
+
+     /// Hands every pending message over to the nodes left in the cluster, once the server has left it with [Federation::leave]
+     /// and its receive clients are disconnected
+     pub(crate) fn hand_off(&self){
+          if !self.is_clustered(){
+               return;
+          }
+          self.rebalance_now();
+          let left = self.pending_messages();
+          if left>0{
+               warn!("{left} pending messages could not be handed over to another node");
+          }
+     }
+
+     /// Closes every link, the threads running them exit on their next read
+     pub(crate) fn close(&self){
+          //the writers are locked once the state is released, a link being registered holds its writer first
+          let writers:Vec<Arc<Mutex<TcpStream>>> = self.state.lock().unwrap().links.iter().map(|l|l.writer.clone()).collect();
+          for writer in writers{
+               let _ = writer.lock().unwrap().shutdown(Shutdown::Both);
+          }
+     }
+
+     /// Returns the name of a linked peer hosting `alias`, if any
+     pub fn route(&self, alias:&str)->Option<String>{
+          let state = self.state.lock().unwrap();
+          state.hosted.iter()
+               .find(|(_, aliases)|aliases.contains(alias))
+               .map(|(peer, _)|peer.clone())
+     }
+
+     /// Returns the node owning `alias` when it is another node of the cluster, the messages to the alias are deposited with it
+     /// while the alias is registered nowhere. None when the server is not clustered or owns the alias
+     pub fn remote_owner(&self, alias:&str)->Option<String>{
+          self.owner(alias).filter(|owner|*owner!=self.name)
+     }
+
+     /// Forwards a message to the peer hosting its recipient and waits for its answer
+     ///
+     /// # Returns
+     /// - `String`: The response frame of the peer, or [Status::ServerError] when the peer could not answer
+     pub fn forward(&self, peer:&str, pto:BaseProto)->String{
+          self.transfer(peer, "FORWARD", pto)
+     }
+
+     /// Deposits a message to an alias registered nowhere in the mailbox of `node`, the node owning the alias, and waits for its answer
+     ///
+     /// # Returns
+     /// - `String`: The response frame of the node, or [Status::ServerError] when the node could not answer
+     pub fn deposit(&self, node:&str, pto:BaseProto)->String{
+          self.transfer(node, "DEPOSIT", pto)
+     }
+
+     /// Writes a message to a linked peer as a `kind` frame, FORWARD or DEPOSIT, and waits for its answer
+     fn transfer(&self, peer:&str, kind:&str, pto:BaseProto)->String{
+          let id = pto.get_id().unwrap_or(0);
+          let to = pto.get_receiver().clone();
+          let raw = match BaseProtocol::new().to_raw(pto){
+               Ok(raw)=>raw,
+               Err(e)=>return Response::generate_res_for(id, Status::ServerError, e.to_string())
+          };
+
+          //registering the message before writing it, the answer may be read before the write returns
+          let (tx, rx) = channel();
+          let (writer, key) = {
+               let mut state = self.state.lock().unwrap();
+               match state.links.iter().find(|l|l.peer==peer).map(|l|l.writer.clone()){
+                    None=>return Response::generate_res_for(id, Status::UnknownRecipient, format!("No receive client is registered for '{to}'")),
+                    Some(writer)=>{
+                         state.transfer_counter+=1;
+                         let key = (peer.to_string(), state.transfer_counter);
+                         state.pending.insert(key.clone(), tx);
+                         (writer, key)
+                    }
+               }
+          };
+          let mut frame = format!("{kind};{};{}\n", key.1, self.name).into_bytes();
+          frame.extend_from_slice(&raw);
+
+          if let Err(e) = write_frame(&mut *writer.lock().unwrap(), &frame){
+               error!("Could not write message {id} to {{ peer: {peer}; kind: {kind} }} {}", e);
+               self.state.lock().unwrap().pending.remove(&key);
+               return Response::generate_res_for(id, Status::ServerError, format!("Could not forward the message to peer '{peer}'"));
+          }
+          info!("Wrote message {id} to {{ peer: {peer}; kind: {kind}; username: {to} }}");
+
+          match rx.recv_timeout(FORWARD_TIMEOUT){
+               Ok(res)=>res,
+               Err(RecvTimeoutError::Timeout)=>{
+                    self.state.lock().unwrap().pending.remove(&key);
+                    Response::generate_res_for(id, Status::ServerError, format!("Peer '{peer}' did not answer in time"))
+               },
+               Err(RecvTimeoutError::Disconnected)=>{
+                    Response::generate_res_for(id, Status::ServerError, format!("Peer '{peer}' went down before answering"))
+               }
+          }
+     }
+
+     /// Advertises to every peer whether `alias` is registered locally, once one of its receive clients registered or deregistered
+     pub fn announce(&self, alias:&str){
+          if !self.is_enabled(){
+               return;
+          }
+          let writers:Vec<Arc<Mutex<TcpStream>>> = self.state.lock().unwrap().links.iter().map(|l|l.writer.clone()).collect();
+          for writer in writers{
+               //the registry is looked up under the lock of the link, so that the advertisements of a link follow its registrations
+               let mut writer = writer.lock().unwrap();
+               let frame = match self.rcp.contains(alias){
+                    true=>format!("ONLINE;{alias}"),
+                    false=>format!("OFFLINE;{alias}")
+               };
+               if let Err(e) = write_frame(&mut *writer, frame.as_bytes()){
+                    warn!("Could not advertise {{ alias: {alias} }} to a peer {}", e);
+               }
+          }
+     }
+
+     /// Dials the peer at `addr` until `shutdown` is triggered, running its link whenever it is established
+     fn dial(&self, addr:String, shutdown:ShutdownHandle){
+          let mut delay = RECONNECT_DELAY;
+          while !shutdown.is_requested(){
+               match self.connect(&addr){
+                    Ok((stream, reader, peer))=>{
+                         info!("Linked to peer {{ name: {peer}; addr: {addr} }}");
+                         delay = RECONNECT_DELAY;
+                         self.run_link(peer, stream, reader);
+                    },
+                    Err(e)=>{
+                         warn!("Could not link to peer {{ addr: {addr} }} {}, retrying in {}ms", e, delay.as_millis());
+                    }
+               }
+
+               let deadline = Instant::now()+delay;
+               while Instant::now()<deadline && !shutdown.is_requested(){
+                    sleep(SHUTDOWN_POLL_INTERVAL);
+               }
+               delay = (delay*2).min(MAX_RECONNECT_DELAY);
+          }
+     }
+
+     /// Opens a link to the peer at `addr`
+     ///
+     /// # Returns
+     /// - `(TcpStream, FrameReader, String)`: The stream of the link, its reader and the name of the peer
+     fn connect(&self, addr:&str)->Result<(TcpStream, FrameReader, String), ServerError>{
+          let mut stream = TcpStream::connect(addr).map_err(|e|ServerError::PeerLinkError(e.to_string()))?;
+          stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e|ServerError::PeerLinkError(e.to_string()))?;
+
+          let handshake = format!("PEER;{};secret={}", self.name, self.secret.as_deref().unwrap_or(""));
+          write_frame(&mut stream, handshake.as_bytes()).map_err(|e|ServerError::PeerLinkError(e.to_string()))?;
+
+          let mut reader = FrameReader::new(self.max_frame_size);
+          let frame = match reader.read_frame(&mut stream){
+               Ok(Some(frame))=>frame,
+               Ok(None)=>return Err(ServerError::PeerLinkError("the peer closed the link during the handshake".to_string())),
+               Err(e)=>return Err(ServerError::PeerLinkError(e.to_string()))
+          };
+          let res = Response::parse(&String::from_utf8_lossy(&frame)).map_err(|e|ServerError::PeerLinkError(e.to_string()))?;
+          if !res.get_status().is_success(){
+               return Err(ServerError::PeerLinkError(format!("the peer refused the link, {res}")));
+          }
+          Ok((stream, reader, res.get_message().clone()))
+     }
+
+     /// Runs a link established with `peer`, whichever side dialed it, until it goes down.
+     /// The aliases registered locally are advertised first, then the frames of the peer are handled
+     /// while a heartbeat thread keeps the link alive and a delivery thread delivers the messages of the peer
+     pub(crate) fn run_link(&self, peer:String, mut stream:TcpStream, mut reader:FrameReader){
+          reader.set_max_frame_size(self.max_frame_size);
+          let writer = match stream.try_clone(){
+               Ok(writer)=>Arc::new(Mutex::new(writer)),
+               Err(e)=>{
+                    error!("Could not clone the stream of the link to {{ peer: {peer} }} {}", e);
+                    return;
+               }
+          };
+          if let Err(e) = stream.set_read_timeout(Some(LINK_TIMEOUT)).and(stream.set_write_timeout(Some(LINK_TIMEOUT))){
+               error!("Could not set the timeouts of the link to {{ peer: {peer} }} {}", e);
+               return;
+          }
+
+          //the link is registered under the lock of its writer, so that no advertisement is written before the aliases
+          let link_id = {
+               let mut guard = writer.lock().unwrap();
+               let link_id = self.register_link(&peer, writer.clone());
+               for batch in batches(self.rcp.aliases()){
+                    if let Err(e) = write_frame(&mut *guard, format!("ONLINE;{batch}").as_bytes()){
+                         warn!("Could not advertise the aliases to {{ peer: {peer} }} {}", e);
+                    }
+               }
+               link_id
+          };
+
+          //heartbeat thread, stops once the link is removed
+          let heartbeat = writer.clone();
+          let federation = self.clone();
+          spawn(move ||{
+               loop {
+                    sleep(HEARTBEAT_INTERVAL);
+                    if !federation.has_link(link_id) || write_frame(&mut *heartbeat.lock().unwrap(), b"PING").is_err(){
+                         break;
+                    }
+               }
+          });
+
+          //delivery thread, a delivery waiting on a full queue does not hold up the frames read after it, eg.. the
+          //results of the messages forwarded to the peer. It stops once the link is closed and its deliveries are done
+          let (deliveries, queued) = channel::<(String, String)>();
+          let delivery_writer = writer.clone();
+          let delivery_peer = peer.clone();
+          let federation = self.clone();
+          spawn(move ||{
+               for (kind, transferred) in queued{
+                    federation.deliver_transferred(&delivery_peer, &delivery_writer, &kind, &transferred);
+               }
+          });
+
+          let reason = loop {
+               let frame = match reader.read_frame(&mut stream){
+                    Ok(Some(frame))=>frame,
+                    Ok(None)=>break "closed by the peer".to_string(),
+                    Err(FrameError::TooLarge(size))=>{
+                         warn!("Refused a frame of {size} bytes from {{ peer: {peer} }}");
+                         continue;
+                    },
+                    Err(FrameError::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)=>{
+                         break format!("nothing read for {}s", LINK_TIMEOUT.as_secs());
+                    },
+                    Err(e)=>break e.to_string()
+               };
+               self.handle_frame(&peer, &deliveries, &String::from_utf8_lossy(&frame));
+          };
+
+          self.remove_link(link_id, &peer);
+          let _ = stream.shutdown(Shutdown::Both);
+          warn!("Link to peer closed -- {{ peer: {peer}; reason: {reason} }}");
+     }
+
+     /// Handles a frame read from the link to `peer`, the messages it carries are handed to the delivery thread of the link
+     fn handle_frame(&self, peer:&str, deliveries:&Sender<(String, String)>, frame:&str){
+          let (kind, rest) = frame.split_once(';').unwrap_or((frame, ""));
+          match kind{
+               "PING"=>(),
+               "ONLINE"=>{
+                    let aliases:Vec<String> = rest.split(',').filter(|a|!a.is_empty()).map(|a|a.to_string()).collect();
+                    {
+                         let mut state = self.state.lock().unwrap();
+                         if state.leaving.contains(peer){
+                              return;
+                         }
+                         state.hosted.entry(peer.to_string()).or_default().extend(aliases.iter().cloned());
+                    }
+                    //pushing the pending messages of the aliases to the peer they registered on
+                    let held = {
+                         let mailbox = self.mailbox.lock().unwrap();
+                         aliases.iter().any(|alias|mailbox.len(alias)>0)
+                    };
+                    if held{
+                         self.rebalance();
+                    }
+               },
+               "OFFLINE"=>{
+                    if let Some(hosted) = self.state.lock().unwrap().hosted.get_mut(peer){
+                         hosted.remove(rest);
+                    }
+               },
+               "FORWARD" | "DEPOSIT"=>{
+                    let _ = deliveries.send((kind.to_string(), rest.to_string()));
+               },
+               "LEAVING"=>{
+                    info!("Peer is leaving {{ peer: {peer} }}");
+                    {
+                         let mut state = self.state.lock().unwrap();
+                         state.leaving.insert(peer.to_string());
+                         state.hosted.remove(peer);
+                         state.ring.remove(peer);
+                    }
+                    self.rebalance();
+               },
+               "RESULT"=>{
+                    let (seq, res) = match rest.split_once(';').and_then(|(seq, res)|Some((seq.parse::<u64>().ok()?, res))){
+                         Some((seq, res)) if Response::parse(res).is_ok()=>(seq, res),
+                         _=>{
+                              warn!("Invalid result from {{ peer: {peer} }} '{rest}'");
+                              return;
+                         }
+                    };
+                    //the sender may have timed out already
+                    if let Some(tx) = self.state.lock().unwrap().pending.remove(&(peer.to_string(), seq)){
+                         let _ = tx.send(res.to_string());
+                    }
+               },
+               _=>warn!("Unknown frame from {{ peer: {peer} }} '{kind}'")
+          }
+     }
+
+     /// Delivers a FORWARD or DEPOSIT message of `peer` and answers it on the link with the sequence number of the transfer,
+     /// from the delivery thread of the link
+     fn deliver_transferred(&self, peer:&str, writer:&Arc<Mutex<TcpStream>>, kind:&str, transferred:&str){
+          let (seq, transferred) = match transferred.split_once(';').and_then(|(seq, rest)|Some((seq.parse::<u64>().ok()?, rest))){
+               Some(parsed)=>parsed,
+               None=>{
+                    warn!("Invalid {kind} frame from {{ peer: {peer} }}, it has no sequence number");
+                    return;
+               }
+          };
+          let res = match kind{
+               "FORWARD"=>self.deliver_forwarded(transferred),
+               _=>self.accept_deposit(transferred)
+          };
+          if let Err(e) = write_frame(&mut *writer.lock().unwrap(), format!("RESULT;{seq};{res}").as_bytes()){
+               error!("Could not answer a {kind} message of {{ peer: {peer} }} {}", e);
+          }
+     }
+
+     /// Delivers a message forwarded by a peer to the receive clients registered locally, it is never forwarded again
+     ///
+     /// # Returns
+     /// - `String`: The response frame of the message
+     fn deliver_forwarded(&self, forwarded:&str)->String{
+          let (via, pto) = match self.parse_transferred(forwarded){
+               Ok(t)=>t,
+               Err(res)=>return res
+          };
+          let to = pto.get_receiver().clone();
+          info!("Delivering message {} forwarded {{ via: {via}; username: {to} }}", pto.get_id().unwrap_or(0));
+          dispatch(&self.rcp, &self.mailbox, self.rcp.senders_for(&to), pto)
+     }
+
+     /// Takes in a pending message deposited by a node, delivered right away when its recipient is registered locally.
+     /// A message to an alias registered on a peer is pushed to it by a rebalance, it is kept in the mailbox otherwise
+     ///
+     /// # Returns
+     /// - `String`: The response frame of the message
+     fn accept_deposit(&self, deposited:&str)->String{
+          let (via, pto) = match self.parse_transferred(deposited){
+               Ok(t)=>t,
+               Err(res)=>return res
+          };
+          let id = pto.get_id().unwrap_or(0);
+          let to = pto.get_receiver().clone();
+          let senders = self.rcp.senders_for(&to);
+          if !senders.is_empty(){
+               return dispatch(&self.rcp, &self.mailbox, senders, pto);
+          }
+
+          info!("Holding message {id} deposited {{ via: {via}; username: {to} }}");
+          //a full mailbox refuses the message, the node that deposited it keeps it
+          if self.mailbox.lock().unwrap().admit(&to, pto).is_err(){
+               return Response::generate_res_for(id, Status::QueueFull, format!("The mailbox of '{}' is full", self.name));
+          }
+          if self.route(&to).is_some(){
+               self.rebalance();
+          }
+          Response::generate_res_for(id, Status::Success, format!("The message has been deposited in the mailbox of '{to}'"))
+     }
+
+     /// Parses a forwarded or deposited message, refusing it when it already went through this server
+     ///
+     /// # Returns
+     /// - `Result<(&str, BaseProto), String>`: The servers the message went through and the message, or the response frame of its refusal
+     fn parse_transferred<'a>(&self, transferred:&'a str)->Result<(&'a str, BaseProto), String>{
+          let (via, message) = transferred.split_once('\n').unwrap_or((transferred, ""));
+          let parsed = match BaseProtocol::new().parse(Data::Utf8(message.as_bytes().to_vec())){
+               Ok(parsed)=>parsed,
+               Err(e)=>return Err(Response::generate_res(Status::InvalidFormat, e.to_string()))
+          };
+          let id = match parsed.get_id(){
+               Some(id)=>id,
+               None=>return Err(Response::generate_res(Status::InvalidFormat, "The forwarded message has no id".to_string()))
+          };
+
+          //refusing a message that already went through this server
+          if via.split(',').any(|name|name==self.name){
+               warn!("Refused forwarded message {id}, it already went through this server {{ via: {via} }}");
+               return Err(Response::generate_res_for(id, Status::ServerError, format!("Forwarding loop through {via}")));
+          }
+
+          let body = parsed.get_body().map(|b|b.to_string()).unwrap_or_default();
+          Ok((via, BaseProto::create(parsed.get_client_id().to_string(), body, parsed.get_to().to_string()).with_id(id)))
+     }
+
+     /// Wakes the rebalance worker to hand the pending messages held by the server to the node they belong to.
+     /// Requests made while a rebalance runs are served by a single rebalance after it. Does nothing unless the server is clustered
+     pub fn rebalance(&self){
+          if !self.is_clustered(){
+               return;
+          }
+          let (requested, wake) = &*self.rebalance_requested;
+          *requested.lock().unwrap() = true;
+          wake.notify_one();
+     }
+
+     /// Runs the rebalances requested with [Federation::rebalance] one after the other, until `shutdown` is triggered.
+     /// The pending messages left once the server shuts down are handed over by [Federation::hand_off]
+     fn run_rebalancer(&self, shutdown:ShutdownHandle){
+          let (requested, wake) = &*self.rebalance_requested;
+          loop {
+               {
+                    let mut requested = requested.lock().unwrap();
+                    while !*requested && !shutdown.is_requested(){
+                         requested = wake.wait_timeout(requested, SHUTDOWN_POLL_INTERVAL).unwrap().0;
+                    }
+                    if shutdown.is_requested(){
+                         return;
+                    }
+                    *requested = false;
+               }
+               self.rebalance_now();
+          }
+     }
+
+     /// Hands the pending messages of every alias to the node they belong to: the node the alias is registered on,
+     /// or else the owner of the alias. Messages that could not be handed over are kept, in order
+     fn rebalance_now(&self){
+          let _rebalancing = self.rebalancing.lock().unwrap();
+          let aliases = self.mailbox.lock().unwrap().pending_aliases();
+          for alias in aliases{
+               if self.rcp.contains(&alias){
+                    redeliver_pending(&alias, &self.rcp, &self.mailbox);
+                    continue;
+               }
+               let (node, kind) = match (self.route(&alias), self.owner(&alias)){
+                    (Some(peer), _)=>(peer, "FORWARD"),
+                    (None, Some(owner)) if owner!=self.name=>(owner, "DEPOSIT"),
+                    _=>continue
+               };
+
+               let mut pending = self.mailbox.lock().unwrap().take(&alias).into_iter();
+               let mut moved = 0;
+               while let Some(pto) = pending.next(){
+                    let res = self.transfer(&node, kind, pto.clone());
+                    if !Response::parse(&res).is_ok_and(|r|r.get_status().is_success()){
+                         warn!("Could not hand a pending message of {{ alias: {alias} }} to {{ node: {node} }} {}", res);
+                         let mut remaining = vec![pto];
+                         remaining.extend(pending);
+                         self.mailbox.lock().unwrap().restore(&alias, remaining);
+                         break;
+                    }
+                    moved+=1;
+               }
+               info!("Handed {moved} pending messages of {{ alias: {alias} }} to {{ node: {node} }}");
+          }
+     }
+
+     /// Registers a link to `peer`
+     ///
+     /// # Returns
+     /// - `u64`: The id of the link
+     fn register_link(&self, peer:&str, writer:Arc<Mutex<TcpStream>>)->u64{
+          let mut state = self.state.lock().unwrap();
+          state.link_counter+=1;
+          let id = state.link_counter;
+          state.links.push(PeerLink{ id, peer:peer.to_string(), writer });
+
+          //a node joining the cluster takes over the aliases hashed to its points
+          if self.is_clustered() && !state.ring.contains(peer) && !state.leaving.contains(peer){
+               state.ring.add(peer);
+               info!("Node joined the cluster {{ node: {peer}; members: {} }}", state.ring.nodes().join(","));
+               drop(state);
+               self.rebalance();
+          }
+          id
+     }
+
+     fn has_link(&self, id:u64)->bool{
+          self.state.lock().unwrap().links.iter().any(|l|l.id==id)
+     }
+
+     /// Removes a link that went down. Once no link to `peer` is left its aliases are no longer routed,
+     /// the messages waiting on its answer are answered with [Status::ServerError] and it leaves the cluster
+     fn remove_link(&self, id:u64, peer:&str){
+          let mut state = self.state.lock().unwrap();
+          state.links.retain(|l|l.id!=id);
+          if state.links.iter().any(|l|l.peer==peer){
+               return;
+          }
+          state.hosted.remove(peer);
+          state.leaving.remove(peer);
+          //dropping the channels wakes the waiting senders
+          state.pending.retain(|(p, _), _|p!=peer);
+
+          if state.ring.contains(peer){
+               state.ring.remove(peer);
+               info!("Node left the cluster {{ node: {peer}; members: {} }}", state.ring.nodes().join(","));
+               drop(state);
+               self.rebalance();
+          }
+     }
+}
+
+/// Joins aliases into comma separated batches of at most [ADVERTISE_BATCH_SIZE] bytes
+fn batches(aliases:Vec<String>)->Vec<String>{
+     let mut batches:Vec<String> = Vec::new();
+     let mut batch = String::new();
+     for alias in aliases{
+          if !batch.is_empty() && batch.len()+alias.len()+1>ADVERTISE_BATCH_SIZE{
+               batches.push(std::mem::take(&mut batch));
+          }
+          if !batch.is_empty(){
+               batch.push(',');
+          }
+          batch.push_str(&alias);
+     }
+     if !batch.is_empty(){
+          batches.push(batch);
+     }
+     batches
+}
@@ -1,10 +1,22 @@
-use std::{io::{Read, Write}, net::TcpStream, sync::{mpsc::{Receiver, Sender}, Arc, Mutex, MutexGuard}};
+use std::{collections::VecDeque, io::Read, net::{Shutdown, TcpStream}, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}};
 use log::{error, info, warn};
 
 
 use crate::server::protocol::res::{Response, Status};
-use crate::server::protocol::BaseProtocol;
-use super::{container::ClientReceiverContainer, error::{ServerError,ThreadError}, protocol::{pto::{BaseProto, Proto}, Data, DataTransferProtocol, DataTransferProtocolParsed}};
+use crate::server::protocol::{BaseProtocol, PresenceCommand, AdminCommand, MAX_HEADER_SIZE, get_presence_command, get_admin_command};
+use crate::server::protocol::frame::{FrameError, FrameReader, write_frame};
+use super::DEFAULT_MAX_BODY_SIZE;
+use super::presence::{PresenceEvent, PresenceRegistry};
+use super::mailbox::Mailbox;
+use super::queue::{QueueCloser, QueueReceiver, QueueSender};
+use super::delivery::{deposit, slow_consumer_notice, Dispatch};
+use super::federation::Federation;
+use super::auth::AliasOwnership;
+use super::pool::{Slot, WorkerPool};
+use super::quota::QuotaTracker;
+use super::registry::ReceiverRegistry;
+use super::ratelimit::{RateLimiter, Verdict};
+use super::{container::{ClientReceiverContainer, DisconnectReason}, error::ServerError, protocol::{pto::{next_message_id, BaseProto, Proto}, Data, DataTransferProtocol, DataTransferProtocolParsed}};
 
 /// A struct representing a stream handler
 /// Handles a stream exclusiive to one transmit type:['Send'] or ['Receive']
@@ -14,7 +26,9 @@ use super::{container::ClientReceiverContainer, error::{ServerError,ThreadError}
 ///
 /// - `stream`: the TcpStream that this handler object handles ['TransmitService']
 /// - `transmit`: The transmit service subscribed by the client
-/// = 'protocol': The protocol type followed by this handler which implements ['DataTransferProtocol']
+/// - 'protocol': The protocol type followed by this handler which implements ['DataTransferProtocol']
+/// - `reader`: The [FrameReader] splitting the stream into frames
+/// - `max_body_size`: The maximum size of the body of a message sent by the client
 /// 
 /// ['TransmitService']: TransmitService
 /// ['Send']: TransmitService::Send
@@ -25,6 +39,8 @@ where P:DataTransferProtocol<String,String,String>{
      stream:TcpStream,
      transmit:TransmitService,
      protocol: P,
+     reader:FrameReader,
+     max_body_size:usize
 }
 
 
@@ -33,15 +49,24 @@ where P:DataTransferProtocol<String,String,String>{
 /// Each client subscribes to one of the service for which they respresent.
 /// 1. Receive only Client
 /// 2. Send only Client
+/// 3. Presence Client
+/// 4. Admin Client
+/// 5. Peer, a federated server
 ///
 /// # Variants
 ///
-/// - `Sender`: Respresents a client that only sends data.
+/// - `Sender`: Respresents a client that only sends data, from the alias it holds.
 /// - `Receive`: Represents a client that only receives data.
+/// - `Presence`: Represents a client that queries and watches the online status of aliases.
+/// - `Admin`: Represents an operator inspecting and resetting the counters of the server.
+/// - `Peer`: Represents a federated server linking to this server, see [crate::server::federation].
 #[derive(Debug)]
 pub enum TransmitService{
     Send(String),
-    Receive(String)
+    Receive(String),
+    Presence(String),
+    Admin(String),
+    Peer(String)
 }
 
 impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
@@ -64,62 +89,122 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
           Ok(Self{
                stream:tcp_stream,
                transmit:service,
-               protocol:protocol
+               protocol,
+               reader:FrameReader::new(DEFAULT_MAX_BODY_SIZE+MAX_HEADER_SIZE),
+               max_body_size:DEFAULT_MAX_BODY_SIZE
           })
      }
 
+     /// Sets the maximum size of the body of a message sent by the client.
+     /// Larger messages are answered with [Status::MessageTooLarge]
+     pub fn set_max_body_size(&mut self, max_body_size:usize){
+          self.max_body_size = max_body_size;
+          self.reader.set_max_frame_size(max_body_size+MAX_HEADER_SIZE);
+     }
+
+     /// Replaces the [FrameReader] of the handler, keeping the bytes it read past the handshake
+     pub fn set_frame_reader(&mut self, mut reader:FrameReader){
+          reader.set_max_frame_size(self.max_body_size+MAX_HEADER_SIZE);
+          self.reader = reader;
+     }
+
      /// Handles [TransmitService::Send] type client 
      /// `If handler reads 0 data from stream buffer it disconnects from client stream`
      /// 
+     /// # Returns
+     /// - `DisconnectReason`: The reason the handler stopped handling the client
+     /// 
      /// # Arguments
-     /// - `chx`: A [std::sync::mpsc::Sender<T>] object associated with a channel. Since this method handles [TransmitService::Send] type clients it awaits for 
-     ///            incoming data in streams to send to the Receiver type stored in [crate::server] pool
-     ///            Type `<T>` should be a pto object that implements Proto to transfer data between threads
-     pub fn handle_client_send(&mut self, rcp:Arc<Mutex<Vec<ClientReceiverContainer<BaseProto>>>>){
+     /// - `rcp`: The [ReceiverRegistry] of the server. Since this method handles [TransmitService::Send] type clients it awaits for 
+     ///   incoming data in streams to send to the receive clients registered in it
+     /// - `limiter`: The [RateLimiter] shared by the server. Messages over the limit are answered with [Status::Throttled],
+     ///   and banned clients are answered with [Status::Banned] and disconnected
+     /// - `quotas`: The [QuotaTracker] shared by the server. Messages over the quota of the alias are answered with [Status::QuotaExceeded]
+     /// - `mailbox`: The [Mailbox] shared by the server. Messages to an alias without a receive client are deposited in it
+     /// - `federation`: The [Federation] of the server. Messages to an alias not registered in rcp are forwarded to the peer hosting it,
+     ///   or deposited with the node owning it when no peer hosts it
+     /// - `sender`: The alias the client claimed with its secret in its handshake, if any. The rate limits and the quota of an alias
+     ///   only apply to the client claiming it, and its messages from any other alias are refused. The quota of a client
+     ///   claiming no alias is counted against its address
+     pub fn handle_client_send(&mut self, rcp:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>>, limiter:Arc<Mutex<RateLimiter>>, quotas:Arc<Mutex<QuotaTracker>>, mailbox:Arc<Mutex<Mailbox<BaseProto>>>, federation:Federation, sender:Option<String>)->DisconnectReason{
           warn!("Received and handling send");
-          loop {
-               //buffer to read input data
-               let mut buf:[u8;1024] = [0;1024];
+          let ip = match self.stream.peer_addr(){
+               Ok(addr)=>addr.ip(),
+               Err(e)=>{
+                    error!("Could not get the address of the send client {}", e);
+                    return DisconnectReason::StreamError(e.to_string());
+               }
+          };
 
-               //handles if the reading of stream returns an error
-               match self.stream.read(&mut buf){
-                    Err(e)=>{
-                         error!("An error occured while reading stream {{{}}}", e);
-                         continue;
-                    },
-                    Ok(0)=>{       //handles disconnected stream, when 0 data is read
-                         warn!("Stream has disconnected");
-                         break;
-                    },
-                    _=>{
+          loop {
+               //reads the next message frame, disconnects when the stream is closed
+               let frame = match self.next_frame(){
+                    Err(reason)=>return reason,
+                    Ok(frame)=>{
                          info!("Read data");
+                         frame
                     }
                };
+               let read = frame.len();
+               //every frame is identified, so that its response can be told apart
+               let id = next_message_id();
 
                //parses read data
-               let parsed = match self.protocol.parse(Data::Utf8(buf)){
+               let parsed = match self.protocol.parse(Data::Utf8(frame)){
                     Err(e)=>{
                          error!("An error occured while parsing protocol {}", e);
+                         self.respond_to(id, Status::InvalidFormat, e.to_string());
                          continue;
                     },
                     Ok(s)=>s
                };
 
+               //enforcing the maximum body size
+               let body_size = parsed.get_body().map(|b|b.len()).unwrap_or(0);
+               if body_size>self.max_body_size{
+                    warn!("Refused a message body of {body_size} bytes from {{ alias: {} }}", parsed.get_client_id());
+                    self.respond_to(id, Status::MessageTooLarge, format!("The message body exceeds the maximum size of {} bytes", self.max_body_size));
+                    continue;
+               }
+
+               //enforcing the rate limits of the claimed sender alias and ip
+               let verdict = limiter.lock().unwrap().check(sender.as_deref(), ip, read);
+               match verdict{
+                    Verdict::Allowed=>(),
+                    Verdict::Throttled=>{
+                         warn!("Throttled message from {{ alias: {}; ip: {} }}", sender.as_deref().unwrap_or("-"), ip);
+                         self.respond_to(id, Status::Throttled, "The rate limit has been exceeded, the message has been dropped".to_string());
+                         continue;
+                    },
+                    Verdict::Banned(remaining)=>{
+                         warn!("Disconnecting banned client {{ alias: {}; ip: {}; remaining: {}s }}", sender.as_deref().unwrap_or("-"), ip, remaining.as_secs());
+                         self.respond_to(id, Status::Banned, format!("The rate limit has been exceeded repeatedly, banned for {}s", remaining.as_secs()));
+                         return DisconnectReason::Banned;
+                    }
+               }
+
+               //a client that claimed its alias can only send messages from that alias
+               if let Some(claimed) = sender.as_deref().filter(|claimed|*claimed!=parsed.get_client_id()){
+                    warn!("Refused a message from {{ alias: {}; claimed: {}; ip: {} }}", parsed.get_client_id(), claimed, ip);
+                    self.respond_to(id, Status::Unauthorized, format!("The message is sent from '{}' but the client claimed '{claimed}'", parsed.get_client_id()));
+                    continue;
+               }
+
                //extracts data from parsed
                let username = parsed.get_to();
                
 
-               //rcp search for parsed username
-               //arc clone and locking to read data
-               let cloned_rcp:Arc<Mutex<Vec<ClientReceiverContainer<BaseProto>>>> = rcp.clone();
-               let rcp:MutexGuard<Vec<ClientReceiverContainer<BaseProto>>> = cloned_rcp.lock().unwrap();
-               let client_chx_sender = match self.search_rcp_sender_for(username, &rcp){
-                    Some(sender)=>sender,
-                    None=>{
-                         let s = "error getting sender".as_bytes();
-                         self.stream.write(s).expect("Something went wrong while printing error message back to client");
-                         continue;
-                    }
+               //rcp lookup for parsed username
+               //the senders are cloned out of the registry, since a full queue may block the sender
+               let client_chx_senders = rcp.senders_for(username);
+               let peer = match client_chx_senders.is_empty(){
+                    true=>federation.route(username),
+                    false=>None
+               };
+               //an alias registered nowhere has its messages held by the node owning it, once clustered
+               let owner = match client_chx_senders.is_empty() && peer.is_none(){
+                    true=>federation.remote_owner(username),
+                    false=>None
                };
 
                //unpacking parsed data
@@ -127,26 +212,47 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
                     Ok(body)=>body.to_string(),
                     Err(e)=>{
                          warn!("Could not parse body {}",e);
+                         self.respond_to(id, Status::InvalidFormat, e.to_string());
                          continue;
                     }
                };
                let to = parsed.get_to().to_string();
                let alias = parsed.get_client_id().to_string();
+               let size = body.len();
 
-               //Base proto instance creation to transfer data through channel
-               let pto = BaseProto::create(alias, body, to);
+               //reserving the message in the quota of the claimed sender alias, or of the address of the client
+               let account = sender.clone().unwrap_or_else(||ip.to_string());
+               if let Err(e) = quotas.lock().unwrap().consume(&account, size){
+                    warn!("Refused message from {{ account: {} }} {}", account, e);
+                    self.respond_to(id, Status::QuotaExceeded, e.to_string());
+                    continue;
+               }
 
-               //sending data through channel
-               if let Err(e) = client_chx_sender.send(pto){
-                    error!("Error sending data though stream from sender to receiver thread {}", e);
-               };
+               //Base proto instance creation to transfer data through channel, identified by a new message id
+               let pto = BaseProto::create(alias, body, to).with_id(id);
 
-               let res = Response::generate_res(Status::Success, "The message has been dispatched from sender handler".to_string());
-               match self.stream.write(res.as_bytes()){
-                    Err(e)=>{
-                         error!("Error occured while sending response status to client {{ {e} }}");
+               //messages to an alias hosted by a federated peer are forwarded to it and answered with its response,
+               //the messages the owner of the alias could not take are kept in the mailbox of this node until a rebalance
+               let res = match (peer, owner){
+                    (Some(peer), _)=>federation.forward(&peer, pto),
+                    (None, Some(owner))=>{
+                         let res = federation.deposit(&owner, pto.clone());
+                         match Response::parse(&res).is_ok_and(|r|r.get_status().is_success()){
+                              true=>res,
+                              false=>{
+                                   warn!("Could not deposit message {id} with {{ node: {owner} }} {}", res);
+                                   dispatch(&rcp, &mailbox, client_chx_senders, pto)
+                              }
+                         }
                     },
-                    _=>()
+                    (None, None)=>dispatch(&rcp, &mailbox, client_chx_senders, pto)
+               };
+               //only the messages delivered are counted against the quota
+               if !Response::parse(&res).is_ok_and(|r|r.get_status().is_success()){
+                    quotas.lock().unwrap().refund(&account, size);
+               }
+               if let Err(e) = write_frame(&mut self.stream, res.as_bytes()){
+                    error!("Error occured while sending response status to client {{ {e} }}");
                };
                info!("Message has been dispactched to {{ username: {username} }} thread listener...");
 
@@ -157,19 +263,50 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
      /// Handles [TransmitService::Receive] type client 
      /// 
      /// # Arguments
-     /// - `chx`: A [std::sync::mpsc::Receiver<T>] object associated with a channel. Since this method handles [TransmitService::Receive] type clients it awaits for 
-     ///            incoming data from a [std::sync::mpsc::Sender<T>] obejct associated with some other thread stored in the [crate::server] 
-     ///            pool of [crate::server::container::ClientSenderContainer]
-     pub fn handle_client_receive(&mut self, chx:Receiver<BaseProto>)->Result<(), ServerError>{
+     /// - `chx`: A [QueueReceiver<T>] object associated with a bounded delivery queue. Since this method handles [TransmitService::Receive] type clients it awaits for 
+     ///   incoming data from a [QueueSender<T>] obejct associated with some other thread stored in the [crate::server] 
+     ///   pool of [crate::server::container::ClientSenderContainer]
+     /// - `mailbox`: The [Mailbox] shared by the server. Messages still queued or in flight when the client disconnects are
+     ///   deposited in it for redelivery, and the messages written are journaled in it for the client to resume after
+     /// 
+     /// - `watcher`: The [Slot] of the [WorkerPool] reserved for the watcher of the client stream
+     /// 
+     /// A watcher job reads the client stream while the handler awaits messages, so that a client closing its stream
+     /// is noticed right away instead of on the next write
+     /// 
+     /// # Returns
+     /// - `DisconnectReason`: The reason the handler stopped handling the client
+     pub fn handle_client_receive(&mut self, chx:QueueReceiver<BaseProto>, mailbox:Arc<Mutex<Mailbox<BaseProto>>>, watcher:Slot)->DisconnectReason{
           warn!("Received and handling receive");
-          loop {
-               let pto = match chx.recv(){
-                    Err(e)=>{
-                         return Result::Err(ServerError::ThreadError(ThreadError::ChannelReceiveError(e)));
-                    },
-                    Ok(t)=>{
-                         warn!("Received message");
-                         t
+          let alias = match &self.transmit{
+               TransmitService::Receive(alias)=>alias.clone(),
+               _=>String::new()
+          };
+
+          //watcher job closing the queue once the client closes its stream
+          let peer_reason = Arc::new(Mutex::new(None));
+          match self.stream.try_clone(){
+               Ok(stream)=>{
+                    let closer = chx.closer();
+                    let reason = peer_reason.clone();
+                    watcher.execute(move ||watch_receive_stream(stream, closer, reason));
+               },
+               Err(e)=>return DisconnectReason::StreamError(e.to_string())
+          }
+
+          //the pending messages left in the mailbox while the queue was full, delivered before the queue once it is drained
+          let mut backlog = VecDeque::new();
+          let (reason, in_flight) = loop {
+               let pto = match backlog.pop_front(){
+                    Some(pto)=>pto,
+                    None=>match chx.recv(){
+                         Err(_)=>{
+                              break (DisconnectReason::QueueClosed, None);
+                         },
+                         Ok(t)=>{
+                              warn!("Received message");
+                              t
+                         }
                     }
                };
 
@@ -177,7 +314,7 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
                let username = pto.get_receiver().to_owned();
 
                //attempting to convert pto to raw bytes
-               let raw = match self.protocol.to_raw(pto){
+               let raw = match self.protocol.to_raw(pto.clone()){
                     Ok(byte_vec)=>byte_vec,
                     Err(e)=>{
                          error!("Error converting pto to raw bytes in handle_client_receive {}",e);
@@ -186,28 +323,308 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
                };
 
                //writes to receive client stream
-               match self.stream.write(&raw){
-                    Err(e)=>{
-                         error!("Error writing {{ {} }}", e);
-                    },
-                    _=>()
+               if let Err(e) = write_frame(&mut self.stream, &raw){
+                    error!("Error writing {{ {} }}", e);
+                    break (DisconnectReason::StreamError(e.to_string()), Some(pto));
                };
 
+               //journaling the written message, for the client to resume after it
+               let mut mailbox = mailbox.lock().unwrap();
+               mailbox.record(&alias, &pto);
+               if backlog.is_empty() && chx.is_empty(){
+                    backlog.extend(mailbox.take(&alias));
+               }
+               drop(mailbox);
+
                //logs
                info!("Successfully written to {{ username: {}; type: RECEIVE }}", username)
+          };
+
+          //unblocks the watcher job, the write half stays open for the server to notify the client.
+          //The reason is taken beforehand, the watcher reads the end of the stream once unblocked
+          let peer = peer_reason.lock().unwrap().take();
+          let _ = self.stream.shutdown(Shutdown::Read);
+
+          //re-queueing the in-flight and queued messages for redelivery
+          let mut undelivered:Vec<BaseProto> = in_flight.into_iter().collect();
+          undelivered.extend(backlog);
+          undelivered.extend(chx.drain());
+          if !undelivered.is_empty(){
+               info!("Re-queued {} undelivered messages of {{ username: {} }}", undelivered.len(), alias);
+               mailbox.lock().unwrap().deposit(&alias, undelivered);
+          }
+
+          peer.unwrap_or(reason)
+     }
+
+     /// Handles [TransmitService::Presence] type client
+     /// `If handler reads 0 data from stream buffer it disconnects from client stream and unsubscribes from all watched aliases`
+     ///
+     /// Presence changes of watched aliases are written to the client by a separate notifier job as `PRESENCE;<alias>;<state>;<status>`
+     ///
+     /// # Arguments
+     /// - `presence`: The [PresenceRegistry] shared by the server, used to query, watch and set the status of aliases
+     /// - `id`: The unique key of this session, used to subscribe to the registry
+     /// - `claimed`: True if the client claimed its alias with its secret, the status of an unclaimed alias is not set
+     /// - `notifier`: The [Slot] of the [WorkerPool] reserved for the notifier job
+     pub fn handle_client_presence(&mut self, presence:Arc<Mutex<PresenceRegistry>>, id:u64, claimed:bool, notifier:Slot)->Result<(), ServerError>{
+          warn!("Received and handling presence");
+          let alias = match &self.transmit{
+               TransmitService::Presence(alias)=>alias.clone(),
+               _=>String::new()
+          };
+
+          //writer shared between this handler and the notifier job
+          let writer = match self.stream.try_clone(){
+               Ok(stream)=>Arc::new(Mutex::new(stream)),
+               Err(e)=>return Err(ServerError::StreamReadError(e))
+          };
+
+          //notifier job dispatching presence events to the client
+          let (watcher, events):(Sender<PresenceEvent>, Receiver<PresenceEvent>) = channel();
+          let notifier_writer = writer.clone();
+          notifier.execute(move ||{
+               for event in events{
+                    let notification = format!("PRESENCE;{}", event);
+                    if let Err(e) = write_frame(&mut *notifier_writer.lock().unwrap(), notification.as_bytes()){
+                         error!("Error writing presence notification {{ {} }}", e);
+                         break;
+                    }
+               }
+          });
+
+          loop {
+               let frame = match self.next_frame(){
+                    Err(_)=>break,
+                    Ok(frame)=>frame
+               };
+
+               let command = match get_presence_command(&frame){
+                    Ok(c)=>c,
+                    Err(e)=>{
+                         error!("An error occured while parsing presence command {}", e);
+                         let res = Response::generate_res(Status::InvalidFormat, e.to_string());
+                         let _ = write_frame(&mut *writer.lock().unwrap(), res.as_bytes());
+                         continue;
+                    }
+               };
+
+               let res = {
+                    let mut registry = presence.lock().unwrap();
+                    match command{
+                         PresenceCommand::Query(aliases)=>{
+                              let states:Vec<String> = aliases.iter()
+                                   .map(|a|registry.query(a).to_string())
+                                   .collect();
+                              Response::generate_res(Status::Success, states.join("\n"))
+                         },
+                         PresenceCommand::Watch(aliases)=>{
+                              for a in &aliases{
+                                   registry.watch(a, id, watcher.clone());
+                              }
+                              Response::generate_res(Status::Success, format!("Watching {}", aliases.join(",")))
+                         },
+                         PresenceCommand::Unwatch(aliases)=>{
+                              for a in &aliases{
+                                   registry.unwatch(a, id);
+                              }
+                              Response::generate_res(Status::Success, format!("Stopped watching {}", aliases.join(",")))
+                         },
+                         PresenceCommand::Status(_) if !claimed=>{
+                              Response::generate_res(Status::Unauthorized, format!("The status of {alias} can only be set by a client claiming it with its secret"))
+                         },
+                         PresenceCommand::Status(status)=>{
+                              registry.set_status(&alias, &status);
+                              Response::generate_res(Status::Success, format!("Status of {alias} has been updated"))
+                         }
+                    }
+               };
+
+               if let Err(e) = write_frame(&mut *writer.lock().unwrap(), res.as_bytes()){
+                    error!("Error occured while sending response status to client {{ {e} }}");
+               };
           }
+
+          //unsubscribing drops the watcher channels which ends the notifier job
+          presence.lock().unwrap().unwatch_all(id);
+          Ok(())
      }
 
-     fn search_rcp_sender_for<X>(&self,username:&String, rcp:&Vec<ClientReceiverContainer<X>>)->Option<Sender<X>>
-     where X:Proto<String,String,String>{
-          for crp in rcp{
-               if crp.get_alias().to_string()==username.to_string(){
-                    return crp.get_sender();
+     /// Handles [TransmitService::Admin] type client
+     /// `If handler reads 0 data from stream buffer it disconnects from client stream`
+     ///
+     /// # Arguments
+     /// - `quotas`: The [QuotaTracker] shared by the server, whose counters are inspected and reset
+     /// - `limiter`: The [RateLimiter] shared by the server, whose bans are lifted
+     /// - `pool`: The [WorkerPool] running the handlers, whose metrics are inspected
+     /// - `federation`: The [Federation] of the server, whose cluster members and alias owners are inspected
+     /// - `ownership`: The [AliasOwnership] of the server, whose owners are released
+     pub fn handle_client_admin(&mut self, quotas:Arc<Mutex<QuotaTracker>>, limiter:Arc<Mutex<RateLimiter>>, pool:WorkerPool, federation:Federation, ownership:Arc<Mutex<AliasOwnership>>)->Result<(), ServerError>{
+          warn!("Received and handling admin");
+          loop {
+               let frame = match self.next_frame(){
+                    Err(_)=>break,
+                    Ok(frame)=>frame
+               };
+
+               let command = match get_admin_command(&frame){
+                    Ok(c)=>c,
+                    Err(e)=>{
+                         error!("An error occured while parsing admin command {}", e);
+                         self.respond(Status::InvalidFormat, e.to_string());
+                         continue;
+                    }
+               };
+
+               match command{
+                    AdminCommand::Quota(None)=>{
+                         let usages:Vec<String> = quotas.lock().unwrap().usages().iter()
+                              .map(|(alias, usage)|format!("{alias};{usage}"))
+                              .collect();
+                         self.respond(Status::Success, usages.join("\n"));
+                    },
+                    AdminCommand::Quota(Some(alias))=>{
+                         let usage = quotas.lock().unwrap().usage(&alias);
+                         self.respond(Status::Success, format!("{alias};{usage}"));
+                    },
+                    AdminCommand::Reset(None)=>{
+                         quotas.lock().unwrap().reset_all();
+                         info!("Admin reset the quota counters of every alias");
+                         self.respond(Status::Success, "The quota counters of every alias have been reset".to_string());
+                    },
+                    AdminCommand::Reset(Some(alias))=>{
+                         quotas.lock().unwrap().reset(&alias);
+                         info!("Admin reset the quota counters of {{ alias: {alias} }}");
+                         self.respond(Status::Success, format!("The quota counters of {alias} have been reset"));
+                    },
+                    AdminCommand::Unban(target)=>{
+                         let mut limiter = limiter.lock().unwrap();
+                         match target.parse(){
+                              Ok(ip)=>limiter.unban_ip(ip),
+                              Err(_)=>limiter.unban_alias(&target)
+                         }
+                         drop(limiter);
+                         info!("Admin lifted the ban of {{ target: {target} }}");
+                         self.respond(Status::Success, format!("The ban of {target} has been lifted"));
+                    },
+                    AdminCommand::Pool=>{
+                         self.respond(Status::Success, pool.metrics().to_string());
+                    },
+                    AdminCommand::Cluster=>{
+                         self.respond(Status::Success, format!("node={};members={};peers={};pending={}",
+                              federation.get_name(), federation.members().join(","), federation.linked_peers().join(","), federation.pending_messages()));
+                    },
+                    AdminCommand::Owner(alias)=>{
+                         let owner = federation.owner(&alias).unwrap_or("-".to_string());
+                         self.respond(Status::Success, format!("{alias};{owner}"));
+                    },
+                    AdminCommand::Release(alias)=>{
+                         match ownership.lock().unwrap().unregister(&alias){
+                              true=>{
+                                   info!("Admin released the owner of {{ alias: {alias} }}");
+                                   self.respond(Status::Success, format!("The owner of {alias} has been released"));
+                              },
+                              false=>self.respond(Status::UnknownRecipient, format!("{alias} has no owner"))
+                         }
+                    }
                }
           }
+          Ok(())
+     }
+
+     /// Reads the next frame from the client stream.
+     /// Frames larger than the maximum message size are answered with [Status::MessageTooLarge] and skipped
+     ///
+     /// # Returns
+     /// - `Err(DisconnectReason)` if the client has disconnected or the stream could not be read
+     fn next_frame(&mut self)->Result<Vec<u8>, DisconnectReason>{
+          loop {
+               match self.reader.read_frame(&mut self.stream){
+                    Ok(Some(frame))=>return Ok(frame),
+                    Ok(None)=>{         //handles disconnected stream, when 0 data is read
+                         warn!("Stream has disconnected");
+                         return Err(DisconnectReason::Closed);
+                    },
+                    Err(FrameError::TooLarge(size))=>{
+                         warn!("Refused a frame of {size} bytes");
+                         self.respond(Status::MessageTooLarge, format!("The message body exceeds the maximum size of {} bytes", self.max_body_size));
+                    },
+                    Err(e)=>{
+                         error!("An error occured while reading stream {{{}}}", e);
+                         return Err(DisconnectReason::StreamError(e.to_string()));
+                    }
+               }
+          }
+     }
+
+     /// Writes a response frame to the client
+     fn respond(&mut self, status:Status, message:String){
+          let res = Response::generate_res(status, message);
+          if let Err(e) = write_frame(&mut self.stream, res.as_bytes()){
+               error!("Error occured while sending response status to client {{ {e} }}");
+          };
+     }
+
+     /// Writes the response frame of the message `id` to the client
+     fn respond_to(&mut self, id:u64, status:Status, message:String){
+          let res = Response::generate_res_for(id, status, message);
+          if let Err(e) = write_frame(&mut self.stream, res.as_bytes()){
+               error!("Error occured while sending response status to client {{ {e} }}");
+          };
+     }
+}
 
-          return None;
+/// Queues a message to every receive client of its recipient, whose `senders` were looked up in rcp.
+/// Receive clients whose queue has been closed for being too slow are disconnected as slow consumers.
+/// A message to an alias without a receive client, or whose receive clients are gone, is deposited in the mailbox
+/// and delivered when the alias registers again.
+/// Used for the messages of send clients and for the messages forwarded by a federated peer
+///
+/// # Returns
+/// - `String`: The response frame of the message, [Status::Success] when it has been queued for at least one session
+///   of the alias or deposited. The sessions that could not take it are named in the message of the response
+pub fn dispatch(rcp:&ReceiverRegistry<ClientReceiverContainer<BaseProto>>, mailbox:&Arc<Mutex<Mailbox<BaseProto>>>, senders:Vec<QueueSender<BaseProto>>, pto:BaseProto)->String{
+     let username = pto.get_receiver().clone();
+     if senders.is_empty(){
+          return deposit(rcp, mailbox, pto);
+     }
+
+     //sending data through channel, to every session registered for the alias
+     let mut outcome = Dispatch::new();
+     let policy = senders[0].get_policy();
+     for client_chx_sender in &senders{
+          outcome.record(&username, client_chx_sender.send(pto.clone()));
      }
+
+     //disconnecting the receive clients whose queue has been closed
+     if outcome.has_slow_consumer(){
+          let notice = slow_consumer_notice();
+          for mut container in rcp.remove_closed(&username){
+               warn!("Disconnecting slow consumer {}", container);
+               container.disconnect(notice.as_bytes());
+          }
+     }
+
+     outcome.respond(rcp, mailbox, pto, policy)
+}
+
+/// Reads the stream of a [TransmitService::Receive] client until it is closed, then closes its delivery queue.
+/// Receive clients are not expected to send data, any data read is discarded.
+/// The reason the stream stopped is stored in `peer_reason`, [DisconnectReason::Closed] when the client closed it
+fn watch_receive_stream(mut stream:TcpStream, closer:QueueCloser<BaseProto>, peer_reason:Arc<Mutex<Option<DisconnectReason>>>){
+     let mut buf = [0;1024];
+     let reason = loop {
+          match stream.read(&mut buf){
+               Ok(0)=>break DisconnectReason::Closed,
+               Ok(_)=>continue,
+               Err(e)=>{
+                    warn!("Receive stream watcher stopped {}", e);
+                    break DisconnectReason::StreamError(e.to_string());
+               }
+          }
+     };
+     *peer_reason.lock().unwrap() = Some(reason);
+     closer.close();
 }
 
 /// creates a new handler object to handle a client by using default protocol ['BaseProtocol']
@@ -227,11 +644,7 @@ impl <P:DataTransferProtocol<String,String,String>> StreamHandler<P>{
 /// * `Result<StreamHandler<BaseProtocol>, ServerError>`
 /// 
 pub fn default_new(tcp_stream:TcpStream, service:TransmitService)->Result<StreamHandler<BaseProtocol>, ServerError>{
-     Ok(StreamHandler{
-          stream:tcp_stream,
-          transmit:service,
-          protocol:BaseProtocol::new()
-     })
+     StreamHandler::new(tcp_stream, BaseProtocol::new(), service)
 }
 
 ///Clone implementation for Transmit Service
@@ -240,6 +653,9 @@ impl Clone for TransmitService {
           match self {
                Self::Send(s) => Self::Send(s.clone()),
                Self::Receive(s) => Self::Receive(s.clone()),
+               Self::Presence(s) => Self::Presence(s.clone()),
+               Self::Admin(s) => Self::Admin(s.clone()),
+               Self::Peer(s) => Self::Peer(s.clone()),
           }
      }
 }
\ No newline at end of file
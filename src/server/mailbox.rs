@@ -0,0 +1,417 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use super::protocol::pto::Proto;
+
+
+/// Time a message written to a receive client stays journaled for the client to resume after it
+pub const JOURNAL_MAX_AGE:Duration = Duration::from_secs(300);
+
+/// Maximum size of the messages journaled for every alias together, the oldest are dropped past it
+pub const JOURNAL_MAX_BYTES:usize = 16*1024*1024;
+
+/// Time a pending message is kept for its alias to register, since it was last deposited
+pub const PENDING_MAX_AGE:Duration = Duration::from_secs(24*3600);
+
+/// Maximum number of pending messages of every alias together, messages sent past it are refused
+pub const PENDING_MAX_MESSAGES:usize = 100_000;
+
+/// Maximum size of the pending messages of every alias together, messages sent past it are refused
+pub const PENDING_MAX_BYTES:usize = 64*1024*1024;
+
+/// Interval at which the pending messages of every alias are checked for expiry
+const SWEEP_INTERVAL:Duration = Duration::from_secs(60);
+
+/// A message awaiting redelivery to an alias
+///
+/// # Fields
+///
+/// - `at`: The time the message was deposited, it expires [PENDING_MAX_AGE] after it
+/// - `size`: The size of the message, counted against [PENDING_MAX_BYTES]
+/// - `message`: The message
+#[derive(Debug)]
+struct Pending<T>{
+     at:Instant,
+     size:usize,
+     message:T
+}
+
+/// A message journaled after it was written to a receive client
+///
+/// # Fields
+///
+/// - `seq`: The position of the entry in the journal of every alias
+/// - `size`: The size of the message, counted against [JOURNAL_MAX_BYTES]
+/// - `message`: The message written
+#[derive(Debug)]
+struct Journaled<T>{
+     seq:u64,
+     size:usize,
+     message:T
+}
+
+/// A struct representing the messages awaiting redelivery to an alias.
+/// Messages that were queued or in flight when a receive client disconnected are deposited here,
+/// and handed to the next receive client registering the alias.
+/// The messages written to each alias are also journaled, so that a receive client resuming after the last message it
+/// was delivered is delivered the messages written after it again, eg.. messages lost with a broken connection.
+/// Journaled messages are kept for [JOURNAL_MAX_AGE], within [JOURNAL_MAX_BYTES] for every alias together.
+/// Pending messages are kept for [PENDING_MAX_AGE], and a message sent to an alias without a receive client is only
+/// admitted within [PENDING_MAX_MESSAGES] and [PENDING_MAX_BYTES] for every alias together.
+/// The mailbox is only held in memory: the pending and journaled messages are lost when the server stops, a client
+/// resuming on a restarted server is only delivered the messages sent since the restart
+///
+/// # Fields
+///
+/// - `pending`: The pending messages of each alias, oldest first
+/// - `pending_messages`: The number of pending messages of every alias
+/// - `pending_bytes`: The size of the pending messages of every alias
+/// - `last_sweep`: The last time the expired pending messages were dropped
+/// - `written`: The journal of the messages last written to each alias, oldest first
+/// - `order`: The position, time and alias of the entries of every journal, oldest first, used to drop the oldest entries.
+///   An entry already dropped from its journal is skipped
+/// - `next_seq`: The position of the next journaled message
+/// - `journal_bytes`: The size of the journaled messages
+/// - `capacity`: The maximum number of pending and journaled messages kept per alias, the oldest are dropped past it
+#[derive(Debug)]
+pub struct Mailbox<T>{
+     pending:HashMap<String, VecDeque<Pending<T>>>,
+     pending_messages:usize,
+     pending_bytes:usize,
+     last_sweep:Instant,
+     written:HashMap<String, VecDeque<Journaled<T>>>,
+     order:VecDeque<(u64, Instant, String)>,
+     next_seq:u64,
+     journal_bytes:usize,
+     capacity:usize
+}
+
+impl <T>Mailbox<T>{
+     /// Default constructor for an empty [Mailbox] keeping at most `capacity` messages per alias
+     pub fn new(capacity:usize)->Self{
+          Mailbox{
+               pending:HashMap::new(),
+               pending_messages:0,
+               pending_bytes:0,
+               last_sweep:Instant::now(),
+               written:HashMap::new(),
+               order:VecDeque::new(),
+               next_seq:0,
+               journal_bytes:0,
+               capacity:capacity.max(1)
+          }
+     }
+
+     /// Takes every pending message of an alias that has not expired, oldest first
+     pub fn take(&mut self, alias:&str)->Vec<T>{
+          let pending = match self.pending.remove(alias){
+               Some(pending)=>pending,
+               None=>return Vec::new()
+          };
+          self.pending_messages-=pending.len();
+          self.pending_bytes-=pending.iter().map(|p|p.size).sum::<usize>();
+          let now = Instant::now();
+          pending.into_iter()
+               .filter(|p|now.duration_since(p.at)<=PENDING_MAX_AGE)
+               .map(|p|p.message)
+               .collect()
+     }
+
+     /// Returns the aliases with pending messages
+     pub fn pending_aliases(&self)->Vec<String>{
+          self.pending.keys().cloned().collect()
+     }
+
+     /// Returns the number of pending messages of an alias
+     pub fn len(&self, alias:&str)->usize{
+          self.pending.get(alias).map(|p|p.len()).unwrap_or(0)
+     }
+
+     /// Returns true if no message is pending for any alias
+     pub fn is_empty(&self)->bool{
+          self.pending.is_empty()
+     }
+
+     /// Sets the maximum number of pending and journaled messages kept per alias
+     pub fn set_capacity(&mut self, capacity:usize){
+          self.capacity = capacity.max(1);
+     }
+
+     /// Drops the pending messages deposited more than [PENDING_MAX_AGE] ago, at most once every [SWEEP_INTERVAL]
+     fn expire(&mut self, now:Instant){
+          if now.duration_since(self.last_sweep)<SWEEP_INTERVAL{
+               return;
+          }
+          self.last_sweep = now;
+          let (mut messages, mut bytes) = (0, 0);
+          self.pending.retain(|alias, pending|{
+               let before = pending.len();
+               while pending.front().is_some_and(|p|now.duration_since(p.at)>PENDING_MAX_AGE){
+                    if let Some(expired) = pending.pop_front(){
+                         bytes+=expired.size;
+                    }
+               }
+               if pending.len()<before{
+                    warn!("Dropped {} expired pending messages of {{ alias: {alias} }}", before-pending.len());
+               }
+               messages+=before-pending.len();
+               !pending.is_empty()
+          });
+          self.pending_messages-=messages;
+          self.pending_bytes-=bytes;
+     }
+
+     /// Drops the oldest pending messages of an alias past the capacity of the mailbox
+     fn trim(&mut self, alias:&str){
+          let pending = match self.pending.get_mut(alias){
+               Some(pending)=>pending,
+               None=>return
+          };
+          if pending.len()>self.capacity{
+               let dropped = pending.len()-self.capacity;
+               self.pending_messages-=dropped;
+               self.pending_bytes-=pending.drain(..dropped).map(|p|p.size).sum::<usize>();
+               warn!("Mailbox of {{ alias: {alias} }} is full, dropped {dropped} pending messages");
+          }
+          if pending.is_empty(){
+               self.pending.remove(alias);
+          }
+     }
+
+     /// Drops the journal of an alias, once its receive client left and will not resume
+     pub fn forget(&mut self, alias:&str){
+          if let Some(written) = self.written.remove(alias){
+               self.journal_bytes-=written.iter().map(|j|j.size).sum::<usize>();
+          }
+     }
+
+     /// Drops the oldest journaled message of every alias together
+     ///
+     /// # Returns
+     /// - `bool`: False once no message is journaled
+     fn drop_oldest(&mut self)->bool{
+          let (seq, _, alias) = match self.order.pop_front(){
+               None=>return false,
+               Some(entry)=>entry
+          };
+          if let Some(written) = self.written.get_mut(&alias){
+               if written.front().is_some_and(|j|j.seq==seq){
+                    if let Some(dropped) = written.pop_front(){
+                         self.journal_bytes-=dropped.size;
+                    }
+               }
+               if written.is_empty(){
+                    self.written.remove(&alias);
+               }
+          }
+          true
+     }
+}
+
+impl <T:Proto<String,String,String>+Clone>Mailbox<T>{
+     /// Deposits messages for an alias, after the messages already pending. Used for the messages the server already
+     /// accepted, eg.. messages re-queued when a receive client disconnected, which are kept past the limits of every alias together
+     pub fn deposit<I:IntoIterator<Item = T>>(&mut self, alias:&str, messages:I){
+          let now = Instant::now();
+          self.expire(now);
+          let pending = self.pending.entry(alias.to_string()).or_default();
+          for message in messages{
+               let size = size_of(&message);
+               self.pending_messages+=1;
+               self.pending_bytes+=size;
+               pending.push_back(Pending{ at:now, size, message });
+          }
+          self.trim(alias);
+     }
+
+     /// Deposits a message sent to an alias without a receive client, unless the pending messages of every alias together
+     /// are at [PENDING_MAX_MESSAGES] or [PENDING_MAX_BYTES]
+     ///
+     /// # Returns
+     /// - `Result<(), T>`: The message handed back when it was refused
+     pub fn admit(&mut self, alias:&str, message:T)->Result<(), T>{
+          self.expire(Instant::now());
+          if self.pending_messages>=PENDING_MAX_MESSAGES || self.pending_bytes+size_of(&message)>PENDING_MAX_BYTES{
+               return Err(message);
+          }
+          self.deposit(alias, [message]);
+          Ok(())
+     }
+
+     /// Puts messages taken from an alias back, before the messages deposited since
+     pub fn restore(&mut self, alias:&str, messages:Vec<T>){
+          let now = Instant::now();
+          let pending = self.pending.entry(alias.to_string()).or_default();
+          for message in messages.into_iter().rev(){
+               let size = size_of(&message);
+               self.pending_messages+=1;
+               self.pending_bytes+=size;
+               pending.push_front(Pending{ at:now, size, message });
+          }
+          self.trim(alias);
+     }
+
+     /// Journals a message written to a receive client of an alias.
+     /// A message written to several sessions of the alias in a row is journaled once
+     pub fn record(&mut self, alias:&str, message:&T){
+          let now = Instant::now();
+          //dropping the messages journaled for too long
+          while self.order.front().is_some_and(|(_, at, _)|now.duration_since(*at)>JOURNAL_MAX_AGE){
+               self.drop_oldest();
+          }
+
+          let written = self.written.entry(alias.to_string()).or_default();
+          if message.get_id().is_some() && written.back().is_some_and(|j|j.message.get_id()==message.get_id()){
+               return;
+          }
+          let size = size_of(message);
+          written.push_back(Journaled{
+               seq:self.next_seq,
+               size,
+               message:message.clone()
+          });
+          if written.len()>self.capacity{
+               if let Some(dropped) = written.pop_front(){
+                    self.journal_bytes-=dropped.size;
+               }
+          }
+          self.order.push_back((self.next_seq, now, alias.to_string()));
+          self.next_seq+=1;
+          self.journal_bytes+=size;
+
+          while self.journal_bytes>JOURNAL_MAX_BYTES && self.drop_oldest(){}
+     }
+
+     /// Takes every pending message of an alias, after the journaled messages written after the message `last`.
+     /// When `last` is no longer journaled, the journaled messages with a greater id are taken instead
+     pub fn take_after(&mut self, alias:&str, last:u64)->Vec<T>{
+          let mut messages = Vec::new();
+          if let Some(mut written) = self.written.remove(alias){
+               self.journal_bytes-=written.iter().map(|j|j.size).sum::<usize>();
+               //the messages up to `last` were delivered, they are dropped from the journal
+               match written.iter().position(|j|j.message.get_id()==Some(last)){
+                    Some(i)=>{
+                         written.drain(..=i);
+                    },
+                    None=>written.retain(|j|j.message.get_id().is_some_and(|id|id>last))
+               }
+               messages.extend(written.into_iter().map(|j|j.message));
+          }
+          messages.extend(self.take(alias));
+          messages
+     }
+}
+
+/// Returns the size of a message, counted against the byte limits of the mailbox
+fn size_of<T:Proto<String,String,String>>(message:&T)->usize{
+     message.get_sender().len()+message.get_receiver().len()+message.get_body().len()
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use crate::server::protocol::pto::BaseProto;
+
+     fn message(id:u64, body:&str)->BaseProto{
+          BaseProto::create("alice".to_string(), body.to_string(), "bob".to_string()).with_id(id)
+     }
+
+     fn ids(messages:&[BaseProto])->Vec<u64>{
+          messages.iter().filter_map(|m|m.get_id()).collect()
+     }
+
+     #[test]
+     fn admits_up_to_the_limit_of_every_alias_together(){
+          let mut mailbox = Mailbox::new(PENDING_MAX_MESSAGES);
+          for i in 0..PENDING_MAX_MESSAGES as u64{
+               assert!(mailbox.admit(&format!("alias{}", i%1000), message(i, "")).is_ok());
+          }
+          assert!(mailbox.admit("bob", message(0, "")).is_err());
+
+          //a message taken out makes room again, re-queued messages are kept past the limit
+          assert_eq!(mailbox.take("alias0").len(), PENDING_MAX_MESSAGES/1000);
+          assert!(mailbox.admit("bob", message(1, "")).is_ok());
+          mailbox.deposit("alias1", (0..10_000).map(|i|message(i, "")));
+          assert_eq!(mailbox.len("alias1"), PENDING_MAX_MESSAGES/1000+10_000);
+     }
+
+     #[test]
+     fn refuses_a_message_past_the_byte_limit(){
+          let mut mailbox = Mailbox::new(16);
+          let large = "x".repeat(PENDING_MAX_BYTES/2);
+          assert!(mailbox.admit("bob", message(1, &large)).is_ok());
+          assert!(mailbox.admit("bob", message(2, &large)).is_err());
+          assert!(mailbox.admit("bob", message(3, "small")).is_ok());
+          assert_eq!(ids(&mailbox.take("bob")), [1, 3]);
+     }
+
+     #[test]
+     fn drops_the_oldest_past_the_capacity_of_an_alias(){
+          let mut mailbox = Mailbox::new(3);
+          mailbox.deposit("bob", (1..=5).map(|i|message(i, "")));
+          assert_eq!(ids(&mailbox.take("bob")), [3, 4, 5]);
+
+          mailbox.deposit("bob", [message(6, "")]);
+          mailbox.restore("bob", vec![message(4, ""), message(5, "")]);
+          assert_eq!(ids(&mailbox.take("bob")), [4, 5, 6]);
+          assert!(mailbox.is_empty());
+     }
+
+     #[test]
+     fn expires_pending_messages(){
+          let mut mailbox = Mailbox::new(16);
+          mailbox.deposit("bob", [message(1, "")]);
+          mailbox.deposit("carol", [message(2, "")]);
+          mailbox.expire(Instant::now()+PENDING_MAX_AGE+SWEEP_INTERVAL);
+          assert!(mailbox.is_empty());
+          assert!(mailbox.admit("bob", message(3, "")).is_ok());
+          assert_eq!(ids(&mailbox.take("bob")), [3]);
+     }
+
+     #[test]
+     fn journals_a_message_written_to_several_sessions_once(){
+          let mut mailbox = Mailbox::new(16);
+          for id in [1, 2, 2, 3]{
+               mailbox.record("bob", &message(id, ""));
+          }
+          assert_eq!(ids(&mailbox.take_after("bob", 1)), [2, 3]);
+          //the journal is taken along with the pending messages
+          assert!(mailbox.take_after("bob", 1).is_empty());
+     }
+
+     #[test]
+     fn resumes_after_a_message_no_longer_journaled(){
+          let mut mailbox = Mailbox::new(2);
+          for id in 1..=4{
+               mailbox.record("bob", &message(id, ""));
+          }
+          mailbox.deposit("bob", [message(5, "")]);
+          //only the last messages of the capacity are journaled, those after the one resumed after are delivered again
+          assert_eq!(ids(&mailbox.take_after("bob", 1)), [3, 4, 5]);
+     }
+
+     #[test]
+     fn drops_the_oldest_journaled_messages_past_the_byte_limit(){
+          let mut mailbox = Mailbox::new(16);
+          let large = "x".repeat(JOURNAL_MAX_BYTES/3);
+          mailbox.record("bob", &message(1, &large));
+          mailbox.record("carol", &message(2, &large));
+          mailbox.record("bob", &message(3, &large));
+          mailbox.record("bob", &message(4, &large));
+          assert!(mailbox.journal_bytes<=JOURNAL_MAX_BYTES);
+          assert!(mailbox.take_after("carol", 0).is_empty());
+          assert_eq!(ids(&mailbox.take_after("bob", 0)), [3, 4]);
+     }
+
+     #[test]
+     fn drops_the_journaled_messages_past_their_age(){
+          let mut mailbox = Mailbox::new(16);
+          mailbox.record("bob", &message(1, ""));
+          mailbox.record("bob", &message(2, ""));
+          mailbox.order[0].1 = Instant::now().checked_sub(JOURNAL_MAX_AGE+Duration::from_secs(1)).unwrap();
+          mailbox.record("carol", &message(3, ""));
+          assert_eq!(ids(&mailbox.take_after("bob", 0)), [2]);
+     }
+}
@@ -1,3 +1,4 @@
+#[cfg(feature="developement")]
 use std::{fmt::Debug, net::TcpStream};
 ///Trait to implement middlewares in the call stack
 #[cfg(feature="developement")]
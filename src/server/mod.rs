@@ -1,49 +1,125 @@
-/// A server service to handle ['transmit_service']
-/// 
-/// ['transmit_service']:handler::TransmitService
+//! A server service to handle ['transmit_service']
+//! 
+//! ['transmit_service']:handler::TransmitService
 
 pub mod protocol;
-pub mod middleware;
+mod middleware;
 pub mod auth;
 pub mod error;
 pub mod handler;
-pub mod container;         //Thread-stream container
-
-use std::{io::Read, net::{
-     TcpListener,
-     TcpStream
-}, sync::{mpsc::{
-     channel,
-     Receiver,
-     Sender
-}, Arc, Mutex, MutexGuard},thread:: {sleep, spawn},
- time::Duration
+mod container;             //Thread-stream container
+pub mod presence;
+pub mod queue;             //Bounded delivery queues
+pub mod ratelimit;
+pub mod quota;
+mod mailbox;
+pub mod shutdown;
+mod registry;              //Indexed container pools
+pub mod pool;                 //Worker thread pool
+mod connection;            //Connection handshake and registration
+mod delivery;              //Delivery decisions shared by the server cores
+pub mod config;               //Configuration file
+pub mod federation;           //Server-to-server links
+pub mod cluster;              //Consistent hashing of aliases onto nodes
+pub mod builder;
+#[cfg(feature="async")]
+pub mod aio;                  //Async server core
+
+use std::{io::ErrorKind, net::{SocketAddr, TcpListener}, sync::{atomic::{AtomicUsize, Ordering}, mpsc::sync_channel, Arc, Mutex},thread::sleep,
+ time::{Duration, Instant}
 };
-use log::{error, info};
+use log::{error, info, warn};
 
 use error::ServerError;
-use container::{ClientReceiverContainer, ClientSenderContainer};
-use handler::{StreamHandler, TransmitService, default_new};
-use protocol::{BaseProtocol, get_type_for_raw_utf8, pto::BaseProto};
+pub use builder::ServerBuilder;
+use auth::{AliasOwnership, DuplicatePolicy};
+use container::ClientReceiverContainer;
+use connection::{ConnectionContext, Handshaking};
+use registry::{ReceiverRegistry, SenderRegistry};
+use pool::{PoolMetrics, TaskHandle, WorkerPool};
+use mailbox::Mailbox;
+use presence::PresenceRegistry;
+use federation::Federation;
+use shutdown::ShutdownHandle;
+use queue::BackpressurePolicy;
+use ratelimit::{RateLimit, RateLimiter};
+use protocol::{frame::write_frame, pto::BaseProto, res::{Response, Status}};
+use quota::{Quota, QuotaTracker};
 
 
+/// Default maximum number of messages queued for each receive client
+pub const DEFAULT_QUEUE_CAPACITY:usize = 1024;
+
+/// Default maximum size of the body of a message, in bytes
+pub const DEFAULT_MAX_BODY_SIZE:usize = 64*1024;
+
+/// Default maximum number of connections handled at once, receive and presence connections count twice
+pub const DEFAULT_MAX_CONNECTIONS:usize = 1024;
+
+/// Default maximum number of connections waiting for a worker once the maximum number of connections is reached
+pub const DEFAULT_MAX_PENDING_CONNECTIONS:usize = 64;
+
+/// Default time given to the receive clients to be delivered their queued messages when the server shuts down
+pub const DEFAULT_DRAIN_TIMEOUT:Duration = Duration::from_secs(5);
+
+/// Default time a client is given to send its handshake
+pub const DEFAULT_HANDSHAKE_TIMEOUT:Duration = Duration::from_secs(10);
+
+/// Interval at which the listener is polled for connections and for a shutdown request
+const ACCEPT_POLL_INTERVAL:Duration = Duration::from_millis(50);
+
+/// Time given to the handler threads to exit once their client is disconnected on shutdown
+const JOIN_GRACE_PERIOD:Duration = Duration::from_millis(500);
+
 /// A struct representing a [Server] instance that binds on an endpoint anc
 /// accepts incoming stream requests and handles them using the [StreamHandler].
 /// It is implemented to use the [BaseProtocol] for transferring data and its pto [BaseProto]
 ///
 /// # Fields
 ///
-/// - `host`: The host on which the server is hosted
-/// - `port`: The port on which the server is posted
+/// - `addrs`: The addresses the server binds on, the first that can be bound is used
+/// - `listener`: The listener bound by [Server::bind], bound by [Server::serve] otherwise
 /// - `stream``: The pool record of incoming streams
-/// - `send_container_pool`: Or scp, a pool of [ClientSenderContainer], contains the pool of active running send client thread handles and their channels. Arc mutex to handle multi-threaded stream handling.
-/// - `receive_container_pool`: Or rcp, a pool of [ClientReceiverContainer], contains the pool of active running receive client thread handles and their channels. Arc mutex to handle multi-threaded stream handling.
+/// - `send_container_pool`: Or scp, a [SenderRegistry] of [ClientSenderContainer], contains the active running send, presence and admin client thread handles and their streams, indexed by container id.
+/// - `receive_container_pool`: Or rcp, a [ReceiverRegistry] of [ClientReceiverContainer], contains the active running receive client thread handles and their channels, indexed by alias with sharded locking.
+/// - `presence`: The [PresenceRegistry] tracking the online status of the aliases registered in rcp, shared with the presence client threads.
+/// - `ownership`: The [AliasOwnership] deciding which client may claim an alias in a RECEIVE or PRESENCE handshake.
+/// - `duplicate_policy`: The [DuplicatePolicy] applied when an alias is registered while another receive client holds it.
+/// - `queue_capacity`: The maximum number of messages queued for each receive client.
+/// - `backpressure_policy`: The [BackpressurePolicy] applied when the delivery queue of a receive client is full.
+/// - `rate_limiter`: The [RateLimiter] enforcing message and byte rates per alias and per source IP on send clients.
+/// - `quotas`: The [QuotaTracker] counting the messages and bytes sent by each alias against its quota.
+/// - `max_body_size`: The maximum size of the body of a message sent by a send client.
+/// - `admin_secret`: The secret admin clients authenticate with. Admin clients are refused when it is not set.
+/// - `mailbox`: The [Mailbox] holding the messages of disconnected receive clients until their alias registers again.
+/// - `shutdown`: The [ShutdownHandle] stopping the server once triggered.
+/// - `drain_timeout`: The time given to the receive clients to be delivered their queued messages on shutdown.
+/// - `pool`: The [WorkerPool] running the handlers, bounding the number of connections handled at once.
+/// - `handshake_timeout`: The time a client is given to send its handshake before it is refused.
+/// - `handshaking`: The number of connections accepted whose client is not registered yet.
+/// - `federation`: The [Federation] linking the server to its peers, messages to aliases hosted by a peer are forwarded to it.
 #[derive(Debug)]
 pub struct Server{
-     host:String,
-     port:i32,
-     send_container_pool:Arc<Mutex<Vec<ClientSenderContainer<BaseProto>>>>,
-     receive_container_pool:Arc<Mutex<Vec<ClientReceiverContainer<BaseProto>>>>,
+     addrs:Vec<SocketAddr>,
+     listener:Option<TcpListener>,
+     send_container_pool:Arc<SenderRegistry>,
+     receive_container_pool:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>>,
+     presence:Arc<Mutex<PresenceRegistry>>,
+     ownership:Arc<Mutex<AliasOwnership>>,
+     duplicate_policy:DuplicatePolicy,
+     queue_capacity:usize,
+     backpressure_policy:BackpressurePolicy,
+     rate_limiter:Arc<Mutex<RateLimiter>>,
+     quotas:Arc<Mutex<QuotaTracker>>,
+     max_body_size:usize,
+     admin_secret:Option<String>,
+     mailbox:Arc<Mutex<Mailbox<BaseProto>>>,
+     shutdown:ShutdownHandle,
+     drain_timeout:Duration,
+     pool:WorkerPool,
+     handshake_timeout:Duration,
+     handshaking:Arc<AtomicUsize>,
+     federation:Federation,
      stream_counter:u64,       //maintains the id for each incoming stream
      // middleware_pool:Vec<Box<dyn middleware::Middleware>>
 }
@@ -55,129 +131,341 @@ impl Server{
      /// 
      /// # Arguments
      ///
-     /// * `host` - the host on which the server has to run
-     /// * `port` - The port on which the server should run on
+     /// * `addr` - The address the server binds on, port 0 binds an ephemeral port. See [ServerBuilder] to bind on one of several addresses
      /// 
-     pub fn new(host:String, port:i32)->Self{
-          //container pool initialization
-          let scp:Vec<ClientSenderContainer<BaseProto>> = Vec::new();
-          let rcp:Vec<ClientReceiverContainer<BaseProto>> = Vec::new();
+     pub fn new(addr:SocketAddr)->Self{
+          Self::with_addrs(vec![addr])
+     }
 
-          //initialiing shared mutable datasource for multithreaded stream handlers
-          let rcp_shared:Arc<Mutex<Vec<ClientReceiverContainer<BaseProto>>>> = Arc::new(Mutex::new(rcp));
-          let scp_shared:Arc<Mutex<Vec<ClientSenderContainer<BaseProto>>>> = Arc::new(Mutex::new(scp));
+     /// Constructor for a server binding on the first of `addrs` that can be bound, used by [ServerBuilder]
+     fn with_addrs(addrs:Vec<SocketAddr>)->Self{
+          //initialiing the shared container registries for multithreaded stream handlers
+          let rcp_shared:Arc<ReceiverRegistry<ClientReceiverContainer<BaseProto>>> = Arc::new(ReceiverRegistry::new());
+          let scp_shared:Arc<SenderRegistry> = Arc::new(SenderRegistry::new());
+          let mailbox = Arc::new(Mutex::new(Mailbox::new(DEFAULT_QUEUE_CAPACITY)));
 
           info!("Initialized server.");
           Server{
-               host,
-               port,
+               addrs,
+               listener:None,
                send_container_pool:scp_shared,
-               receive_container_pool:rcp_shared,
+               receive_container_pool:rcp_shared.clone(),
+               presence:Arc::new(Mutex::new(PresenceRegistry::new())),
+               ownership:Arc::new(Mutex::new(AliasOwnership::new())),
+               duplicate_policy:DuplicatePolicy::default(),
+               queue_capacity:DEFAULT_QUEUE_CAPACITY,
+               backpressure_policy:BackpressurePolicy::default(),
+               rate_limiter:Arc::new(Mutex::new(RateLimiter::new(RateLimit::default()))),
+               quotas:Arc::new(Mutex::new(QuotaTracker::new(Quota::default()))),
+               max_body_size:DEFAULT_MAX_BODY_SIZE,
+               admin_secret:None,
+               mailbox:mailbox.clone(),
+               shutdown:ShutdownHandle::new(),
+               drain_timeout:DEFAULT_DRAIN_TIMEOUT,
+               pool:WorkerPool::new(DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_PENDING_CONNECTIONS),
+               handshake_timeout:DEFAULT_HANDSHAKE_TIMEOUT,
+               handshaking:Arc::new(AtomicUsize::new(0)),
+               federation:Federation::new(rcp_shared, mailbox),
                stream_counter:0
           }
      }
 
-     ///Starts serving at host port initailized while constructing the instance 
+     /// Binds the listener of the server without serving yet
+     ///
+     /// # Returns
+     /// - `SocketAddr`: The address the server is bound on, with the port picked by the system when port 0 was given
+     pub fn bind(&mut self)->Result<SocketAddr, ServerError>{
+          let listener = self.bind_listener()?;
+          let addr = match listener.local_addr(){
+               Ok(addr)=>addr,
+               Err(e)=>return Err(ServerError::AddressBindError(e))
+          };
+          self.listener = Some(listener);
+          Ok(addr)
+     }
+
+     ///Starts serving on the listener bound by [Server::bind], binding it first if needed
      /// Call this to run server
+     /// 
+     /// Returns once the [ShutdownHandle] of the server is triggered and the connected clients have been drained
      pub fn serve(&mut self)->Result<(), ServerError>{
-          //constructs address
-          let addr = self.construct_addr();
-          info!("Server is initialized and is starting on \"{addr}\"");
-
-          //initializing TcpListener
-          let listener = match TcpListener::bind(addr){
-               Ok(e)=>e,
-               Err(e)=>return Err(ServerError::AddressBindError(e))
+          let listener = match self.listener.take(){
+               Some(listener)=>listener,
+               None=>self.bind_listener()?
           };
+          if let Ok(addr) = listener.local_addr(){
+               info!("Server is initialized and is starting on \"{addr}\"");
+          }
+          self.federation.start(self.shutdown.clone());
+
+          //polling the listener so that a shutdown request is noticed without an incoming connection
+          if let Err(e) = listener.set_nonblocking(true){
+               return Err(ServerError::AddressBindError(e));
+          }
 
           loop {
+               if self.shutdown.is_requested(){
+                    drop(listener);
+                    self.drain();
+                    return Ok(());
+               }
+
                //accepting incoming streams
                let (mut stream, addr) = match listener.accept(){
                     Ok(tas)=>tas,
+                    Err(e) if e.kind()==ErrorKind::WouldBlock=>{
+                         sleep(ACCEPT_POLL_INTERVAL);
+                         continue;
+                    },
                     Err(e)=>return Err(ServerError::StreamAcceptError(e))
                };
+               if let Err(e) = stream.set_nonblocking(false){
+                    error!("Could not set incoming stream from {addr} to blocking mode {}", e);
+                    continue;
+               }
 
-               let client_service = match self.identify_request_type(&mut stream){
-                    None=>{continue;},
-                    Some(t)=>t
+               //reaping the containers of threads that exited without deregistering
+               self.reap();
 
-               };
+               //refusing banned addresses before the handshake
+               if let Some(remaining) = self.rate_limiter.lock().unwrap().is_banned_ip(addr.ip()){
+                    warn!("Refused incoming request from banned address {addr}");
+                    let res = Response::generate_res(Status::Banned, format!("Banned for {}s", remaining.as_secs()));
+                    let _ = write_frame(&mut stream, res.as_bytes());
+                    continue;
+               }
 
-               //handler creation to handle the incoming stream
-               let mut handler:StreamHandler<BaseProtocol> =  match default_new(stream, client_service.clone()){
-                    Ok(e)=>e,
-                    Err(e)=>{
-                         error!("Could not initialize stream handler due to... {}", e);
+               //reserving a worker for the connection, refusing it when the pool is full
+               let slot = match self.pool.reserve(){
+                    Some(slot)=>slot,
+                    None=>{
+                         warn!("Refused incoming request from {addr} -- worker pool is full {{ {} }}", self.pool.metrics());
+                         let res = Response::generate_res(Status::Busy, "The server is busy, try again later".to_string());
+                         let _ = write_frame(&mut stream, res.as_bytes());
                          continue;
                     }
                };
 
+               //the handshake is read by the worker of the connection, a silent client never holds up the accept loop
+               let key = self.generate_id();      //key generation for container id
+               let context = self.connection_context();
+               let handshaking = Handshaking::new(self.handshaking.clone());
+               let (task_tx, task_rx) = sync_channel(1);
+               let handle = slot.execute(move ||context.establish(stream, addr, key, task_rx, handshaking));
+               let _ = task_tx.send(handle);
+          }
+     }
 
-               //channels creartion to communicate between streams in different thread
-               let(sender, receiver):
-                         (Sender<BaseProto>, Receiver<BaseProto>) = channel();
+     /// Sets the [DuplicatePolicy] applied when an alias is registered while another receive client holds it
+     pub fn set_duplicate_policy(&mut self, policy:DuplicatePolicy){
+          self.duplicate_policy = policy;
+     }
 
+     /// Sets the maximum number of messages queued for each receive client, and kept for redelivery per alias
+     pub fn set_queue_capacity(&mut self, capacity:usize){
+          self.queue_capacity = capacity;
+          self.mailbox.lock().unwrap().set_capacity(capacity);
+     }
 
-               let key = self.generate_id();      //key generation for container id
+     /// Sets the [BackpressurePolicy] applied when the delivery queue of a receive client is full
+     pub fn set_backpressure_policy(&mut self, policy:BackpressurePolicy){
+          self.backpressure_policy = policy;
+     }
 
-               //moving the handling of each stream to their handlers in separate threads
-               match client_service {
-                    TransmitService::Receive(s)=>{
-                         let handle = spawn(move ||{
-                              let _ =handler.handle_client_receive(receiver);
-                         });
-                         info!("Accepted incoming request from {addr} -- {{ id: {}; receive_alias: {} }}", key, s);          //logging
-                         // container creation for this above handler and channel compoenents
-                         let container = ClientReceiverContainer::new(handle, sender, key,s);
-                         // cloning arc
-                         let cloned_shared_rcp = self.receive_container_pool.clone();
-                         // locking mutex
-                         let mut rcp = cloned_shared_rcp.lock().unwrap();
-                         rcp.push(container);
+     /// Replaces the [RateLimit] enforced on send clients, resetting the rate limiter
+     pub fn set_rate_limit(&mut self, limit:RateLimit){
+          self.rate_limiter = Arc::new(Mutex::new(RateLimiter::new(limit)));
+     }
 
-                    },
-                    TransmitService::Send(to)=>{
-                         let cloned_scp:Arc<Mutex<Vec<ClientReceiverContainer<BaseProto>>>> = self.receive_container_pool.clone();
-                         let handle = spawn(move ||{
-                              handler.handle_client_send(cloned_scp);
-                         });
-                         info!("Accepted incoming request from {addr} -- {{ id: {}; to_alias: {} }}", key, to);              //logging
-                         //container creation for this above handler and channel compoenents
-                         let container:ClientSenderContainer<BaseProto> = ClientSenderContainer::new(handle, receiver, key, to);
-                         //cloning scp arc
-                         let cloned_shared_scp:Arc<Mutex<Vec<ClientSenderContainer<BaseProto>>>> = self.send_container_pool.clone();
-                         //locking scp mutex
-                         let mut scp:MutexGuard<Vec<ClientSenderContainer<BaseProto>>> = cloned_shared_scp.lock().unwrap();
-                         scp.push(container);
-                         
-                    }
-               };
+     /// Returns the [RateLimiter] of the server to inspect or lift bans
+     pub fn get_rate_limiter(&self)->Arc<Mutex<RateLimiter>>{
+          self.rate_limiter.clone()
+     }
+
+     /// Replaces the [Quota] enforced on every alias, resetting the counters
+     pub fn set_quota(&mut self, quota:Quota){
+          self.quotas = Arc::new(Mutex::new(QuotaTracker::new(quota)));
+     }
+
+     /// Returns the [QuotaTracker] of the server to inspect and reset the counters of aliases
+     pub fn get_quotas(&self)->Arc<Mutex<QuotaTracker>>{
+          self.quotas.clone()
+     }
+
+     /// Sets the maximum size of the body of a message sent by a send client
+     pub fn set_max_body_size(&mut self, max_body_size:usize){
+          self.max_body_size = max_body_size;
+          self.federation.set_max_body_size(max_body_size);
+     }
+
+     /// Sets the secret admin clients authenticate with
+     pub fn set_admin_secret(&mut self, secret:String){
+          self.admin_secret = Some(secret);
+     }
 
+     /// Federates the server with its peers under `name`, peers authenticate with the shared `secret`.
+     /// Peer links are refused until it is called
+     pub fn set_federation(&mut self, name:String, secret:String){
+          self.federation.set_identity(name, secret);
+     }
+
+     /// Adds the `host:port` address of a peer dialed once the server is serving, and dialed again whenever its link goes down
+     pub fn add_peer(&mut self, addr:String){
+          self.federation.add_peer(addr);
+     }
+
+     /// Makes the federated server a node of a cluster, owning the pending messages of the aliases hashed to its
+     /// `virtual_nodes` points on the [cluster::HashRing]. Every node of the cluster is expected to be linked to every other node
+     pub fn set_cluster(&mut self, virtual_nodes:usize){
+          self.federation.set_cluster(virtual_nodes);
+     }
+
+     /// Returns the nodes of the cluster as seen by the server, empty when it is not clustered
+     pub fn get_cluster_members(&self)->Vec<String>{
+          self.federation.members()
+     }
+
+     /// Returns the names of the peers the server is currently linked to
+     pub fn get_linked_peers(&self)->Vec<String>{
+          self.federation.linked_peers()
+     }
+
+     /// Returns a [ShutdownHandle] stopping the server once triggered, from another thread or a signal handler
+     pub fn get_shutdown_handle(&self)->ShutdownHandle{
+          self.shutdown.clone()
+     }
+
+     /// Sets the time given to the receive clients to be delivered their queued messages when the server shuts down
+     pub fn set_drain_timeout(&mut self, timeout:Duration){
+          self.drain_timeout = timeout;
+     }
+
+     /// Sets the maximum number of connections handled at once, and waiting for a worker past it.
+     /// Connections past both limits are answered with [Status::Busy]. Each receive and presence connection takes two workers,
+     /// its handler and the job watching or notifying it, and is answered with [Status::Busy] when the second cannot be reserved
+     pub fn set_connection_limits(&mut self, max_connections:usize, max_pending:usize){
+          self.pool = WorkerPool::new(max_connections, max_pending);
+     }
+
+     /// Sets the time a client is given to send its handshake, clients sending none in time are refused
+     pub fn set_handshake_timeout(&mut self, timeout:Duration){
+          self.handshake_timeout = timeout;
+     }
+
+     /// Returns the [PoolMetrics] of the worker pool running the handlers
+     pub fn get_pool_metrics(&self)->PoolMetrics{
+          self.pool.metrics()
+     }
+
+     /// Returns the [AliasOwnership] of the server to register the owners of aliases
+     pub fn get_ownership(&self)->Arc<Mutex<AliasOwnership>>{
+          self.ownership.clone()
+     }
+
+     /// Removes the containers whose handling thread has finished from the pools.
+     /// Threads deregister their own container when their handler returns, this catches the ones that panicked
+     fn reap(&self){
+          for container in self.receive_container_pool.remove_finished(){
+               warn!("Reaped dead container {}", container);
+          }
+          for container in self.send_container_pool.remove_finished(){
+               warn!("Reaped dead container {}", container);
           }
      }
 
-     /// method to identify request type from stream data {initial handshake}
-     fn identify_request_type(&self, tcp_stream:&mut TcpStream)->Option<TransmitService>{
-          let mut buf = [0;1024];  //buffer to read initial handshake
+     /// Disconnects every client once the server has stopped accepting connections.
+     /// Send, presence and admin clients are no longer read from, then receive clients are delivered their queued messages until the drain timeout.
+     /// Every client is then sent [Status::ShuttingDown] and its handling thread is joined
+     fn drain(&self){
+          let deadline = Instant::now()+self.drain_timeout;
+          let notice = Response::generate_res(Status::ShuttingDown, "Server is shutting down".to_string());
+
+          //the connections still waiting for a worker are dropped, the ones reading their handshake are refused once it is read
+          self.pool.close();
+          //peers stop routing messages to the server, the links stay open to hand its pending messages over
+          self.federation.leave();
+          while self.handshaking.load(Ordering::SeqCst)>0 && Instant::now()<deadline{
+               sleep(ACCEPT_POLL_INTERVAL);
+          }
 
-          if let Err(e) = tcp_stream.read(&mut buf){
-               error!("An error occured when type was being extracted from incoming stream {:?}", e);
-               sleep(Duration::from_secs(1));     //thread sleep
-               return None;
+          //taking the containers out of the pools, the threads deregistering themselves no longer find them
+          let mut scp = self.send_container_pool.remove_all();
+          let mut rcp = self.receive_container_pool.remove_all();
+          info!("Shutting down -- {{ send_clients: {}; receive_clients: {} }}", scp.len(), rcp.len());
+
+          //stopping the send, presence and admin clients so that no message is queued anymore
+          for container in scp.iter(){
+               container.stop_reading();
+          }
+          wait_until_finished(scp.iter().map(|c|c.get_thread_handle()), deadline);
+
+          //the receive handlers exit once their queue is empty and its sender is dropped
+          for container in rcp.iter_mut(){
+               container.close_sender();
           }
+          wait_until_finished(rcp.iter().map(|c|c.get_thread_handle()), deadline);
 
-          //readining initial handshake request
-          let client_service = match get_type_for_raw_utf8(&mut buf){
-               Ok(t)=>Some(t),
-               Err(e)=>{
-                    error!("An error occured when type was being extracted from incoming stream {:?}", e);
-                    None
+          for container in scp.iter_mut(){
+               container.disconnect(notice.as_bytes());
+          }
+          for container in rcp.iter_mut(){
+               if !container.get_thread_handle().is_finished(){
+                    warn!("Drain timeout reached, disconnecting {}", container);
                }
-          };
+               container.disconnect(notice.as_bytes());
+          }
 
-          return client_service;
+          //joining the handler threads, a thread still blocked past the deadline is left behind
+          let handles = scp.into_iter().map(|c|c.into_thread_handle())
+               .chain(rcp.into_iter().map(|c|c.into_thread_handle()));
+          let join_deadline = deadline.max(Instant::now()+JOIN_GRACE_PERIOD);
+          let mut abandoned = 0;
+          for handle in handles{
+               if wait_until_finished([&handle].into_iter(), join_deadline){
+                    handle.join();
+               }else{
+                    abandoned+=1;
+               }
+          }
+          if abandoned>0{
+               warn!("{} handler threads did not finish before shutting down", abandoned);
+          }
 
-          
+          self.federation.hand_off();
+          self.federation.close();
+
+          let mailbox = self.mailbox.lock().unwrap();
+          if !mailbox.is_empty(){
+               warn!("Shut down with undelivered messages left in the mailbox");
+          }
+          info!("Server has shut down");
+     }
+
+     /// Returns the [PresenceRegistry] of the server to query the online status of aliases
+     pub fn get_presence(&self)->Arc<Mutex<PresenceRegistry>>{
+          self.presence.clone()
+     }
+
+     /// Returns the state of the server handed to the worker establishing a connection
+     fn connection_context(&self)->ConnectionContext{
+          ConnectionContext{
+               send_container_pool:self.send_container_pool.clone(),
+               receive_container_pool:self.receive_container_pool.clone(),
+               presence:self.presence.clone(),
+               ownership:self.ownership.clone(),
+               duplicate_policy:self.duplicate_policy,
+               queue_capacity:self.queue_capacity,
+               backpressure_policy:self.backpressure_policy,
+               rate_limiter:self.rate_limiter.clone(),
+               quotas:self.quotas.clone(),
+               max_body_size:self.max_body_size,
+               admin_secret:self.admin_secret.clone(),
+               mailbox:self.mailbox.clone(),
+               shutdown:self.shutdown.clone(),
+               pool:self.pool.clone(),
+               handshake_timeout:self.handshake_timeout,
+               federation:self.federation.clone()
+          }
      }
 
      fn generate_id(&mut self)->u64{
@@ -185,15 +473,33 @@ impl Server{
           self.stream_counter
      }
 
-     /// Method that costructs address from port number and address
-     fn construct_addr(&self)->String{
-          let mut addr = self.host.trim()
-               .to_string();
-          addr.push(':');
-          addr.push_str(self.port.to_string().as_str());
+     /// Binds a listener on the first of the addresses of the server that can be bound
+     fn bind_listener(&self)->Result<TcpListener, ServerError>{
+          match TcpListener::bind(&self.addrs[..]){
+               Ok(e)=>Ok(e),
+               Err(e)=>Err(ServerError::AddressBindError(e))
+          }
+     }
 
-          addr
+     /// Returns the address the server is bound on, once [Server::bind] has been called
+     pub fn local_addr(&self)->Option<SocketAddr>{
+          self.listener.as_ref().and_then(|listener|listener.local_addr().ok())
      }
 
 }
 
+/// Waits until every handler job has finished or the deadline is reached
+///
+/// # Returns
+/// - `bool`: true if every thread has finished
+fn wait_until_finished<'a, I:Iterator<Item = &'a TaskHandle>+Clone>(handles:I, deadline:Instant)->bool{
+     loop {
+          if handles.clone().all(|h|h.is_finished()){
+               return true;
+          }
+          if Instant::now()>=deadline{
+               return false;
+          }
+          sleep(ACCEPT_POLL_INTERVAL);
+     }
+}
@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::spawn;
+
+use log::{error, warn};
+
+
+/// A connection handling job run by a worker
+type Job = Box<dyn FnOnce()+Send+'static>;
+
+/// A struct representing the metrics of a [WorkerPool]
+///
+/// # Fields
+///
+/// - `max_workers`: The maximum number of connections handled at once
+/// - `max_pending`: The maximum number of connections waiting for a worker
+/// - `workers`: The number of worker threads started
+/// - `busy`: The number of workers handling a connection
+/// - `pending`: The number of connections waiting for a worker
+/// - `peak_busy`: The highest number of workers handling a connection at once
+/// - `accepted`: The number of connections handed to the pool
+/// - `rejected`: The number of connections refused because the pool was full
+/// - `saturated`: The number of connections that had to wait for a worker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolMetrics{
+     pub max_workers:usize,
+     pub max_pending:usize,
+     pub workers:usize,
+     pub busy:usize,
+     pub pending:usize,
+     pub peak_busy:usize,
+     pub accepted:u64,
+     pub rejected:u64,
+     pub saturated:u64
+}
+
+/// The state shared by the pool and its workers
+struct State{
+     queue:VecDeque<(Job, Completion)>,
+     reserved:usize,
+     idle:usize,
+     closed:bool,
+     metrics:PoolMetrics
+}
+
+struct Shared{
+     state:Mutex<State>,
+     available:Condvar
+}
+
+/// A struct representing a bounded pool of worker threads handling connections.
+/// At most `max_workers` jobs are run at once, and at most `max_pending` wait for a worker.
+/// Jobs past both limits are refused, workers are started on demand and kept once idle.
+/// A receive or presence connection runs two jobs, its handler and a helper reserved with [WorkerPool::reserve]
+/// watching or notifying its client, so that a pool of `max_workers` workers handles `max_workers/2` such connections
+///
+/// # Fields
+///
+/// - `shared`: The queue of jobs and the metrics, shared with the workers
+#[derive(Clone)]
+pub struct WorkerPool{
+     shared:Arc<Shared>
+}
+
+/// A slot reserved in a [WorkerPool] for a connection, released if dropped before a job is executed in it
+///
+/// # Fields
+///
+/// - `shared`: The state of the pool the slot is reserved in
+/// - `reserved`: True until a job is queued in the slot, the reservation is then handed to the job under the same lock
+pub struct Slot{
+     shared:Arc<Shared>,
+     reserved:bool
+}
+
+/// A handle on a job executed by a [WorkerPool], analogous to [std::thread::JoinHandle]
+#[derive(Debug, Clone)]
+pub struct TaskHandle{
+     done:Arc<(Mutex<bool>, Condvar)>
+}
+
+/// Marks its task finished when dropped, whether the job returned, panicked or was dropped without running
+struct Completion{
+     done:Arc<(Mutex<bool>, Condvar)>
+}
+
+impl WorkerPool{
+     /// Default constructor for a [WorkerPool]
+     ///
+     /// # Arguments
+     ///
+     /// * `max_workers`: The maximum number of connections handled at once, at least 1
+     /// * `max_pending`: The maximum number of connections waiting for a worker
+     pub fn new(max_workers:usize, max_pending:usize)->Self{
+          WorkerPool{
+               shared:Arc::new(Shared{
+                    state:Mutex::new(State{
+                         queue:VecDeque::new(),
+                         reserved:0,
+                         idle:0,
+                         closed:false,
+                         metrics:PoolMetrics{
+                              max_workers:max_workers.max(1),
+                              max_pending,
+                              ..PoolMetrics::default()
+                         }
+                    }),
+                    available:Condvar::new()
+               })
+          }
+     }
+
+     /// Reserves a slot for a connection
+     ///
+     /// # Returns
+     /// - `None`: The pool is full or closed, the connection has to be refused
+     pub fn reserve(&self)->Option<Slot>{
+          let mut state = self.shared.state.lock().unwrap();
+          let in_use = state.metrics.busy+state.queue.len()+state.reserved;
+          if state.closed || in_use>=state.metrics.max_workers+state.metrics.max_pending{
+               state.metrics.rejected+=1;
+               return None;
+          }
+          state.reserved+=1;
+          Some(Slot{
+               shared:self.shared.clone(),
+               reserved:true
+          })
+     }
+
+     /// Closes the pool, refusing new connections. The jobs still waiting for a worker are dropped without running
+     pub fn close(&self){
+          let mut state = self.shared.state.lock().unwrap();
+          state.closed = true;
+          let dropped:Vec<(Job, Completion)> = state.queue.drain(..).collect();
+          state.metrics.pending = 0;
+          self.shared.available.notify_all();
+          drop(state);
+
+          if !dropped.is_empty(){
+               warn!("Dropped {} connections waiting for a worker", dropped.len());
+          }
+     }
+
+     /// Returns the current [PoolMetrics]
+     pub fn metrics(&self)->PoolMetrics{
+          self.shared.state.lock().unwrap().metrics
+     }
+}
+
+impl Slot{
+     /// Executes a job in the reserved slot, on an idle worker or once one becomes available
+     pub fn execute<F:FnOnce()+Send+'static>(mut self, job:F)->TaskHandle{
+          let handle = TaskHandle{
+               done:Arc::new((Mutex::new(false), Condvar::new()))
+          };
+          let completion = Completion{
+               done:handle.done.clone()
+          };
+
+          //the queued job takes over the reservation, a worker popping it never sees the slot counted twice
+          let mut state = self.shared.state.lock().unwrap();
+          state.queue.push_back((Box::new(job), completion));
+          state.reserved-=1;
+          self.reserved = false;
+          state.metrics.accepted+=1;
+          state.metrics.pending = state.queue.len();
+
+          //starting a worker on demand, or waiting for a busy one to finish.
+          //idle workers that were notified but have not woken up yet are still counted as idle
+          if state.queue.len()>state.idle{
+               if state.metrics.workers<state.metrics.max_workers{
+                    state.metrics.workers+=1;
+                    let shared = self.shared.clone();
+                    spawn(move ||work(shared));
+               }else{
+                    state.metrics.saturated+=1;
+                    warn!("Worker pool is saturated, {} connections waiting for a worker", state.queue.len());
+               }
+          }
+          self.shared.available.notify_one();
+          drop(state);
+
+          handle
+     }
+}
+
+/// Runs the jobs of the pool until it is closed
+fn work(shared:Arc<Shared>){
+     loop {
+          let mut state = shared.state.lock().unwrap();
+          let (job, completion) = loop {
+               if let Some(next) = state.queue.pop_front(){
+                    break next;
+               }
+               if state.closed{
+                    state.metrics.workers-=1;
+                    return;
+               }
+               state.idle+=1;
+               state = shared.available.wait(state).unwrap();
+               state.idle-=1;
+          };
+          state.metrics.pending = state.queue.len();
+          state.metrics.busy+=1;
+          state.metrics.peak_busy = state.metrics.peak_busy.max(state.metrics.busy);
+          drop(state);
+
+          //a panicking handler does not take its worker down
+          if catch_unwind(AssertUnwindSafe(job)).is_err(){
+               error!("A connection handler panicked");
+          }
+          drop(completion);
+
+          shared.state.lock().unwrap().metrics.busy-=1;
+     }
+}
+
+impl TaskHandle{
+     /// Returns true once the job has returned, panicked or was dropped without running
+     pub fn is_finished(&self)->bool{
+          *self.done.0.lock().unwrap()
+     }
+
+     /// Blocks until the job is finished
+     pub fn join(&self){
+          let (lock, finished) = &*self.done;
+          let mut done = lock.lock().unwrap();
+          while !*done{
+               done = finished.wait(done).unwrap();
+          }
+     }
+}
+
+///Drop implementation for Slot, releases the slot if no job was executed in it
+impl Drop for Slot{
+     fn drop(&mut self) {
+          if self.reserved{
+               self.shared.state.lock().unwrap().reserved-=1;
+          }
+     }
+}
+
+///Drop implementation for Completion, wakes the threads joining the task
+impl Drop for Completion{
+     fn drop(&mut self) {
+          let (lock, finished) = &*self.done;
+          *lock.lock().unwrap() = true;
+          finished.notify_all();
+     }
+}
+
+/// Debug implementation for WorkerPool
+impl std::fmt::Debug for WorkerPool{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "WorkerPool {{ {} }}", self.metrics())
+     }
+}
+
+/// Display implementation for PoolMetrics
+impl Display for PoolMetrics{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "workers={},busy={},pending={},max_workers={},max_pending={},peak_busy={},accepted={},rejected={},saturated={}",
+               self.workers, self.busy, self.pending, self.max_workers, self.max_pending, self.peak_busy, self.accepted, self.rejected, self.saturated)
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::sync::mpsc::channel;
+     use std::sync::Barrier;
+     use std::thread::sleep;
+     use std::time::{Duration, Instant};
+
+     /// Waits until the workers of the pool are done with their jobs
+     fn wait_idle(pool:&WorkerPool){
+          let deadline = Instant::now()+Duration::from_secs(5);
+          while pool.metrics().busy>0 && Instant::now()<deadline{
+               sleep(Duration::from_millis(1));
+          }
+     }
+
+     #[test]
+     fn burst_starts_workers_past_the_idle_ones(){
+          //an idle worker notified of a job counts as idle until it wakes up, the burst is repeated to catch it
+          for _ in 0..20{
+               let pool = WorkerPool::new(4, 4);
+               //leaving a single idle worker
+               pool.reserve().unwrap().execute(||()).join();
+               wait_idle(&pool);
+
+               //every job of the burst waits for the others, they only finish if each one has a worker
+               let burst = 3;
+               let barrier = Arc::new(Barrier::new(burst));
+               let (tx, rx) = channel();
+               for _ in 0..burst{
+                    let (barrier, tx) = (barrier.clone(), tx.clone());
+                    pool.reserve().unwrap().execute(move ||{
+                         barrier.wait();
+                         let _ = tx.send(());
+                    });
+               }
+               for _ in 0..burst{
+                    rx.recv_timeout(Duration::from_secs(2)).expect("a connection of the burst waited behind a busy worker");
+               }
+
+               let metrics = pool.metrics();
+               assert_eq!(metrics.workers, burst);
+               assert_eq!(metrics.saturated, 0);
+               pool.close();
+          }
+     }
+
+     #[test]
+     fn connections_wait_once_every_worker_is_busy(){
+          let pool = WorkerPool::new(1, 1);
+          let (release, held) = channel::<()>();
+          let busy = pool.reserve().unwrap().execute(move ||{
+               let _ = held.recv();
+          });
+          let waiting = pool.reserve().unwrap().execute(||());
+          assert!(pool.reserve().is_none());
+
+          let metrics = pool.metrics();
+          assert_eq!(metrics.workers, 1);
+          assert_eq!(metrics.saturated, 1);
+          assert_eq!(metrics.rejected, 1);
+
+          drop(release);
+          busy.join();
+          waiting.join();
+     }
+}
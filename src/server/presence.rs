@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+use super::protocol::error::ProtocolError;
+
+
+/// An enum representing the online state of an alias
+///
+/// # Variants
+///
+/// - `Online`: At least one receive client is registered for the alias
+/// - `Offline`: No receive client is registered for the alias
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceState{
+     Online,
+     Offline
+}
+
+/// A struct representing a presence change or a presence query result of an alias
+///
+/// # Fields
+///
+/// - `alias`: The alias the event is about
+/// - `state`: The online state of the alias
+/// - `status`: The custom status text set by the alias (away, busy...), if any
+#[derive(Debug, Clone)]
+pub struct PresenceEvent{
+     alias:String,
+     state:PresenceState,
+     status:Option<String>
+}
+
+/// A struct representing the presence registry of the server.
+/// Keeps track of the aliases that have receive clients registered, the custom status text of the aliases
+/// and the watchers subscribed to presence changes of an alias.
+///
+/// # Diagram
+///
+/// ```text
+/// +-----------------------+                     +---------------Registry---------------+
+/// |      Client  (rx)     | ----> register ---> | set_online(alias) / set_offline()    |
+/// +-----------------------+                     +------------------+-------------------+
+///                                                                  |
+///                                                                  V
+///                                                     +-----------------------+
+///                                                     |   watcher.send(event) |  ----> Client (presence)
+///                                                     +-----------------------+
+/// ```
+///
+/// # Fields
+///
+/// - `online`: The number of registered receive clients per alias
+/// - `status`: The custom status text per alias
+/// - `watchers`: The channels of presence sessions watching an alias, keyed by watched alias. Each channel is stored along with the id of its session
+#[derive(Debug, Default)]
+pub struct PresenceRegistry{
+     online:HashMap<String, usize>,
+     status:HashMap<String, String>,
+     watchers:HashMap<String, Vec<(u64, Sender<PresenceEvent>)>>
+}
+
+impl PresenceEvent{
+     pub fn new(alias:String, state:PresenceState, status:Option<String>)->Self{
+          PresenceEvent{
+               alias,
+               state,
+               status
+          }
+     }
+
+     //----Getters----
+     pub fn get_alias(&self)->&String{
+          &self.alias
+     }
+
+     pub fn get_state(&self)->PresenceState{
+          self.state
+     }
+
+     pub fn get_status(&self)->Option<&String>{
+          self.status.as_ref()
+     }
+}
+
+impl PresenceRegistry{
+     /// Default constructor for an empty [PresenceRegistry]
+     pub fn new()->Self{
+          PresenceRegistry::default()
+     }
+
+     /// Registers a receive client for the alias.
+     /// Watchers are notified only when the alias goes from offline to online
+     pub fn set_online(&mut self, alias:&str){
+          let count = self.online.entry(alias.to_string()).or_insert(0);
+          *count+=1;
+
+          if *count==1{
+               self.notify(alias);
+          }
+     }
+
+     /// Deregisters a receive client of the alias.
+     /// Watchers are notified only when the last receive client of the alias is gone
+     pub fn set_offline(&mut self, alias:&str){
+          let remaining = match self.online.get_mut(alias){
+               None=>return,
+               Some(count)=>{
+                    *count-=1;
+                    *count
+               }
+          };
+
+          if remaining==0{
+               self.online.remove(alias);
+               self.notify(alias);
+          }
+     }
+
+     /// Sets the custom status text of an alias and notifies its watchers.
+     /// An empty status clears the status text
+     pub fn set_status(&mut self, alias:&str, status:&str){
+          let status = status.trim();
+          if status.is_empty(){
+               self.status.remove(alias);
+          }else{
+               self.status.insert(alias.to_string(), status.to_string());
+          }
+          self.notify(alias);
+     }
+
+     /// Returns the current presence of an alias
+     pub fn query(&self, alias:&str)->PresenceEvent{
+          let state = match self.online.contains_key(alias){
+               true=>PresenceState::Online,
+               false=>PresenceState::Offline
+          };
+          PresenceEvent::new(alias.to_string(), state, self.status.get(alias).cloned())
+     }
+
+     /// Subscribes the channel of a presence session to the presence changes of an alias
+     ///
+     /// # Arguments
+     ///
+     /// * `alias`: The alias to watch
+     /// * `id`: The id of the watching session, used to unsubscribe
+     /// * `watcher`: The Sender object of the channel the events are dispatched to
+     pub fn watch(&mut self, alias:&str, id:u64, watcher:Sender<PresenceEvent>){
+          let watchers = self.watchers.entry(alias.to_string()).or_default();
+          if watchers.iter().any(|(w, _)|*w==id){
+               return;
+          }
+          watchers.push((id, watcher));
+     }
+
+     /// Unsubscribes a presence session from the presence changes of an alias
+     pub fn unwatch(&mut self, alias:&str, id:u64){
+          if let Some(watchers) = self.watchers.get_mut(alias){
+               watchers.retain(|(w, _)|*w!=id);
+               if watchers.is_empty(){
+                    self.watchers.remove(alias);
+               }
+          }
+     }
+
+     /// Unsubscribes a presence session from all the aliases it watches.
+     /// Call this when the presence session disconnects
+     pub fn unwatch_all(&mut self, id:u64){
+          for watchers in self.watchers.values_mut(){
+               watchers.retain(|(w, _)|*w!=id);
+          }
+          self.watchers.retain(|_, w|!w.is_empty());
+     }
+
+     /// Dispatches the current presence of an alias to its watchers.
+     /// Watchers whose channel has been closed are dropped
+     fn notify(&mut self, alias:&str){
+          let event = self.query(alias);
+          if let Some(watchers) = self.watchers.get_mut(alias){
+               watchers.retain(|(_, w)|w.send(event.clone()).is_ok());
+          }
+     }
+}
+
+/// Display implementation for PresenceState
+impl Display for PresenceState{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Online=>write!(f, "online"),
+               Self::Offline=>write!(f, "offline")
+          }
+     }
+}
+
+/// Display implementation for PresenceEvent
+/// Formats the event as `<alias>;<state>;<status>`
+impl Display for PresenceEvent{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "{};{};{}", self.alias, self.state, self.status.as_deref().unwrap_or(""))
+     }
+}
+
+/// FromStr implementation for PresenceState
+impl FromStr for PresenceState{
+     type Err = ProtocolError;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          match s{
+               "online"=>Ok(PresenceState::Online),
+               "offline"=>Ok(PresenceState::Offline),
+               _=>Err(ProtocolError::FromatError(format!("Unknown presence state '{s}'")))
+          }
+     }
+}
+
+/// FromStr implementation for PresenceEvent
+/// Parses an event formatted as `<alias>;<state>;<status>`
+impl FromStr for PresenceEvent{
+     type Err = ProtocolError;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          let mut parts = s.splitn(3, ';');
+          match (parts.next(), parts.next(), parts.next()){
+               (Some(alias), Some(state), status)=>Ok(PresenceEvent{
+                    alias:alias.to_string(),
+                    state:PresenceState::from_str(state)?,
+                    status:status.filter(|s|!s.is_empty()).map(|s|s.to_string())
+               }),
+               _=>Err(ProtocolError::FromatError(format!("Could not extract the alias and state of presence '{s}'")))
+          }
+     }
+}
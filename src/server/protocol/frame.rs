@@ -0,0 +1,169 @@
+use std::fmt::Display;
+use std::io::{Error, Read, Write};
+
+/*
+ * II. Framing
+     - Every message, response and notification is terminated by a null byte
+     - Handshakes may also be terminated by a new line
+
+     /*Format-----------------------
+     <frame>\0
+      ------------------------------*/
+ */
+/// Byte terminating every frame
+pub const FRAME_TERMINATOR:u8 = 0;
+
+/// Size of the chunks read from the stream
+const READ_CHUNK:usize = 1024;
+
+/// An enum representing the errors that can occur while reading a frame
+///
+/// # Variants
+///
+/// - `TooLarge`: The frame exceeded the maximum frame size, its remaining bytes are discarded up to the next terminator
+/// - `Io`: The stream could not be read
+#[derive(Debug)]
+pub enum FrameError{
+     TooLarge(usize),
+     Io(Error)
+}
+
+/// A struct reading null terminated frames from a stream.
+/// Bytes read past a terminator are kept for the next frame, so that frames sent back to back are not merged.
+/// Streams that are not [Read] (eg.. async streams) [FrameReader::feed] the bytes they read and take the buffered frames
+///
+/// # Fields
+///
+/// - `buf`: The bytes read from the stream that are not part of a returned frame yet
+/// - `scanned`: The number of bytes of `buf` already searched for a terminator
+/// - `max_frame_size`: The maximum size of a frame, terminator excluded
+/// - `discarding`: True while the remaining bytes of a frame that was too large are being dropped
+#[derive(Debug)]
+pub struct FrameReader{
+     buf:Vec<u8>,
+     scanned:usize,
+     max_frame_size:usize,
+     discarding:bool
+}
+
+impl FrameReader{
+     /// Default constructor for a [FrameReader] accepting frames up to `max_frame_size` bytes
+     pub fn new(max_frame_size:usize)->Self{
+          FrameReader{
+               buf:Vec::new(),
+               scanned:0,
+               max_frame_size,
+               discarding:false
+          }
+     }
+
+     /// Reads the next frame from the stream
+     ///
+     /// # Returns
+     ///
+     /// - `Ok(Some(frame))`: The next frame without its terminator
+     /// - `Ok(None)`: The stream has disconnected, an unterminated trailing frame is dropped
+     /// - `Err(FrameError::TooLarge)`: The frame was too large, the next call continues with the following frame
+     pub fn read_frame<R:Read>(&mut self, stream:&mut R)->Result<Option<Vec<u8>>, FrameError>{
+          self.read_until(stream, |b|b==FRAME_TERMINATOR)
+     }
+
+     /// Reads the handshake frame, which may be terminated by a null byte or a new line
+     pub fn read_handshake<R:Read>(&mut self, stream:&mut R)->Result<Option<Vec<u8>>, FrameError>{
+          self.read_until(stream, |b|b==FRAME_TERMINATOR || b==b'\n')
+     }
+
+     /// Appends bytes read from the stream
+     pub fn feed(&mut self, bytes:&[u8]){
+          self.buf.extend_from_slice(bytes);
+     }
+
+     /// Takes the next frame from the bytes fed so far, without reading from the stream
+     ///
+     /// # Returns
+     ///
+     /// - `None`: No complete frame has been fed yet
+     /// - `Some(Err(FrameError::TooLarge))`: The frame was too large, the next call continues with the following frame
+     pub fn next_buffered_frame(&mut self)->Option<Result<Vec<u8>, FrameError>>{
+          self.take_until(|b|b==FRAME_TERMINATOR)
+     }
+
+     /// Takes the handshake frame from the bytes fed so far, which may be terminated by a null byte or a new line
+     pub fn next_buffered_handshake(&mut self)->Option<Result<Vec<u8>, FrameError>>{
+          self.take_until(|b|b==FRAME_TERMINATOR || b==b'\n')
+     }
+
+     fn read_until<R:Read, F:Fn(u8)->bool>(&mut self, stream:&mut R, is_terminator:F)->Result<Option<Vec<u8>>, FrameError>{
+          loop {
+               if let Some(frame) = self.take_until(&is_terminator){
+                    return frame.map(Some);
+               }
+
+               let mut chunk = [0;READ_CHUNK];
+               match stream.read(&mut chunk){
+                    Ok(0)=>return Ok(None),
+                    Ok(n)=>self.feed(&chunk[..n]),
+                    Err(e)=>return Err(FrameError::Io(e))
+               }
+          }
+     }
+
+     fn take_until<F:Fn(u8)->bool>(&mut self, is_terminator:F)->Option<Result<Vec<u8>, FrameError>>{
+          loop {
+               if let Some(pos) = self.buf[self.scanned..].iter().position(|b|is_terminator(*b)){
+                    let end = self.scanned+pos;
+                    let frame:Vec<u8> = self.buf.drain(..=end).take(end).collect();
+                    self.scanned = 0;
+                    if self.discarding{
+                         self.discarding = false;
+                         continue;
+                    }
+                    if frame.len()>self.max_frame_size{
+                         return Some(Err(FrameError::TooLarge(frame.len())));
+                    }
+                    return Some(Ok(frame));
+               }
+
+               //dropping the bytes of a frame that is too large
+               if self.buf.len()>self.max_frame_size{
+                    let size = self.buf.len();
+                    self.buf.clear();
+                    self.scanned = 0;
+                    if !self.discarding{
+                         self.discarding = true;
+                         return Some(Err(FrameError::TooLarge(size)));
+                    }
+               }else{
+                    self.scanned = self.buf.len();
+               }
+               return None;
+          }
+     }
+
+     /// Sets the maximum size of a frame, terminator excluded
+     pub fn set_max_frame_size(&mut self, max_frame_size:usize){
+          self.max_frame_size = max_frame_size;
+     }
+
+     pub fn get_max_frame_size(&self)->usize{
+          self.max_frame_size
+     }
+}
+
+/// Writes a frame followed by its terminator
+pub fn write_frame<W:Write>(stream:&mut W, frame:&[u8])->Result<(), Error>{
+     let mut framed = Vec::with_capacity(frame.len()+1);
+     framed.extend_from_slice(frame);
+     framed.push(FRAME_TERMINATOR);
+     stream.write_all(&framed)
+}
+
+/// Display implementation for FrameError
+impl Display for FrameError{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::TooLarge(size)=>write!(f, "{{ error: FrameTooLarge; info: frame exceeded {} bytes }}", size),
+               Self::Io(e)=>write!(f, "{{ error: FrameIo; info: {} }}", e)
+          }
+     }
+}
@@ -1,12 +1,18 @@
 pub mod error;
+pub mod frame;
 pub mod pto;
 pub mod res;
 
+use std::collections::HashMap;
+
 use error::ProtocolError;
 use pto::Proto;
 
 use super::handler::TransmitService;
 
+/// Maximum size of the `<alias>-<to>` header of a message, on top of the maximum body size
+pub const MAX_HEADER_SIZE:usize = 512;
+
 /// A struct representing a protocol defining a structure of how data is transmited in a stream
 ///
 /// # Fields
@@ -15,25 +21,28 @@ use super::handler::TransmitService;
 /// - `alias`: The unique identifier of the client (as a part of data in raw_bytes)
 /// - `to`: The client id of the client this data is being sent to
 /// - `body`: The body of the data transmitted
+/// - `id`: The id the server assigned to the message, written by the server to receive clients
+///
 /// ['Utf8']: Data::Utf8
 /// ['Utf16']: Data::Utf16
 pub struct BaseProtocol{
      /*Format-----------------------
-     <alias>-<to>(/n)
+     <alias>-<to>(;id=<id>)(/n)
      <body>
       ------------------------------*/
 }
 
 pub struct ParsedData{
      /*Format-----------------------
-     <alias>-<to>(/n)
+     <alias>-<to>(;id=<id>)(/n)
      <body>
       ------------------------------*/
 
      raw:Data,
      to:String,
      alias:String,
-     body:String
+     body:String,
+     id:Option<u64>
 }
 
 /// A trait for working which parsed data
@@ -98,8 +107,8 @@ pub trait DataTransferProtocol<I,S,B> {
 /// ['Utf16']: Data::Utf16
 /// ['Utf8']: Data::Utf8
 pub enum Data {
-    Utf8([u8;1024]),
-    Utf16([u16;1024])
+    Utf8(Vec<u8>),
+    Utf16(Vec<u16>)
 }
 
 impl BaseProtocol{
@@ -113,6 +122,25 @@ impl BaseProtocol{
      }
 }
 
+/// Default implementation for BaseProtocol
+impl Default for BaseProtocol{
+     fn default() -> Self {
+          Self::new()
+     }
+}
+
+impl ParsedData{
+     /// Returns the raw data the message was parsed from
+     pub fn get_raw(&self)->&Data{
+          &self.raw
+     }
+
+     /// Returns the id the server assigned to the message, if the header carries one
+     pub fn get_id(&self)->Option<u64>{
+          self.id
+     }
+}
+
 impl DataTransferProtocol<String,String,String> for BaseProtocol{
           
      type Parsed = ParsedData;
@@ -121,7 +149,7 @@ impl DataTransferProtocol<String,String,String> for BaseProtocol{
      /// # Arguments
      /// - `data` of type [Data] which contains raw bytes of encoding [DataType::Utf8] or [DataType::Utf16]
      fn parse(&self, data:Data)->Result<ParsedData, ProtocolError>{
-          let raw_str = match data{
+          let raw_str = match &data{
                Data::Utf16(d)=>{
                     //trimming all null (unwritten values of the array) from the array 
                     let mut v = d.to_vec();
@@ -132,7 +160,7 @@ impl DataTransferProtocol<String,String,String> for BaseProtocol{
                     //trimming all null (unwritten values of the array) from the array 
                     let mut v = d.to_vec();
                     v.retain(|s|*s!=0);
-                    String::from_utf8_lossy(&d).to_string()
+                    String::from_utf8_lossy(&v).to_string()
                }
           };
 
@@ -142,6 +170,15 @@ impl DataTransferProtocol<String,String,String> for BaseProtocol{
                Some(x)=>x
           };
 
+          //the id follows the header of messages written by the server
+          let (head, id) = match head.split_once(';'){
+               None=>(head, None),
+               Some((head, option))=>match option.strip_prefix("id=").map(|id|id.parse::<u64>()){
+                    Some(Ok(id))=>(head, Some(id)),
+                    _=>return Err(ProtocolError::FromatError(format!("Could not extract the id from the header option '{option}'")))
+               }
+          };
+
           let (alias, to) = match  head.split_once("-"){
                None=>return Err(ProtocolError::FromatError("Could not extract alias and to".to_string())),
                Some(t)=>t
@@ -151,7 +188,8 @@ impl DataTransferProtocol<String,String,String> for BaseProtocol{
                raw:data,
                to:to.to_string(),
                alias:alias.to_string(),
-               body:body.to_string()
+               body:body.to_string(),
+               id
           })
      }
 
@@ -164,7 +202,10 @@ impl DataTransferProtocol<String,String,String> for BaseProtocol{
      /// - `Result<Vec<u8>, ProtocolError>` a result which contains the vector of u8 bytes of data
      fn to_raw<T:Proto<String,String,String>>(&self, pto:T)->Result<Vec<u8>, ProtocolError> {
           //formatting to protocol standard
-          let raw_str = format!("{}-{}\n{}", pto.get_sender(), pto.get_receiver(), pto.get_body());
+          let raw_str = match pto.get_id(){
+               Some(id)=>format!("{}-{};id={}\n{}", pto.get_sender(), pto.get_receiver(), id, pto.get_body()),
+               None=>format!("{}-{}\n{}", pto.get_sender(), pto.get_receiver(), pto.get_body())
+          };
           //converting string to vector bytes
           let vec_raw = raw_str.as_bytes().to_vec();
           Ok(vec_raw)
@@ -190,59 +231,210 @@ impl DataTransferProtocolParsed for ParsedData{
      }
 }
 
-/**
- * I. SEND/RECEIVE
+/// A struct representing a parsed handshake request
+///
+/// # Fields
+///
+/// - `service`: The [TransmitService] requested by the client
+/// - `options`: The `<key>=<value>` options following the username, eg.. `secret`
+#[derive(Debug)]
+pub struct Handshake{
+     service:TransmitService,
+     options:HashMap<String, String>
+}
+
+impl Handshake{
+     //----Getters----
+     pub fn get_service(&self)->&TransmitService{
+          &self.service
+     }
+
+     pub fn get_option(&self, key:&str)->Option<&String>{
+          self.options.get(key)
+     }
+
+     /// Returns the secret the client claims its alias with
+     pub fn get_secret(&self)->Option<&String>{
+          self.get_option("secret")
+     }
+
+     /// Returns the id of the last message a resuming receive client was delivered
+     pub fn get_resume(&self)->Option<u64>{
+          self.get_option("resume").and_then(|id|id.parse().ok())
+     }
+}
+
+/*
+ * I. SEND/RECEIVE/PRESENCE
      - The client initializes a handshake by specifying the client type to the server
+     - The username is the alias of the client, the alias a send client sends from
+     - Options such as the secret owning the alias follow the username
+     - A receive client reconnecting sends the id of the last message it was delivered as `resume`,
+       the messages written to its alias after it are delivered again
 
      /*Format-----------------------
-     <type(SEND;<to-username>/RECEIVE<self-usrname>)>
+     <type(SEND;<self-username>/RECEIVE;<self-username>/PRESENCE;<self-username>/ADMIN;<self-username>/PEER;<server-name>)>(;<key>=<value>)..
       ------------------------------*/
  */
-///Method to parse the handshake request, to identify the client as [TransmitService::Send] or [TransmitService::Receive]
+///Method to parse the handshake request, to identify the client as [TransmitService::Send], [TransmitService::Receive], [TransmitService::Presence] or [TransmitService::Admin]
 pub fn get_type_for_raw_utf8(raw:&[u8])->Result<TransmitService, ProtocolError>{
-     //slizcing data for converting to string
-
-     // let raw_send = &raw[0..4];
-     // let raw_receive = &raw[0..7];
-
-     //parsing raw to string
-     let raw_parsed_send = &String::from_utf8_lossy(raw).trim().replace("\n", "")[0..4];
-     let raw_parsed_receive = &String::from_utf8_lossy(raw).trim().replace("\n", "")[0..7];
-
-     if raw_parsed_send=="SEND"{
-          //converting array to vec
-          let mut raw_vec = raw.to_vec();
-          //retaining all non zero values
-          raw_vec.retain(|&x| x!=0);
-          //converting bytes vec to string
-          let raw_string = String::from_utf8_lossy(&raw_vec);
-
-          //unpacking data to extract username from handshake data
-          let mut username = match raw_string.split_once(";"){
-               None=>{return Err(ProtocolError::FromatError("Could not find ';' delemiter while extracting username from handshake data".to_string()))},
-               Some((_,b))=>b.to_string()
-          };
-          username = username.trim().replace("\n", "");          //clearing any escape seq
-
-          return Ok(TransmitService::Send(username));
-     }else if  raw_parsed_receive=="RECEIVE" {
-          //converting array to vector
-          let mut raw_vec = raw.to_vec();
-          //retain all non null values
-          raw_vec.retain(|&x| x!=0);
-          //converting to string
-          let raw_string = String::from_utf8_lossy(&raw_vec);
-
-          //unpacking data to extract username from handshake data
-          let mut to_username = match raw_string.split_once(";"){
-               None=>{return Err(ProtocolError::FromatError("Could not find ';' delemiter while extracting username from handshake data".to_string()))},
-               Some((_,b))=>b.to_string()
-          };
-          to_username = to_username.trim().replace("\n", "");    //clearing any escape seq
+     get_handshake_for_raw_utf8(raw).map(|h|h.service)
+}
 
-          return Ok(TransmitService::Receive(to_username));
+///Method to parse the handshake request along with its options
+pub fn get_handshake_for_raw_utf8(raw:&[u8])->Result<Handshake, ProtocolError>{
+     //converting array to vec
+     let mut raw_vec = raw.to_vec();
+     //retaining all non zero values
+     raw_vec.retain(|&x| x!=0);
+     //converting bytes vec to string
+     let raw_string = String::from_utf8_lossy(&raw_vec).trim().replace("\n", "");          //clearing any escape seq
+
+     //unpacking data to extract the service type, username and options from handshake data
+     let mut parts = raw_string.split(';');
+     let service = parts.next().unwrap_or("").trim().to_string();
+     let username = match parts.next(){
+          None=>{return Err(ProtocolError::FromatError("Could not find ';' delemiter while extracting username from handshake data".to_string()))},
+          Some(u)=>u.trim().to_string()
+     };
+
+     let mut options = HashMap::new();
+     for option in parts{
+          match option.split_once('='){
+               Some((k, v))=>{options.insert(k.trim().to_string(), v.trim().to_string());},
+               None=>return Err(ProtocolError::FromatError(format!("Handshake option '{option}' is not formatted as <key>=<value>")))
+          }
+     }
+     if let Some(id) = options.get("resume"){
+          if id.parse::<u64>().is_err(){
+               return Err(ProtocolError::FromatError(format!("Handshake option 'resume={id}' is not a message id")));
+          }
+     }
+
+     let service = match service.as_str(){
+          "SEND"=>TransmitService::Send(username),
+          "RECEIVE"=>TransmitService::Receive(username),
+          "PRESENCE"=>TransmitService::Presence(username),
+          "ADMIN"=>TransmitService::Admin(username),
+          "PEER"=>TransmitService::Peer(username),
+          _=>return Err(ProtocolError::SessionExtractionError("Could not determine wether the session was send, receive, presence, admin or peer.".to_string()))
+     };
+
+     Ok(Handshake{
+          service,
+          options
+     })
+}
+
+/*
+ * IV. PRESENCE
+     - A presence client sends one command per message after the handshake
+
+     /*Format-----------------------
+     <command(QUERY;<alias>,<alias>../WATCH;<alias>,<alias>../UNWATCH;<alias>,<alias>../STATUS;<status-text>)>
+      ------------------------------*/
+ */
+/// An enum representing the commands a [TransmitService::Presence] client can send
+///
+/// # Variants
+///
+/// - `Query`: Requests the current presence of the aliases
+/// - `Watch`: Subscribes to the presence changes of the aliases
+/// - `Unwatch`: Unsubscribes from the presence changes of the aliases
+/// - `Status`: Sets the custom status text (away, busy...) of the presence client's alias
+#[derive(Debug)]
+pub enum PresenceCommand{
+     Query(Vec<String>),
+     Watch(Vec<String>),
+     Unwatch(Vec<String>),
+     Status(String)
+}
+
+///Method to parse a command sent by a [TransmitService::Presence] client
+pub fn get_presence_command(raw:&[u8])->Result<PresenceCommand, ProtocolError>{
+     let mut raw_vec = raw.to_vec();
+     raw_vec.retain(|&x| x!=0);
+     let raw_string = String::from_utf8_lossy(&raw_vec);
+
+     let (command, args) = match raw_string.trim().split_once(";"){
+          None=>(raw_string.trim().to_string(), String::new()),
+          Some((a,b))=>(a.trim().to_string(), b.trim().to_string())
+     };
+
+     //splits the comma separated aliases, ignoring empty entries
+     let aliases:Vec<String> = args.split(',')
+          .map(|a|a.trim().to_string())
+          .filter(|a|!a.is_empty())
+          .collect();
+
+     match command.as_str(){
+          "QUERY"=>Ok(PresenceCommand::Query(aliases)),
+          "WATCH"=>Ok(PresenceCommand::Watch(aliases)),
+          "UNWATCH"=>Ok(PresenceCommand::Unwatch(aliases)),
+          "STATUS"=>Ok(PresenceCommand::Status(args)),
+          _=>Err(ProtocolError::FromatError(format!("Unknown presence command '{command}'")))
      }
+}
+
+/*
+ * V. ADMIN
+     - An admin client sends one command per message after the handshake
+     - Omitting the alias applies the command to every alias
+
+     /*Format-----------------------
+     <command(QUOTA;(<alias>)/RESET;(<alias>)/UNBAN;<alias/ip>/POOL/CLUSTER/OWNER;<alias>/RELEASE;<alias>)>
+      ------------------------------*/
+ */
+/// An enum representing the commands a [TransmitService::Admin] client can send
+///
+/// # Variants
+///
+/// - `Quota`: Requests the quota usage of an alias, or of every alias
+/// - `Reset`: Resets the quota counters of an alias, or of every alias
+/// - `Unban`: Lifts the rate limit ban of an alias or an ip
+/// - `Pool`: Requests the metrics of the worker pool
+/// - `Cluster`: Requests the nodes of the cluster, the linked peers and the number of pending messages held
+/// - `Owner`: Requests the node owning the pending messages of an alias
+/// - `Release`: Releases the owner of an alias, the next secret it is claimed with owns it
+#[derive(Debug)]
+pub enum AdminCommand{
+     Quota(Option<String>),
+     Reset(Option<String>),
+     Unban(String),
+     Pool,
+     Cluster,
+     Owner(String),
+     Release(String)
+}
 
-     Err(ProtocolError::SessionExtractionError("Could not determine wether the session was send or receive.".to_string()))
+///Method to parse a command sent by a [TransmitService::Admin] client
+pub fn get_admin_command(raw:&[u8])->Result<AdminCommand, ProtocolError>{
+     let mut raw_vec = raw.to_vec();
+     raw_vec.retain(|&x| x!=0);
+     let raw_string = String::from_utf8_lossy(&raw_vec);
 
+     let (command, arg) = match raw_string.trim().split_once(";"){
+          None=>(raw_string.trim().to_string(), None),
+          Some((a,b))=>(a.trim().to_string(), Some(b.trim().to_string()).filter(|b|!b.is_empty()))
+     };
+
+     match command.as_str(){
+          "QUOTA"=>Ok(AdminCommand::Quota(arg)),
+          "RESET"=>Ok(AdminCommand::Reset(arg)),
+          "UNBAN"=>match arg{
+               Some(target)=>Ok(AdminCommand::Unban(target)),
+               None=>Err(ProtocolError::FromatError("UNBAN requires an alias or an ip".to_string()))
+          },
+          "POOL"=>Ok(AdminCommand::Pool),
+          "CLUSTER"=>Ok(AdminCommand::Cluster),
+          "OWNER"=>match arg{
+               Some(alias)=>Ok(AdminCommand::Owner(alias)),
+               None=>Err(ProtocolError::FromatError("OWNER requires an alias".to_string()))
+          },
+          "RELEASE"=>match arg{
+               Some(alias)=>Ok(AdminCommand::Release(alias)),
+               None=>Err(ProtocolError::FromatError("RELEASE requires an alias".to_string()))
+          },
+          _=>Err(ProtocolError::FromatError(format!("Unknown admin command '{command}'")))
+     }
 }
@@ -1,5 +1,11 @@
 //Protocol transfer objects
 
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Id handed out by [next_message_id] last
+static LAST_MESSAGE_ID:AtomicU64 = AtomicU64::new(0);
+
 ///  A trait type for objects transferring data between threads, processes, etc.
 /// 
 ///  # Type Parameters
@@ -31,6 +37,11 @@ pub trait Proto<I,S,B> {
      /// # Returns
      /// * `B`: The type for body (data)
      fn get_body(&self)->&B;
+
+     /// Returns the id the server assigned to the data, if any
+     fn get_id(&self)->Option<u64>{
+          None
+     }
 }
 
 ///  A struct for implementing ProtocolTransferObject on BaseProtocol
@@ -42,11 +53,13 @@ pub trait Proto<I,S,B> {
 ///  - `alias`: The unique identifier of the client (as a part of data in raw_bytes)
 ///  - `body`: The body of the data transmitted
 ///  - `to`: The unique identifier of the client to which the user wishes to send data
-#[derive(Debug)]
+///  - `id`: The id assigned by the server when it accepted the message, see [next_message_id]
+#[derive(Debug, Clone)]
 pub struct BaseProto{
      alias:String,
      body:String,
-     to:String
+     to:String,
+     id:Option<u64>
 }
 
 impl BaseProto{
@@ -55,7 +68,8 @@ impl BaseProto{
           BaseProto{
                alias,
                body:body_stripped,
-               to   
+               to,
+               id:None
           }
      }
 
@@ -63,9 +77,24 @@ impl BaseProto{
           BaseProto{
                alias:c.get_client_id().to_string(),
                body:c.get_body().to_string(),
-               to:c.get_receiver().to_string()
+               to:c.get_receiver().to_string(),
+               id:c.get_id()
           }
      }
+
+     /// Sets the id of the message
+     pub fn with_id(mut self, id:u64)->Self{
+          self.id = Some(id);
+          self
+     }
+}
+
+/// Returns a new message id, greater than every id returned before.
+/// Ids start from the current time in microseconds, so that they keep increasing across restarts of the server
+pub fn next_message_id()->u64{
+     let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d|d.as_micros() as u64).unwrap_or(0);
+     LAST_MESSAGE_ID.fetch_max(now, Ordering::SeqCst);
+     LAST_MESSAGE_ID.fetch_add(1, Ordering::SeqCst)+1
 }
 
 impl Proto<String,String,String> for BaseProto{
@@ -100,4 +129,12 @@ impl Proto<String,String,String> for BaseProto{
      fn get_receiver(&self)->&String {
          &self.to
      }
+
+     ///Returns the id the server assigned to the message
+     /// 
+     /// # Returns
+     /// - `Option<u64>`: id
+     fn get_id(&self)->Option<u64> {
+          self.id
+     }
 }
\ No newline at end of file
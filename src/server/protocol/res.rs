@@ -1,27 +1,272 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::error::ProtocolError;
+
 
 /// An enum representing all the status codes that can be sent to the client
 ///
 /// # Variants
 ///
-/// - `Success`: Respresent a success message dispatch 
+/// - `Success`: Respresent a success message dispatch
 /// - `InvalidIdentifier`: Represents an invalid client identifier (username)
+/// - `InvalidFormat`: Represents a message or command that does not follow the protocol
+/// - `UnknownRecipient`: Represents a message refused because no receive client is registered for the alias it is sent to
 /// - `ServerError`: Server Error
+/// - `Unauthorized`: Represents a client that is not the owner of the alias it claims
+/// - `Conflict`: Represents an alias that is already registered by another client
+/// - `QueueFull`: Represents a message dropped because the delivery queue of the receiver is full
+/// - `SlowConsumer`: Represents a receiver disconnected because it could not keep up with its delivery queue
+/// - `Throttled`: Represents a message refused because the client exceeded its rate limit
+/// - `Banned`: Represents a client disconnected and temporarily banned for repeatedly exceeding its rate limit
+/// - `MessageTooLarge`: Represents a message refused because its body exceeds the maximum message size
+/// - `QuotaExceeded`: Represents a message refused because the sender alias has used up its quota
+/// - `ShuttingDown`: Represents a client disconnected because the server is shutting down
+/// - `Busy`: Represents a client refused because the server is handling its maximum number of connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     Success,
     InvalidIdentifier,
-    ServerError
+    InvalidFormat,
+    UnknownRecipient,
+    ServerError,
+    Unauthorized,
+    Conflict,
+    QueueFull,
+    SlowConsumer,
+    Throttled,
+    Banned,
+    MessageTooLarge,
+    QuotaExceeded,
+    ShuttingDown,
+    Busy
+}
+
+/// Every status, in the order of their codes
+const STATUSES:[Status;15] = [
+     Status::Success,
+     Status::InvalidIdentifier,
+     Status::Unauthorized,
+     Status::QuotaExceeded,
+     Status::Banned,
+     Status::UnknownRecipient,
+     Status::Conflict,
+     Status::SlowConsumer,
+     Status::MessageTooLarge,
+     Status::InvalidFormat,
+     Status::Throttled,
+     Status::ServerError,
+     Status::ShuttingDown,
+     Status::QueueFull,
+     Status::Busy
+];
+
+impl Status{
+     /// Returns the numeric code of the status, 2xx for success, 4xx for refusals caused by the client
+     /// and 5xx for refusals caused by the state of the server
+     pub fn code(&self)->u16{
+          match self{
+               Status::Success=>200,
+               Status::InvalidIdentifier=>400,
+               Status::Unauthorized=>401,
+               Status::QuotaExceeded=>402,
+               Status::Banned=>403,
+               Status::UnknownRecipient=>404,
+               Status::Conflict=>409,
+               Status::SlowConsumer=>410,
+               Status::MessageTooLarge=>413,
+               Status::InvalidFormat=>422,
+               Status::Throttled=>429,
+               Status::ServerError=>500,
+               Status::ShuttingDown=>503,
+               Status::QueueFull=>507,
+               Status::Busy=>529
+          }
+     }
+
+     /// Returns the status of a numeric code
+     pub fn from_code(code:u16)->Option<Status>{
+          STATUSES.into_iter().find(|s|s.code()==code)
+     }
+
+     /// Returns true for [Status::Success]
+     pub fn is_success(&self)->bool{
+          *self==Status::Success
+     }
 }
 
-/// Struct for generating responses after client handles the message and sends the status code along with message
-pub struct Response;
-/*<Status>;<Message>*/
+/// A struct representing a response frame sent to a client, after a message, a command or a refused handshake,
+/// and as a notice before the server closes the connection
+///
+/// # Fields
+///
+/// - `status`: The [Status] of the response, sent along with its numeric code
+/// - `id`: The id of the message the response is about, if any
+/// - `message`: The human readable message of the response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response{
+     status:Status,
+     id:Option<u64>,
+     message:String
+}
+/*<Code>;<Status>;<Id or ->;<Message>*/
 
 impl Response{
+     /// Default constructor for a [Response]
+     pub fn new(status:Status, id:Option<u64>, message:String)->Self{
+          Response{
+               status,
+               id,
+               message
+          }
+     }
+
+     /// Generates the frame of a response that is not about a message
      pub fn generate_res(code:Status, message:String)->String{
-          match code {
-              Status::InvalidIdentifier=>format!("InvalidIdentifier;{}", message),
-              Status::ServerError=>format!("ServerError;{}", message),
-              Status::Success=>format!("Success;{}", message)
+          Response::new(code, None, message).to_string()
+     }
+
+     /// Generates the frame of a response about the message `id`
+     pub fn generate_res_for(id:u64, code:Status, message:String)->String{
+          Response::new(code, Some(id), message).to_string()
+     }
+
+     /// Parses a response frame
+     ///
+     /// # Errors
+     /// - [ProtocolError::FromatError]: The frame is not a response, or its code and status do not match
+     pub fn parse(raw:&str)->Result<Response, ProtocolError>{
+          let mut parts = raw.splitn(4, ';');
+          let (code, status, id, message) = match (parts.next(), parts.next(), parts.next(), parts.next()){
+               (Some(code), Some(status), Some(id), Some(message))=>(code, status, id, message),
+               _=>return Err(ProtocolError::FromatError(format!("Could not extract the code, status, id and message of the response '{raw}'")))
+          };
+
+          let status = Status::from_str(status)?;
+          match code.parse::<u16>(){
+               Ok(code) if code==status.code()=>(),
+               _=>return Err(ProtocolError::FromatError(format!("The code '{code}' does not match the status {status}")))
+          }
+          let id = match id{
+               "-"=>None,
+               id=>match id.parse(){
+                    Ok(id)=>Some(id),
+                    Err(_)=>return Err(ProtocolError::FromatError(format!("Could not extract the message id of the response '{raw}'")))
+               }
+          };
+
+          Ok(Response{
+               status,
+               id,
+               message:message.to_string()
+          })
+     }
+
+     //----Getters----
+     pub fn get_code(&self)->u16{
+          self.status.code()
+     }
+
+     pub fn get_status(&self)->Status{
+          self.status
+     }
+
+     pub fn get_id(&self)->Option<u64>{
+          self.id
+     }
+
+     pub fn get_message(&self)->&String{
+          &self.message
+     }
+}
+
+/// Display implementation for Response, the frame it is sent as
+impl Display for Response{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self.id{
+               Some(id)=>write!(f, "{};{};{};{}", self.status.code(), self.status, id, self.message),
+               None=>write!(f, "{};{};-;{}", self.status.code(), self.status, self.message)
+          }
+     }
+}
+
+/// Display implementation for Status, the name it is sent under
+impl Display for Status{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          let name = match self{
+               Status::Success=>"Success",
+               Status::InvalidIdentifier=>"InvalidIdentifier",
+               Status::InvalidFormat=>"InvalidFormat",
+               Status::UnknownRecipient=>"UnknownRecipient",
+               Status::ServerError=>"ServerError",
+               Status::Unauthorized=>"Unauthorized",
+               Status::Conflict=>"Conflict",
+               Status::QueueFull=>"QueueFull",
+               Status::SlowConsumer=>"SlowConsumer",
+               Status::Throttled=>"Throttled",
+               Status::Banned=>"Banned",
+               Status::MessageTooLarge=>"MessageTooLarge",
+               Status::QuotaExceeded=>"QuotaExceeded",
+               Status::ShuttingDown=>"ShuttingDown",
+               Status::Busy=>"Busy"
+          };
+          write!(f, "{name}")
+     }
+}
+
+/// FromStr implementation for Status, parsing the name it is sent under
+impl FromStr for Status{
+     type Err = ProtocolError;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          STATUSES.into_iter()
+               .find(|status|status.to_string()==s)
+               .ok_or(ProtocolError::FromatError(format!("Unknown status '{s}'")))
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+
+     #[test]
+     fn round_trips_every_status(){
+          for status in STATUSES{
+               assert_eq!(Status::from_code(status.code()), Some(status));
+               assert_eq!(Status::from_str(&status.to_string()).unwrap(), status);
+
+               let res = Response::new(status, Some(42), format!("{status} answer"));
+               let parsed = Response::parse(&res.to_string()).unwrap();
+               assert_eq!(parsed, res);
+               assert_eq!(parsed.get_code(), status.code());
+          }
+          //codes are unique and increasing
+          assert!(STATUSES.windows(2).all(|pair|pair[0].code()<pair[1].code()));
+     }
+
+     #[test]
+     fn parses_a_response_about_no_message(){
+          let frame = Response::generate_res(Status::Busy, "The server is busy, try again later".to_string());
+          assert_eq!(frame, "529;Busy;-;The server is busy, try again later");
+          let res = Response::parse(&frame).unwrap();
+          assert_eq!(res.get_id(), None);
+          assert_eq!(res.get_status(), Status::Busy);
+          assert_eq!(res.get_message(), "The server is busy, try again later");
+     }
+
+     #[test]
+     fn keeps_the_separators_of_the_message(){
+          let frame = Response::generate_res_for(7, Status::Success, "bob;alice;;carol".to_string());
+          let res = Response::parse(&frame).unwrap();
+          assert_eq!(res.get_id(), Some(7));
+          assert_eq!(res.get_message(), "bob;alice;;carol");
+          assert_eq!(Response::parse("200;Success;-;").unwrap().get_message(), "");
+     }
+
+     #[test]
+     fn refuses_malformed_responses(){
+          for raw in ["", "200;Success;-", "200;Success", "abc;Success;-;message", "404;Success;-;message", "200;Unknown;-;message", "200;Success;x;message", "200;Success;-1;message"]{
+               assert!(matches!(Response::parse(raw), Err(ProtocolError::FromatError(_))), "{raw}");
           }
      }
-}
\ No newline at end of file
+}
@@ -0,0 +1,397 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::mpsc::RecvError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// Maximum time a sender waits for room in a full queue under [BackpressurePolicy::Block] before the message is dropped
+pub const BLOCK_TIMEOUT:Duration = Duration::from_secs(5);
+
+
+/// An enum representing the policy applied when a message is pushed to a full delivery queue
+///
+/// # Variants
+///
+/// - `Block`: The sender waits until the receiver makes room in the queue, the message is dropped as with `DropNewest`
+///   once [BLOCK_TIMEOUT] is reached
+/// - `DropOldest`: The oldest queued message is dropped to make room for the new message
+/// - `DropNewest`: The new message is dropped and the sender is answered with an error
+/// - `Disconnect`: The queue is closed and the slow receiver is disconnected, its queued messages are kept for redelivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy{
+     #[default]
+     Block,
+     DropOldest,
+     DropNewest,
+     Disconnect
+}
+
+/// An enum representing a successful push to a delivery queue
+///
+/// # Variants
+///
+/// - `Queued`: The message has been queued
+/// - `DroppedOldest`: The message has been queued after the oldest queued message was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery{
+     Queued,
+     DroppedOldest
+}
+
+/// An enum representing a failed push to a delivery queue. The message is handed back to the caller
+///
+/// # Variants
+///
+/// - `Full`: The queue is full and the message was dropped ([BackpressurePolicy::DropNewest], or [BackpressurePolicy::Block] timing out)
+/// - `Closed`: The receiver is gone, the message was never queued and may be kept for redelivery
+/// - `Evicted`: The queue was full and its receiver has been disconnected for being too slow ([BackpressurePolicy::Disconnect])
+#[derive(Debug)]
+pub enum QueueError<T>{
+     Full(T),
+     Closed(T),
+     Evicted(T)
+}
+
+/// A trait for the delivery queues pending messages are handed over to, such as [QueueSender]
+pub trait Offer<T>{
+     /// Pushes a message to the queue only if it has room, without applying the backpressure policy.
+     /// Used to hand pending messages over without dropping nor waiting on the messages already queued
+     fn offer(&self, item:T)->Result<(), QueueError<T>>;
+}
+
+/// The state shared by the sending and receiving halves of a delivery queue
+#[derive(Debug)]
+struct State<T>{
+     items:VecDeque<T>,
+     senders:usize,
+     closed:bool
+}
+
+#[derive(Debug)]
+struct Shared<T>{
+     state:Mutex<State<T>>,
+     not_empty:Condvar,
+     not_full:Condvar,
+     capacity:usize,
+     policy:BackpressurePolicy
+}
+
+/// The sending half of a bounded delivery queue, analogous to [std::sync::mpsc::Sender].
+/// Can be cloned to push from multiple threads
+#[derive(Debug)]
+pub struct QueueSender<T>{
+     shared:Arc<Shared<T>>
+}
+
+/// The receiving half of a bounded delivery queue, analogous to [std::sync::mpsc::Receiver].
+/// Dropping it closes the queue
+#[derive(Debug)]
+pub struct QueueReceiver<T>{
+     shared:Arc<Shared<T>>
+}
+
+/// A handle closing a delivery queue from another thread, waking its receiver.
+/// Unlike a [QueueSender] it does not keep the queue open
+#[derive(Debug)]
+pub struct QueueCloser<T>{
+     shared:Arc<Shared<T>>
+}
+
+/// Creates a bounded delivery queue holding at most `capacity` messages.
+/// When the queue is full the `policy` decides the outcome of a push
+///
+/// # Arguments
+///
+/// * `capacity`: The maximum number of queued messages, at least 1
+/// * `policy`: The [BackpressurePolicy] applied when the queue is full
+pub fn bounded<T>(capacity:usize, policy:BackpressurePolicy)->(QueueSender<T>, QueueReceiver<T>){
+     let shared = Arc::new(Shared{
+          state:Mutex::new(State{
+               items:VecDeque::new(),
+               senders:1,
+               closed:false
+          }),
+          not_empty:Condvar::new(),
+          not_full:Condvar::new(),
+          capacity:capacity.max(1),
+          policy
+     });
+
+     (QueueSender{shared:shared.clone()}, QueueReceiver{shared})
+}
+
+impl <T>QueueSender<T>{
+     /// Pushes a message to the queue, applying the backpressure policy when the queue is full
+     pub fn send(&self, item:T)->Result<Delivery, QueueError<T>>{
+          let mut state = self.shared.state.lock().unwrap();
+          let mut delivery = Delivery::Queued;
+
+          if state.closed{
+               return Err(QueueError::Closed(item));
+          }
+
+          if state.items.len()>=self.shared.capacity{
+               match self.shared.policy{
+                    BackpressurePolicy::Block=>{
+                         let deadline = Instant::now()+BLOCK_TIMEOUT;
+                         while state.items.len()>=self.shared.capacity && !state.closed{
+                              let remaining = deadline.saturating_duration_since(Instant::now());
+                              if remaining.is_zero(){
+                                   return Err(QueueError::Full(item));
+                              }
+                              state = self.shared.not_full.wait_timeout(state, remaining).unwrap().0;
+                         }
+                         if state.closed{
+                              return Err(QueueError::Closed(item));
+                         }
+                    },
+                    BackpressurePolicy::DropOldest=>{
+                         state.items.pop_front();
+                         delivery = Delivery::DroppedOldest;
+                    },
+                    BackpressurePolicy::DropNewest=>{
+                         return Err(QueueError::Full(item));
+                    },
+                    BackpressurePolicy::Disconnect=>{
+                         state.closed = true;
+                         self.shared.not_empty.notify_all();
+                         self.shared.not_full.notify_all();
+                         return Err(QueueError::Evicted(item));
+                    }
+               }
+          }
+
+          state.items.push_back(item);
+          self.shared.not_empty.notify_one();
+          Ok(delivery)
+     }
+
+     /// Returns true if the queue has been closed, by the receiver or for being too slow
+     pub fn is_closed(&self)->bool{
+          self.shared.state.lock().unwrap().closed
+     }
+
+     /// Returns the [BackpressurePolicy] of the queue
+     pub fn get_policy(&self)->BackpressurePolicy{
+          self.shared.policy
+     }
+}
+
+/// Offer implementation for QueueSender
+impl <T>Offer<T> for QueueSender<T>{
+     fn offer(&self, item:T)->Result<(), QueueError<T>>{
+          let mut state = self.shared.state.lock().unwrap();
+          if state.closed{
+               return Err(QueueError::Closed(item));
+          }
+          if state.items.len()>=self.shared.capacity{
+               return Err(QueueError::Full(item));
+          }
+          state.items.push_back(item);
+          self.shared.not_empty.notify_one();
+          Ok(())
+     }
+}
+
+/// Offer implementation for a shared queue
+impl <T, Q:Offer<T>>Offer<T> for Arc<Q>{
+     fn offer(&self, item:T)->Result<(), QueueError<T>>{
+          self.as_ref().offer(item)
+     }
+}
+
+impl <T>QueueReceiver<T>{
+     /// Blocks until a message is available.
+     /// Returns an error once the queue is closed, or once every sender is gone and the queue is empty
+     pub fn recv(&self)->Result<T, RecvError>{
+          let mut state = self.shared.state.lock().unwrap();
+          loop {
+               if state.closed{
+                    return Err(RecvError);
+               }
+               if let Some(item) = state.items.pop_front(){
+                    self.shared.not_full.notify_one();
+                    return Ok(item);
+               }
+               if state.senders==0{
+                    return Err(RecvError);
+               }
+               state = self.shared.not_empty.wait(state).unwrap();
+          }
+     }
+
+     /// Takes every queued message, even once the queue is closed
+     pub fn drain(&self)->Vec<T>{
+          self.shared.state.lock().unwrap().items.drain(..).collect()
+     }
+
+     /// Returns a [QueueCloser] for this queue
+     pub fn closer(&self)->QueueCloser<T>{
+          QueueCloser{
+               shared:self.shared.clone()
+          }
+     }
+
+     /// Returns the number of queued messages
+     pub fn len(&self)->usize{
+          self.shared.state.lock().unwrap().items.len()
+     }
+
+     /// Returns true if no message is queued
+     pub fn is_empty(&self)->bool{
+          self.len()==0
+     }
+}
+
+impl <T>QueueCloser<T>{
+     /// Closes the queue, the receiver stops awaiting messages and senders are refused
+     pub fn close(&self){
+          let mut state = self.shared.state.lock().unwrap();
+          state.closed = true;
+          self.shared.not_empty.notify_all();
+          self.shared.not_full.notify_all();
+     }
+}
+
+///Clone implementation for QueueSender
+impl <T>Clone for QueueSender<T>{
+     fn clone(&self) -> Self {
+          self.shared.state.lock().unwrap().senders+=1;
+          QueueSender{
+               shared:self.shared.clone()
+          }
+     }
+}
+
+///Drop implementation for QueueSender, wakes the receiver once the last sender is gone
+impl <T>Drop for QueueSender<T>{
+     fn drop(&mut self) {
+          let mut state = self.shared.state.lock().unwrap();
+          state.senders-=1;
+          if state.senders==0{
+               self.shared.not_empty.notify_all();
+          }
+     }
+}
+
+///Drop implementation for QueueReceiver, closes the queue and wakes blocked senders
+impl <T>Drop for QueueReceiver<T>{
+     fn drop(&mut self) {
+          let mut state = self.shared.state.lock().unwrap();
+          state.closed = true;
+          self.shared.not_full.notify_all();
+     }
+}
+
+/// Display implementation for BackpressurePolicy
+impl Display for BackpressurePolicy{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Block=>write!(f, "block"),
+               Self::DropOldest=>write!(f, "drop-oldest"),
+               Self::DropNewest=>write!(f, "drop-newest"),
+               Self::Disconnect=>write!(f, "disconnect")
+          }
+     }
+}
+
+/// FromStr implementation for BackpressurePolicy, parses the names it is displayed with
+impl FromStr for BackpressurePolicy{
+     type Err = String;
+
+     fn from_str(s: &str) -> Result<Self, Self::Err> {
+          match s {
+               "block"=>Ok(Self::Block),
+               "drop-oldest"=>Ok(Self::DropOldest),
+               "drop-newest"=>Ok(Self::DropNewest),
+               "disconnect"=>Ok(Self::Disconnect),
+               _=>Err(format!("unknown backpressure policy '{s}', expected block, drop-oldest, drop-newest or disconnect"))
+          }
+     }
+}
+
+/// Display implementation for QueueError
+impl <T>Display for QueueError<T>{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Full(_)=>write!(f, "{{ error: QueueFull; info: the delivery queue is full }}"),
+               Self::Closed(_)=>write!(f, "{{ error: QueueClosed; info: the receiver of the delivery queue is gone }}"),
+               Self::Evicted(_)=>write!(f, "{{ error: QueueEvicted; info: the receiver of the delivery queue has been disconnected for being too slow }}")
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::thread::{sleep, spawn};
+
+     fn full(policy:BackpressurePolicy)->(QueueSender<u32>, QueueReceiver<u32>){
+          let (sender, receiver) = bounded(2, policy);
+          assert_eq!(sender.send(1).unwrap(), Delivery::Queued);
+          assert_eq!(sender.send(2).unwrap(), Delivery::Queued);
+          (sender, receiver)
+     }
+
+     #[test]
+     fn blocks_until_the_receiver_makes_room(){
+          let (sender, receiver) = full(BackpressurePolicy::Block);
+          let taker = spawn(move ||{
+               sleep(Duration::from_millis(50));
+               let first = receiver.recv().unwrap();
+               (first, receiver)
+          });
+          assert_eq!(sender.send(3).unwrap(), Delivery::Queued);
+          let (first, receiver) = taker.join().unwrap();
+          assert_eq!(first, 1);
+          assert_eq!(receiver.drain(), [2, 3]);
+
+          //a sender waiting on a queue whose receiver is gone gets its message back
+          let (sender, receiver) = full(BackpressurePolicy::Block);
+          let closer = spawn(move ||{
+               sleep(Duration::from_millis(50));
+               drop(receiver);
+          });
+          assert!(matches!(sender.send(3), Err(QueueError::Closed(3))));
+          closer.join().unwrap();
+     }
+
+     #[test]
+     fn drops_the_oldest_message(){
+          let (sender, receiver) = full(BackpressurePolicy::DropOldest);
+          assert_eq!(sender.send(3).unwrap(), Delivery::DroppedOldest);
+          assert_eq!(receiver.drain(), [2, 3]);
+     }
+
+     #[test]
+     fn drops_the_newest_message(){
+          let (sender, receiver) = full(BackpressurePolicy::DropNewest);
+          assert!(matches!(sender.send(3), Err(QueueError::Full(3))));
+          assert_eq!(receiver.drain(), [1, 2]);
+     }
+
+     #[test]
+     fn evicts_the_slow_receiver(){
+          let (sender, receiver) = full(BackpressurePolicy::Disconnect);
+          assert!(matches!(sender.send(3), Err(QueueError::Evicted(3))));
+          assert!(sender.is_closed());
+          assert!(receiver.recv().is_err());
+          assert!(matches!(sender.send(4), Err(QueueError::Closed(4))));
+          //the queued messages are kept for redelivery
+          assert_eq!(receiver.drain(), [1, 2]);
+     }
+
+     #[test]
+     fn offers_without_applying_the_policy(){
+          for policy in [BackpressurePolicy::Block, BackpressurePolicy::DropOldest, BackpressurePolicy::DropNewest, BackpressurePolicy::Disconnect]{
+               let (sender, receiver) = full(policy);
+               assert!(matches!(sender.offer(3), Err(QueueError::Full(3))), "{policy}");
+               assert!(!sender.is_closed());
+               assert_eq!(receiver.recv().unwrap(), 1);
+               assert!(sender.offer(3).is_ok());
+               assert_eq!(receiver.drain(), [2, 3]);
+          }
+     }
+}
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+
+/// An enum representing the period over which the usage of an alias is counted
+///
+/// # Variants
+///
+/// - `Daily`: The usage is reset at midnight UTC
+/// - `Rolling`: The usage is counted over the last given duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPeriod{
+     Daily,
+     Rolling(Duration)
+}
+
+/// A struct representing the quota of every alias
+///
+/// # Fields
+///
+/// - `max_messages`: The number of messages an alias may send per period, 0 disables the limit
+/// - `max_bytes`: The number of bytes an alias may send per period, 0 disables the limit
+/// - `period`: The [QuotaPeriod] over which the usage is counted
+#[derive(Debug, Clone)]
+pub struct Quota{
+     pub max_messages:u64,
+     pub max_bytes:u64,
+     pub period:QuotaPeriod
+}
+
+/// An enum representing an exceeded quota
+///
+/// # Variants
+///
+/// - `Messages`: The alias has sent its maximum number of messages for the period
+/// - `Bytes`: The message would exceed the maximum number of bytes for the period
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaExceeded{
+     Messages(u64),
+     Bytes(u64)
+}
+
+/// A struct representing the usage of an alias in the current period
+///
+/// # Fields
+///
+/// - `messages`: The number of messages sent
+/// - `bytes`: The number of bytes sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage{
+     pub messages:u64,
+     pub bytes:u64
+}
+
+/// Number of slots a rolling period is split into
+const ROLLING_SLOTS:u32 = 60;
+
+/// The counters of one alias
+#[derive(Debug)]
+enum Counter{
+     /// Usage of the current UTC day, identified by its number of days since the unix epoch
+     Daily(u64, QuotaUsage),
+     /// Usage of the slots of a rolling period, oldest first
+     Rolling(VecDeque<(Instant, QuotaUsage)>)
+}
+
+/// A struct tracking the usage of each alias against the [Quota].
+/// The server counts the messages of a send client against the alias it claimed, or against its address when it claimed none.
+/// Nothing is tracked while the quota is disabled. Counters can be inspected and reset by an admin
+///
+/// # Fields
+///
+/// - `quota`: The [Quota] enforced on every alias
+/// - `counters`: The usage counters of each alias
+/// - `reservations`: The number of reservations since the last prune of the expired counters
+#[derive(Debug)]
+pub struct QuotaTracker{
+     quota:Quota,
+     counters:HashMap<String, Counter>,
+     reservations:u32
+}
+
+/// Number of reservations after which the counters of the past periods are pruned from the tracker
+const PRUNE_INTERVAL:u32 = 4096;
+
+impl Default for Quota{
+     fn default() -> Self {
+          Quota{
+               max_messages:0,
+               max_bytes:0,
+               period:QuotaPeriod::Daily
+          }
+     }
+}
+
+/// Returns the number of days since the unix epoch (UTC)
+fn current_day()->u64{
+     SystemTime::now().duration_since(UNIX_EPOCH)
+          .map(|d|d.as_secs()/86400)
+          .unwrap_or(0)
+}
+
+impl Counter{
+     fn new(period:QuotaPeriod)->Self{
+          match period{
+               QuotaPeriod::Daily=>Counter::Daily(current_day(), QuotaUsage::default()),
+               QuotaPeriod::Rolling(_)=>Counter::Rolling(VecDeque::new())
+          }
+     }
+
+     /// Returns the usage of the current period, dropping expired counts
+     fn usage(&mut self, period:QuotaPeriod, now:Instant)->QuotaUsage{
+          match (self, period){
+               (Counter::Daily(day, usage), _)=>{
+                    let today = current_day();
+                    if *day!=today{
+                         *day = today;
+                         *usage = QuotaUsage::default();
+                    }
+                    *usage
+               },
+               (Counter::Rolling(slots), QuotaPeriod::Rolling(window))=>{
+                    while slots.front().is_some_and(|(start, _)|now.duration_since(*start)>=window){
+                         slots.pop_front();
+                    }
+                    slots.iter().fold(QuotaUsage::default(), |acc, (_, u)|QuotaUsage{
+                         messages:acc.messages+u.messages,
+                         bytes:acc.bytes+u.bytes
+                    })
+               },
+               (Counter::Rolling(_), QuotaPeriod::Daily)=>QuotaUsage::default()
+          }
+     }
+
+     /// Takes a message counted with [Counter::add] back out of the current period
+     fn remove(&mut self, bytes:u64){
+          let usage = match self{
+               Counter::Daily(day, usage) if *day==current_day()=>usage,
+               Counter::Rolling(slots)=>match slots.back_mut(){
+                    Some((_, usage))=>usage,
+                    None=>return
+               },
+               _=>return
+          };
+          usage.messages = usage.messages.saturating_sub(1);
+          usage.bytes = usage.bytes.saturating_sub(bytes);
+     }
+
+     fn add(&mut self, period:QuotaPeriod, bytes:u64, now:Instant){
+          match (self, period){
+               (Counter::Daily(_, usage), _)=>{
+                    usage.messages+=1;
+                    usage.bytes+=bytes;
+               },
+               (Counter::Rolling(slots), QuotaPeriod::Rolling(window))=>{
+                    let slot = window/ROLLING_SLOTS;
+                    match slots.back_mut(){
+                         Some((start, usage)) if now.duration_since(*start)<slot=>{
+                              usage.messages+=1;
+                              usage.bytes+=bytes;
+                         },
+                         _=>slots.push_back((now, QuotaUsage{messages:1, bytes}))
+                    }
+               },
+               (Counter::Rolling(_), QuotaPeriod::Daily)=>()
+          }
+     }
+}
+
+impl QuotaTracker{
+     /// Default constructor for a [QuotaTracker] enforcing the given [Quota]
+     pub fn new(quota:Quota)->Self{
+          QuotaTracker{
+               quota,
+               counters:HashMap::new(),
+               reservations:0
+          }
+     }
+
+     /// Returns true if the quota limits the messages or the bytes of an alias
+     pub fn is_enabled(&self)->bool{
+          self.quota.max_messages>0 || self.quota.max_bytes>0
+     }
+
+     /// Reserves a message of `bytes` bytes sent by `alias` if it fits in the quota, it is counted right away so that
+     /// the messages sent at once cannot exceed the quota together. A message that is not delivered is given back with [QuotaTracker::refund]
+     ///
+     /// # Returns
+     /// - `QuotaUsage`: The usage of the alias once the message is counted
+     pub fn consume(&mut self, alias:&str, bytes:usize)->Result<QuotaUsage, QuotaExceeded>{
+          if !self.is_enabled(){
+               return Ok(QuotaUsage::default());
+          }
+          self.reservations+=1;
+          if self.reservations>=PRUNE_INTERVAL{
+               self.reservations = 0;
+               self.prune();
+          }
+
+          let usage = self.check(alias, bytes)?;
+          let now = Instant::now();
+          let period = self.quota.period;
+          self.counters.entry(alias.to_string()).or_insert_with(||Counter::new(period)).add(period, bytes as u64, now);
+          Ok(usage)
+     }
+
+     /// Gives back a message of `bytes` bytes reserved with [QuotaTracker::consume] that has not been delivered
+     pub fn refund(&mut self, alias:&str, bytes:usize){
+          if let Some(counter) = self.counters.get_mut(alias){
+               counter.remove(bytes as u64);
+          }
+     }
+
+     /// Checks whether a message of `bytes` bytes sent by `alias` fits in the quota, without counting it
+     fn check(&mut self, alias:&str, bytes:usize)->Result<QuotaUsage, QuotaExceeded>{
+          let usage = self.usage(alias);
+          if self.quota.max_messages>0 && usage.messages>=self.quota.max_messages{
+               return Err(QuotaExceeded::Messages(self.quota.max_messages));
+          }
+          if self.quota.max_bytes>0 && usage.bytes+bytes as u64>self.quota.max_bytes{
+               return Err(QuotaExceeded::Bytes(self.quota.max_bytes));
+          }
+          Ok(QuotaUsage{
+               messages:usage.messages+1,
+               bytes:usage.bytes+bytes as u64
+          })
+     }
+
+     /// Returns the usage of an alias in the current period
+     pub fn usage(&mut self, alias:&str)->QuotaUsage{
+          let now = Instant::now();
+          let period = self.quota.period;
+          match self.counters.get_mut(alias){
+               None=>QuotaUsage::default(),
+               Some(counter)=>counter.usage(period, now)
+          }
+     }
+
+     /// Returns the usage of every alias that sent messages in the current period, sorted by alias
+     pub fn usages(&mut self)->Vec<(String, QuotaUsage)>{
+          let now = Instant::now();
+          let period = self.quota.period;
+          let mut usages:Vec<(String, QuotaUsage)> = self.counters.iter_mut()
+               .map(|(alias, counter)|(alias.clone(), counter.usage(period, now)))
+               .filter(|(_, usage)|*usage!=QuotaUsage::default())
+               .collect();
+          usages.sort_by(|a, b|a.0.cmp(&b.0));
+          usages
+     }
+
+     /// Drops the counters of the aliases that sent no message in the current period, to bound memory usage
+     pub fn prune(&mut self){
+          let now = Instant::now();
+          let period = self.quota.period;
+          self.counters.retain(|_, counter|counter.usage(period, now)!=QuotaUsage::default());
+     }
+
+     /// Resets the counters of an alias
+     pub fn reset(&mut self, alias:&str){
+          self.counters.remove(alias);
+     }
+
+     /// Resets the counters of every alias
+     pub fn reset_all(&mut self){
+          self.counters.clear();
+     }
+
+     pub fn get_quota(&self)->&Quota{
+          &self.quota
+     }
+}
+
+/// Display implementation for QuotaExceeded
+impl Display for QuotaExceeded{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Messages(max)=>write!(f, "{{ error: QuotaExceeded; info: the quota of {} messages per period has been reached }}", max),
+               Self::Bytes(max)=>write!(f, "{{ error: QuotaExceeded; info: the quota of {} bytes per period has been reached }}", max)
+          }
+     }
+}
+
+/// Display implementation for QuotaUsage
+impl Display for QuotaUsage{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          write!(f, "messages={},bytes={}", self.messages, self.bytes)
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::thread::sleep;
+
+     fn tracker(max_messages:u64, max_bytes:u64, period:QuotaPeriod)->QuotaTracker{
+          QuotaTracker::new(Quota{ max_messages, max_bytes, period })
+     }
+
+     #[test]
+     fn counts_the_messages_and_bytes_of_the_day(){
+          let mut quotas = tracker(2, 100, QuotaPeriod::Daily);
+          assert_eq!(quotas.consume("bob", 10).unwrap(), QuotaUsage{ messages:1, bytes:10 });
+          assert_eq!(quotas.consume("bob", 10).unwrap(), QuotaUsage{ messages:2, bytes:20 });
+          assert_eq!(quotas.consume("bob", 10), Err(QuotaExceeded::Messages(2)));
+          //every alias has its own counters
+          assert_eq!(quotas.consume("alice", 101), Err(QuotaExceeded::Bytes(100)));
+          assert!(quotas.consume("alice", 100).is_ok());
+
+          //a message that was not delivered is given back
+          quotas.refund("bob", 10);
+          assert_eq!(quotas.usage("bob"), QuotaUsage{ messages:1, bytes:10 });
+          assert!(quotas.consume("bob", 10).is_ok());
+     }
+
+     #[test]
+     fn starts_over_on_a_new_day(){
+          let mut quotas = tracker(1, 0, QuotaPeriod::Daily);
+          quotas.counters.insert("bob".to_string(), Counter::Daily(current_day()-1, QuotaUsage{ messages:1, bytes:10 }));
+          assert_eq!(quotas.usage("bob"), QuotaUsage::default());
+          assert!(quotas.consume("bob", 10).is_ok());
+     }
+
+     #[test]
+     fn counts_the_messages_of_the_rolling_window(){
+          let mut quotas = tracker(2, 0, QuotaPeriod::Rolling(Duration::from_millis(300)));
+          assert!(quotas.consume("bob", 1).is_ok());
+          assert!(quotas.consume("bob", 1).is_ok());
+          assert_eq!(quotas.consume("bob", 1), Err(QuotaExceeded::Messages(2)));
+
+          //the messages older than the window are no longer counted
+          sleep(Duration::from_millis(350));
+          assert_eq!(quotas.usage("bob"), QuotaUsage::default());
+          assert!(quotas.consume("bob", 1).is_ok());
+     }
+
+     #[test]
+     fn tracks_nothing_while_disabled(){
+          let mut quotas = tracker(0, 0, QuotaPeriod::Daily);
+          for _ in 0..10{
+               assert!(quotas.consume("bob", 1000).is_ok());
+          }
+          assert!(quotas.counters.is_empty());
+     }
+
+     #[test]
+     fn prunes_the_counters_of_past_periods(){
+          let mut quotas = tracker(1, 0, QuotaPeriod::Daily);
+          quotas.counters.insert("idle".to_string(), Counter::Daily(current_day()-1, QuotaUsage{ messages:1, bytes:10 }));
+          assert!(quotas.consume("bob", 1).is_ok());
+          quotas.prune();
+          assert_eq!(quotas.counters.keys().collect::<Vec<_>>(), ["bob"]);
+     }
+}
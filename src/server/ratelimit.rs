@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+
+/// A struct representing the rate limits enforced on send clients.
+/// The limits apply per source IP, and per alias to the send clients that claimed their alias with its secret.
+///
+/// # Fields
+///
+/// - `messages_per_second`: The sustained number of messages allowed per second, 0 disables the limit
+/// - `bytes_per_second`: The sustained number of bytes allowed per second, 0 disables the limit
+/// - `burst_seconds`: The number of seconds worth of tokens a bucket can hold, allowing short bursts
+/// - `max_violations`: The number of throttled messages within `violation_window` after which the client is banned
+/// - `violation_window`: The window in which violations are counted
+/// - `ban_duration`: How long a banned alias or IP is refused
+#[derive(Debug, Clone)]
+pub struct RateLimit{
+     pub messages_per_second:u32,
+     pub bytes_per_second:u64,
+     pub burst_seconds:u32,
+     pub max_violations:u32,
+     pub violation_window:Duration,
+     pub ban_duration:Duration
+}
+
+/// An enum representing the outcome of a rate limit check
+///
+/// # Variants
+///
+/// - `Allowed`: The message is within the limits
+/// - `Throttled`: The message exceeds the limits and has to be refused
+/// - `Banned`: The alias or IP is banned for the given remaining duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict{
+     Allowed,
+     Throttled,
+     Banned(Duration)
+}
+
+/// A key identifying a rate limited client
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RateKey{
+     Alias(String),
+     Ip(IpAddr)
+}
+
+/// A token bucket refilled continuously at a fixed rate
+#[derive(Debug)]
+struct TokenBucket{
+     capacity:f64,
+     tokens:f64,
+     refill_per_second:f64,
+     last_refill:Instant
+}
+
+/// The buckets and violations of one rate limited client
+#[derive(Debug)]
+struct ClientState{
+     messages:TokenBucket,
+     bytes:TokenBucket,
+     violations:u32,
+     window_start:Instant,
+     last_seen:Instant,
+     banned_until:Option<Instant>
+}
+
+/// A struct representing a token bucket rate limiter keyed by alias and by source IP.
+/// Clients that are throttled `max_violations` times within the violation window are banned for `ban_duration`
+///
+/// # Fields
+///
+/// - `limit`: The [RateLimit] enforced on every client
+/// - `clients`: The state of each alias and IP seen by the limiter
+/// - `checks`: The number of checks since the last prune of idle clients
+#[derive(Debug)]
+pub struct RateLimiter{
+     limit:RateLimit,
+     clients:HashMap<RateKey, ClientState>,
+     checks:u32
+}
+
+/// Number of checks after which idle clients are pruned from the limiter
+const PRUNE_INTERVAL:u32 = 4096;
+
+impl Default for RateLimit{
+     fn default() -> Self {
+          RateLimit{
+               messages_per_second:1000,
+               bytes_per_second:1024*1024,
+               burst_seconds:2,
+               max_violations:100,
+               violation_window:Duration::from_secs(10),
+               ban_duration:Duration::from_secs(60)
+          }
+     }
+}
+
+impl RateLimit{
+     /// A [RateLimit] that never throttles
+     pub fn unlimited()->Self{
+          RateLimit{
+               messages_per_second:0,
+               bytes_per_second:0,
+               ..RateLimit::default()
+          }
+     }
+}
+
+impl TokenBucket{
+     fn new(per_second:f64, burst_seconds:u32)->Self{
+          let capacity = per_second*burst_seconds.max(1) as f64;
+          TokenBucket{
+               capacity,
+               tokens:capacity,
+               refill_per_second:per_second,
+               last_refill:Instant::now()
+          }
+     }
+
+     /// Takes `amount` tokens if available. A bucket with a rate of 0 is unlimited
+     fn try_take(&mut self, amount:f64, now:Instant)->bool{
+          if self.refill_per_second<=0.0{
+               return true;
+          }
+          let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+          self.tokens = (self.tokens+elapsed*self.refill_per_second).min(self.capacity);
+          self.last_refill = now;
+
+          if self.tokens>=amount{
+               self.tokens-=amount;
+               true
+          }else{
+               false
+          }
+     }
+}
+
+impl ClientState{
+     fn new(limit:&RateLimit, now:Instant)->Self{
+          ClientState{
+               messages:TokenBucket::new(limit.messages_per_second as f64, limit.burst_seconds),
+               bytes:TokenBucket::new(limit.bytes_per_second as f64, limit.burst_seconds),
+               violations:0,
+               window_start:now,
+               last_seen:now,
+               banned_until:None
+          }
+     }
+
+     fn banned_for(&mut self, now:Instant)->Option<Duration>{
+          match self.banned_until{
+               Some(until) if until>now=>Some(until-now),
+               Some(_)=>{
+                    self.banned_until = None;
+                    self.violations = 0;
+                    None
+               },
+               None=>None
+          }
+     }
+
+     fn check(&mut self, limit:&RateLimit, bytes:usize, now:Instant)->Verdict{
+          self.last_seen = now;
+          if let Some(remaining) = self.banned_for(now){
+               return Verdict::Banned(remaining);
+          }
+
+          //a message refused by one bucket does not consume the tokens of the other
+          let messages_ok = self.messages.try_take(1.0, now);
+          let bytes_ok = messages_ok && self.bytes.try_take(bytes as f64, now);
+          if messages_ok && bytes_ok{
+               return Verdict::Allowed;
+          }
+          if messages_ok{
+               self.messages.tokens+=1.0;
+          }
+
+          //counting the violation in the current window
+          if now.duration_since(self.window_start)>limit.violation_window{
+               self.window_start = now;
+               self.violations = 0;
+          }
+          self.violations+=1;
+
+          if limit.max_violations>0 && self.violations>=limit.max_violations{
+               self.banned_until = Some(now+limit.ban_duration);
+               return Verdict::Banned(limit.ban_duration);
+          }
+          Verdict::Throttled
+     }
+}
+
+impl RateLimiter{
+     /// Default constructor for a [RateLimiter] enforcing the given [RateLimit]
+     pub fn new(limit:RateLimit)->Self{
+          RateLimiter{
+               limit,
+               clients:HashMap::new(),
+               checks:0
+          }
+     }
+
+     /// Checks a message of `bytes` bytes sent from `ip` by the send client of the claimed `alias`, if any, against the limits of both.
+     /// The IP is checked first, a message refused for its IP does not consume the tokens of the alias
+     pub fn check(&mut self, alias:Option<&str>, ip:IpAddr, bytes:usize)->Verdict{
+          self.checks+=1;
+          if self.checks>=PRUNE_INTERVAL{
+               self.checks = 0;
+               self.prune();
+          }
+
+          let now = Instant::now();
+          let limit = self.limit.clone();
+
+          let ip_verdict = self.clients.entry(RateKey::Ip(ip))
+               .or_insert_with(||ClientState::new(&limit, now))
+               .check(&limit, bytes, now);
+          let alias = match alias{
+               Some(alias) if ip_verdict==Verdict::Allowed=>alias,
+               _=>return ip_verdict
+          };
+
+          self.clients.entry(RateKey::Alias(alias.to_string()))
+               .or_insert_with(||ClientState::new(&limit, now))
+               .check(&limit, bytes, now)
+     }
+
+     /// Returns the remaining ban duration of an IP, if it is banned
+     pub fn is_banned_ip(&mut self, ip:IpAddr)->Option<Duration>{
+          let now = Instant::now();
+          self.clients.get_mut(&RateKey::Ip(ip)).and_then(|c|c.banned_for(now))
+     }
+
+     /// Lifts the ban of an alias
+     pub fn unban_alias(&mut self, alias:&str){
+          self.clients.remove(&RateKey::Alias(alias.to_string()));
+     }
+
+     /// Lifts the ban of an IP
+     pub fn unban_ip(&mut self, ip:IpAddr){
+          self.clients.remove(&RateKey::Ip(ip));
+     }
+
+     /// Drops the state of clients that are not banned and were idle for a whole violation window, to bound memory usage
+     pub fn prune(&mut self){
+          let now = Instant::now();
+          let window = self.limit.violation_window;
+          self.clients.retain(|_, c|{
+               c.banned_until.is_some_and(|until|until>now) || now.duration_since(c.last_seen)<=window
+          });
+     }
+
+     pub fn get_limit(&self)->&RateLimit{
+          &self.limit
+     }
+}
+
+/// Display implementation for Verdict
+impl Display for Verdict{
+     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+          match self {
+               Self::Allowed=>write!(f, "allowed"),
+               Self::Throttled=>write!(f, "throttled"),
+               Self::Banned(d)=>write!(f, "banned for {}s", d.as_secs())
+          }
+     }
+}
+
+#[cfg(test)]
+mod tests{
+     use super::*;
+     use std::net::Ipv4Addr;
+     use std::thread::sleep;
+
+     fn ip(last:u8)->IpAddr{
+          IpAddr::V4(Ipv4Addr::new(10, 0, 0, last))
+     }
+
+     fn limit(messages_per_second:u32, max_violations:u32)->RateLimit{
+          RateLimit{
+               messages_per_second,
+               bytes_per_second:0,
+               burst_seconds:1,
+               max_violations,
+               ..RateLimit::default()
+          }
+     }
+
+     #[test]
+     fn refills_the_bucket_at_its_rate(){
+          let start = Instant::now();
+          let mut bucket = TokenBucket::new(10.0, 2);
+          assert!((0..20).all(|_|bucket.try_take(1.0, start)));
+          assert!(!bucket.try_take(1.0, start));
+
+          //a tenth of a second refills one token, and the bucket never holds more than its burst
+          assert!(bucket.try_take(1.0, start+Duration::from_millis(100)));
+          assert!(!bucket.try_take(1.0, start+Duration::from_millis(100)));
+          let later = start+Duration::from_secs(60);
+          assert!((0..20).all(|_|bucket.try_take(1.0, later)));
+          assert!(!bucket.try_take(1.0, later));
+     }
+
+     #[test]
+     fn bans_a_client_throttled_repeatedly(){
+          let mut limiter = RateLimiter::new(limit(1, 3));
+          assert_eq!(limiter.check(None, ip(1), 0), Verdict::Allowed);
+          assert_eq!(limiter.check(None, ip(1), 0), Verdict::Throttled);
+          assert_eq!(limiter.check(None, ip(1), 0), Verdict::Throttled);
+          assert!(matches!(limiter.check(None, ip(1), 0), Verdict::Banned(_)));
+          assert!(limiter.is_banned_ip(ip(1)).is_some());
+          assert!(matches!(limiter.check(None, ip(1), 0), Verdict::Banned(_)));
+
+          //the other addresses are not affected, and an unbanned address starts over
+          assert_eq!(limiter.check(None, ip(2), 0), Verdict::Allowed);
+          limiter.unban_ip(ip(1));
+          assert_eq!(limiter.check(None, ip(1), 0), Verdict::Allowed);
+     }
+
+     #[test]
+     fn checks_the_ip_before_the_alias(){
+          let mut limiter = RateLimiter::new(limit(1, 0));
+          assert_eq!(limiter.check(Some("bob"), ip(1), 0), Verdict::Allowed);
+          //the alias is limited whatever the address it sends from
+          assert_eq!(limiter.check(Some("bob"), ip(2), 0), Verdict::Throttled);
+
+          //a message refused for its address does not consume the tokens of the alias
+          assert_eq!(limiter.check(Some("carol"), ip(1), 0), Verdict::Throttled);
+          assert_eq!(limiter.check(Some("carol"), ip(3), 0), Verdict::Allowed);
+     }
+
+     #[test]
+     fn prunes_the_idle_clients_every_interval(){
+          let mut limiter = RateLimiter::new(RateLimit{ violation_window:Duration::ZERO, ..RateLimit::default() });
+          for i in 0..PRUNE_INTERVAL-1{
+               let ip = IpAddr::V4(Ipv4Addr::from(i));
+               assert_eq!(limiter.check(None, ip, 0), Verdict::Allowed);
+          }
+          assert_eq!(limiter.clients.len(), PRUNE_INTERVAL as usize-1);
+
+          sleep(Duration::from_millis(5));
+          limiter.check(None, ip(1), 0);
+          assert_eq!(limiter.clients.len(), 1);
+     }
+}
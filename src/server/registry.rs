@@ -0,0 +1,237 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, RwLock};
+
+use super::container::{ClientReceiverContainer, ClientSenderContainer};
+use super::queue::QueueSender;
+
+
+/// Number of shards the receive clients are split into
+const SHARD_COUNT:usize = 16;
+
+/// The receive clients of the aliases hashed to one shard
+type Shard<C> = HashMap<String, Vec<C>>;
+
+/// A trait for the receive client entries of a [ReceiverRegistry], such as [ClientReceiverContainer]
+pub trait Registration{
+     /// The handle through which messages are delivered to the receive client
+     type Sender;
+
+     /// Returns the unique identifier of the entry
+     fn get_id(&self)->u64;
+
+     /// Returns the handle delivering messages to the receive client, if it is still open
+     fn get_sender(&self)->Option<Self::Sender>;
+
+     /// Returns true if the delivery queue of the receive client has been closed
+     fn is_closed(&self)->bool;
+
+     /// Returns true if the task handling the receive client has finished
+     fn is_finished(&self)->bool;
+}
+
+/// A struct representing the registry of the receive clients, indexed by alias.
+/// The aliases are split into shards each behind its own [RwLock], so that sends to different aliases do not contend
+/// and lookups only take a read lock. No method holds a lock while writing to a client stream,
+/// containers to disconnect are removed from the registry and handed back to the caller
+///
+/// # Fields
+///
+/// - `shards`: The receive clients of each alias, an alias registered by several clients holds several containers
+#[derive(Debug)]
+pub struct ReceiverRegistry<C>{
+     shards:Vec<RwLock<Shard<C>>>
+}
+
+/// A struct representing the registry of the send clients, indexed by container id
+///
+/// # Fields
+///
+/// - `containers`: The send clients by the id of their container
+#[derive(Debug)]
+pub struct SenderRegistry{
+     containers:Mutex<HashMap<u64, ClientSenderContainer>>
+}
+
+impl <C:Registration>ReceiverRegistry<C>{
+     /// Default constructor for an empty [ReceiverRegistry]
+     pub fn new()->Self{
+          ReceiverRegistry{
+               shards:(0..SHARD_COUNT).map(|_|RwLock::new(HashMap::new())).collect()
+          }
+     }
+
+     fn shard(&self, alias:&str)->&RwLock<Shard<C>>{
+          let mut hasher = DefaultHasher::new();
+          alias.hash(&mut hasher);
+          &self.shards[hasher.finish() as usize % SHARD_COUNT]
+     }
+
+     /// Registers the container built by `build` under `alias`.
+     /// The shard is locked while the container is built, so that a thread spawned by `build` cannot deregister before it is registered
+     pub fn register_with<F:FnOnce()->C>(&self, alias:&str, build:F){
+          let mut shard = self.shard(alias).write().unwrap();
+          let container = build();
+          shard.entry(alias.to_string()).or_default().push(container);
+     }
+
+     /// Removes the container with the given id registered under `alias`
+     pub fn deregister(&self, alias:&str, id:u64)->Option<C>{
+          let mut shard = self.shard(alias).write().unwrap();
+          let containers = shard.get_mut(alias)?;
+          let pos = containers.iter().position(|c|c.get_id()==id)?;
+          let container = containers.remove(pos);
+          if containers.is_empty(){
+               shard.remove(alias);
+          }
+          Some(container)
+     }
+
+     /// Removes every container registered under `alias`
+     pub fn remove_alias(&self, alias:&str)->Vec<C>{
+          self.shard(alias).write().unwrap().remove(alias).unwrap_or_default()
+     }
+
+     /// Removes the containers registered under `alias` whose delivery queue has been closed
+     pub fn remove_closed(&self, alias:&str)->Vec<C>{
+          let mut shard = self.shard(alias).write().unwrap();
+          let containers = match shard.get_mut(alias){
+               None=>return Vec::new(),
+               Some(containers)=>containers
+          };
+          let (closed, open):(Vec<_>, Vec<_>) = containers.drain(..).partition(|c|c.is_closed());
+          *containers = open;
+          if containers.is_empty(){
+               shard.remove(alias);
+          }
+          closed
+     }
+
+     /// Removes the containers whose handling thread has finished
+     pub fn remove_finished(&self)->Vec<C>{
+          let mut finished = Vec::new();
+          for shard in self.shards.iter(){
+               let mut shard = shard.write().unwrap();
+               for containers in shard.values_mut(){
+                    let (done, running):(Vec<_>, Vec<_>) = containers.drain(..).partition(|c|c.is_finished());
+                    *containers = running;
+                    finished.extend(done);
+               }
+               shard.retain(|_, containers|!containers.is_empty());
+          }
+          finished
+     }
+
+     /// Removes every container
+     pub fn remove_all(&self)->Vec<C>{
+          self.shards.iter()
+               .flat_map(|shard|std::mem::take(&mut *shard.write().unwrap()).into_values())
+               .flatten()
+               .collect()
+     }
+
+     /// Returns the senders of every receive client registered under `alias`
+     pub fn senders_for(&self, alias:&str)->Vec<C::Sender>{
+          match self.shard(alias).read().unwrap().get(alias){
+               None=>Vec::new(),
+               Some(containers)=>containers.iter().filter_map(|c|c.get_sender()).collect()
+          }
+     }
+
+     /// Returns true if at least one receive client is registered under `alias`
+     pub fn contains(&self, alias:&str)->bool{
+          self.shard(alias).read().unwrap().contains_key(alias)
+     }
+
+     /// Returns the aliases at least one receive client is registered under
+     pub fn aliases(&self)->Vec<String>{
+          self.shards.iter()
+               .flat_map(|shard|shard.read().unwrap().keys().cloned().collect::<Vec<String>>())
+               .collect()
+     }
+
+     /// Returns the number of registered receive clients
+     pub fn len(&self)->usize{
+          self.shards.iter()
+               .map(|shard|shard.read().unwrap().values().map(|c|c.len()).sum::<usize>())
+               .sum()
+     }
+
+     /// Returns true if no receive client is registered
+     pub fn is_empty(&self)->bool{
+          self.shards.iter().all(|shard|shard.read().unwrap().is_empty())
+     }
+}
+
+impl SenderRegistry{
+     /// Default constructor for an empty [SenderRegistry]
+     pub fn new()->Self{
+          SenderRegistry{
+               containers:Mutex::new(HashMap::new())
+          }
+     }
+
+     /// Registers the container built by `build`.
+     /// The registry is locked while the container is built, so that a thread spawned by `build` cannot deregister before it is registered
+     pub fn register_with<F:FnOnce()->ClientSenderContainer>(&self, build:F){
+          let mut containers = self.containers.lock().unwrap();
+          let container = build();
+          containers.insert(container.get_id(), container);
+     }
+
+     /// Removes the container with the given id
+     pub fn deregister(&self, id:u64)->Option<ClientSenderContainer>{
+          self.containers.lock().unwrap().remove(&id)
+     }
+
+     /// Removes the containers whose handling thread has finished
+     pub fn remove_finished(&self)->Vec<ClientSenderContainer>{
+          let mut containers = self.containers.lock().unwrap();
+          let finished:Vec<u64> = containers.values()
+               .filter(|c|c.get_thread_handle().is_finished())
+               .map(|c|c.get_id())
+               .collect();
+          finished.iter().filter_map(|id|containers.remove(id)).collect()
+     }
+
+     /// Removes every container
+     pub fn remove_all(&self)->Vec<ClientSenderContainer>{
+          self.containers.lock().unwrap().drain().map(|(_, c)|c).collect()
+     }
+}
+
+/// Registration implementation for ClientReceiverContainer
+impl <T>Registration for ClientReceiverContainer<T>{
+     type Sender = QueueSender<T>;
+
+     fn get_id(&self)->u64{
+          ClientReceiverContainer::get_id(self)
+     }
+
+     fn get_sender(&self)->Option<QueueSender<T>>{
+          ClientReceiverContainer::get_sender(self)
+     }
+
+     fn is_closed(&self)->bool{
+          ClientReceiverContainer::is_closed(self)
+     }
+
+     fn is_finished(&self)->bool{
+          self.get_thread_handle().is_finished()
+     }
+}
+
+/// Default implementation for ReceiverRegistry
+impl <C:Registration>Default for ReceiverRegistry<C>{
+     fn default() -> Self {
+          ReceiverRegistry::new()
+     }
+}
+
+/// Default implementation for SenderRegistry
+impl Default for SenderRegistry{
+     fn default() -> Self {
+          SenderRegistry::new()
+     }
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+
+/// Set by the signal handler installed with [ShutdownHandle::on_signal]
+static SIGNALLED:AtomicBool = AtomicBool::new(false);
+
+/// A struct representing a handle requesting the graceful shutdown of a [crate::server::Server].
+/// It can be cloned and moved to other threads, the server stops accepting connections once it is triggered
+///
+/// # Fields
+///
+/// - `requested`: True once the shutdown has been requested
+/// - `signals`: True if a shutdown signal received by the process also triggers this handle
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle{
+     requested:Arc<AtomicBool>,
+     signals:Arc<AtomicBool>
+}
+
+impl ShutdownHandle{
+     /// Default constructor for a [ShutdownHandle] that has not been triggered
+     pub fn new()->Self{
+          ShutdownHandle::default()
+     }
+
+     /// Requests the shutdown of the server
+     pub fn shutdown(&self){
+          self.requested.store(true, Ordering::SeqCst);
+     }
+
+     /// Triggers this handle when the process receives SIGINT or SIGTERM, or Ctrl-C and the close events on Windows
+     pub fn on_signal(&self){
+          self.signals.store(true, Ordering::SeqCst);
+          signal::install();
+     }
+
+     /// Returns true once the shutdown has been requested
+     pub fn is_requested(&self)->bool{
+          self.requested.load(Ordering::SeqCst) || (self.signals.load(Ordering::SeqCst) && SIGNALLED.load(Ordering::SeqCst))
+     }
+}
+
+mod signal{
+     use std::sync::atomic::Ordering;
+     use std::sync::Once;
+
+     use log::error;
+
+     /// Installs the process wide handler of SIGINT and SIGTERM, once
+     pub fn install(){
+          static INSTALL:Once = Once::new();
+          INSTALL.call_once(||{
+               if let Err(e) = ctrlc::set_handler(||super::SIGNALLED.store(true, Ordering::SeqCst)){
+                    error!("Could not install the shutdown signal handler {}", e);
+               }
+          });
+     }
+}
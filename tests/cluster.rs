@@ -0,0 +1,59 @@
+//! Three clustered nodes on ephemeral ports: messages to an alias online nowhere are held by its owner,
+//! and handed to the node the alias registers on
+
+mod common;
+
+use raw::client::{Client, Receiver};
+use raw::server::cluster::{HashRing, DEFAULT_VIRTUAL_NODES};
+use raw::{Response, Status};
+
+use common::{expect_body, send_until, wait_linked, Node};
+
+/// Returns an alias owned by `node` in a cluster of the nodes a, b and c
+fn alias_owned_by(node:&str)->String{
+     let mut ring = HashRing::new(DEFAULT_VIRTUAL_NODES);
+     for name in ["a", "b", "c"]{
+          ring.add(name);
+     }
+     (0..).map(|i|format!("user{i}")).find(|alias|ring.owner(alias).is_some_and(|owner|owner==node)).unwrap()
+}
+
+/// Starts the nodes a, b and c linked to each other
+fn cluster()->(Node, Node, Node){
+     let a = Node::start("a", &[], true);
+     let b = Node::start("b", &[a.addr], true);
+     let c = Node::start("c", &[a.addr, b.addr], true);
+     wait_linked(b.addr, a.addr, "probe_a");
+     wait_linked(c.addr, a.addr, "probe_a");
+     wait_linked(c.addr, b.addr, "probe_b");
+     (a, b, c)
+}
+
+#[test]
+fn deposits_with_the_owner_of_an_alias_online_nowhere(){
+     let (_a, b, c) = cluster();
+     let alias = alias_owned_by("a");
+
+     //sent through b, held by a
+     let mut alice = Client::connect(b.addr, "alice").unwrap();
+     let res = alice.send(&alias, "hello").unwrap();
+     assert_eq!(res.get_status(), Status::Success);
+     assert!(res.get_message().contains(&format!("deposited in the mailbox of '{alias}'")), "{res}");
+
+     //pushed by a to c once the alias registers there
+     let mut receiver = Receiver::connect(c.addr, &alias, None).unwrap();
+     expect_body(&mut receiver, "hello");
+}
+
+#[test]
+fn keeps_the_messages_of_the_aliases_it_owns(){
+     let (_a, b, c) = cluster();
+     let alias = alias_owned_by("b");
+
+     let mut alice = Client::connect(b.addr, "alice").unwrap();
+     let queued = |res:&Result<Response, _>|matches!(res, Ok(res) if res.get_message().contains("queued for redelivery"));
+     assert!(queued(&send_until(&mut alice, &alias, "hello", queued)));
+
+     let mut receiver = Receiver::connect(c.addr, &alias, None).unwrap();
+     expect_body(&mut receiver, "hello");
+}
@@ -0,0 +1,101 @@
+//! Servers on ephemeral ports and client helpers shared by the integration tests
+
+use std::net::SocketAddr;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use raw::client::{Client, ClientError, Incoming, Receiver};
+use raw::server::cluster::DEFAULT_VIRTUAL_NODES;
+use raw::server::shutdown::ShutdownHandle;
+use raw::{BaseProto, Proto, Response, ServerBuilder};
+
+pub const SECRET:&str = "federation-secret";
+
+/// Time waited for the servers to link, or to notice a link went down
+pub const LINK_WAIT:Duration = Duration::from_secs(10);
+
+/// A federated server serving from a thread of its own, stopped when dropped
+pub struct Node{
+     pub addr:SocketAddr,
+     shutdown:ShutdownHandle,
+     thread:Option<JoinHandle<()>>
+}
+
+impl Node{
+     /// Serves a federated server named `name` on an ephemeral port, dialing `peers`, as a node of a cluster if `clustered`
+     pub fn start(name:&str, peers:&[SocketAddr], clustered:bool)->Self{
+          let mut builder = ServerBuilder::new()
+               .addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+               .require_secret(false)
+               .drain_timeout(Duration::from_millis(200))
+               .federation(name.to_string(), SECRET.to_string());
+          for peer in peers{
+               builder = builder.peer(peer.to_string());
+          }
+          if clustered{
+               builder = builder.cluster(DEFAULT_VIRTUAL_NODES);
+          }
+          Self::serve(builder)
+     }
+
+     fn serve(builder:ServerBuilder)->Self{
+          let mut server = builder.build();
+          let addr = server.bind().unwrap();
+          let shutdown = server.get_shutdown_handle();
+          let thread = spawn(move ||server.serve().unwrap());
+          Node{
+               addr,
+               shutdown,
+               thread:Some(thread)
+          }
+     }
+
+     pub fn stop(&mut self){
+          self.shutdown.shutdown();
+          if let Some(thread) = self.thread.take(){
+               thread.join().unwrap();
+          }
+     }
+}
+
+impl Drop for Node{
+     fn drop(&mut self){
+          self.stop();
+     }
+}
+
+/// Sends from `client` to `to` until `done` accepts the outcome of a send, returns that outcome
+pub fn send_until(client:&mut Client, to:&str, body:&str, done:impl Fn(&Result<Response, ClientError>)->bool)->Result<Response, ClientError>{
+     let deadline = Instant::now()+LINK_WAIT;
+     loop {
+          let res = client.send(to, body);
+          if done(&res) || Instant::now()>=deadline{
+               return res;
+          }
+          sleep(Duration::from_millis(50));
+     }
+}
+
+/// Returns true once a message was delivered to a receive client, rather than deposited in a mailbox
+pub fn delivered(res:&Result<Response, ClientError>)->bool{
+     matches!(res, Ok(res) if res.get_message().contains("dispatched"))
+}
+
+/// Waits until the node at `from` forwards to the node at `to`, through a probe receive client registered on `to`
+pub fn wait_linked(from:SocketAddr, to:SocketAddr, probe:&str){
+     let _receiver = Receiver::connect(to, probe, None).unwrap();
+     let mut client = Client::connect(from, "prober").unwrap();
+     assert!(delivered(&send_until(&mut client, probe, "probe", delivered)), "{from} never forwarded to {to}");
+}
+
+/// Waits for the next message with `body` delivered to `receiver`, skipping the others
+pub fn expect_body(receiver:&mut Receiver, body:&str)->BaseProto{
+     receiver.set_read_timeout(Some(LINK_WAIT)).unwrap();
+     loop {
+          match receiver.recv().unwrap(){
+               Some(Incoming::Message(message)) if message.get_body()==body=>return message,
+               Some(_)=>continue,
+               None=>panic!("the server closed the receiver before '{body}' was delivered")
+          }
+     }
+}
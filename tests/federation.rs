@@ -0,0 +1,119 @@
+//! Two federated servers on ephemeral ports: forwarding, a peer going down and the refusal of a forwarding loop
+
+mod common;
+
+use std::net::TcpStream;
+use std::thread::spawn;
+
+use raw::client::{Client, Receiver};
+use raw::server::protocol::frame::{write_frame, FrameReader};
+use raw::{BaseProto, BaseProtocol, DataTransferProtocol, Proto, Response, Status};
+
+use common::{delivered, expect_body, send_until, wait_linked, Node, LINK_WAIT, SECRET};
+
+/// Links to the server at `addr` as the peer `name`
+fn link(addr:std::net::SocketAddr, name:&str)->(TcpStream, FrameReader){
+     let mut link = TcpStream::connect(addr).unwrap();
+     link.set_read_timeout(Some(LINK_WAIT)).unwrap();
+     let mut reader = FrameReader::new(64*1024);
+     write_frame(&mut link, format!("PEER;{name};secret={SECRET}").as_bytes()).unwrap();
+     let answer = String::from_utf8(reader.read_frame(&mut link).unwrap().unwrap()).unwrap();
+     assert!(Response::parse(&answer).unwrap().get_status().is_success(), "{answer}");
+     (link, reader)
+}
+
+/// Reads frames from a link until one starts with `prefix`, returns the rest of it
+fn read_until(link:&mut TcpStream, reader:&mut FrameReader, prefix:&str)->String{
+     loop {
+          let frame = String::from_utf8(reader.read_frame(link).unwrap().unwrap()).unwrap();
+          if let Some(rest) = frame.strip_prefix(prefix){
+               return rest.to_string();
+          }
+     }
+}
+
+#[test]
+fn forwards_to_the_peer_hosting_the_alias(){
+     let a = Node::start("a", &[], false);
+     let b = Node::start("b", &[a.addr], false);
+     wait_linked(b.addr, a.addr, "probe");
+
+     let mut bob = Receiver::connect(a.addr, "bob", None).unwrap();
+     let mut alice = Client::connect(b.addr, "alice").unwrap();
+     //bob is advertised to b once registered, the messages sent before are held by b
+     let res = send_until(&mut alice, "bob", "hello", |res|matches!(res, Ok(res) if !res.get_message().contains("queued for redelivery")));
+     assert_eq!(res.unwrap().get_status(), Status::Success);
+
+     let message = expect_body(&mut bob, "hello");
+     assert_eq!(message.get_sender(), "alice");
+}
+
+#[test]
+fn holds_messages_once_the_peer_is_down(){
+     let mut a = Node::start("a", &[], false);
+     let b = Node::start("b", &[a.addr], false);
+     wait_linked(b.addr, a.addr, "probe");
+
+     let _bob = Receiver::connect(a.addr, "bob", None).unwrap();
+     let mut alice = Client::connect(b.addr, "alice").unwrap();
+     assert!(delivered(&send_until(&mut alice, "bob", "before", delivered)));
+
+     a.stop();
+     //once a is down bob is no longer routed, the message is kept by b for redelivery
+     let queued = |res:&Result<Response, _>|matches!(res, Ok(res) if res.get_message().contains("queued for redelivery"));
+     let res = send_until(&mut alice, "bob", "after", queued);
+     assert!(queued(&res), "{:?}", res.map(|res|res.to_string()));
+}
+
+#[test]
+fn refuses_a_message_that_went_through_the_server(){
+     let a = Node::start("a", &[], false);
+
+     let (mut link, mut reader) = link(a.addr, "evil");
+
+     //a message listing a in its via is never delivered again
+     let pto = BaseProto::create("alice".to_string(), "looped".to_string(), "bob".to_string()).with_id(42);
+     let mut frame = b"FORWARD;7;evil,a\n".to_vec();
+     frame.extend_from_slice(&BaseProtocol::new().to_raw(pto).unwrap());
+     write_frame(&mut link, &frame).unwrap();
+
+     let result = read_until(&mut link, &mut reader, "RESULT;7;");
+     let res = Response::parse(&result).unwrap();
+     assert_eq!(res.get_status(), Status::ServerError);
+     assert_eq!(res.get_id(), Some(42));
+     assert!(res.get_message().contains("Forwarding loop"), "{result}");
+}
+
+#[test]
+fn answers_each_forwarded_message_with_its_own_result(){
+     let a = Node::start("a", &[], false);
+     let (mut link, mut reader) = link(a.addr, "peer");
+     write_frame(&mut link, b"ONLINE;bob").unwrap();
+
+     //two senders waiting on the peer at once
+     let senders:Vec<_> = ["first", "second"].into_iter().map(|body|{
+          let addr = a.addr;
+          spawn(move ||{
+               let mut client = Client::connect(addr, body).unwrap();
+               let res = send_until(&mut client, "bob", body, |res|!matches!(res, Ok(res) if res.get_message().contains("queued for redelivery")));
+               (body, res.unwrap())
+          })
+     }).collect();
+
+     //answering in the reverse order with the same message id, each with the name of the sender it answers
+     let mut forwarded = Vec::new();
+     while forwarded.len()<2{
+          let rest = read_until(&mut link, &mut reader, "FORWARD;");
+          let (seq, message) = rest.split_once(';').unwrap();
+          let sender = message.split_once('\n').unwrap().1.split('-').next().unwrap().to_string();
+          forwarded.push((seq.to_string(), sender));
+     }
+     for (seq, sender) in forwarded.iter().rev(){
+          write_frame(&mut link, format!("RESULT;{seq};200;Success;1;{sender}").as_bytes()).unwrap();
+     }
+
+     for sender in senders{
+          let (body, res) = sender.join().unwrap();
+          assert_eq!(res.get_message(), body);
+     }
+}
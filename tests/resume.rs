@@ -0,0 +1,96 @@
+//! A receive client resuming after the last message it was delivered, and reconnecting to a restarted server
+
+use std::net::SocketAddr;
+use std::sync::mpsc::channel;
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+use raw::client::{Backoff, Client, Incoming, ReconnectEvent, ReconnectingReceiver, Receiver};
+use raw::server::auth::DuplicatePolicy;
+use raw::server::shutdown::ShutdownHandle;
+use raw::{Proto, ServerBuilder};
+
+/// Time waited for a message or a reconnection
+const WAIT:Duration = Duration::from_secs(10);
+
+/// Serves a server from a thread of its own on `addr`, returns its address and what stops it
+fn serve(builder:ServerBuilder, addr:SocketAddr)->(SocketAddr, ShutdownHandle, JoinHandle<()>){
+     let mut server = builder.addr(addr).drain_timeout(Duration::from_millis(200)).build();
+     let addr = server.bind().unwrap();
+     let shutdown = server.get_shutdown_handle();
+     (addr, shutdown, spawn(move ||server.serve().unwrap()))
+}
+
+/// Waits for the next message delivered to `receiver`, skipping the notices of the server
+fn next_body(receiver:&mut Receiver)->String{
+     receiver.set_read_timeout(Some(WAIT)).unwrap();
+     loop {
+          match receiver.recv().unwrap(){
+               Some(Incoming::Message(message))=>return message.get_body().to_string(),
+               Some(Incoming::Notice(_))=>continue,
+               None=>panic!("the server closed the receiver")
+          }
+     }
+}
+
+#[test]
+fn delivers_again_the_messages_written_after_the_one_resumed_after(){
+     let builder = ServerBuilder::new().duplicate_policy(DuplicatePolicy::Coexist);
+     let (addr, shutdown, server) = serve(builder, SocketAddr::from(([127, 0, 0, 1], 0)));
+
+     let mut bob = Receiver::connect(addr, "bob", Some("bob-secret")).unwrap();
+     let mut alice = Client::connect(addr, "alice").unwrap();
+     for body in ["one", "two", "three"]{
+          alice.send("bob", body).unwrap();
+     }
+     assert_eq!(next_body(&mut bob), "one");
+     let one = bob.get_last_id().unwrap();
+     assert_eq!(next_body(&mut bob), "two");
+     assert_eq!(next_body(&mut bob), "three");
+
+     //a session resuming while the journal of the alias is kept, eg.. after its connection broke, is written them again
+     let mut resumed = Receiver::resume(addr, "bob", Some("bob-secret"), one).unwrap();
+     assert_eq!(next_body(&mut resumed), "two");
+     assert_eq!(next_body(&mut resumed), "three");
+
+     shutdown.shutdown();
+     server.join().unwrap();
+}
+
+#[test]
+fn reconnects_to_a_restarted_server(){
+     let (addr, shutdown, server) = serve(ServerBuilder::new(), SocketAddr::from(([127, 0, 0, 1], 0)));
+
+     let (events, connections) = channel();
+     let mut bob = ReconnectingReceiver::new(&addr.to_string(), "bob");
+     bob.set_secret("bob-secret".to_string());
+     bob.set_backoff(Backoff::new(Duration::from_millis(20), Duration::from_millis(100)));
+     bob.on_event(move |event|if let ReconnectEvent::Connected{ resumed_after, .. } = event{
+          let _ = events.send(*resumed_after);
+     });
+     let mut next_body = ||loop {
+          match bob.recv().unwrap(){
+               Some(Incoming::Message(message))=>return (message.get_body().to_string(), message.get_id()),
+               Some(Incoming::Notice(_))=>continue,
+               None=>panic!("the receiver gave up")
+          }
+     };
+
+     let mut alice = Client::connect(addr, "alice").unwrap();
+     alice.send("bob", "before").unwrap();
+     let (body, before) = next_body();
+     assert_eq!(body, "before");
+     assert_eq!(connections.recv_timeout(WAIT).unwrap(), None);
+
+     //the restarted server journaled nothing, the receiver resumes after the last message it was delivered all the same
+     shutdown.shutdown();
+     server.join().unwrap();
+     let (_, shutdown, server) = serve(ServerBuilder::new(), addr);
+     let mut alice = Client::connect(addr, "alice").unwrap();
+     alice.send("bob", "after").unwrap();
+     assert_eq!(next_body().0, "after");
+     assert_eq!(connections.recv_timeout(WAIT).unwrap(), before);
+
+     shutdown.shutdown();
+     server.join().unwrap();
+}